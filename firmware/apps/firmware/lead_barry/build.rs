@@ -149,6 +149,11 @@ fn main() {
      *  Linker configuration
      **************************************************************************************/
 
+    // The host simulation links as a regular executable
+    if env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os != "none") {
+        return;
+    }
+
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
     copy_memory_x().expect("Failed to copy memory.x");
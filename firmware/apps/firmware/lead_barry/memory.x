@@ -1,7 +1,13 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    /* Program flash - 1.75MB */
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 4K
+    /* Program flash - 1.5MB */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 512K - 8K - 4K
+
+    /* Reading history - 512KB before the runtime counters */
+    HISTORY_FLASH : ORIGIN = 0x1017D000, LENGTH = 512K
+
+    /* Runtime counters - 8KB before the user data storage, two records written in turns */
+    COUNTERS_FLASH : ORIGIN = 0x101FD000, LENGTH = 8K
 
     /* User data storage area - last 4KB of flash */
     USER_FLASH : ORIGIN = 0x101FF000, LENGTH = 4K
@@ -15,6 +21,10 @@ _stack_size = 16K;
 
 _user_flash_start = ORIGIN(USER_FLASH);
 _user_flash_size = LENGTH(USER_FLASH);
+_history_flash_start = ORIGIN(HISTORY_FLASH);
+_history_flash_size = LENGTH(HISTORY_FLASH);
+_counters_flash_start = ORIGIN(COUNTERS_FLASH);
+_counters_flash_size = LENGTH(COUNTERS_FLASH);
 
 EXTERN(BOOT2_FIRMWARE)
 
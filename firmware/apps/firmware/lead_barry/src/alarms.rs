@@ -0,0 +1,211 @@
+//! The alarms active across the services.
+//!
+//! An alarm is not latched here: it is derived from the latest status of the service which raises it
+//! and clears as soon as the condition is gone. The VCP alerts are latched by the chip until the next
+//! poll, so a short over-current is still reported once.
+
+use serde::Serialize;
+
+use crate::battery::HealthStatus;
+use crate::configuration::MAX_FUSES;
+use crate::efuse::{EFuseStatus, FuseState};
+use crate::lvd::{LvdState, LvdStatus};
+use crate::outputs::OutputId;
+use crate::poe_watchdog::{PoeWatchdogState, PoeWatchdogStatus};
+use crate::temperature::TemperatureStatus;
+use crate::vcp_sensors::{ChannelNum, VCP_CHANNELS, VcpAlertFlags, VcpHealth};
+
+/// The low voltage, the fuses, the critical and the warning current of every channel, the sensor,
+/// the temperature, the battery health and the PoE watchdog
+pub const MAX_ALARMS: usize = 1 + MAX_FUSES + 2 * VCP_CHANNELS + 4;
+
+#[derive(Copy, Clone, PartialEq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum Alarm {
+    /// The low-voltage disconnect is about to shed, has shed or is reconnecting the loads
+    LowVoltage(LvdState),
+    /// The fuse of the output tripped, the output is off until the cool-down ends
+    FuseTripped(OutputId),
+    /// The fuse of the output tripped too many times, the output is off until a manual reset
+    FuseLocked(OutputId),
+    /// The current of the channel exceeded its critical limit
+    CriticalCurrent(ChannelNum),
+    /// The current of the channel exceeded its warning limit
+    WarningCurrent(ChannelNum),
+    /// The INA3221 fails to respond
+    SensorFault(VcpHealth),
+    /// The temperature compensation is enabled but no probe gives a reading
+    TemperatureSensorFault,
+    /// The battery health suggests replacing the battery
+    ReplaceBattery,
+    /// The PoE watchdog gave up power cycling the device for today
+    PoeWatchdogLimitReached,
+}
+
+pub type Alarms = heapless::Vec<Alarm, MAX_ALARMS>;
+
+/// The latest statuses the alarms are derived from
+pub struct AlarmSources<'a> {
+    pub lvd: &'a LvdStatus,
+    pub efuse: &'a EFuseStatus,
+    pub vcp_alerts: VcpAlertFlags,
+    pub vcp_health: VcpHealth,
+    pub temperature: &'a TemperatureStatus,
+    pub battery_health: &'a HealthStatus,
+    pub poe_watchdog: &'a PoeWatchdogStatus,
+    /// The channels of the fitted chips
+    pub channel_count: usize,
+}
+
+impl AlarmSources<'_> {
+    /// The alarms active now, the most severe kinds first
+    pub fn active_alarms(&self) -> Alarms {
+        let mut alarms = Alarms::new();
+        // The capacity covers all the alarms at once
+        let mut raise = |alarm| {
+            let _ = alarms.push(alarm);
+        };
+
+        if self.lvd.enabled && self.lvd.state != LvdState::Normal {
+            raise(Alarm::LowVoltage(self.lvd.state));
+        }
+        for fuse in self.efuse.fuses.iter() {
+            match fuse.state {
+                FuseState::Armed => {}
+                FuseState::CoolingDown => raise(Alarm::FuseTripped(fuse.output)),
+                FuseState::Locked => raise(Alarm::FuseLocked(fuse.output)),
+            }
+        }
+        let channel_count = self.channel_count.min(VCP_CHANNELS);
+        for channel in 0..channel_count {
+            if self.vcp_alerts.critical[channel] {
+                raise(Alarm::CriticalCurrent(channel as ChannelNum));
+            } else if self.vcp_alerts.warning[channel] {
+                raise(Alarm::WarningCurrent(channel as ChannelNum));
+            }
+        }
+        if self.vcp_health != VcpHealth::Ok {
+            raise(Alarm::SensorFault(self.vcp_health));
+        }
+        if self.temperature.enabled && self.temperature.temperature_c.is_none() {
+            raise(Alarm::TemperatureSensorFault);
+        }
+        if self.battery_health.replace_soon {
+            raise(Alarm::ReplaceBattery);
+        }
+        if self.poe_watchdog.state == PoeWatchdogState::LimitReached {
+            raise(Alarm::PoeWatchdogLimitReached);
+        }
+
+        alarms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efuse::FuseStatus;
+
+    struct Statuses {
+        lvd: LvdStatus,
+        efuse: EFuseStatus,
+        vcp_alerts: VcpAlertFlags,
+        vcp_health: VcpHealth,
+        temperature: TemperatureStatus,
+        battery_health: HealthStatus,
+        poe_watchdog: PoeWatchdogStatus,
+    }
+
+    impl Statuses {
+        fn new() -> Self {
+            Self {
+                lvd: LvdStatus::new(),
+                efuse: EFuseStatus::new(),
+                vcp_alerts: VcpAlertFlags::new(),
+                vcp_health: VcpHealth::Ok,
+                temperature: TemperatureStatus::new(),
+                battery_health: HealthStatus::new(),
+                poe_watchdog: PoeWatchdogStatus::new(),
+            }
+        }
+
+        fn alarms(&self, channel_count: usize) -> Alarms {
+            AlarmSources {
+                lvd: &self.lvd,
+                efuse: &self.efuse,
+                vcp_alerts: self.vcp_alerts,
+                vcp_health: self.vcp_health,
+                temperature: &self.temperature,
+                battery_health: &self.battery_health,
+                poe_watchdog: &self.poe_watchdog,
+                channel_count,
+            }
+            .active_alarms()
+        }
+    }
+
+    fn fuse(output: OutputId, state: FuseState) -> FuseStatus {
+        FuseStatus {
+            channel: 0,
+            output,
+            state,
+            attempts: 0,
+            trips: 0,
+        }
+    }
+
+    #[test]
+    fn no_alarms_while_all_is_fine() {
+        let mut statuses = Statuses::new();
+        statuses.lvd.enabled = true;
+        statuses
+            .efuse
+            .fuses
+            .push(fuse(OutputId::Dc1, FuseState::Armed))
+            .unwrap();
+
+        assert!(statuses.alarms(3).is_empty());
+    }
+
+    #[test]
+    fn alarms_follow_the_statuses() {
+        let mut statuses = Statuses::new();
+        statuses.lvd.enabled = true;
+        statuses.lvd.state = LvdState::Shedding;
+        statuses
+            .efuse
+            .fuses
+            .push(fuse(OutputId::Dc1, FuseState::CoolingDown))
+            .unwrap();
+        statuses
+            .efuse
+            .fuses
+            .push(fuse(OutputId::Poe, FuseState::Locked))
+            .unwrap();
+        statuses.vcp_alerts.critical[1] = true;
+        statuses.vcp_alerts.warning[1] = true;
+        statuses.vcp_alerts.warning[2] = true;
+        // The channels of a chip which is not fitted are ignored
+        statuses.vcp_alerts.critical[4] = true;
+        statuses.vcp_health = VcpHealth::Degraded;
+        statuses.temperature.enabled = true;
+        statuses.battery_health.replace_soon = true;
+        statuses.poe_watchdog.state = PoeWatchdogState::LimitReached;
+
+        let alarms = statuses.alarms(3);
+        assert!(
+            alarms.as_slice()
+                == [
+                    Alarm::LowVoltage(LvdState::Shedding),
+                    Alarm::FuseTripped(OutputId::Dc1),
+                    Alarm::FuseLocked(OutputId::Poe),
+                    Alarm::CriticalCurrent(1),
+                    Alarm::WarningCurrent(2),
+                    Alarm::SensorFault(VcpHealth::Degraded),
+                    Alarm::TemperatureSensorFault,
+                    Alarm::ReplaceBattery,
+                    Alarm::PoeWatchdogLimitReached,
+                ]
+        );
+    }
+}
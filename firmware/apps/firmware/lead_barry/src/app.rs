@@ -0,0 +1,628 @@
+//! The services of the controller, their tasks and the glue between them. The board and the host
+//! simulation build them the same way from their own devices.
+
+use defmt_or_log as log;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Ticker};
+use static_cell::StaticCell;
+
+use crate::battery::*;
+use crate::charger::*;
+use crate::configuration::{
+    BatteryProfile, ChannelMap, ConfigurationStorage, Counters, CountersStore, EFuseSettings, HealthSettings,
+    LvdSettings, Settings, TemperatureSettings,
+};
+use crate::efuse::*;
+use crate::global_types::*;
+use crate::history::*;
+use crate::input::ButtonControllerRunner;
+use crate::lvd::*;
+use crate::outputs::*;
+use crate::poe_watchdog::*;
+use crate::rtc::{self, RtcDs3231Ref};
+use crate::shared_resources::*;
+use crate::temperature::*;
+use crate::ui::{UiControl, UiRunner};
+use crate::vcp_sensors::*;
+
+/// How often the lifetime energy totals are written to the flash. Longer periods spare the flash
+/// and lose more of the totals on a power loss.
+const ENERGY_CHECKPOINT_PERIOD_S: u64 = 3600;
+/// How often the cycle statistics are written to the flash
+const CYCLE_CHECKPOINT_PERIOD_S: u64 = 3600;
+
+static VCP_SENSORS_STATE: StaticCell<VcpSensorsState> = StaticCell::new();
+static VCP_SENSORS_CONTROL: StaticCell<VcpControl> = StaticCell::new();
+static BATTERY_MONITOR_STATE: StaticCell<BatteryMonitorState> = StaticCell::new();
+static BATTERY_MONITOR_CONTROL: StaticCell<BatteryMonitorControl> = StaticCell::new();
+static HEALTH_STATE: StaticCell<HealthServiceState> = StaticCell::new();
+static HEALTH_CONTROL: StaticCell<HealthControl> = StaticCell::new();
+static CYCLE_STATE: StaticCell<CycleServiceState> = StaticCell::new();
+static CYCLE_CONTROL: StaticCell<CycleControl> = StaticCell::new();
+static HISTORY_STATE: StaticCell<HistoryServiceState> = StaticCell::new();
+static HISTORY_CONTROL: StaticCell<HistoryControl> = StaticCell::new();
+static LVD_STATE: StaticCell<LvdServiceState> = StaticCell::new();
+static LVD_CONTROL: StaticCell<LvdControl> = StaticCell::new();
+static OUTPUTS_STATE: StaticCell<OutputsState> = StaticCell::new();
+static OUTPUTS_CONTROL: StaticCell<OutputsControl> = StaticCell::new();
+static POE_WATCHDOG_STATE: StaticCell<PoeWatchdogServiceState> = StaticCell::new();
+static POE_WATCHDOG_CONTROL: StaticCell<PoeWatchdogControl> = StaticCell::new();
+static EFUSE_STATE: StaticCell<EFuseServiceState> = StaticCell::new();
+static EFUSE_CONTROL: StaticCell<EFuseControl> = StaticCell::new();
+static CHARGER_STATE: StaticCell<ChargerServiceState> = StaticCell::new();
+static CHARGER_CONTROL: StaticCell<ChargerControl> = StaticCell::new();
+static TEMPERATURE_STATE: StaticCell<TemperatureServiceState> = StaticCell::new();
+static TEMPERATURE_CONTROL: StaticCell<TemperatureControl> = StaticCell::new();
+static EQUALIZATION_STATE: StaticCell<EqualizationServiceState> = StaticCell::new();
+static EQUALIZATION_CONTROL: StaticCell<EqualizationControl> = StaticCell::new();
+static CHANNEL_MAP: StaticCell<ChannelMap> = StaticCell::new();
+static SHARED_RESOURCES: StaticCell<SharedResources> = StaticCell::new();
+static RTC_DS3231: StaticCell<RtcDs3231Ref<RtcI2cDevice>> = StaticCell::new();
+
+/// The devices the services run on
+pub struct AppDevices {
+    pub vcp_sensor: VcpSensorDevice,
+    pub outputs_driver: OutputDriverDevice,
+    pub rtc_i2c: RtcI2cDevice,
+    /// The flash area of the reading history
+    pub history_flash: HistoryFlashDevice,
+    /// The battery temperature probe, only set up if it is the configured source
+    pub temperature_probe: Option<TemperatureProbeDevice>,
+    #[cfg(not(feature = "sim"))]
+    pub led_controller: LedController,
+}
+
+pub struct AppRunners {
+    vcp_runner: Option<VcpSensorsRunner<'static>>,
+    battery_monitor_runner: Option<BatteryMonitorRunner<'static>>,
+    health_runner: Option<HealthRunner<'static>>,
+    cycle_runner: Option<CycleRunner<'static, RtcI2cDevice>>,
+    history_runner: Option<HistoryRunner<'static, RtcI2cDevice>>,
+    lvd_runner: Option<LvdRunner<'static>>,
+    outputs_runner: Option<OutputsRunner<'static>>,
+    poe_watchdog_runner: Option<PoeWatchdogRunner<'static>>,
+    efuse_runner: Option<EFuseRunner<'static>>,
+    charger_runner: Option<ChargerRunner<'static>>,
+    temperature_runner: Option<TemperatureRunner<'static, RtcI2cDevice, TemperatureProbeDevice>>,
+    equalization_runner: Option<EqualizationRunner<'static, RtcI2cDevice>>,
+}
+
+/// Creates all the services from the settings and the counters restored from the flash
+pub fn init(
+    devices: AppDevices,
+    settings: &Settings,
+    counters: &Counters,
+    configuration_storage: &'static ConfigurationStorage,
+    counters_store: &'static CountersStore,
+    ui_control: &'static UiControl<'static>,
+) -> (AppRunners, &'static SharedResources) {
+    // Initialize the VCP sensors
+    log::info!("Initializing VCP sensors...");
+    let vcp_state_ref = VCP_SENSORS_STATE.init_with(VcpSensorsState::new);
+    let channel_count = devices.vcp_sensor.channel_count();
+    log::info!("VCP sensor has {} channels", channel_count);
+    // The channel map decides which channel the SoC estimation and the LVD treat as the battery
+    let channel_map = if settings.channel_map.is_valid(channel_count) {
+        settings.channel_map.clone()
+    } else {
+        log::warn!("Invalid channel map, using the default one");
+        ChannelMap::default()
+    };
+    let channel_map: &'static ChannelMap = CHANNEL_MAP.init(channel_map);
+    // The battery channel limits and the power-valid window follow the configured battery profile
+    let battery_profile = if settings.battery_profile.is_valid() {
+        settings.battery_profile
+    } else {
+        log::warn!("Invalid battery profile, using the default one");
+        BatteryProfile::default()
+    };
+    let battery_monitor_config =
+        BatteryMonitorConfig::from_profile(&battery_profile).with_channel(channel_map.battery_channel());
+    let battery_channel = battery_monitor_config.channel;
+    // The SoC estimation and the LVD rely on the battery readings whatever channel the UI shows
+    let mut vcp_config = VcpConfig::default().with_pinned(battery_channel);
+    vcp_config = vcp_config.with_limits(
+        battery_channel,
+        battery_profile.vcp_limits(vcp_config.limits[battery_channel as usize]),
+    );
+    vcp_config.global_pv_limit = Some(battery_profile.power_valid_limits());
+    if settings.vcp_settings.sampling.is_valid() {
+        vcp_config = vcp_config.with_sampling(settings.vcp_settings.sampling);
+    }
+    vcp_config = vcp_config.with_lifetime_energy(counters.energy_totals.channels);
+    if settings.calibration_settings.is_valid() {
+        vcp_config = vcp_config.with_calibration(settings.calibration_settings.channels);
+    } else {
+        log::warn!("Invalid VCP calibration, using the nominal shunts");
+    }
+    // A fuse on a channel the sensor does not have would index past the channel limits
+    let efuse_settings = if settings.efuse_settings.is_valid(channel_count) {
+        settings.efuse_settings.clone()
+    } else {
+        log::warn!("Invalid e-fuse settings, using the default ones");
+        EFuseSettings::default()
+    };
+    // The channels guarded by a fuse report overcurrent above the trip current. The sensor latches the
+    // warning alert at the same current and raises the critical alert at the critical current.
+    for fuse in efuse_settings.fuses.iter().filter(|fuse| fuse.enabled) {
+        let limits = vcp_config.limits[fuse.channel as usize]
+            .with_max_current(fuse.trip_current_a)
+            .with_warning_current(Some(fuse.trip_current_a))
+            .with_critical_current(Some(fuse.critical_current_a));
+        vcp_config = vcp_config.with_limits(fuse.channel, limits).with_pinned(fuse.channel);
+    }
+    let (vcp_runner, vcp_control) = VcpSensorsService::new(devices.vcp_sensor, vcp_state_ref, vcp_config);
+    let vcp_control: &'static VcpControl = VCP_SENSORS_CONTROL.init(vcp_control);
+
+    // Initialize the battery monitor
+    log::info!("Initializing battery monitor...");
+    let battery_monitor_state_ref = BATTERY_MONITOR_STATE.init_with(BatteryMonitorState::new);
+    let (battery_monitor_runner, battery_control) =
+        BatteryMonitorService::new(vcp_control, battery_monitor_state_ref, battery_monitor_config);
+    let battery_control: &'static BatteryMonitorControl = BATTERY_MONITOR_CONTROL.init(battery_control);
+
+    // Initialize the state-of-health tracking
+    log::info!("Initializing state of health...");
+    let health_state_ref = HEALTH_STATE.init_with(HealthServiceState::new);
+    let health_settings = if settings.health_settings.is_valid() {
+        settings.health_settings.clone()
+    } else {
+        log::warn!("Invalid state-of-health settings, using the default ones");
+        HealthSettings::default()
+    };
+    let health_config = HealthConfig::from_profile(battery_channel, &battery_profile, &health_settings)
+        .with_trend(counters.health_trend.clone());
+    let (health_runner, health_control) = HealthService::new(vcp_control, health_state_ref, health_config);
+    let health_control: &'static HealthControl = HEALTH_CONTROL.init(health_control);
+
+    // Initialize the low-voltage disconnect
+    log::info!("Initializing LVD...");
+    let lvd_state_ref = LVD_STATE.init_with(LvdServiceState::new);
+    // The reconnect voltage has to stay above the disconnect voltage of the profile, or the loads would toggle
+    let lvd_settings = if settings.lvd_settings.is_valid(battery_profile.lvd_voltage) {
+        settings.lvd_settings.clone()
+    } else {
+        log::warn!("Invalid LVD settings, using the default ones");
+        LvdSettings::default()
+    };
+    let lvd_config = LvdServiceConfig::from_settings(battery_channel, &lvd_settings);
+    let (lvd_runner, lvd_control) = LvdService::new(vcp_control, lvd_state_ref, lvd_config);
+    let lvd_control: &'static LvdControl = LVD_CONTROL.init(lvd_control);
+
+    // Initialize the outputs and switch them to their default state
+    log::info!("Initializing outputs...");
+    let outputs_state_ref = OUTPUTS_STATE.init_with(OutputsState::new);
+    let (outputs_runner, outputs_control) =
+        OutputsService::new(devices.outputs_driver, outputs_state_ref, &settings.outputs_settings);
+    let outputs_control: &'static OutputsControl = OUTPUTS_CONTROL.init(outputs_control);
+
+    // Initialize the PoE watchdog. It starts probing once the network is up.
+    log::info!("Initializing PoE watchdog...");
+    let poe_watchdog_state_ref = POE_WATCHDOG_STATE.init_with(PoeWatchdogServiceState::new);
+    let poe_watchdog_config = PoeWatchdogConfig::from_settings(&settings.poe_watchdog_settings);
+    let (poe_watchdog_runner, poe_watchdog_control) =
+        PoeWatchdogService::new(outputs_control, poe_watchdog_state_ref, poe_watchdog_config);
+    let poe_watchdog_control: &'static PoeWatchdogControl = POE_WATCHDOG_CONTROL.init(poe_watchdog_control);
+
+    // Initialize the electronic fuses
+    log::info!("Initializing e-fuses...");
+    let efuse_state_ref = EFUSE_STATE.init_with(EFuseServiceState::new);
+    let efuse_config = EFuseConfig::from_settings(&efuse_settings);
+    let (efuse_runner, efuse_control) = EFuseService::new(vcp_control, outputs_control, efuse_state_ref, efuse_config);
+    let efuse_control: &'static EFuseControl = EFUSE_CONTROL.init(efuse_control);
+
+    // Initialize the charge-stage detector
+    log::info!("Initializing charger monitor...");
+    let charger_state_ref = CHARGER_STATE.init_with(ChargerServiceState::new);
+    let charger_config = ChargerConfig::from_profile(&battery_profile, channel_map);
+    let (charger_runner, charger_control) = ChargerService::new(vcp_control, charger_state_ref, charger_config);
+    let charger_control: &'static ChargerControl = CHARGER_CONTROL.init(charger_control);
+
+    // Initialize the RTC DS3231
+    log::info!("Initializing RTC DS3231...");
+    let rtc_ds3231 = rtc::create_rtc_ds3231(devices.rtc_i2c);
+    let rtc_ds3231_ref: &'static RtcDs3231Ref<RtcI2cDevice> = RTC_DS3231.init(rtc_ds3231);
+
+    // Initialize the temperature compensation. The probe is only read if it is the configured
+    // source, otherwise the DS3231 sensor is used.
+    log::info!("Initializing temperature compensation...");
+    let temperature_settings = temperature_settings(settings);
+    let temperature_state_ref = TEMPERATURE_STATE.init_with(TemperatureServiceState::new);
+    let temperature_config =
+        TemperatureConfig::from_settings(&temperature_settings, &battery_profile, lvd_settings.reconnect_voltage);
+    let (temperature_runner, temperature_control) = TemperatureService::new(
+        rtc_ds3231_ref,
+        devices.temperature_probe,
+        temperature_state_ref,
+        temperature_config,
+    );
+    let temperature_control: &'static TemperatureControl = TEMPERATURE_CONTROL.init(temperature_control);
+
+    // Initialize the cycle counter. It stamps the deepest discharge with the RTC time.
+    log::info!("Initializing cycle counter...");
+    let cycle_state_ref = CYCLE_STATE.init_with(CycleServiceState::new);
+    let (cycle_runner, cycle_control) = CycleService::new(
+        battery_control,
+        rtc_ds3231_ref,
+        cycle_state_ref,
+        CycleCounterConfig::default(),
+        counters.cycle_stats.clone(),
+    );
+    let cycle_control: &'static CycleControl = CYCLE_CONTROL.init(cycle_control);
+
+    // Initialize the reading history. It continues the records kept in the flash.
+    log::info!("Initializing reading history...");
+    let history_state_ref = HISTORY_STATE.init(HistoryServiceState::new(devices.history_flash));
+    let (history_runner, history_control) = HistoryService::new(vcp_control, rtc_ds3231_ref, history_state_ref);
+    let history_control: &'static HistoryControl = HISTORY_CONTROL.init(history_control);
+
+    // Initialize the equalization scheduler. It switches the charger through the charger-control output,
+    // so it only runs when the battery profile allows the equalization and that output is set.
+    log::info!("Initializing equalization scheduler...");
+    let equalization_config = EqualizationConfig::from_profile(&battery_profile, &settings.equalization_settings);
+    let equalization_state_ref = EQUALIZATION_STATE.init_with(EqualizationServiceState::new);
+    let (equalization_runner, equalization_control) = EqualizationService::new(
+        charger_control,
+        temperature_control,
+        outputs_control,
+        rtc_ds3231_ref,
+        equalization_state_ref,
+        equalization_config,
+        counters.equalization_record.clone(),
+    );
+    if !equalization_config.is_supported() {
+        log::info!("Equalization is not configured");
+    }
+    let equalization_control: &'static EqualizationControl = EQUALIZATION_CONTROL.init(equalization_control);
+
+    let shared_resources: &'static SharedResources = SHARED_RESOURCES.init(SharedResources {
+        rtc: rtc_ds3231_ref,
+        ui_control,
+        vcp_control,
+        battery_control,
+        health_control,
+        cycle_control,
+        history_control,
+        lvd_control,
+        outputs_control,
+        poe_watchdog_control,
+        efuse_control,
+        charger_control,
+        temperature_control,
+        equalization_control,
+        battery_profile,
+        channel_map,
+        configuration_storage,
+        counters_store,
+        #[cfg(not(feature = "sim"))]
+        led_controller: devices.led_controller,
+    });
+
+    let runners = AppRunners {
+        vcp_runner: Some(vcp_runner),
+        battery_monitor_runner: Some(battery_monitor_runner),
+        health_runner: Some(health_runner),
+        cycle_runner: Some(cycle_runner),
+        history_runner: Some(history_runner),
+        lvd_runner: Some(lvd_runner),
+        outputs_runner: Some(outputs_runner),
+        poe_watchdog_runner: Some(poe_watchdog_runner),
+        efuse_runner: Some(efuse_runner),
+        charger_runner: Some(charger_runner),
+        temperature_runner: Some(temperature_runner),
+        equalization_runner: equalization_config.is_supported().then_some(equalization_runner),
+    };
+    (runners, shared_resources)
+}
+
+/// The temperature settings to apply, the default ones if the stored ones are invalid
+pub fn temperature_settings(settings: &Settings) -> TemperatureSettings {
+    if settings.temperature_settings.is_valid() {
+        settings.temperature_settings.clone()
+    } else {
+        log::warn!("Invalid temperature settings, using the default ones");
+        TemperatureSettings::default()
+    }
+}
+
+/// Spawns the tasks of the services and the glue between them
+pub fn spawn_tasks(spawner: Spawner, runners: AppRunners, shared: &'static SharedResources) {
+    // Spawn the VCP sensors task
+    if let Some(vcp_runner) = runners.vcp_runner {
+        spawner.spawn(vcp_sensors_runner_task(vcp_runner)).unwrap();
+    }
+
+    // Spawn the battery monitor task
+    if let Some(battery_monitor_runner) = runners.battery_monitor_runner {
+        spawner.spawn(battery_monitor_task(battery_monitor_runner)).unwrap();
+    }
+
+    // Spawn the state-of-health task
+    if let Some(health_runner) = runners.health_runner {
+        spawner.spawn(health_task(health_runner)).unwrap();
+    }
+
+    // Spawn the cycle counter task
+    if let Some(cycle_runner) = runners.cycle_runner {
+        spawner.spawn(cycle_task(cycle_runner)).unwrap();
+    }
+
+    // Spawn the reading history task
+    if let Some(history_runner) = runners.history_runner {
+        spawner.spawn(history_task(history_runner)).unwrap();
+    }
+
+    // Spawn the LVD task
+    if let Some(lvd_runner) = runners.lvd_runner {
+        spawner.spawn(lvd_task(lvd_runner)).unwrap();
+    }
+
+    // Spawn the outputs task
+    if let Some(outputs_runner) = runners.outputs_runner {
+        spawner.spawn(outputs_task(outputs_runner)).unwrap();
+    }
+
+    // Spawn the PoE watchdog task
+    if let Some(poe_watchdog_runner) = runners.poe_watchdog_runner {
+        spawner.spawn(poe_watchdog_task(poe_watchdog_runner)).unwrap();
+    }
+
+    // Spawn the e-fuse task
+    if let Some(efuse_runner) = runners.efuse_runner {
+        spawner.spawn(efuse_task(efuse_runner)).unwrap();
+    }
+
+    // Spawn the charger monitor task
+    if let Some(charger_runner) = runners.charger_runner {
+        spawner.spawn(charger_task(charger_runner)).unwrap();
+    }
+
+    // Spawn the temperature compensation task
+    if let Some(temperature_runner) = runners.temperature_runner {
+        spawner.spawn(temperature_task(temperature_runner)).unwrap();
+    }
+
+    // Spawn the equalization task
+    if let Some(equalization_runner) = runners.equalization_runner {
+        spawner.spawn(equalization_task(equalization_runner)).unwrap();
+    }
+
+    spawner
+        .spawn(lvd_load_shedding_task(shared.lvd_control, shared.outputs_control))
+        .unwrap();
+
+    spawner
+        .spawn(temperature_compensation_task(
+            shared.temperature_control,
+            shared.vcp_control,
+            shared.lvd_control,
+            shared.charger_control,
+            shared.channel_map.battery_channel(),
+            shared.battery_profile,
+        ))
+        .unwrap();
+
+    spawner
+        .spawn(energy_checkpoint_task(shared.vcp_control, shared.counters_store))
+        .unwrap();
+
+    spawner
+        .spawn(cycle_checkpoint_task(shared.cycle_control, shared.counters_store))
+        .unwrap();
+
+    spawner
+        .spawn(health_checkpoint_task(shared.health_control, shared.counters_store))
+        .unwrap();
+
+    spawner
+        .spawn(equalization_checkpoint_task(
+            shared.equalization_control,
+            shared.counters_store,
+        ))
+        .unwrap();
+}
+
+#[embassy_executor::task]
+pub async fn buttons_controller_task(button_controller_runner: ButtonControllerRunner<'static>) -> ! {
+    log::info!("Starting buttons controller task...");
+    button_controller_runner.run().await
+}
+
+#[embassy_executor::task]
+pub async fn ui_runner_task(mut ui_runner: UiRunner<'static>) -> ! {
+    log::info!("Starting UI task...");
+    ui_runner.run().await;
+}
+
+#[embassy_executor::task]
+async fn vcp_sensors_runner_task(mut vcp_sensors_runner: VcpSensorsRunner<'static>) -> ! {
+    log::info!("Starting VCP sensors task...");
+    vcp_sensors_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn battery_monitor_task(mut battery_monitor_runner: BatteryMonitorRunner<'static>) -> ! {
+    log::info!("Starting battery monitor task...");
+    battery_monitor_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn health_task(mut health_runner: HealthRunner<'static>) -> ! {
+    log::info!("Starting state-of-health task...");
+    health_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn cycle_task(mut cycle_runner: CycleRunner<'static, RtcI2cDevice>) -> ! {
+    log::info!("Starting cycle counter task...");
+    cycle_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn history_task(mut history_runner: HistoryRunner<'static, RtcI2cDevice>) -> ! {
+    log::info!("Starting reading history task...");
+    history_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn lvd_task(mut lvd_runner: LvdRunner<'static>) -> ! {
+    log::info!("Starting LVD task...");
+    lvd_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn outputs_task(mut outputs_runner: OutputsRunner<'static>) -> ! {
+    log::info!("Starting outputs task...");
+    outputs_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn poe_watchdog_task(mut poe_watchdog_runner: PoeWatchdogRunner<'static>) -> ! {
+    log::info!("Starting PoE watchdog task...");
+    poe_watchdog_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn efuse_task(mut efuse_runner: EFuseRunner<'static>) -> ! {
+    log::info!("Starting e-fuse task...");
+    efuse_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn charger_task(mut charger_runner: ChargerRunner<'static>) -> ! {
+    log::info!("Starting charger monitor task...");
+    charger_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn temperature_task(
+    mut temperature_runner: TemperatureRunner<'static, RtcI2cDevice, TemperatureProbeDevice>,
+) -> ! {
+    log::info!("Starting temperature compensation task...");
+    temperature_runner.run().await
+}
+
+#[embassy_executor::task]
+async fn equalization_task(mut equalization_runner: EqualizationRunner<'static, RtcI2cDevice>) -> ! {
+    log::info!("Starting equalization task...");
+    equalization_runner.run().await
+}
+
+/// Applies the loads shed and restored by the LVD to the outputs
+#[embassy_executor::task]
+async fn lvd_load_shedding_task(
+    lvd_control: &'static LvdControl<'static>,
+    outputs_control: &'static OutputsControl<'static>,
+) -> ! {
+    log::info!("Starting LVD load shedding task...");
+    loop {
+        let (load, shed) = match lvd_control.receive_action().await {
+            LvdAction::Shed(load) => (load, true),
+            LvdAction::Restore(load) => (load, false),
+        };
+        match OutputId::try_from(load) {
+            Ok(output) => outputs_control.set_lvd_shed(output, shed).await,
+            Err(_) => log::error!("LVD load {} is not an output", load),
+        }
+    }
+}
+
+/// Moves the battery voltage limits, the LVD reconnect voltage and the charge-stage setpoints with
+/// the battery temperature
+#[embassy_executor::task]
+async fn temperature_compensation_task(
+    temperature_control: &'static TemperatureControl<'static>,
+    vcp_control: &'static VcpControl<'static>,
+    lvd_control: &'static LvdControl<'static>,
+    charger_control: &'static ChargerControl<'static>,
+    battery_channel: ChannelNum,
+    battery_profile: BatteryProfile,
+) -> ! {
+    log::info!("Starting temperature compensation glue task...");
+    loop {
+        let setpoints = temperature_control.wait_setpoints().await;
+        let limits = setpoints.apply_to(&battery_profile).vcp_limits(VcpLimits::default());
+        vcp_control
+            .set_voltage_limits(battery_channel, limits.min_voltage, limits.max_voltage)
+            .await;
+        lvd_control.set_reconnect_voltage(setpoints.reconnect_voltage);
+        charger_control.set_setpoints(setpoints.absorption_voltage, setpoints.float_voltage);
+    }
+}
+
+/// Writes the lifetime energy totals to the flash periodically
+#[embassy_executor::task]
+async fn energy_checkpoint_task(
+    vcp_control: &'static VcpControl<'static>,
+    counters_store: &'static CountersStore,
+) -> ! {
+    log::info!("Starting energy checkpoint task...");
+    let mut ticker = Ticker::every(Duration::from_secs(ENERGY_CHECKPOINT_PERIOD_S));
+    loop {
+        ticker.next().await;
+        let lifetime = vcp_control.energy().map(|channel| channel.lifetime);
+        match counters_store
+            .checkpoint(|counters| counters.energy_totals.channels = lifetime)
+            .await
+        {
+            Ok(_) => log::debug!("Energy totals saved"),
+            Err(e) => log::error!("Failed to save energy totals: {:?}", e),
+        }
+    }
+}
+
+/// Writes the cycle statistics to the flash periodically
+#[embassy_executor::task]
+async fn cycle_checkpoint_task(
+    cycle_control: &'static CycleControl<'static>,
+    counters_store: &'static CountersStore,
+) -> ! {
+    log::info!("Starting cycle checkpoint task...");
+    let mut ticker = Ticker::every(Duration::from_secs(CYCLE_CHECKPOINT_PERIOD_S));
+    loop {
+        ticker.next().await;
+        let stats = cycle_control.status().await.stats;
+        match counters_store.checkpoint(|counters| counters.cycle_stats = stats).await {
+            Ok(_) => log::debug!("Cycle statistics saved"),
+            Err(e) => log::error!("Failed to save cycle statistics: {:?}", e),
+        }
+    }
+}
+
+/// Writes the state-of-health trend to the flash whenever a full cycle or a reset changes it
+#[embassy_executor::task]
+async fn health_checkpoint_task(
+    health_control: &'static HealthControl<'static>,
+    counters_store: &'static CountersStore,
+) -> ! {
+    log::info!("Starting state-of-health checkpoint task...");
+    loop {
+        let trend = health_control.wait_trend().await;
+        match counters_store
+            .checkpoint(|counters| counters.health_trend = trend)
+            .await
+        {
+            Ok(_) => log::debug!("State-of-health trend saved"),
+            Err(e) => log::error!("Failed to save state-of-health trend: {:?}", e),
+        }
+    }
+}
+
+/// Writes the equalization record to the flash after every run
+#[embassy_executor::task]
+async fn equalization_checkpoint_task(
+    equalization_control: &'static EqualizationControl<'static>,
+    counters_store: &'static CountersStore,
+) -> ! {
+    log::info!("Starting equalization checkpoint task...");
+    loop {
+        let record = equalization_control.wait_record().await;
+        match counters_store
+            .checkpoint(|counters| counters.equalization_record = record)
+            .await
+        {
+            Ok(_) => log::debug!("Equalization record saved"),
+            Err(e) => log::error!("Failed to save equalization record: {:?}", e),
+        }
+    }
+}
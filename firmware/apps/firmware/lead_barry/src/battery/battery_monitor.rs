@@ -0,0 +1,166 @@
+use defmt_or_log as log;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, pubsub::WaitResult};
+use embassy_time::Instant;
+use serde::Serialize;
+
+use crate::battery::soc_estimator::*;
+use crate::vcp_sensors::{ChannelNum, VcpControl, VcpReadingSubscriber};
+
+const DEFAULT_BATTERY_CHANNEL: ChannelNum = 0;
+
+/// The latest battery state published for the display and the web API.
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct BatteryStatus {
+    /// State of charge in percent
+    pub soc_percent: f32,
+    pub confidence: SocConfidence,
+    /// Battery terminal voltage in volts
+    pub voltage: f32,
+    /// Battery current in amps, positive while discharging
+    pub current: f32,
+}
+
+impl BatteryStatus {
+    pub const fn new() -> Self {
+        Self {
+            soc_percent: 0.0,
+            confidence: SocConfidence::Unknown,
+            voltage: 0.0,
+            current: 0.0,
+        }
+    }
+}
+
+impl Default for BatteryStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct BatteryMonitorConfig {
+    /// The VCP channel the battery is connected to
+    pub channel: ChannelNum,
+    pub soc: SocEstimatorConfig,
+}
+
+impl BatteryMonitorConfig {
+    pub const fn const_default() -> Self {
+        Self {
+            channel: DEFAULT_BATTERY_CHANNEL,
+            soc: SocEstimatorConfig::const_default(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel: ChannelNum) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    pub fn with_soc(mut self, soc: SocEstimatorConfig) -> Self {
+        self.soc = soc;
+        self
+    }
+}
+
+impl Default for BatteryMonitorConfig {
+    fn default() -> Self {
+        Self::const_default()
+    }
+}
+
+pub struct BatteryMonitorState {
+    status: Mutex<CriticalSectionRawMutex, BatteryStatus>,
+}
+
+impl BatteryMonitorState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(BatteryStatus::new()),
+        }
+    }
+}
+
+pub struct BatteryMonitorRunner<'a> {
+    readings: VcpReadingSubscriber<'a>,
+    state: &'a BatteryMonitorState,
+    channel: ChannelNum,
+    estimator: SocEstimator,
+    last_reading: Option<Instant>,
+}
+
+pub struct BatteryMonitorControl<'a> {
+    state: &'a BatteryMonitorState,
+}
+
+impl<'a> BatteryMonitorControl<'a> {
+    /// Returns the latest battery status
+    pub async fn status(&self) -> BatteryStatus {
+        *self.state.status.lock().await
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BatteryMonitorService(());
+
+impl BatteryMonitorService {
+    /// Creates a new battery monitor instance fed by the readings of the VCP sensors
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        vcp_control: &'a VcpControl<'a>,
+        state: &'a mut BatteryMonitorState,
+        config: BatteryMonitorConfig,
+    ) -> (BatteryMonitorRunner<'a>, BatteryMonitorControl<'a>) {
+        let state: &'a BatteryMonitorState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the battery monitor");
+        (
+            BatteryMonitorRunner {
+                readings,
+                state,
+                channel: config.channel,
+                estimator: SocEstimator::new(config.soc),
+                last_reading: None,
+            },
+            BatteryMonitorControl { state },
+        )
+    }
+}
+
+impl<'a> BatteryMonitorRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let reading = match self.readings.next_message().await {
+                WaitResult::Lagged(missed) => {
+                    log::warn!("Battery monitor missed {} readings", missed);
+                    continue;
+                }
+                WaitResult::Message(reading) => reading,
+            };
+            if reading.channel != self.channel {
+                continue;
+            }
+
+            let now = Instant::now();
+            let dt_s = self
+                .last_reading
+                .map(|last| (now - last).as_micros() as f32 / 1_000_000.0)
+                .unwrap_or_default();
+            self.last_reading = Some(now);
+
+            let voltage = reading.voltage.value();
+            let current = reading.current.value();
+            self.estimator.update(voltage, current, dt_s);
+
+            *self.state.status.lock().await = BatteryStatus {
+                soc_percent: self.estimator.soc_percent(),
+                confidence: self.estimator.confidence(),
+                voltage,
+                current,
+            };
+        }
+    }
+}
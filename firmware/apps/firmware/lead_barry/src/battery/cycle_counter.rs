@@ -0,0 +1,228 @@
+#![allow(dead_code)]
+
+//! Charge cycle counting on top of the state-of-charge estimate.
+//!
+//! The counter follows the state of charge between its peaks and valleys. A turn is taken only
+//! once the state of charge has moved back by the hysteresis, so the noise of the estimate and
+//! the small top-ups of a solar charger do not split a discharge into many shallow ones. Every
+//! discharge from a peak to the following valley is counted once, with its depth added to the
+//! depth-of-discharge histogram and to the equivalent full cycles.
+//!
+//! The counter also accumulates the time spent below 50% state of charge, which sulfates a
+//! lead-acid battery, and remembers the deepest discharge ever reached.
+
+use crate::configuration::{CycleStats, DOD_BINS};
+
+const DEFAULT_HYSTERESIS: f32 = 0.02; // Fraction of the capacity
+const HALF_SOC: f32 = 0.5;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+enum Direction {
+    Rising,
+    Falling,
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct CycleCounterConfig {
+    /// How far the state of charge has to move back before a peak or a valley is taken
+    pub hysteresis: f32,
+}
+
+impl CycleCounterConfig {
+    pub const fn const_default() -> Self {
+        Self {
+            hysteresis: DEFAULT_HYSTERESIS,
+        }
+    }
+}
+
+impl Default for CycleCounterConfig {
+    fn default() -> Self {
+        Self::const_default()
+    }
+}
+
+/// Returns the histogram bin of a discharge depth in range 0.0..=1.0
+pub fn dod_bin(depth: f32) -> usize {
+    ((depth * DOD_BINS as f32) as usize).min(DOD_BINS - 1)
+}
+
+pub struct CycleCounter {
+    config: CycleCounterConfig,
+    stats: CycleStats,
+    direction: Direction,
+    /// The last peak, the discharge starts there
+    peak: f32,
+    /// The highest state of charge while rising, the lowest while falling
+    extreme: Option<f32>,
+    below_half_ms: u32,
+}
+
+impl CycleCounter {
+    /// Creates a counter continuing the statistics restored from the flash
+    pub const fn new(config: CycleCounterConfig, stats: CycleStats) -> Self {
+        Self {
+            config,
+            stats,
+            direction: Direction::Rising,
+            peak: 0.0,
+            extreme: None,
+            below_half_ms: 0,
+        }
+    }
+
+    pub fn stats(&self) -> &CycleStats {
+        &self.stats
+    }
+
+    /// The depth of the running discharge in range 0.0..=1.0, `None` while charging
+    pub fn current_depth(&self) -> Option<f32> {
+        match (self.direction, self.extreme) {
+            (Direction::Falling, Some(valley)) => Some(self.peak - valley),
+            _ => None,
+        }
+    }
+
+    /// Clears the statistics, e.g. after the battery has been replaced
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config, CycleStats::new());
+    }
+
+    /// Processes a new state of charge in range 0.0..=1.0.
+    /// - dt_ms: time elapsed since the previous update
+    /// - now_s: the wall-clock time in seconds since 1970, if known
+    ///
+    /// Returns the depth of the discharge this update has finished, if any.
+    pub fn update(&mut self, soc: f32, dt_ms: u32, now_s: Option<i64>) -> Option<f32> {
+        if soc < HALF_SOC {
+            self.below_half_ms += dt_ms;
+            self.stats.time_below_half_soc_s += self.below_half_ms / 1000;
+            self.below_half_ms %= 1000;
+        }
+
+        let soc_percent = soc * 100.0;
+        if self
+            .stats
+            .deepest_soc_percent
+            .is_none_or(|deepest| soc_percent < deepest)
+        {
+            self.stats.deepest_soc_percent = Some(soc_percent);
+            self.stats.deepest_at = now_s;
+        }
+
+        let Some(extreme) = self.extreme else {
+            // The first reading is the peak the first discharge starts from
+            self.peak = soc;
+            self.extreme = Some(soc);
+            return None;
+        };
+
+        match self.direction {
+            Direction::Rising if soc > extreme => self.extreme = Some(soc),
+            Direction::Rising if soc < extreme - self.config.hysteresis => {
+                self.peak = extreme;
+                self.direction = Direction::Falling;
+                self.extreme = Some(soc);
+            }
+            Direction::Falling if soc < extreme => self.extreme = Some(soc),
+            Direction::Falling if soc > extreme + self.config.hysteresis => {
+                let depth = self.peak - extreme;
+                self.count(depth);
+                self.direction = Direction::Rising;
+                self.extreme = Some(soc);
+                return Some(depth);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn count(&mut self, depth: f32) {
+        self.stats.cycles += 1;
+        self.stats.equivalent_full_cycles += depth;
+        self.stats.dod_histogram[dod_bin(depth)] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ramp {
+        counter: CycleCounter,
+        now_s: i64,
+    }
+
+    impl Ramp {
+        fn new() -> Self {
+            Self {
+                counter: CycleCounter::new(CycleCounterConfig::default(), CycleStats::new()),
+                now_s: 1_700_000_000,
+            }
+        }
+
+        /// Moves the state of charge linearly in 1% steps of a second each
+        fn to(&mut self, from: f32, to: f32) -> heapless::Vec<f32, 8> {
+            let mut depths = heapless::Vec::new();
+            let steps = ((to - from).abs() * 100.0).round() as u32;
+            for step in 1..=steps {
+                self.now_s += 1;
+                let soc = from + (to - from) * step as f32 / steps as f32;
+                if let Some(depth) = self.counter.update(soc, 1000, Some(self.now_s)) {
+                    depths.push(depth).unwrap();
+                }
+            }
+            depths
+        }
+    }
+
+    #[test]
+    fn test_peak_valley_counting() {
+        let mut ramp = Ramp::new();
+        ramp.counter.update(1.0, 0, Some(ramp.now_s));
+
+        ramp.to(1.0, 0.35);
+        let deepest_at = ramp.now_s;
+        let depths = ramp.to(0.35, 1.0);
+        assert_eq!(depths.len(), 1);
+        assert!((depths[0] - 0.65).abs() < 1e-3);
+
+        ramp.to(1.0, 0.85);
+        ramp.to(0.85, 1.0);
+
+        let stats = ramp.counter.stats();
+        assert_eq!(stats.cycles, 2);
+        assert!((stats.equivalent_full_cycles - 0.8).abs() < 1e-3);
+        assert_eq!(stats.dod_histogram[6], 1);
+        assert_eq!(stats.dod_histogram[1], 1);
+        // 15 steps of a second below 50% on the way down and 14 on the way up
+        assert_eq!(stats.time_below_half_soc_s, 29);
+        assert!((stats.deepest_soc_percent.unwrap() - 35.0).abs() < 1e-3);
+        assert_eq!(stats.deepest_at, Some(deepest_at));
+    }
+
+    #[test]
+    fn test_noise_within_hysteresis_is_not_counted() {
+        let mut ramp = Ramp::new();
+        ramp.counter.update(0.9, 0, Some(ramp.now_s));
+        ramp.to(0.9, 0.6);
+        // A top-up of 1% does not end the discharge
+        ramp.to(0.6, 0.61);
+        ramp.to(0.61, 0.45);
+        assert_eq!(ramp.counter.stats().cycles, 0);
+        assert!((ramp.counter.current_depth().unwrap() - 0.45).abs() < 1e-3);
+
+        ramp.to(0.45, 0.9);
+        assert_eq!(ramp.counter.stats().cycles, 1);
+        assert_eq!(ramp.counter.stats().dod_histogram[4], 1);
+    }
+
+    #[test]
+    fn test_dod_bins() {
+        assert_eq!(dod_bin(0.0), 0);
+        assert_eq!(dod_bin(0.25), 2);
+        assert_eq!(dod_bin(1.0), DOD_BINS - 1);
+    }
+}
@@ -0,0 +1,143 @@
+use defmt_or_log as log;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Instant, Ticker};
+use serde::Serialize;
+
+use crate::battery::battery_monitor::BatteryMonitorControl;
+use crate::battery::cycle_counter::*;
+use crate::battery::soc_estimator::SocConfidence;
+use crate::configuration::CycleStats;
+use crate::rtc::{RtcDs3231Ref, WallClock};
+use crate::units::TimeExt as _;
+
+const UPDATE_PERIOD_S: u64 = 1;
+
+/// The cycle statistics and the running discharge published for the web API
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct CycleStatus {
+    /// The depth of the running discharge in percent, `None` while charging
+    pub current_depth_percent: Option<f32>,
+    pub stats: CycleStats,
+}
+
+impl CycleStatus {
+    pub const fn new() -> Self {
+        Self {
+            current_depth_percent: None,
+            stats: CycleStats::new(),
+        }
+    }
+}
+
+impl Default for CycleStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct CycleServiceState {
+    status: Mutex<CriticalSectionRawMutex, CycleStatus>,
+    reset: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl CycleServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(CycleStatus::new()),
+            reset: Signal::new(),
+        }
+    }
+}
+
+pub struct CycleRunner<'a, I2C> {
+    battery_control: &'a BatteryMonitorControl<'a>,
+    rtc: &'a RtcDs3231Ref<I2C>,
+    state: &'a CycleServiceState,
+    counter: CycleCounter,
+    clock: WallClock,
+}
+
+pub struct CycleControl<'a> {
+    state: &'a CycleServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> CycleControl<'a> {
+    /// Returns the cycle statistics
+    pub async fn status(&self) -> CycleStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Clears the statistics after the battery has been replaced
+    pub fn reset(&self) {
+        self.state.reset.signal(());
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CycleService(());
+
+impl CycleService {
+    /// Creates a new cycle counter following the state of charge of the battery monitor. The
+    /// statistics continue from the ones restored from the flash.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a, I2C, E>(
+        battery_control: &'a BatteryMonitorControl<'a>,
+        rtc: &'a RtcDs3231Ref<I2C>,
+        state: &'a mut CycleServiceState,
+        config: CycleCounterConfig,
+        stats: CycleStats,
+    ) -> (CycleRunner<'a, I2C>, CycleControl<'a>)
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    {
+        let state: &'a CycleServiceState = state;
+        (
+            CycleRunner {
+                battery_control,
+                rtc,
+                state,
+                counter: CycleCounter::new(config, stats),
+                clock: WallClock::new(),
+            },
+            CycleControl { state },
+        )
+    }
+}
+
+impl<'a, I2C, E> CycleRunner<'a, I2C>
+where
+    I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub async fn run(&mut self) -> ! {
+        let mut ticker = Ticker::every(UPDATE_PERIOD_S.s());
+        let mut last_update = Instant::now();
+        loop {
+            ticker.next().await;
+            if self.state.reset.try_take().is_some() {
+                log::info!("Battery replaced, clearing the cycle statistics");
+                self.counter.reset();
+            }
+
+            let now = Instant::now();
+            let dt_ms = (now - last_update).as_millis() as u32;
+            last_update = now;
+
+            let battery = self.battery_control.status().await;
+            if battery.confidence == SocConfidence::Unknown {
+                continue;
+            }
+            let now_s = self.clock.now_s(self.rtc, now).await;
+            if let Some(depth) = self.counter.update(battery.soc_percent / 100.0, dt_ms, now_s) {
+                log::info!("Discharge of {}% counted", depth * 100.0);
+            }
+
+            *self.state.status.lock().await = CycleStatus {
+                current_depth_percent: self.counter.current_depth().map(|depth| depth * 100.0),
+                stats: self.counter.stats().clone(),
+            };
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use defmt_or_log as log;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, pubsub::WaitResult, signal::Signal};
+use embassy_time::Instant;
+use serde::Serialize;
+
+use crate::battery::profile::BatteryProfileExt;
+use crate::battery::state_of_health::*;
+use crate::configuration::{BatteryProfile, HealthSettings, HealthTrend};
+use crate::vcp_sensors::{ChannelNum, VcpControl, VcpReadingSubscriber};
+
+/// The latest state-of-health estimates published for the display and the web API
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HealthStatus {
+    /// The internal resistance in milliohms, `None` until enough load steps have been seen
+    pub resistance_mohm: Option<f32>,
+    /// The usable capacity measured in the last full cycle in ampere-hours
+    pub capacity_ah: Option<f32>,
+    /// The usable capacity in percent of the nominal capacity
+    pub capacity_percent: Option<f32>,
+    /// The depth of the running full cycle in percent, `None` before its first deep rest
+    pub cycle_depth_percent: Option<f32>,
+    pub warnings: HealthWarnings,
+    pub replace_soon: bool,
+    pub trend: HealthTrend,
+}
+
+impl HealthStatus {
+    pub const fn new() -> Self {
+        Self {
+            resistance_mohm: None,
+            capacity_ah: None,
+            capacity_percent: None,
+            cycle_depth_percent: None,
+            warnings: HealthWarnings::new(),
+            replace_soon: false,
+            trend: HealthTrend::new(),
+        }
+    }
+}
+
+impl Default for HealthStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HealthConfig {
+    /// The VCP channel the battery is connected to
+    pub channel: ChannelNum,
+    pub nominal_capacity_ah: f32,
+    pub resistance: ResistanceFitConfig,
+    pub capacity: CapacityEstimatorConfig,
+    pub settings: HealthSettings,
+    /// The trend restored from the flash
+    pub trend: HealthTrend,
+}
+
+impl HealthConfig {
+    pub fn from_profile(channel: ChannelNum, profile: &BatteryProfile, settings: &HealthSettings) -> Self {
+        Self {
+            channel,
+            nominal_capacity_ah: profile.capacity_ah,
+            resistance: ResistanceFitConfig::default(),
+            capacity: CapacityEstimatorConfig::new(profile.ocv_table()),
+            settings: settings.clone(),
+            trend: HealthTrend::new(),
+        }
+    }
+
+    pub fn with_trend(mut self, trend: HealthTrend) -> Self {
+        self.trend = trend;
+        self
+    }
+}
+
+pub struct HealthServiceState {
+    status: Mutex<CriticalSectionRawMutex, HealthStatus>,
+    /// The trend to be written to the flash
+    trend: Signal<CriticalSectionRawMutex, HealthTrend>,
+    reset: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl HealthServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(HealthStatus::new()),
+            trend: Signal::new(),
+            reset: Signal::new(),
+        }
+    }
+}
+
+pub struct HealthRunner<'a> {
+    readings: VcpReadingSubscriber<'a>,
+    state: &'a HealthServiceState,
+    config: HealthConfig,
+    resistance: ResistanceEstimator,
+    capacity: CapacityEstimator,
+    trend: HealthTrend,
+    last_reading: Option<Instant>,
+}
+
+pub struct HealthControl<'a> {
+    state: &'a HealthServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> HealthControl<'a> {
+    /// Returns the latest state-of-health estimates
+    pub async fn status(&self) -> HealthStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Waits until the trend changes and has to be written to the flash
+    pub async fn wait_trend(&self) -> HealthTrend {
+        self.state.trend.wait().await
+    }
+
+    /// Clears the trend and the resistance baseline after the battery has been replaced
+    pub fn reset(&self) {
+        self.state.reset.signal(());
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HealthService(());
+
+impl HealthService {
+    /// Creates a new state-of-health tracker fed by the readings of the VCP sensors
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        vcp_control: &'a VcpControl<'a>,
+        state: &'a mut HealthServiceState,
+        config: HealthConfig,
+    ) -> (HealthRunner<'a>, HealthControl<'a>) {
+        let state: &'a HealthServiceState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the state of health");
+        (
+            HealthRunner {
+                readings,
+                state,
+                resistance: ResistanceEstimator::new(config.resistance),
+                capacity: CapacityEstimator::new(config.capacity),
+                trend: config.trend.clone(),
+                config,
+                last_reading: None,
+            },
+            HealthControl { state },
+        )
+    }
+}
+
+impl<'a> HealthRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        let mut warnings = HealthWarnings::new();
+        loop {
+            let reading = match select(self.readings.next_message(), self.state.reset.wait()).await {
+                Either::First(WaitResult::Lagged(missed)) => {
+                    log::warn!("State of health missed {} readings", missed);
+                    continue;
+                }
+                Either::First(WaitResult::Message(reading)) => reading,
+                Either::Second(()) => {
+                    log::info!("Battery replaced, clearing the state-of-health trend");
+                    self.trend = HealthTrend::new();
+                    self.resistance = ResistanceEstimator::new(self.config.resistance);
+                    self.capacity = CapacityEstimator::new(self.config.capacity);
+                    self.state.trend.signal(self.trend.clone());
+                    self.publish(HealthWarnings::new()).await;
+                    continue;
+                }
+            };
+            if reading.channel != self.config.channel {
+                continue;
+            }
+
+            let now = Instant::now();
+            let dt_s = self
+                .last_reading
+                .map(|last| (now - last).as_micros() as f32 / 1_000_000.0)
+                .unwrap_or_default();
+            self.last_reading = Some(now);
+
+            let sample = BatterySample {
+                voltage: reading.voltage.value(),
+                current: reading.current.value(),
+                time_ms: now.as_millis(),
+            };
+            let mut trend_changed = false;
+            let resistance = self.resistance.update(sample);
+            if self.trend.baseline_resistance_mohm.is_none() && resistance.is_some() {
+                // The first estimate of a new battery is the baseline the later ones are compared to
+                self.trend.baseline_resistance_mohm = self.resistance_mohm();
+                log::info!(
+                    "Battery resistance baseline {:?} mOhm",
+                    self.trend.baseline_resistance_mohm
+                );
+                trend_changed = true;
+            }
+            if let Some(capacity_ah) = self.capacity.update(sample.voltage, sample.current, dt_s) {
+                log::info!("Full cycle finished, usable capacity {} Ah", capacity_ah);
+                self.trend.record(self.resistance_mohm(), capacity_ah);
+                trend_changed = true;
+            }
+            if trend_changed {
+                self.state.trend.signal(self.trend.clone());
+            }
+
+            let new_warnings = assess_health(
+                &self.config.settings,
+                self.config.nominal_capacity_ah,
+                self.resistance_mohm(),
+                &self.trend,
+            );
+            if new_warnings != warnings && new_warnings.replace_soon() {
+                log::warn!("Replace battery soon: {:?}", new_warnings);
+            }
+            warnings = new_warnings;
+            self.publish(warnings).await;
+        }
+    }
+
+    fn resistance_mohm(&self) -> Option<f32> {
+        self.resistance.resistance().map(|resistance| resistance * 1000.0)
+    }
+
+    async fn publish(&self, warnings: HealthWarnings) {
+        let capacity_ah = self.trend.latest_capacity_ah();
+        *self.state.status.lock().await = HealthStatus {
+            resistance_mohm: self.resistance_mohm(),
+            capacity_ah,
+            capacity_percent: capacity_ah.map(|capacity_ah| capacity_ah / self.config.nominal_capacity_ah * 100.0),
+            cycle_depth_percent: self.capacity.cycle_depth().map(|depth| depth * 100.0),
+            warnings,
+            replace_soon: warnings.replace_soon(),
+            trend: self.trend.clone(),
+        };
+    }
+}
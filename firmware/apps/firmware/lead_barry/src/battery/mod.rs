@@ -0,0 +1,17 @@
+#![allow(unused_imports)]
+
+mod battery_monitor;
+mod cycle_counter;
+mod cycle_service;
+mod health_service;
+mod profile;
+mod soc_estimator;
+mod state_of_health;
+
+pub use self::battery_monitor::*;
+pub use self::cycle_counter::*;
+pub use self::cycle_service::*;
+pub use self::health_service::*;
+pub use self::profile::*;
+pub use self::soc_estimator::*;
+pub use self::state_of_health::*;
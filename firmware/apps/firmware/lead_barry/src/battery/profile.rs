@@ -0,0 +1,60 @@
+//! Derives the runtime configuration of the services from the persisted battery profile.
+
+use crate::battery::battery_monitor::BatteryMonitorConfig;
+use crate::battery::soc_estimator::*;
+use crate::configuration::{BatteryChemistry, BatteryProfile};
+use crate::vcp_sensors::{VcpLimits, VcpPowerLimits};
+
+/// Headroom above the highest charge voltage before the battery voltage is reported as high.
+const OVERVOLTAGE_MARGIN: f32 = 0.3; // Volts
+
+pub trait BatteryProfileExt {
+    /// Open-circuit voltage table of the profile chemistry
+    fn ocv_table(&self) -> OcvTable;
+
+    /// State-of-charge estimator configuration matching the profile
+    fn soc_config(&self) -> SocEstimatorConfig;
+
+    /// Voltage limits of the battery channel. Readings below the LVD voltage are reported as low
+    /// and readings above the highest charge voltage as high.
+    fn vcp_limits(&self, limits: VcpLimits) -> VcpLimits;
+
+    /// INA3221 power-valid window: the power is valid once the battery reaches the float voltage
+    /// and invalid when it drops below the LVD voltage.
+    fn power_valid_limits(&self) -> VcpPowerLimits;
+}
+
+impl BatteryProfileExt for BatteryProfile {
+    fn ocv_table(&self) -> OcvTable {
+        match self.chemistry {
+            BatteryChemistry::Flooded => OcvTable::new(FLOODED_OCV_POINTS),
+            BatteryChemistry::Agm | BatteryChemistry::Gel => OcvTable::new(VRLA_OCV_POINTS),
+            BatteryChemistry::Calcium => OcvTable::new(CALCIUM_OCV_POINTS),
+        }
+    }
+
+    fn soc_config(&self) -> SocEstimatorConfig {
+        SocEstimatorConfig::default()
+            .with_capacity_ah(self.capacity_ah)
+            .with_ocv_table(self.ocv_table())
+    }
+
+    fn vcp_limits(&self, limits: VcpLimits) -> VcpLimits {
+        limits
+            .with_min_voltage(self.lvd_voltage)
+            .with_max_voltage(self.max_charge_voltage() + OVERVOLTAGE_MARGIN)
+    }
+
+    fn power_valid_limits(&self) -> VcpPowerLimits {
+        VcpPowerLimits {
+            upper_voltage: self.float_voltage,
+            lower_voltage: self.lvd_voltage,
+        }
+    }
+}
+
+impl BatteryMonitorConfig {
+    pub fn from_profile(profile: &BatteryProfile) -> Self {
+        Self::default().with_soc(profile.soc_config())
+    }
+}
@@ -0,0 +1,446 @@
+#![allow(dead_code)]
+
+//! State-of-charge estimation for 12V lead-acid batteries. The open-circuit voltage of a rested battery
+//! anchors the estimate and the charge counted through the shunt moves it in between.
+
+use serde::{Deserialize, Serialize};
+
+/// Typical resting voltage of a 12V flooded lead-acid battery at 25°C.
+/// The points are (open-circuit voltage in volts, state of charge in range 0.0..=1.0), sorted by voltage.
+pub const FLOODED_OCV_POINTS: &[(f32, f32)] = &[
+    (11.31, 0.0),
+    (11.51, 0.1),
+    (11.66, 0.2),
+    (11.81, 0.3),
+    (11.96, 0.4),
+    (12.10, 0.5),
+    (12.24, 0.6),
+    (12.37, 0.7),
+    (12.50, 0.8),
+    (12.62, 0.9),
+    (12.73, 1.0),
+];
+
+/// Typical resting voltage of a 12V valve-regulated (AGM and gel) battery at 25°C.
+pub const VRLA_OCV_POINTS: &[(f32, f32)] = &[
+    (11.80, 0.0),
+    (11.95, 0.1),
+    (12.05, 0.2),
+    (12.15, 0.3),
+    (12.25, 0.4),
+    (12.35, 0.5),
+    (12.45, 0.6),
+    (12.55, 0.7),
+    (12.65, 0.8),
+    (12.75, 0.9),
+    (12.85, 1.0),
+];
+
+/// Typical resting voltage of a 12V calcium (Ca/Ca) battery at 25°C.
+pub const CALCIUM_OCV_POINTS: &[(f32, f32)] = &[
+    (11.64, 0.0),
+    (11.76, 0.1),
+    (11.88, 0.2),
+    (12.00, 0.3),
+    (12.12, 0.4),
+    (12.24, 0.5),
+    (12.36, 0.6),
+    (12.48, 0.7),
+    (12.60, 0.8),
+    (12.72, 0.9),
+    (12.84, 1.0),
+];
+
+/// Default battery capacity used until a battery profile is configured.
+pub const DEFAULT_CAPACITY_AH: f32 = 100.0; // Ampere-hours
+const DEFAULT_REST_CURRENT_A: f32 = 0.2; // Amps
+const DEFAULT_REST_DURATION_S: f32 = 1800.0; // Seconds
+const DEFAULT_CHARGE_EFFICIENCY: f32 = 0.85; // Fraction of the charge stored by the battery
+const DEFAULT_DRIFT_LIMIT: f32 = 0.1; // Fraction of the capacity
+const DEFAULT_MAX_GAP_S: f32 = 5.0; // Seconds
+
+/// Open-circuit voltage to state-of-charge lookup table.
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct OcvTable {
+    points: &'static [(f32, f32)],
+}
+
+impl OcvTable {
+    /// Creates a lookup table from the (voltage, state of charge) points sorted by voltage.
+    pub const fn new(points: &'static [(f32, f32)]) -> Self {
+        if points.len() < 2 {
+            panic!("OCV table requires at least two points");
+        }
+        Self { points }
+    }
+
+    /// Returns the state of charge (0.0..=1.0) for the given open-circuit voltage, linearly
+    /// interpolated between the table points and clamped to the table range.
+    pub fn soc(&self, voltage: f32) -> f32 {
+        let (first_voltage, first_soc) = self.points[0];
+        if voltage <= first_voltage {
+            return first_soc;
+        }
+
+        for window in self.points.windows(2) {
+            let (v0, soc0) = window[0];
+            let (v1, soc1) = window[1];
+            if voltage <= v1 {
+                return soc0 + (soc1 - soc0) * (voltage - v0) / (v1 - v0);
+            }
+        }
+
+        self.points[self.points.len() - 1].1
+    }
+
+    /// Returns the open-circuit voltage for the given state of charge (0.0..=1.0).
+    pub fn voltage(&self, soc: f32) -> f32 {
+        let (first_voltage, first_soc) = self.points[0];
+        if soc <= first_soc {
+            return first_voltage;
+        }
+
+        for window in self.points.windows(2) {
+            let (v0, soc0) = window[0];
+            let (v1, soc1) = window[1];
+            if soc <= soc1 {
+                return v0 + (v1 - v0) * (soc - soc0) / (soc1 - soc0);
+            }
+        }
+
+        self.points[self.points.len() - 1].0
+    }
+}
+
+/// How much the reported state of charge can be trusted.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum SocConfidence {
+    /// No reading has been processed yet.
+    Unknown,
+    /// The estimate is based on a voltage measured under load, or the reading stream had a gap.
+    Low,
+    /// The estimate is based on coulomb counting which has drifted far from the last rest anchor.
+    Medium,
+    /// The estimate is anchored to a recent open-circuit voltage measured at rest.
+    High,
+}
+
+impl SocConfidence {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            SocConfidence::Unknown => "unknown",
+            SocConfidence::Low => "low",
+            SocConfidence::Medium => "medium",
+            SocConfidence::High => "high",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct SocEstimatorConfig {
+    /// Nominal battery capacity in ampere-hours.
+    pub capacity_ah: f32,
+    /// The absolute current below which the battery is considered to be resting.
+    pub rest_current_a: f32,
+    /// How long the battery has to rest before its voltage is trusted as open-circuit voltage.
+    pub rest_duration_s: f32,
+    /// The fraction of the charging current that is actually stored in the battery.
+    pub charge_efficiency: f32,
+    /// The charge counted since the last rest anchor (as a fraction of the capacity) above which
+    /// the confidence drops from high to medium.
+    pub drift_limit: f32,
+    /// The longest gap between two readings that is still integrated. Longer gaps are not counted.
+    pub max_gap_s: f32,
+    /// Open-circuit voltage lookup table of the battery chemistry.
+    pub ocv_table: OcvTable,
+}
+
+impl SocEstimatorConfig {
+    pub const fn const_default() -> Self {
+        Self {
+            capacity_ah: DEFAULT_CAPACITY_AH,
+            rest_current_a: DEFAULT_REST_CURRENT_A,
+            rest_duration_s: DEFAULT_REST_DURATION_S,
+            charge_efficiency: DEFAULT_CHARGE_EFFICIENCY,
+            drift_limit: DEFAULT_DRIFT_LIMIT,
+            max_gap_s: DEFAULT_MAX_GAP_S,
+            ocv_table: OcvTable::new(FLOODED_OCV_POINTS),
+        }
+    }
+
+    pub fn with_capacity_ah(mut self, capacity_ah: f32) -> Self {
+        self.capacity_ah = capacity_ah;
+        self
+    }
+
+    pub fn with_ocv_table(mut self, ocv_table: OcvTable) -> Self {
+        self.ocv_table = ocv_table;
+        self
+    }
+
+    pub fn with_rest_duration_s(mut self, rest_duration_s: f32) -> Self {
+        self.rest_duration_s = rest_duration_s;
+        self
+    }
+}
+
+impl Default for SocEstimatorConfig {
+    fn default() -> Self {
+        Self::const_default()
+    }
+}
+
+/// State-of-charge estimator.
+///
+/// The battery current is positive while discharging and negative while charging.
+pub struct SocEstimator {
+    config: SocEstimatorConfig,
+    soc: f32,
+    confidence: SocConfidence,
+    rest_time_s: f32,
+    drift_ah: f32,
+}
+
+impl SocEstimator {
+    pub const fn new(config: SocEstimatorConfig) -> Self {
+        Self {
+            config,
+            soc: 0.0,
+            confidence: SocConfidence::Unknown,
+            rest_time_s: 0.0,
+            drift_ah: 0.0,
+        }
+    }
+
+    /// State of charge in range 0.0..=1.0.
+    pub fn soc(&self) -> f32 {
+        self.soc
+    }
+
+    /// State of charge in percent.
+    pub fn soc_percent(&self) -> f32 {
+        self.soc * 100.0
+    }
+
+    pub fn confidence(&self) -> SocConfidence {
+        self.confidence
+    }
+
+    pub fn config(&self) -> &SocEstimatorConfig {
+        &self.config
+    }
+
+    /// Replaces the estimator configuration, e.g. when the battery profile changes.
+    /// The current estimate is kept, but it is no longer trusted until the next rest anchor.
+    pub fn reconfigure(&mut self, config: SocEstimatorConfig) {
+        self.config = config;
+        self.degrade(SocConfidence::Low);
+    }
+
+    /// Processes a new battery reading.
+    /// - voltage: battery terminal voltage in volts
+    /// - current: battery current in amps, positive while discharging
+    /// - dt_s: time elapsed since the previous reading in seconds
+    pub fn update(&mut self, voltage: f32, current: f32, dt_s: f32) {
+        if self.confidence == SocConfidence::Unknown {
+            // The very first reading: the best guess is the voltage, even if the battery is loaded
+            self.soc = self.config.ocv_table.soc(voltage);
+            self.confidence = SocConfidence::Low;
+            self.rest_time_s = 0.0;
+            self.drift_ah = 0.0;
+            return;
+        }
+
+        if dt_s > self.config.max_gap_s {
+            // Charge that flowed during the gap is unknown
+            self.rest_time_s = 0.0;
+            self.degrade(SocConfidence::Low);
+            return;
+        }
+
+        self.count_coulombs(current, dt_s);
+
+        if current.abs() <= self.config.rest_current_a {
+            let was_rested = self.rest_time_s >= self.config.rest_duration_s;
+            self.rest_time_s += dt_s;
+            if !was_rested && self.rest_time_s >= self.config.rest_duration_s {
+                self.anchor_to_ocv(voltage);
+            }
+        } else {
+            self.rest_time_s = 0.0;
+        }
+    }
+
+    fn count_coulombs(&mut self, current: f32, dt_s: f32) {
+        let charge_ah = current * dt_s / 3600.0;
+        // Only part of the charging current is stored, the rest is lost to gassing and heat
+        let stored_ah = if charge_ah < 0.0 {
+            charge_ah * self.config.charge_efficiency
+        } else {
+            charge_ah
+        };
+
+        self.soc = (self.soc - stored_ah / self.config.capacity_ah).clamp(0.0, 1.0);
+        self.drift_ah += stored_ah.abs();
+
+        if self.confidence == SocConfidence::High && self.drift_ah > self.config.drift_limit * self.config.capacity_ah {
+            self.confidence = SocConfidence::Medium;
+        }
+    }
+
+    fn anchor_to_ocv(&mut self, voltage: f32) {
+        self.soc = self.config.ocv_table.soc(voltage);
+        self.confidence = SocConfidence::High;
+        self.drift_ah = 0.0;
+    }
+
+    fn degrade(&mut self, confidence: SocConfidence) {
+        if self.confidence > confidence {
+            self.confidence = confidence;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded C/20 discharge of a 100Ah flooded battery at 25°C: (hour, loaded voltage at 5A).
+    const C20_DISCHARGE_CURVE: &[(f32, f32)] = &[
+        (0.0, 12.58),
+        (1.0, 12.49),
+        (2.0, 12.41),
+        (3.0, 12.33),
+        (4.0, 12.25),
+        (5.0, 12.17),
+        (6.0, 12.09),
+        (7.0, 12.01),
+        (8.0, 11.93),
+        (9.0, 11.85),
+        (10.0, 11.77),
+    ];
+    /// The open-circuit voltage of the same battery measured after a 2 hour rest at the end of the discharge.
+    const C20_RESTED_OCV_AFTER_10H: f32 = 12.09;
+    const C20_CURRENT_A: f32 = 5.0;
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {} ± {}, got {}",
+            expected,
+            tolerance,
+            actual
+        );
+    }
+
+    fn rest(estimator: &mut SocEstimator, voltage: f32, duration_s: f32) {
+        let mut t = 0.0;
+        while t < duration_s {
+            estimator.update(voltage, 0.0, 1.0);
+            t += 1.0;
+        }
+    }
+
+    fn replay_discharge_curve(estimator: &mut SocEstimator) {
+        const STEP_S: f32 = 1.0;
+        for window in C20_DISCHARGE_CURVE.windows(2) {
+            let (h0, v0) = window[0];
+            let (h1, v1) = window[1];
+            let steps = ((h1 - h0) * 3600.0 / STEP_S) as u32;
+            for step in 0..steps {
+                let voltage = v0 + (v1 - v0) * step as f32 / steps as f32;
+                estimator.update(voltage, C20_CURRENT_A, STEP_S);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ocv_lookup_interpolates_and_clamps() {
+        let table = OcvTable::new(FLOODED_OCV_POINTS);
+        assert_near(table.soc(12.73), 1.0, 1e-6);
+        assert_near(table.soc(12.10), 0.5, 1e-6);
+        assert_near(table.soc(12.17), 0.55, 1e-3);
+        assert_near(table.soc(13.50), 1.0, 1e-6);
+        assert_near(table.soc(10.50), 0.0, 1e-6);
+        assert_near(table.voltage(0.55), 12.17, 1e-3);
+    }
+
+    #[test]
+    fn test_first_loaded_reading_has_low_confidence() {
+        let mut estimator = SocEstimator::new(SocEstimatorConfig::default());
+        assert_eq!(estimator.confidence(), SocConfidence::Unknown);
+
+        estimator.update(C20_DISCHARGE_CURVE[0].1, C20_CURRENT_A, 0.0);
+        assert_eq!(estimator.confidence(), SocConfidence::Low);
+    }
+
+    #[test]
+    fn test_rest_anchors_to_ocv() {
+        let config = SocEstimatorConfig::default();
+        let mut estimator = SocEstimator::new(config);
+
+        rest(&mut estimator, 12.73, config.rest_duration_s + 1.0);
+        assert_eq!(estimator.confidence(), SocConfidence::High);
+        assert_near(estimator.soc_percent(), 100.0, 0.1);
+    }
+
+    #[test]
+    fn test_c20_discharge_curve_is_tracked_by_coulomb_counting() {
+        let config = SocEstimatorConfig::default();
+        let mut estimator = SocEstimator::new(config);
+        rest(&mut estimator, 12.73, config.rest_duration_s + 1.0);
+
+        replay_discharge_curve(&mut estimator);
+
+        // 10h at 5A removes 50Ah from a 100Ah battery
+        assert_near(estimator.soc_percent(), 50.0, 0.5);
+        // 50Ah of counted charge is far beyond the drift limit
+        assert_eq!(estimator.confidence(), SocConfidence::Medium);
+
+        // The rest after the discharge re-anchors the estimate to the measured OCV
+        rest(&mut estimator, C20_RESTED_OCV_AFTER_10H, config.rest_duration_s + 1.0);
+        assert_eq!(estimator.confidence(), SocConfidence::High);
+        assert_near(estimator.soc_percent(), 50.0, 1.0);
+    }
+
+    #[test]
+    fn test_charging_applies_efficiency() {
+        let config = SocEstimatorConfig::default();
+        let mut estimator = SocEstimator::new(config);
+        rest(&mut estimator, 12.10, config.rest_duration_s + 1.0);
+
+        // 10Ah pushed into the battery at 10A for 1h
+        for _ in 0..3600 {
+            estimator.update(13.8, -10.0, 1.0);
+        }
+
+        let expected = 50.0 + 10.0 * config.charge_efficiency;
+        assert_near(estimator.soc_percent(), expected, 0.1);
+    }
+
+    #[test]
+    fn test_reading_gap_is_not_integrated() {
+        let config = SocEstimatorConfig::default();
+        let mut estimator = SocEstimator::new(config);
+        rest(&mut estimator, 12.73, config.rest_duration_s + 1.0);
+
+        estimator.update(12.5, 50.0, config.max_gap_s * 10.0);
+        assert_near(estimator.soc_percent(), 100.0, 0.1);
+        assert_eq!(estimator.confidence(), SocConfidence::Low);
+    }
+
+    #[test]
+    fn test_soc_is_clamped() {
+        let config = SocEstimatorConfig::default().with_capacity_ah(1.0);
+        let mut estimator = SocEstimator::new(config);
+        rest(&mut estimator, 11.40, config.rest_duration_s + 1.0);
+
+        for _ in 0..3600 {
+            estimator.update(11.0, 5.0, 1.0);
+        }
+        assert_near(estimator.soc(), 0.0, 1e-6);
+    }
+}
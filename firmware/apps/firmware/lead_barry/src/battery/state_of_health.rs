@@ -0,0 +1,466 @@
+#![allow(dead_code)]
+
+//! State-of-health estimation for lead-acid batteries. The internal resistance is fitted to the voltage
+//! steps at the load switches, the usable capacity is measured over the full discharge cycles.
+
+use serde::Serialize;
+
+use crate::battery::soc_estimator::OcvTable;
+use crate::configuration::{HealthSettings, HealthTrend};
+
+const DEFAULT_MIN_STEP_A: f32 = 1.0; // Amps
+const DEFAULT_MAX_STEP_MS: u64 = 2_000; // Milliseconds
+const DEFAULT_MAX_RESISTANCE_OHM: f32 = 0.5; // Ohms
+const DEFAULT_MIN_STEPS: usize = 3;
+const DEFAULT_REST_CURRENT_A: f32 = 0.2; // Amps
+const DEFAULT_REST_DURATION_S: f32 = 1800.0; // Seconds
+const DEFAULT_CHARGE_EFFICIENCY: f32 = 0.85; // Fraction of the charge stored by the battery
+const DEFAULT_FULL_SOC: f32 = 0.98; // Fraction of the capacity
+const DEFAULT_MIN_DEPTH: f32 = 0.3; // Fraction of the capacity
+const DEFAULT_MAX_GAP_S: f32 = 5.0; // Seconds
+/// Number of the latest load steps the resistance is fitted to
+pub const LOAD_STEP_WINDOW: usize = 16;
+
+/// A battery reading with the voltage and the current sampled together
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct BatterySample {
+    /// Battery terminal voltage in volts
+    pub voltage: f32,
+    /// Battery current in amps, positive while discharging
+    pub current: f32,
+    pub time_ms: u64,
+}
+
+/// The voltage and the current change between two consecutive samples
+#[derive(Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct LoadStep {
+    pub delta_current: f32,
+    pub delta_voltage: f32,
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ResistanceFitConfig {
+    /// The smallest current change accepted as a load step
+    pub min_step_a: f32,
+    /// The longest time between the samples of a load step. Slower changes mix in the voltage
+    /// drift of the discharge.
+    pub max_step_ms: u64,
+    /// Steps giving a higher resistance are measurement errors
+    pub max_resistance_ohm: f32,
+    /// The battery is charging below this negative current and the steps are ignored
+    pub rest_current_a: f32,
+    /// Number of steps required before the first estimate
+    pub min_steps: usize,
+}
+
+impl ResistanceFitConfig {
+    pub const fn const_default() -> Self {
+        Self {
+            min_step_a: DEFAULT_MIN_STEP_A,
+            max_step_ms: DEFAULT_MAX_STEP_MS,
+            max_resistance_ohm: DEFAULT_MAX_RESISTANCE_OHM,
+            rest_current_a: DEFAULT_REST_CURRENT_A,
+            min_steps: DEFAULT_MIN_STEPS,
+        }
+    }
+}
+
+impl Default for ResistanceFitConfig {
+    fn default() -> Self {
+        Self::const_default()
+    }
+}
+
+/// Returns the load step between two consecutive samples, `None` if they do not form a usable step
+pub fn detect_load_step(
+    previous: &BatterySample,
+    sample: &BatterySample,
+    config: &ResistanceFitConfig,
+) -> Option<LoadStep> {
+    if sample.time_ms.saturating_sub(previous.time_ms) > config.max_step_ms {
+        return None;
+    }
+    if previous.current < -config.rest_current_a || sample.current < -config.rest_current_a {
+        return None;
+    }
+    let step = LoadStep {
+        delta_current: sample.current - previous.current,
+        delta_voltage: sample.voltage - previous.voltage,
+    };
+    if step.delta_current.abs() < config.min_step_a {
+        return None;
+    }
+    // More discharge current must pull the voltage down
+    let resistance = -step.delta_voltage / step.delta_current;
+    if resistance <= 0.0 || resistance > config.max_resistance_ohm {
+        return None;
+    }
+    Some(step)
+}
+
+/// Least squares fit of `ΔV = -R * ΔI` to the load steps. Returns the resistance in ohms.
+pub fn fit_resistance(steps: &[LoadStep]) -> Option<f32> {
+    let (covariance, variance) = steps.iter().fold((0.0, 0.0), |(covariance, variance), step| {
+        (
+            covariance + step.delta_current * step.delta_voltage,
+            variance + step.delta_current * step.delta_current,
+        )
+    });
+    if variance <= 0.0 {
+        return None;
+    }
+    Some(-covariance / variance)
+}
+
+/// Fits the internal resistance to the latest load steps
+pub struct ResistanceEstimator {
+    config: ResistanceFitConfig,
+    previous: Option<BatterySample>,
+    steps: heapless::Vec<LoadStep, LOAD_STEP_WINDOW>,
+    resistance: Option<f32>,
+}
+
+impl ResistanceEstimator {
+    pub const fn new(config: ResistanceFitConfig) -> Self {
+        Self {
+            config,
+            previous: None,
+            steps: heapless::Vec::new(),
+            resistance: None,
+        }
+    }
+
+    /// The internal resistance in ohms
+    pub fn resistance(&self) -> Option<f32> {
+        self.resistance
+    }
+
+    /// Feeds a new sample and returns the new estimate if the sample completed a load step
+    pub fn update(&mut self, sample: BatterySample) -> Option<f32> {
+        let step = self
+            .previous
+            .replace(sample)
+            .and_then(|previous| detect_load_step(&previous, &sample, &self.config))?;
+        if self.steps.is_full() {
+            self.steps.remove(0);
+        }
+        self.steps.push(step).ok();
+        if self.steps.len() < self.config.min_steps {
+            return None;
+        }
+        self.resistance = fit_resistance(&self.steps);
+        self.resistance
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct CapacityEstimatorConfig {
+    /// Open-circuit voltage lookup table of the battery chemistry
+    pub ocv_table: OcvTable,
+    /// The absolute current below which the battery is considered to be resting
+    pub rest_current_a: f32,
+    /// How long the battery has to rest before its voltage is trusted as open-circuit voltage
+    pub rest_duration_s: f32,
+    /// The fraction of the charging current that is actually stored in the battery
+    pub charge_efficiency: f32,
+    /// A rested battery at or above this state of charge is full
+    pub full_soc: f32,
+    /// Shallower cycles are too inaccurate for a capacity estimate
+    pub min_depth: f32,
+    /// The longest gap between two readings that is still integrated. A longer gap ends the cycle.
+    pub max_gap_s: f32,
+}
+
+impl CapacityEstimatorConfig {
+    pub const fn new(ocv_table: OcvTable) -> Self {
+        Self {
+            ocv_table,
+            rest_current_a: DEFAULT_REST_CURRENT_A,
+            rest_duration_s: DEFAULT_REST_DURATION_S,
+            charge_efficiency: DEFAULT_CHARGE_EFFICIENCY,
+            full_soc: DEFAULT_FULL_SOC,
+            min_depth: DEFAULT_MIN_DEPTH,
+            max_gap_s: DEFAULT_MAX_GAP_S,
+        }
+    }
+
+    pub fn with_rest_duration_s(mut self, rest_duration_s: f32) -> Self {
+        self.rest_duration_s = rest_duration_s;
+        self
+    }
+}
+
+/// A full cycle in progress
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+struct Cycle {
+    /// The state of charge the cycle started at
+    start_soc: f32,
+    /// The net charge taken out since the start in ampere-hours
+    removed_ah: f32,
+    /// The deepest rested depth of the cycle and the capacity estimated there
+    deepest: Option<(f32, f32)>,
+}
+
+/// Estimates the usable capacity from the charge taken out between two rests
+pub struct CapacityEstimator {
+    config: CapacityEstimatorConfig,
+    rest_time_s: f32,
+    cycle: Option<Cycle>,
+}
+
+impl CapacityEstimator {
+    pub const fn new(config: CapacityEstimatorConfig) -> Self {
+        Self {
+            config,
+            rest_time_s: 0.0,
+            cycle: None,
+        }
+    }
+
+    /// The depth of the running cycle at the last rest, in range 0.0..=1.0
+    pub fn cycle_depth(&self) -> Option<f32> {
+        self.cycle.and_then(|cycle| cycle.deepest).map(|(depth, _)| depth)
+    }
+
+    /// Processes a new battery reading and returns the capacity in ampere-hours measured by the
+    /// full cycle this reading has finished, if any.
+    /// - voltage: battery terminal voltage in volts
+    /// - current: battery current in amps, positive while discharging
+    /// - dt_s: time elapsed since the previous reading in seconds
+    pub fn update(&mut self, voltage: f32, current: f32, dt_s: f32) -> Option<f32> {
+        if dt_s > self.config.max_gap_s {
+            // The charge that flowed during the gap is unknown
+            self.cycle = None;
+            self.rest_time_s = 0.0;
+            return None;
+        }
+
+        if let Some(cycle) = self.cycle.as_mut() {
+            let charge_ah = current * dt_s / 3600.0;
+            cycle.removed_ah += if charge_ah < 0.0 {
+                charge_ah * self.config.charge_efficiency
+            } else {
+                charge_ah
+            };
+        }
+
+        if current.abs() > self.config.rest_current_a {
+            self.rest_time_s = 0.0;
+            return None;
+        }
+        let was_rested = self.rest_time_s >= self.config.rest_duration_s;
+        self.rest_time_s += dt_s;
+        if was_rested || self.rest_time_s < self.config.rest_duration_s {
+            return None;
+        }
+        self.on_rest(self.config.ocv_table.soc(voltage))
+    }
+
+    fn on_rest(&mut self, soc: f32) -> Option<f32> {
+        if soc >= self.config.full_soc {
+            // A full battery ends the running cycle and starts the next one
+            let finished = self
+                .cycle
+                .and_then(|cycle| cycle.deepest)
+                .map(|(_, capacity_ah)| capacity_ah);
+            self.cycle = Some(Cycle {
+                start_soc: soc,
+                removed_ah: 0.0,
+                deepest: None,
+            });
+            return finished;
+        }
+
+        if let Some(cycle) = self.cycle.as_mut() {
+            let depth = cycle.start_soc - soc;
+            let deeper = cycle.deepest.is_none_or(|(deepest, _)| depth > deepest);
+            if depth >= self.config.min_depth && cycle.removed_ah > 0.0 && deeper {
+                cycle.deepest = Some((depth, cycle.removed_ah / depth));
+            }
+        }
+        None
+    }
+}
+
+/// The reasons to replace the battery
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HealthWarnings {
+    /// The internal resistance rose above the threshold
+    pub high_resistance: bool,
+    /// The usable capacity dropped below the threshold
+    pub low_capacity: bool,
+}
+
+impl HealthWarnings {
+    pub const fn new() -> Self {
+        Self {
+            high_resistance: false,
+            low_capacity: false,
+        }
+    }
+
+    pub fn replace_soon(&self) -> bool {
+        self.high_resistance || self.low_capacity
+    }
+}
+
+/// Compares the latest estimates to the thresholds of the settings
+pub fn assess_health(
+    settings: &HealthSettings,
+    nominal_capacity_ah: f32,
+    resistance_mohm: Option<f32>,
+    trend: &HealthTrend,
+) -> HealthWarnings {
+    if !settings.enabled {
+        return HealthWarnings::new();
+    }
+    let high_resistance = match (resistance_mohm, trend.baseline_resistance_mohm) {
+        (Some(resistance), Some(baseline)) => {
+            resistance > baseline * (1.0 + settings.max_resistance_rise_percent / 100.0)
+        }
+        _ => false,
+    };
+    let low_capacity = trend
+        .latest_capacity_ah()
+        .is_some_and(|capacity_ah| capacity_ah < nominal_capacity_ah * settings.min_capacity_percent / 100.0);
+    HealthWarnings {
+        high_resistance,
+        low_capacity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battery::soc_estimator::FLOODED_OCV_POINTS;
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {} ± {}, got {}",
+            expected,
+            tolerance,
+            actual
+        );
+    }
+
+    fn sample(voltage: f32, current: f32, time_ms: u64) -> BatterySample {
+        BatterySample {
+            voltage,
+            current,
+            time_ms,
+        }
+    }
+
+    fn capacity_estimator() -> CapacityEstimator {
+        let config = CapacityEstimatorConfig::new(OcvTable::new(FLOODED_OCV_POINTS)).with_rest_duration_s(60.0);
+        CapacityEstimator::new(config)
+    }
+
+    /// Rests the battery for twice the rest duration and returns the finished cycle capacity
+    fn rest(estimator: &mut CapacityEstimator, voltage: f32) -> Option<f32> {
+        (0..120).filter_map(|_| estimator.update(voltage, 0.0, 1.0)).last()
+    }
+
+    #[test]
+    fn test_resistance_fit_of_load_steps() {
+        const RESISTANCE_OHM: f32 = 0.012;
+        let mut estimator = ResistanceEstimator::new(ResistanceFitConfig::default());
+        let mut time_ms = 0;
+        let mut estimate = None;
+        // A 10A load toggling on a battery which slowly discharges, with some measurement noise
+        for toggle in 0..8 {
+            let current = if toggle % 2 == 0 { 10.5 } else { 0.5 };
+            let noise = if toggle % 3 == 0 { 0.002 } else { -0.001 };
+            let voltage = 12.6 - 0.001 * toggle as f32 - RESISTANCE_OHM * current + noise;
+            time_ms += 500;
+            estimate = estimator.update(sample(voltage, current, time_ms)).or(estimate);
+        }
+        assert_near(estimate.unwrap(), RESISTANCE_OHM, 0.001);
+    }
+
+    #[test]
+    fn test_unusable_steps_are_rejected() {
+        let config = ResistanceFitConfig::default();
+        // Too slow
+        assert_eq!(
+            detect_load_step(&sample(12.6, 0.0, 0), &sample(12.4, 10.0, 10_000), &config),
+            None
+        );
+        // Too small
+        assert_eq!(
+            detect_load_step(&sample(12.6, 0.0, 0), &sample(12.59, 0.5, 500), &config),
+            None
+        );
+        // While charging
+        assert_eq!(
+            detect_load_step(&sample(13.8, -10.0, 0), &sample(13.8, 0.0, 500), &config),
+            None
+        );
+        // The voltage rises with the load
+        assert_eq!(
+            detect_load_step(&sample(12.6, 0.0, 0), &sample(12.7, 10.0, 500), &config),
+            None
+        );
+        assert!(detect_load_step(&sample(12.6, 0.0, 0), &sample(12.5, 10.0, 500), &config).is_some());
+        assert_eq!(fit_resistance(&[]), None);
+    }
+
+    #[test]
+    fn test_capacity_of_a_full_cycle() {
+        let mut estimator = capacity_estimator();
+
+        // Full battery at rest starts the cycle
+        assert_eq!(rest(&mut estimator, 12.73), None);
+        // 40Ah out of a battery which turns out to hold 80Ah: rests at 50%
+        for _ in 0..4 * 3600 {
+            estimator.update(12.2, 10.0, 1.0);
+        }
+        assert_eq!(rest(&mut estimator, 12.10), None);
+        assert_near(estimator.cycle_depth().unwrap(), 0.5, 0.01);
+
+        // The capacity is reported once the battery is full again
+        for _ in 0..5 * 3600 {
+            estimator.update(14.4, -10.0, 1.0);
+        }
+        assert_near(rest(&mut estimator, 12.73).unwrap(), 80.0, 1.0);
+    }
+
+    #[test]
+    fn test_shallow_cycle_gives_no_capacity() {
+        let mut estimator = capacity_estimator();
+        rest(&mut estimator, 12.73);
+        for _ in 0..3600 {
+            estimator.update(12.5, 10.0, 1.0);
+        }
+        rest(&mut estimator, 12.50);
+        assert_eq!(estimator.cycle_depth(), None);
+        assert_eq!(rest(&mut estimator, 12.73), None);
+    }
+
+    #[test]
+    fn test_health_warnings() {
+        let settings = HealthSettings::new();
+        let mut trend = HealthTrend::new();
+        assert!(!assess_health(&settings, 100.0, Some(20.0), &trend).replace_soon());
+
+        trend.baseline_resistance_mohm = Some(8.0);
+        trend.record(Some(9.0), 95.0);
+        assert_eq!(
+            assess_health(&settings, 100.0, Some(9.0), &trend),
+            HealthWarnings::new()
+        );
+
+        trend.record(Some(17.0), 75.0);
+        let warnings = assess_health(&settings, 100.0, Some(17.0), &trend);
+        assert!(warnings.high_resistance);
+        assert!(warnings.low_capacity);
+        assert_eq!(trend.cycles, 2);
+    }
+}
@@ -0,0 +1,301 @@
+#![allow(dead_code)]
+
+//! Charge-stage detector. Follows the bulk, absorption and float stages of a lead-acid charger from the
+//! battery side, taking a new stage only after it has held for the settle time.
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum ChargeStage {
+    /// No charge source is present
+    None,
+    Bulk,
+    Absorption,
+    Float,
+}
+
+impl ChargeStage {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            ChargeStage::None => "No charge",
+            ChargeStage::Bulk => "Bulk",
+            ChargeStage::Absorption => "Absorption",
+            ChargeStage::Float => "Float",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum ChargeEvent {
+    SourcePresent,
+    SourceLost,
+    Stage(ChargeStage),
+}
+
+/// A single sample fed to the detector.
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChargeSample {
+    /// Battery voltage in volts
+    pub battery_voltage: f32,
+    /// Current into the battery in amps, negative while discharging
+    pub charge_current: f32,
+    /// Current delivered by the charge source in amps, `None` if no channel measures it
+    pub source_current: Option<f32>,
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChargeStageConfig {
+    /// The absorption voltage of the battery profile in volts
+    pub absorption_voltage: f32,
+    /// The float voltage of the battery profile in volts
+    pub float_voltage: f32,
+    /// How far below a setpoint the battery voltage is still considered at the setpoint
+    pub voltage_tolerance: f32,
+    /// The charge current below which the battery is considered full
+    pub tail_current_a: f32,
+    /// The source current above which the charge source is considered present
+    pub presence_current_a: f32,
+    /// How long a new stage or source state has to hold before it is reported
+    pub settle_ms: u64,
+}
+
+/// A state which changes only after the new value has held for the settle time
+struct Debounced<T> {
+    value: T,
+    candidate: T,
+    candidate_since_ms: u64,
+}
+
+impl<T: Copy + PartialEq> Debounced<T> {
+    const fn new(value: T) -> Self {
+        Self {
+            value,
+            candidate: value,
+            candidate_since_ms: 0,
+        }
+    }
+
+    /// Returns `true` if the value has changed
+    fn update(&mut self, candidate: T, now_ms: u64, settle_ms: u64) -> bool {
+        if candidate == self.value {
+            self.candidate = candidate;
+            return false;
+        }
+        if candidate != self.candidate {
+            self.candidate = candidate;
+            self.candidate_since_ms = now_ms;
+        }
+        if now_ms.saturating_sub(self.candidate_since_ms) >= settle_ms {
+            self.value = candidate;
+            return true;
+        }
+        false
+    }
+
+    /// Takes the value at once
+    fn set(&mut self, value: T) {
+        self.value = value;
+        self.candidate = value;
+    }
+}
+
+pub struct ChargeStageDetector {
+    config: ChargeStageConfig,
+    source_present: Debounced<bool>,
+    stage: Debounced<ChargeStage>,
+    stage_since_ms: u64,
+}
+
+impl ChargeStageDetector {
+    pub const fn new(config: ChargeStageConfig) -> Self {
+        Self {
+            config,
+            source_present: Debounced::new(false),
+            stage: Debounced::new(ChargeStage::None),
+            stage_since_ms: 0,
+        }
+    }
+
+    pub fn stage(&self) -> ChargeStage {
+        self.stage.value
+    }
+
+    pub fn source_present(&self) -> bool {
+        self.source_present.value
+    }
+
+    pub fn time_in_stage_ms(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.stage_since_ms)
+    }
+
+    /// Moves the absorption and float setpoints, e.g. for the temperature compensation
+    pub fn set_setpoints(&mut self, absorption_voltage: f32, float_voltage: f32) {
+        self.config.absorption_voltage = absorption_voltage;
+        self.config.float_voltage = float_voltage;
+    }
+
+    /// Feeds a new sample taken at `now_ms` and returns the events it caused
+    pub fn update(&mut self, sample: ChargeSample, now_ms: u64) -> heapless::Vec<ChargeEvent, 2> {
+        let mut events = heapless::Vec::new();
+        let source_current = sample.source_current.unwrap_or(sample.charge_current);
+        let present = source_current > self.config.presence_current_a;
+
+        if self.source_present.update(present, now_ms, self.config.settle_ms) {
+            let event = if present {
+                ChargeEvent::SourcePresent
+            } else {
+                ChargeEvent::SourceLost
+            };
+            events.push(event).ok();
+        }
+
+        if !self.source_present.value {
+            // The stage ends together with the source
+            if self.stage.value != ChargeStage::None {
+                self.enter(ChargeStage::None, now_ms);
+                events.push(ChargeEvent::Stage(ChargeStage::None)).ok();
+            }
+            return events;
+        }
+
+        let candidate = self.classify(&sample);
+        if self.stage.value == ChargeStage::None {
+            // The source has just appeared, take the stage at once
+            self.enter(candidate, now_ms);
+            events.push(ChargeEvent::Stage(candidate)).ok();
+        } else if self.stage.update(candidate, now_ms, self.config.settle_ms) {
+            // The stage began when it was first seen, not when it settled
+            self.stage_since_ms = self.stage.candidate_since_ms;
+            events.push(ChargeEvent::Stage(candidate)).ok();
+        }
+        events
+    }
+
+    fn enter(&mut self, stage: ChargeStage, now_ms: u64) {
+        self.stage.set(stage);
+        self.stage_since_ms = now_ms;
+    }
+
+    fn classify(&self, sample: &ChargeSample) -> ChargeStage {
+        let config = &self.config;
+        if sample.battery_voltage >= config.absorption_voltage - config.voltage_tolerance {
+            ChargeStage::Absorption
+        } else if sample.battery_voltage >= config.float_voltage - config.voltage_tolerance
+            && sample.charge_current <= config.tail_current_a
+        {
+            ChargeStage::Float
+        } else {
+            ChargeStage::Bulk
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLE_MS: u64 = 5_000;
+
+    fn config() -> ChargeStageConfig {
+        ChargeStageConfig {
+            absorption_voltage: 14.4,
+            float_voltage: 13.5,
+            voltage_tolerance: 0.1,
+            tail_current_a: 2.0,
+            presence_current_a: 0.1,
+            settle_ms: SETTLE_MS,
+        }
+    }
+
+    fn sample(battery_voltage: f32, charge_current: f32, source_current: f32) -> ChargeSample {
+        ChargeSample {
+            battery_voltage,
+            charge_current,
+            source_current: Some(source_current),
+        }
+    }
+
+    /// Feeds the same sample for the given duration and collects the events
+    fn feed(
+        detector: &mut ChargeStageDetector,
+        now_ms: &mut u64,
+        sample: ChargeSample,
+        duration_ms: u64,
+    ) -> heapless::Vec<ChargeEvent, 8> {
+        let mut events = heapless::Vec::new();
+        let end_ms = *now_ms + duration_ms;
+        while *now_ms < end_ms {
+            *now_ms += 100;
+            for event in detector.update(sample, *now_ms) {
+                events.push(event).unwrap();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_full_charge_cycle() {
+        let mut detector = ChargeStageDetector::new(config());
+        let mut now_ms = 0;
+
+        let events = feed(&mut detector, &mut now_ms, sample(12.6, 10.0, 11.0), 10_000);
+        assert_eq!(
+            events.as_slice(),
+            &[ChargeEvent::SourcePresent, ChargeEvent::Stage(ChargeStage::Bulk)]
+        );
+
+        let events = feed(&mut detector, &mut now_ms, sample(14.38, 8.0, 9.0), 10_000);
+        assert_eq!(events.as_slice(), &[ChargeEvent::Stage(ChargeStage::Absorption)]);
+        assert_eq!(detector.time_in_stage_ms(now_ms), 9_900);
+
+        let events = feed(&mut detector, &mut now_ms, sample(13.5, 0.5, 1.5), 10_000);
+        assert_eq!(events.as_slice(), &[ChargeEvent::Stage(ChargeStage::Float)]);
+
+        let events = feed(&mut detector, &mut now_ms, sample(12.8, -1.0, 0.0), 10_000);
+        assert_eq!(
+            events.as_slice(),
+            &[ChargeEvent::SourceLost, ChargeEvent::Stage(ChargeStage::None)]
+        );
+        assert_eq!(detector.stage(), ChargeStage::None);
+    }
+
+    #[test]
+    fn test_short_dips_are_ignored() {
+        let mut detector = ChargeStageDetector::new(config());
+        let mut now_ms = 0;
+        feed(&mut detector, &mut now_ms, sample(14.4, 5.0, 6.0), 10_000);
+        assert_eq!(detector.stage(), ChargeStage::Absorption);
+
+        // A cloud passing for two seconds
+        let events = feed(&mut detector, &mut now_ms, sample(12.9, -0.5, 0.0), 2_000);
+        assert!(events.is_empty());
+        let events = feed(&mut detector, &mut now_ms, sample(14.4, 5.0, 6.0), 10_000);
+        assert!(events.is_empty());
+        assert!(detector.source_present());
+    }
+
+    #[test]
+    fn test_battery_current_without_source_channel() {
+        let mut detector = ChargeStageDetector::new(config());
+        let mut now_ms = 0;
+        let mut charging = sample(13.0, 4.0, 0.0);
+        charging.source_current = None;
+
+        feed(&mut detector, &mut now_ms, charging, 10_000);
+        assert!(detector.source_present());
+        assert_eq!(detector.stage(), ChargeStage::Bulk);
+    }
+
+    #[test]
+    fn test_compensated_setpoints() {
+        let mut detector = ChargeStageDetector::new(config());
+        let mut now_ms = 0;
+        // Cold battery, the absorption voltage is raised
+        detector.set_setpoints(14.7, 13.8);
+        feed(&mut detector, &mut now_ms, sample(14.4, 5.0, 6.0), 10_000);
+        assert_eq!(detector.stage(), ChargeStage::Bulk);
+    }
+}
@@ -0,0 +1,240 @@
+use defmt_or_log as log;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    mutex::Mutex,
+    pubsub::{ImmediatePublisher, PubSubChannel, Subscriber, WaitResult},
+    signal::Signal,
+};
+use embassy_time::Instant;
+use serde::Serialize;
+
+use crate::charger::charge_stage::*;
+use crate::configuration::{BatteryProfile, ChannelMap, ChannelRole};
+use crate::vcp_sensors::{ChannelNum, VcpControl, VcpReadingSubscriber};
+
+/// Number of the latest charge events kept for the web API
+pub const CHARGE_EVENT_LOG_SIZE: usize = 8;
+const CHARGE_EVENTS_QUEUE_SIZE: usize = 4;
+const CHARGE_EVENT_SUBSCRIBERS: usize = 2;
+
+/// The battery voltage is still at a setpoint this far below it
+const VOLTAGE_TOLERANCE: f32 = 0.1; // Volts
+/// The battery is full once the absorption current drops below this fraction of the capacity
+const TAIL_CURRENT_C: f32 = 0.02;
+const PRESENCE_CURRENT_A: f32 = 0.1;
+const SETTLE_MS: u64 = 10_000;
+
+type ChargeEventChannel =
+    PubSubChannel<CriticalSectionRawMutex, ChargeEvent, CHARGE_EVENTS_QUEUE_SIZE, CHARGE_EVENT_SUBSCRIBERS, 1>;
+pub type ChargeEventSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, ChargeEvent, CHARGE_EVENTS_QUEUE_SIZE, CHARGE_EVENT_SUBSCRIBERS, 1>;
+type ChargeEventPublisher<'a> =
+    ImmediatePublisher<'a, CriticalSectionRawMutex, ChargeEvent, CHARGE_EVENTS_QUEUE_SIZE, CHARGE_EVENT_SUBSCRIBERS, 1>;
+
+/// A charge event kept in the event log
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChargeEventRecord {
+    pub event: ChargeEvent,
+    /// Seconds since boot
+    pub uptime_s: u32,
+}
+
+/// The latest charge state published for the display and the web API
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChargerStatus {
+    pub stage: ChargeStage,
+    pub time_in_stage_s: u32,
+    pub source_present: bool,
+    /// The last battery voltage in volts
+    pub battery_voltage: f32,
+    /// The last current into the battery in amps
+    pub charge_current: f32,
+    /// The last current of the charge source in amps, `None` without a source channel
+    pub source_current: Option<f32>,
+    /// The latest events, the oldest first
+    pub events: heapless::Vec<ChargeEventRecord, CHARGE_EVENT_LOG_SIZE>,
+}
+
+impl ChargerStatus {
+    pub const fn new() -> Self {
+        Self {
+            stage: ChargeStage::None,
+            time_in_stage_s: 0,
+            source_present: false,
+            battery_voltage: 0.0,
+            charge_current: 0.0,
+            source_current: None,
+            events: heapless::Vec::new(),
+        }
+    }
+}
+
+impl Default for ChargerStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChargerConfig {
+    /// The VCP channel the battery is connected to
+    pub battery_channel: ChannelNum,
+    /// The VCP channel the charge source is connected to, if any
+    pub source_channel: Option<ChannelNum>,
+    pub detector: ChargeStageConfig,
+}
+
+impl ChargerConfig {
+    pub fn from_profile(profile: &BatteryProfile, channel_map: &ChannelMap) -> Self {
+        let source_channel = channel_map
+            .channel_of(ChannelRole::ChargerInput)
+            .or_else(|| channel_map.channel_of(ChannelRole::SolarInput));
+        Self {
+            battery_channel: channel_map.battery_channel(),
+            source_channel,
+            detector: ChargeStageConfig {
+                absorption_voltage: profile.absorption_voltage,
+                float_voltage: profile.float_voltage,
+                voltage_tolerance: VOLTAGE_TOLERANCE,
+                tail_current_a: profile.capacity_ah * TAIL_CURRENT_C,
+                presence_current_a: PRESENCE_CURRENT_A,
+                settle_ms: SETTLE_MS,
+            },
+        }
+    }
+}
+
+pub struct ChargerServiceState {
+    status: Mutex<CriticalSectionRawMutex, ChargerStatus>,
+    events: ChargeEventChannel,
+    /// The absorption and the float voltages
+    setpoints: Signal<CriticalSectionRawMutex, (f32, f32)>,
+}
+
+impl ChargerServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(ChargerStatus::new()),
+            events: ChargeEventChannel::new(),
+            setpoints: Signal::new(),
+        }
+    }
+}
+
+pub struct ChargerRunner<'a> {
+    readings: VcpReadingSubscriber<'a>,
+    state: &'a ChargerServiceState,
+    event_publisher: ChargeEventPublisher<'a>,
+    config: ChargerConfig,
+    detector: ChargeStageDetector,
+    source_current: Option<f32>,
+}
+
+pub struct ChargerControl<'a> {
+    state: &'a ChargerServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> ChargerControl<'a> {
+    /// Returns the latest charge stage and the event log
+    pub async fn status(&self) -> ChargerStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Moves the absorption and the float voltages, e.g. for the temperature compensation
+    pub fn set_setpoints(&self, absorption_voltage: f32, float_voltage: f32) {
+        self.state.setpoints.signal((absorption_voltage, float_voltage));
+    }
+
+    /// Subscribes to the charge stage and charge source events
+    pub fn subscribe_events(&self) -> Option<ChargeEventSubscriber<'a>> {
+        self.state.events.subscriber().ok()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ChargerService(());
+
+impl ChargerService {
+    /// Creates a new charge-stage detector instance fed by the readings of the VCP sensors
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        vcp_control: &'a VcpControl<'a>,
+        state: &'a mut ChargerServiceState,
+        config: ChargerConfig,
+    ) -> (ChargerRunner<'a>, ChargerControl<'a>) {
+        let state: &'a ChargerServiceState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the charger");
+        (
+            ChargerRunner {
+                readings,
+                state,
+                event_publisher: state.events.immediate_publisher(),
+                config,
+                detector: ChargeStageDetector::new(config.detector),
+                source_current: None,
+            },
+            ChargerControl { state },
+        )
+    }
+}
+
+impl<'a> ChargerRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let reading = match select(self.readings.next_message(), self.state.setpoints.wait()).await {
+                Either::First(WaitResult::Lagged(missed)) => {
+                    log::warn!("Charger missed {} readings", missed);
+                    continue;
+                }
+                Either::First(WaitResult::Message(reading)) => reading,
+                Either::Second((absorption_voltage, float_voltage)) => {
+                    self.detector.set_setpoints(absorption_voltage, float_voltage);
+                    continue;
+                }
+            };
+            if Some(reading.channel) == self.config.source_channel {
+                self.source_current = Some(reading.current.value());
+                continue;
+            }
+            if reading.channel != self.config.battery_channel {
+                continue;
+            }
+
+            // The battery current is positive while discharging
+            let sample = ChargeSample {
+                battery_voltage: reading.voltage.value(),
+                charge_current: -reading.current.value(),
+                source_current: self.source_current,
+            };
+            let now_ms = Instant::now().as_millis();
+            let events = self.detector.update(sample, now_ms);
+
+            let mut status = self.state.status.lock().await;
+            for event in events {
+                log::info!("Charge event: {}", event);
+                self.event_publisher.publish_immediate(event);
+                if status.events.is_full() {
+                    status.events.remove(0);
+                }
+                let record = ChargeEventRecord {
+                    event,
+                    uptime_s: (now_ms / 1000) as u32,
+                };
+                status.events.push(record).ok();
+            }
+            status.stage = self.detector.stage();
+            status.time_in_stage_s = (self.detector.time_in_stage_ms(now_ms) / 1000) as u32;
+            status.source_present = self.detector.source_present();
+            status.battery_voltage = sample.battery_voltage;
+            status.charge_current = sample.charge_current;
+            status.source_current = sample.source_current;
+        }
+    }
+}
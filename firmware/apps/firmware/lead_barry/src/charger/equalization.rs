@@ -0,0 +1,355 @@
+#![allow(dead_code)]
+
+//! Equalization of flooded lead-acid batteries.
+//!
+//! An equalization is a controlled overcharge which mixes the stratified electrolyte and breaks up
+//! the soft sulfate. The equalizer does not regulate the voltage itself: it switches the charger
+//! into its equalization mode for the duration taken from the battery profile and watches the
+//! battery while it is there. The run is aborted when the battery gets too hot or the charge
+//! current exceeds the limit for longer than a short settling time.
+//!
+//! Scheduled runs are due every interval of the battery profile after the previous run, and start
+//! only once the charger has brought the battery to float, so a discharged battery is never
+//! equalized. A run aborted by a safety limit is retried a day later.
+
+use crate::configuration::{BatteryProfile, EqualizationRecord, EqualizationResult, EqualizationSettings};
+use crate::outputs::OutputId;
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+/// How long after an aborted run the scheduled one is retried
+const RETRY_AFTER_ABORT_S: i64 = SECONDS_PER_DAY;
+/// How long the charge current may exceed the limit, the charger needs a moment to settle
+const OVERCURRENT_DELAY_MS: u64 = 10_000;
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EqualizationConfig {
+    /// The equalization voltage at 25°C, `None` if the battery must not be equalized
+    pub voltage: Option<f32>,
+    /// Seconds between the scheduled runs, 0 to equalize only on request
+    pub interval_s: u32,
+    pub duration_s: u32,
+    /// Start the runs automatically when they are due
+    pub scheduled: bool,
+    pub max_temperature_c: f32,
+    pub max_current_a: f32,
+    /// The output switching the charger into the equalization mode, `None` if there is none
+    pub charger_output: Option<OutputId>,
+}
+
+impl EqualizationConfig {
+    pub fn from_profile(profile: &BatteryProfile, settings: &EqualizationSettings) -> Self {
+        Self {
+            voltage: profile.equalize_voltage,
+            interval_s: profile.equalize_interval_days as u32 * SECONDS_PER_DAY as u32,
+            duration_s: profile.equalize_duration_min as u32 * 60,
+            scheduled: settings.scheduled,
+            max_temperature_c: settings.max_temperature_c,
+            max_current_a: settings.max_current_a,
+            charger_output: settings.charger_output,
+        }
+    }
+
+    /// The battery profile allows the equalization and an output switches the charger into it
+    pub fn is_supported(&self) -> bool {
+        self.voltage.is_some() && self.charger_output.is_some()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum EqualizationError {
+    /// The battery profile has no equalization voltage or no charger output is set
+    NotSupported,
+    AlreadyRunning,
+    NotRunning,
+}
+
+/// What the equalizer watches on every update
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EqualizationInputs {
+    /// Milliseconds since boot
+    pub now_ms: u64,
+    /// The wall-clock time in seconds since 1970, if known
+    pub now_s: Option<i64>,
+    /// The battery temperature in degrees Celsius, if known
+    pub temperature_c: Option<f32>,
+    /// The current into the battery in amps
+    pub charge_current: f32,
+    /// The charger holds the battery at float
+    pub battery_full: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum EqualizationEvent {
+    /// The first run has been scheduled, the record has to be written to the flash
+    Scheduled,
+    /// A scheduled run has started
+    Started,
+    Finished(EqualizationResult),
+}
+
+#[derive(Copy, Clone)]
+struct Run {
+    started_ms: u64,
+    started_at: Option<i64>,
+    overcurrent_since_ms: Option<u64>,
+}
+
+pub struct Equalizer {
+    config: EqualizationConfig,
+    record: EqualizationRecord,
+    run: Option<Run>,
+}
+
+impl Equalizer {
+    /// Creates an equalizer continuing the record restored from the flash
+    pub const fn new(config: EqualizationConfig, record: EqualizationRecord) -> Self {
+        Self {
+            config,
+            record,
+            run: None,
+        }
+    }
+
+    pub fn config(&self) -> &EqualizationConfig {
+        &self.config
+    }
+
+    pub fn record(&self) -> &EqualizationRecord {
+        &self.record
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.run.is_some()
+    }
+
+    /// Seconds since the running equalization started, `None` if there is none
+    pub fn elapsed_s(&self, now_ms: u64) -> Option<u32> {
+        self.run
+            .map(|run| (now_ms.saturating_sub(run.started_ms) / 1000) as u32)
+    }
+
+    /// Starts an equalization on request
+    pub fn start(&mut self, now_ms: u64, now_s: Option<i64>) -> Result<(), EqualizationError> {
+        if !self.config.is_supported() {
+            return Err(EqualizationError::NotSupported);
+        }
+        if self.run.is_some() {
+            return Err(EqualizationError::AlreadyRunning);
+        }
+        self.run = Some(Run {
+            started_ms: now_ms,
+            started_at: now_s,
+            overcurrent_since_ms: None,
+        });
+        Ok(())
+    }
+
+    /// Stops the running equalization on request
+    pub fn stop(&mut self, now_ms: u64, now_s: Option<i64>) -> Result<(), EqualizationError> {
+        if self.run.is_none() {
+            return Err(EqualizationError::NotRunning);
+        }
+        self.finish(EqualizationResult::Stopped, now_ms, now_s);
+        Ok(())
+    }
+
+    /// Checks the schedule and the safety limits, returns what has changed
+    pub fn update(&mut self, inputs: EqualizationInputs) -> Option<EqualizationEvent> {
+        let Some(run) = self.run.as_mut() else {
+            return self.check_schedule(inputs);
+        };
+
+        let over_temperature = inputs
+            .temperature_c
+            .is_some_and(|temperature_c| temperature_c > self.config.max_temperature_c);
+        let over_current = if inputs.charge_current > self.config.max_current_a {
+            let since_ms = *run.overcurrent_since_ms.get_or_insert(inputs.now_ms);
+            inputs.now_ms - since_ms >= OVERCURRENT_DELAY_MS
+        } else {
+            run.overcurrent_since_ms = None;
+            false
+        };
+        let completed = self
+            .elapsed_s(inputs.now_ms)
+            .is_some_and(|elapsed_s| elapsed_s >= self.config.duration_s);
+
+        let result = if over_temperature {
+            EqualizationResult::OverTemperature
+        } else if over_current {
+            EqualizationResult::OverCurrent
+        } else if completed {
+            EqualizationResult::Completed
+        } else {
+            return None;
+        };
+        self.finish(result, inputs.now_ms, inputs.now_s);
+        Some(EqualizationEvent::Finished(result))
+    }
+
+    fn check_schedule(&mut self, inputs: EqualizationInputs) -> Option<EqualizationEvent> {
+        if !self.config.scheduled || self.config.interval_s == 0 || !self.config.is_supported() {
+            return None;
+        }
+        let now_s = inputs.now_s?;
+        match self.record.next_due_at {
+            None => {
+                // The first interval starts when the schedule is switched on
+                self.record.next_due_at = Some(now_s + self.config.interval_s as i64);
+                Some(EqualizationEvent::Scheduled)
+            }
+            Some(due_at) if now_s >= due_at && inputs.battery_full => {
+                self.start(inputs.now_ms, Some(now_s)).ok()?;
+                Some(EqualizationEvent::Started)
+            }
+            Some(_) => None,
+        }
+    }
+
+    fn finish(&mut self, result: EqualizationResult, now_ms: u64, now_s: Option<i64>) {
+        let duration_s = self.elapsed_s(now_ms).unwrap_or_default();
+        let Some(run) = self.run.take() else {
+            return;
+        };
+        let next_in_s = if result.is_abort() {
+            Some(RETRY_AFTER_ABORT_S)
+        } else if self.config.interval_s > 0 {
+            Some(self.config.interval_s as i64)
+        } else {
+            None
+        };
+
+        self.record.last_run_at = run.started_at;
+        self.record.last_duration_s = duration_s;
+        self.record.last_result = Some(result);
+        self.record.next_due_at = now_s.zip(next_in_s).map(|(now_s, next_in_s)| now_s + next_in_s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::BatteryChemistry;
+
+    const NOW_S: i64 = 1_700_000_000;
+
+    fn equalizer(scheduled: bool) -> Equalizer {
+        let mut settings = EqualizationSettings::new();
+        settings.scheduled = scheduled;
+        settings.charger_output = Some(OutputId::Dc2);
+        let profile = BatteryProfile::for_chemistry(BatteryChemistry::Flooded);
+        Equalizer::new(
+            EqualizationConfig::from_profile(&profile, &settings),
+            EqualizationRecord::new(),
+        )
+    }
+
+    fn inputs(now_ms: u64) -> EqualizationInputs {
+        EqualizationInputs {
+            now_ms,
+            now_s: Some(NOW_S + (now_ms / 1000) as i64),
+            temperature_c: Some(25.0),
+            charge_current: 2.0,
+            battery_full: true,
+        }
+    }
+
+    #[test]
+    fn test_scheduled_run_waits_for_the_interval_and_a_full_battery() {
+        let mut equalizer = equalizer(true);
+        assert_eq!(equalizer.update(inputs(0)), Some(EqualizationEvent::Scheduled));
+        let interval_ms = equalizer.config().interval_s as u64 * 1000;
+        assert_eq!(equalizer.update(inputs(interval_ms - 1000)), None);
+
+        let charging = EqualizationInputs {
+            battery_full: false,
+            ..inputs(interval_ms)
+        };
+        assert_eq!(equalizer.update(charging), None);
+        assert_eq!(equalizer.update(inputs(interval_ms)), Some(EqualizationEvent::Started));
+        assert!(equalizer.is_running());
+    }
+
+    #[test]
+    fn test_run_completes_after_the_duration() {
+        let mut equalizer = equalizer(false);
+        equalizer.start(0, Some(NOW_S)).unwrap();
+        assert_eq!(equalizer.start(0, Some(NOW_S)), Err(EqualizationError::AlreadyRunning));
+
+        let duration_ms = equalizer.config().duration_s as u64 * 1000;
+        assert_eq!(equalizer.update(inputs(duration_ms - 1000)), None);
+        assert_eq!(
+            equalizer.update(inputs(duration_ms)),
+            Some(EqualizationEvent::Finished(EqualizationResult::Completed))
+        );
+
+        let record = equalizer.record();
+        assert_eq!(record.last_run_at, Some(NOW_S));
+        assert_eq!(record.last_duration_s, equalizer.config().duration_s);
+        assert_eq!(
+            record.next_due_at,
+            Some(NOW_S + (duration_ms / 1000) as i64 + equalizer.config().interval_s as i64)
+        );
+    }
+
+    #[test]
+    fn test_safety_aborts() {
+        let mut equalizer = equalizer(false);
+        equalizer.start(0, Some(NOW_S)).unwrap();
+        let hot = EqualizationInputs {
+            temperature_c: Some(45.0),
+            ..inputs(60_000)
+        };
+        assert_eq!(
+            equalizer.update(hot),
+            Some(EqualizationEvent::Finished(EqualizationResult::OverTemperature))
+        );
+        assert_eq!(equalizer.record().next_due_at, Some(NOW_S + 60 + RETRY_AFTER_ABORT_S));
+
+        // A short current spike is tolerated, a lasting one is not
+        equalizer.start(100_000, Some(NOW_S + 100)).unwrap();
+        let surge = |now_ms| EqualizationInputs {
+            charge_current: 15.0,
+            ..inputs(now_ms)
+        };
+        assert_eq!(equalizer.update(surge(101_000)), None);
+        assert_eq!(equalizer.update(inputs(102_000)), None);
+        assert_eq!(equalizer.update(surge(103_000)), None);
+        assert_eq!(
+            equalizer.update(surge(103_000 + OVERCURRENT_DELAY_MS)),
+            Some(EqualizationEvent::Finished(EqualizationResult::OverCurrent))
+        );
+    }
+
+    #[test]
+    fn test_sealed_battery_is_not_equalized() {
+        let profile = BatteryProfile::for_chemistry(BatteryChemistry::Agm);
+        let mut settings = EqualizationSettings::new();
+        settings.scheduled = true;
+        settings.charger_output = Some(OutputId::Dc2);
+        let mut equalizer = Equalizer::new(
+            EqualizationConfig::from_profile(&profile, &settings),
+            EqualizationRecord::new(),
+        );
+        assert_eq!(equalizer.start(0, Some(NOW_S)), Err(EqualizationError::NotSupported));
+        assert_eq!(equalizer.update(inputs(0)), None);
+        assert_eq!(equalizer.stop(0, Some(NOW_S)), Err(EqualizationError::NotRunning));
+    }
+
+    #[test]
+    fn test_no_charger_output_is_not_equalized() {
+        let profile = BatteryProfile::for_chemistry(BatteryChemistry::Flooded);
+        let mut settings = EqualizationSettings::new();
+        settings.scheduled = true;
+        let mut equalizer = Equalizer::new(
+            EqualizationConfig::from_profile(&profile, &settings),
+            EqualizationRecord::new(),
+        );
+        assert!(!equalizer.config().is_supported());
+        assert_eq!(equalizer.start(0, Some(NOW_S)), Err(EqualizationError::NotSupported));
+        assert_eq!(equalizer.update(inputs(0)), None);
+    }
+}
@@ -0,0 +1,240 @@
+use defmt_or_log as log;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Instant, Ticker};
+use serde::Serialize;
+
+use crate::charger::charge_stage::ChargeStage;
+use crate::charger::charger_service::ChargerControl;
+use crate::charger::equalization::*;
+use crate::configuration::{EqualizationRecord, EqualizationResult};
+use crate::outputs::OutputsControl;
+use crate::rtc::{RtcDs3231Ref, WallClock};
+use crate::temperature::TemperatureControl;
+use crate::units::TimeExt as _;
+
+const UPDATE_PERIOD_S: u64 = 1;
+
+/// The equalization progress published for the display and the web API
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EqualizationStatus {
+    /// The battery profile allows the equalization and a charger output is set
+    pub supported: bool,
+    pub running: bool,
+    /// Seconds since the running equalization started
+    pub elapsed_s: Option<u32>,
+    pub duration_s: u32,
+    /// The temperature compensated equalization voltage in volts
+    pub voltage: Option<f32>,
+    pub battery_voltage: f32,
+    /// The current into the battery in amps
+    pub charge_current: f32,
+    pub temperature_c: Option<f32>,
+    pub record: EqualizationRecord,
+}
+
+impl EqualizationStatus {
+    pub const fn new() -> Self {
+        Self {
+            supported: false,
+            running: false,
+            elapsed_s: None,
+            duration_s: 0,
+            voltage: None,
+            battery_voltage: 0.0,
+            charge_current: 0.0,
+            temperature_c: None,
+            record: EqualizationRecord::new(),
+        }
+    }
+}
+
+impl Default for EqualizationStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+enum EqualizationCommand {
+    Start,
+    Stop,
+}
+
+pub struct EqualizationServiceState {
+    status: Mutex<CriticalSectionRawMutex, EqualizationStatus>,
+    command: Signal<CriticalSectionRawMutex, EqualizationCommand>,
+    /// The record to be written to the flash
+    record: Signal<CriticalSectionRawMutex, EqualizationRecord>,
+}
+
+impl EqualizationServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(EqualizationStatus::new()),
+            command: Signal::new(),
+            record: Signal::new(),
+        }
+    }
+}
+
+pub struct EqualizationRunner<'a, I2C> {
+    charger_control: &'a ChargerControl<'a>,
+    temperature_control: &'a TemperatureControl<'a>,
+    outputs_control: &'a OutputsControl<'a>,
+    rtc: &'a RtcDs3231Ref<I2C>,
+    state: &'a EqualizationServiceState,
+    equalizer: Equalizer,
+    clock: WallClock,
+}
+
+pub struct EqualizationControl<'a> {
+    state: &'a EqualizationServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> EqualizationControl<'a> {
+    /// Returns the equalization progress and the last run
+    pub async fn status(&self) -> EqualizationStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Starts an equalization now, regardless of the schedule
+    pub fn start(&self) {
+        self.state.command.signal(EqualizationCommand::Start);
+    }
+
+    /// Stops the running equalization
+    pub fn stop(&self) {
+        self.state.command.signal(EqualizationCommand::Stop);
+    }
+
+    /// Waits until the record changes and has to be written to the flash
+    pub async fn wait_record(&self) -> EqualizationRecord {
+        self.state.record.wait().await
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EqualizationService(());
+
+impl EqualizationService {
+    /// Creates a new equalization scheduler switching the charger through the output of the
+    /// configuration. The schedule continues from the record restored from the flash.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a, I2C, E>(
+        charger_control: &'a ChargerControl<'a>,
+        temperature_control: &'a TemperatureControl<'a>,
+        outputs_control: &'a OutputsControl<'a>,
+        rtc: &'a RtcDs3231Ref<I2C>,
+        state: &'a mut EqualizationServiceState,
+        config: EqualizationConfig,
+        record: EqualizationRecord,
+    ) -> (EqualizationRunner<'a, I2C>, EqualizationControl<'a>)
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    {
+        let state: &'a EqualizationServiceState = state;
+        (
+            EqualizationRunner {
+                charger_control,
+                temperature_control,
+                outputs_control,
+                rtc,
+                state,
+                equalizer: Equalizer::new(config, record),
+                clock: WallClock::new(),
+            },
+            EqualizationControl { state },
+        )
+    }
+}
+
+impl<'a, I2C, E> EqualizationRunner<'a, I2C>
+where
+    I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub async fn run(&mut self) -> ! {
+        // A reboot in the middle of a run must not leave the charger equalizing
+        self.switch_charger(false).await;
+
+        let mut ticker = Ticker::every(UPDATE_PERIOD_S.s());
+        loop {
+            let command = match select(ticker.next(), self.state.command.wait()).await {
+                Either::First(()) => None,
+                Either::Second(command) => Some(command),
+            };
+
+            let now = Instant::now();
+            let now_ms = now.as_millis();
+            let now_s = self.clock.now_s(self.rtc, now).await;
+            match command {
+                Some(EqualizationCommand::Start) => match self.equalizer.start(now_ms, now_s) {
+                    Ok(()) => self.on_started().await,
+                    Err(e) => log::warn!("Equalization not started: {:?}", e),
+                },
+                Some(EqualizationCommand::Stop) => match self.equalizer.stop(now_ms, now_s) {
+                    Ok(()) => self.on_finished(EqualizationResult::Stopped).await,
+                    Err(e) => log::warn!("Equalization not stopped: {:?}", e),
+                },
+                None => {}
+            }
+
+            let charger = self.charger_control.status().await;
+            let temperature = self.temperature_control.status().await;
+            let inputs = EqualizationInputs {
+                now_ms,
+                now_s,
+                temperature_c: temperature.temperature_c,
+                charge_current: charger.charge_current,
+                battery_full: charger.stage == ChargeStage::Float,
+            };
+            match self.equalizer.update(inputs) {
+                Some(EqualizationEvent::Scheduled) => self.state.record.signal(self.equalizer.record().clone()),
+                Some(EqualizationEvent::Started) => self.on_started().await,
+                Some(EqualizationEvent::Finished(result)) => self.on_finished(result).await,
+                None => {}
+            }
+
+            let config = self.equalizer.config();
+            *self.state.status.lock().await = EqualizationStatus {
+                supported: config.is_supported(),
+                running: self.equalizer.is_running(),
+                elapsed_s: self.equalizer.elapsed_s(now_ms),
+                duration_s: config.duration_s,
+                voltage: config
+                    .voltage
+                    .map(|voltage| temperature.compensated.equalize_voltage.unwrap_or(voltage)),
+                battery_voltage: charger.battery_voltage,
+                charge_current: charger.charge_current,
+                temperature_c: temperature.temperature_c,
+                record: self.equalizer.record().clone(),
+            };
+        }
+    }
+
+    async fn on_started(&self) {
+        log::info!("Equalization started");
+        self.switch_charger(true).await;
+    }
+
+    async fn on_finished(&self, result: EqualizationResult) {
+        if result.is_abort() {
+            log::warn!("Equalization aborted: {}", result.name());
+        } else {
+            log::info!("Equalization finished: {}", result.name());
+        }
+        self.switch_charger(false).await;
+        self.state.record.signal(self.equalizer.record().clone());
+    }
+
+    /// Switches the charger into or out of the equalization mode, if an output controls it
+    async fn switch_charger(&self, equalize: bool) {
+        if let Some(charger_output) = self.equalizer.config().charger_output {
+            self.outputs_control.set_enabled(charger_output, equalize).await;
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#![allow(unused_imports)]
+
+mod charge_stage;
+mod charger_service;
+mod equalization;
+mod equalization_service;
+
+pub use self::charge_stage::*;
+pub use self::charger_service::*;
+pub use self::equalization::*;
+pub use self::equalization_service::*;
@@ -1,35 +1,36 @@
 #![allow(dead_code)]
 
-use super::flash_storage::*;
+use super::flash::{FlashError, FlashStorage};
 use super::settings::*;
 #[cfg(feature_use_static_ip_config)]
 use crate::configuration::settings;
+use crate::global_types::FlashDevice;
 use crc::{CRC_32_ISCSI, Crc};
 use defmt_or_log as log;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use static_cell::StaticCell;
 
-static SHARED_STORAGE: StaticCell<ConfigurationStorage<'static>> = StaticCell::new();
+static SHARED_STORAGE: StaticCell<ConfigurationStorage> = StaticCell::new();
 
 #[defmt_or_log::derive_format_or_debug]
 pub enum Error {
-    StorageRead(embassy_rp::flash::Error),
-    StorageErase(embassy_rp::flash::Error),
-    StorageWrite(embassy_rp::flash::Error),
+    StorageRead(FlashError),
+    StorageErase(FlashError),
+    StorageWrite(FlashError),
     Serialization,
     Deserialization,
 }
 
 pub struct ConfigurationStorageBuilder {
-    flash_storage: Storage<'static>,
+    flash_storage: FlashDevice,
 }
 
 impl ConfigurationStorageBuilder {
-    pub const fn new(flash_storage: Storage<'static>) -> Self {
+    pub const fn new(flash_storage: FlashDevice) -> Self {
         Self { flash_storage }
     }
 
-    pub fn build(mut self) -> &'static ConfigurationStorage<'static> {
+    pub fn build(mut self) -> &'static ConfigurationStorage {
         let initial_settings = match sync_load(&mut self.flash_storage) {
             Ok(settings) => settings,
 
@@ -51,12 +52,12 @@ impl ConfigurationStorageBuilder {
     }
 }
 
-pub struct ConfigurationStorage<'a> {
-    storage: Mutex<CriticalSectionRawMutex, StorageImpl<'a>>,
+pub struct ConfigurationStorage {
+    storage: Mutex<CriticalSectionRawMutex, StorageImpl>,
 }
 
-impl<'a> ConfigurationStorage<'a> {
-    const fn new(flash_storage: Storage<'a>, initial_settings: Settings) -> Self {
+impl ConfigurationStorage {
+    const fn new(flash_storage: FlashDevice, initial_settings: Settings) -> Self {
         Self {
             storage: Mutex::new(StorageImpl::new(flash_storage, initial_settings)),
         }
@@ -87,7 +88,7 @@ impl<'a> ConfigurationStorage<'a> {
     /// Load settings from flash storage asynchronously to the cache and return checked settings.
     pub async fn load(&self) -> Result<Settings, Error> {
         let mut storage = self.storage.lock().await;
-        let mut buffer = [0u8; Storage::storage_size()];
+        let mut buffer = [0u8; FlashDevice::SIZE];
         // Load entire storage into buffer
 
         storage
@@ -96,9 +97,10 @@ impl<'a> ConfigurationStorage<'a> {
             .await
             .map_err(Error::StorageRead)?;
 
-        let crc = Crc::<u32>::new(&CRC_32_ISCSI);
-        storage.settings_cache =
-            postcard::from_bytes_crc32::<Settings>(&buffer, crc.digest()).map_err(|_| Error::Deserialization)?;
+        storage.settings_cache = match decode_settings(&buffer) {
+            Some(settings) => settings,
+            None => migrate_settings(&buffer)?,
+        };
 
         Ok(storage.settings_cache.clone())
     }
@@ -106,7 +108,7 @@ impl<'a> ConfigurationStorage<'a> {
     /// Save settings from cache to flash storage asynchronously.
     pub async fn save(&self) -> Result<(), Error> {
         let mut storage = self.storage.lock().await;
-        let mut buffer = [0u8; Storage::storage_size()]; // Reserve 4 bytes for checksum
+        let mut buffer = [0u8; FlashDevice::SIZE]; // Reserve 4 bytes for checksum
 
         let crc = Crc::<u32>::new(&CRC_32_ISCSI);
         let used = postcard::to_slice_crc32(&storage.settings_cache, &mut buffer, crc.digest())
@@ -130,13 +132,13 @@ impl<'a> ConfigurationStorage<'a> {
     }
 }
 
-struct StorageImpl<'a> {
+struct StorageImpl {
     settings_cache: Settings,
-    flash_storage: Storage<'a>,
+    flash_storage: FlashDevice,
 }
 
-impl<'a> StorageImpl<'a> {
-    pub const fn new(flash_storage: Storage<'a>, initial_settings: Settings) -> Self {
+impl StorageImpl {
+    pub const fn new(flash_storage: FlashDevice, initial_settings: Settings) -> Self {
         Self {
             settings_cache: initial_settings,
             flash_storage,
@@ -144,21 +146,27 @@ impl<'a> StorageImpl<'a> {
     }
 }
 
-fn sync_load(flash_storage: &mut Storage<'_>) -> Result<Settings, Error> {
-    let mut buffer = [0u8; Storage::storage_size()];
+fn sync_load(flash_storage: &mut FlashDevice) -> Result<Settings, Error> {
+    let mut buffer = [0u8; FlashDevice::SIZE];
     // Load entire storage into buffer
     flash_storage
         .blocking_read(0, &mut buffer)
         .map_err(Error::StorageRead)?;
 
-    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
-    let settings = postcard::from_bytes_crc32::<Settings>(&buffer, crc.digest()).map_err(|_| Error::Deserialization)?;
+    if let Some(settings) = decode_settings(&buffer) {
+        return Ok(settings);
+    }
 
+    // Write the migrated settings back, so they are only migrated once
+    let settings = migrate_settings(&buffer)?;
+    if let Err(error) = sync_save(flash_storage, &settings) {
+        log::error!("Can't save migrated settings to storage: {}", error);
+    }
     Ok(settings)
 }
 
-fn sync_save(flash_storage: &mut Storage<'_>, settings: &Settings) -> Result<(), Error> {
-    let mut buffer = [0u8; Storage::storage_size()]; // Reserve 4 bytes for checksum
+fn sync_save(flash_storage: &mut FlashDevice, settings: &Settings) -> Result<(), Error> {
+    let mut buffer = [0u8; FlashDevice::SIZE]; // Reserve 4 bytes for checksum
 
     let crc = Crc::<u32>::new(&CRC_32_ISCSI);
     let used = postcard::to_slice_crc32(settings, &mut buffer, crc.digest()).map_err(|_| Error::Serialization)?;
@@ -170,3 +178,95 @@ fn sync_save(flash_storage: &mut Storage<'_>, settings: &Settings) -> Result<(),
 
     Ok(())
 }
+
+/// Decodes the settings of the current layout
+fn decode_settings(buffer: &[u8]) -> Option<Settings> {
+    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+    postcard::from_bytes_crc32::<Settings>(buffer, crc.digest())
+        .ok()
+        .filter(|settings| settings.settings_version == SETTINGS_VERSION)
+}
+
+/// Migrates the settings saved with another layout. Their length is unknown, so the end is found
+/// by the checksum that follows the settings.
+fn migrate_settings(buffer: &[u8]) -> Result<Settings, Error> {
+    let payload = checked_payload(buffer).ok_or(Error::Deserialization)?;
+    let header = postcard::from_bytes::<SettingsHeader>(payload).map_err(|_| Error::Deserialization)?;
+    log::warn!(
+        "Migrating settings version {} to version {}, only the network settings are kept",
+        header.settings_version,
+        SETTINGS_VERSION
+    );
+    Ok(Settings::migrate(header))
+}
+
+/// The shortest leading bytes of the buffer that are followed by their CRC
+fn checked_payload(buffer: &[u8]) -> Option<&[u8]> {
+    const CRC_SIZE: usize = 4;
+    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+    let mut digest = crc.digest();
+    for end in 1..=buffer.len().saturating_sub(CRC_SIZE) {
+        digest.update(&buffer[end - 1..end]);
+        let stored = u32::from_le_bytes(buffer[end..end + CRC_SIZE].try_into().ok()?);
+        if digest.clone().finalize() == stored {
+            return Some(&buffer[..end]);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    /// The layout of the first settings version
+    #[derive(Serialize)]
+    struct SettingsV1 {
+        network_settings: NetworkSettings,
+        settings_version: u32,
+        fallback_ap: bool,
+    }
+
+    fn save_crc32<T: Serialize>(value: &T, buffer: &mut [u8]) {
+        let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+        postcard::to_slice_crc32(value, buffer, crc.digest()).unwrap();
+    }
+
+    #[test]
+    fn current_settings_round_trip() {
+        let mut buffer = [0xFFu8; FlashDevice::SIZE];
+        let mut settings = Settings::default();
+        settings.fallback_ap = true;
+        save_crc32(&settings, &mut buffer);
+
+        assert!(decode_settings(&buffer) == Some(settings));
+    }
+
+    #[test]
+    fn older_layout_keeps_the_network_settings() {
+        let mut buffer = [0xFFu8; FlashDevice::SIZE];
+        let mut network_settings = NetworkSettings::default();
+        network_settings.wifi_settings.ssid = heapless::String::try_from("barry").unwrap();
+        let old = SettingsV1 {
+            network_settings: network_settings.clone(),
+            settings_version: 1,
+            fallback_ap: true,
+        };
+        save_crc32(&old, &mut buffer);
+
+        assert!(decode_settings(&buffer).is_none());
+        let settings = migrate_settings(&buffer).unwrap();
+        assert!(settings.network_settings == network_settings);
+        assert!(settings.fallback_ap);
+        assert_eq!(settings.settings_version, SETTINGS_VERSION);
+        assert!(settings.battery_profile == BatteryProfile::default());
+    }
+
+    #[test]
+    fn erased_flash_is_not_migrated() {
+        let buffer = [0xFFu8; FlashDevice::SIZE];
+        assert!(decode_settings(&buffer).is_none());
+        assert!(migrate_settings(&buffer).is_err());
+    }
+}
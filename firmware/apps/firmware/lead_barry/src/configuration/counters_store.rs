@@ -0,0 +1,209 @@
+#![allow(dead_code)]
+
+//! The runtime counters in their own flash area, so checkpointing them never puts the settings at risk.
+//!
+//! The area holds two slots of one erase block each, written in turns. Every record carries a growing
+//! sequence number and is followed by its checksum. At the boot the valid record with the highest
+//! sequence number is restored. A power loss while a slot is erased or written leaves the record in
+//! the other slot intact.
+
+use crc::{CRC_32_ISCSI, Crc};
+use defmt_or_log as log;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use serde::{Deserialize, Serialize};
+use static_cell::StaticCell;
+
+use super::configuration_storage::Error;
+use super::flash::FlashStorage;
+use super::settings::{CycleStats, EnergyTotals, EqualizationRecord, HealthTrend};
+use crate::global_types::CountersFlashDevice;
+
+/// The size of a slot, one erase block
+const SLOT_SIZE: usize = 0x1000;
+
+static SHARED_COUNTERS: StaticCell<CountersStore> = StaticCell::new();
+
+/// The counters the services keep across the reboots
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct Counters {
+    pub energy_totals: EnergyTotals,
+    pub health_trend: HealthTrend,
+    pub cycle_stats: CycleStats,
+    pub equalization_record: EqualizationRecord,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            energy_totals: EnergyTotals::new(),
+            health_trend: HealthTrend::new(),
+            cycle_stats: CycleStats::new(),
+            equalization_record: EqualizationRecord::new(),
+        }
+    }
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct CountersStoreBuilder {
+    flash_storage: CountersFlashDevice,
+}
+
+impl CountersStoreBuilder {
+    pub const fn new(flash_storage: CountersFlashDevice) -> Self {
+        Self { flash_storage }
+    }
+
+    pub fn build(mut self) -> &'static CountersStore {
+        let (sequence, counters) = match sync_load(&mut self.flash_storage) {
+            Some((sequence, counters)) => (sequence.wrapping_add(1), counters),
+            None => {
+                log::warn!("No counters in the storage, starting from zero");
+                (0, Counters::new())
+            }
+        };
+
+        SHARED_COUNTERS.init(CountersStore {
+            storage: Mutex::new(CountersImpl {
+                counters_cache: counters,
+                next_sequence: sequence,
+                flash_storage: self.flash_storage,
+            }),
+        })
+    }
+}
+
+pub struct CountersStore {
+    storage: Mutex<CriticalSectionRawMutex, CountersImpl>,
+}
+
+impl CountersStore {
+    /// Get a clone of the current counters from cache asynchronously.
+    pub async fn get_counters(&self) -> Counters {
+        let s = self.storage.lock().await;
+        s.counters_cache.clone()
+    }
+
+    /// Modifies the counters and writes them to the flash if they have changed.
+    pub async fn checkpoint<F>(&self, modify_fn: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Counters),
+    {
+        let mut s = self.storage.lock().await;
+        let mut counters = s.counters_cache.clone();
+        modify_fn(&mut counters);
+        if counters == s.counters_cache {
+            return Ok(());
+        }
+        s.counters_cache = counters;
+        s.save()
+    }
+
+    /// Clears all the counters in the flash
+    pub async fn factory_reset(&self) -> Result<(), Error> {
+        let mut s = self.storage.lock().await;
+        s.counters_cache = Counters::new();
+        s.save()
+    }
+}
+
+struct CountersImpl {
+    counters_cache: Counters,
+    next_sequence: u32,
+    flash_storage: CountersFlashDevice,
+}
+
+impl CountersImpl {
+    fn save(&mut self) -> Result<(), Error> {
+        sync_save(&mut self.flash_storage, self.next_sequence, &self.counters_cache)?;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// Restores the record with the highest sequence number, `None` if no slot holds a valid record
+fn sync_load<F: FlashStorage>(flash_storage: &mut F) -> Option<(u32, Counters)> {
+    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+    let mut buffer = [0u8; SLOT_SIZE];
+    let mut latest: Option<(u32, Counters)> = None;
+    for slot in 0..F::SIZE / SLOT_SIZE {
+        if let Err(error) = flash_storage.blocking_read(slot * SLOT_SIZE, &mut buffer) {
+            log::error!("Can't read counters slot {}: {:?}", slot, error);
+            continue;
+        }
+        // The sequence number comes first, then the counters
+        let Ok(record) = postcard::from_bytes_crc32::<(u32, Counters)>(&buffer, crc.digest()) else {
+            continue;
+        };
+        if latest.as_ref().is_none_or(|(sequence, _)| record.0 > *sequence) {
+            latest = Some(record);
+        }
+    }
+    latest
+}
+
+/// Writes the record into the slot of its sequence number, the other slot keeps the previous record
+fn sync_save<F: FlashStorage>(flash_storage: &mut F, sequence: u32, counters: &Counters) -> Result<(), Error> {
+    let mut buffer = [0u8; SLOT_SIZE];
+    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+    let used =
+        postcard::to_slice_crc32(&(sequence, counters), &mut buffer, crc.digest()).map_err(|_| Error::Serialization)?;
+
+    let offset = (sequence as usize % (F::SIZE / SLOT_SIZE)) * SLOT_SIZE;
+    flash_storage
+        .blocking_erase_range(offset, offset + SLOT_SIZE)
+        .map_err(Error::StorageErase)?;
+    flash_storage
+        .blocking_write(offset, used)
+        .map_err(Error::StorageWrite)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::flash::MemoryFlash;
+
+    type TestFlash = MemoryFlash<{ 2 * SLOT_SIZE }>;
+
+    fn counters(cycles: u32) -> Counters {
+        let mut counters = Counters::new();
+        counters.cycle_stats.cycles = cycles;
+        counters
+    }
+
+    #[test]
+    fn restores_the_latest_record() {
+        let mut flash = TestFlash::new();
+        assert!(sync_load(&mut flash).is_none());
+
+        for sequence in 0..3 {
+            sync_save(&mut flash, sequence, &counters(sequence + 10)).unwrap();
+        }
+        let (sequence, restored) = sync_load(&mut flash).unwrap();
+        assert_eq!(sequence, 2);
+        assert_eq!(restored.cycle_stats.cycles, 12);
+    }
+
+    #[test]
+    fn damaged_record_falls_back_to_the_previous_one() {
+        let mut flash = TestFlash::new();
+        sync_save(&mut flash, 0, &counters(10)).unwrap();
+        sync_save(&mut flash, 1, &counters(11)).unwrap();
+
+        // A power loss cut the write of the second slot
+        flash.blocking_erase_range(SLOT_SIZE, 2 * SLOT_SIZE).unwrap();
+        flash.blocking_write(SLOT_SIZE, &[2, 0]).unwrap();
+
+        let (sequence, restored) = sync_load(&mut flash).unwrap();
+        assert_eq!(sequence, 0);
+        assert_eq!(restored.cycle_stats.cycles, 10);
+    }
+}
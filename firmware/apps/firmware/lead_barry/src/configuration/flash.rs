@@ -0,0 +1,35 @@
+/// Errors of the flash access
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum FlashError {
+    /// The access does not fit into the storage area
+    OutOfBounds,
+    /// The offset or the buffer is not aligned as the flash requires
+    Unaligned,
+    /// The flash is busy with a background read of another area
+    Busy,
+    Other,
+}
+
+/// A flash area, the settings, the counters or the history. The offsets are relative to the start of the area.
+pub trait FlashStorage {
+    /// The size of the area in bytes
+    const SIZE: usize;
+    /// The size of the blocks the flash is erased in
+    const ERASE_SIZE: usize;
+
+    /// Erases the whole area
+    fn blocking_erase(&mut self) -> Result<(), FlashError> {
+        self.blocking_erase_range(0, Self::SIZE)
+    }
+
+    /// Erases the blocks from `from` up to `to`. Both ends must be aligned to [`Self::ERASE_SIZE`].
+    fn blocking_erase_range(&mut self, from: usize, to: usize) -> Result<(), FlashError>;
+
+    fn blocking_write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Reads without blocking the other tasks. The offset and the buffer must be word-aligned.
+    async fn background_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError>;
+
+    fn blocking_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError>;
+}
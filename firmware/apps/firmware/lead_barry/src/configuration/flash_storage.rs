@@ -5,18 +5,33 @@ use embassy_rp::Peri;
 use embassy_rp::dma::Channel;
 use embassy_rp::flash::{ASYNC_READ_SIZE, Async, ERASE_SIZE, Flash};
 use embassy_rp::peripherals::FLASH;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    mutex::{Mutex, MutexGuard},
+};
+
+use super::flash::{FlashError, FlashStorage};
 
 unsafe extern "C" {
     static _user_flash_start: u32;
     static _user_flash_size: u32;
+    static _history_flash_start: u32;
+    static _history_flash_size: u32;
+    static _counters_flash_start: u32;
+    static _counters_flash_size: u32;
 }
 
 const FLASH_SIZE: usize = (2 * 1024 * 1024) as usize; // 2MB for flash (see memory.x for details)
 const FLASH_BOOT_SIZE: usize = 0x100; // 256B for bootloader (see memory.x for details)
+const FLASH_HISTORY_SIZE: usize = 0x80000; // 512KB for the reading history (see memory.x for details)
+const FLASH_COUNTERS_SIZE: usize = 0x2000; // 8KB for the runtime counters (see memory.x for details)
 const FLASH_STORAGE_SIZE: usize = 0x1000; // 4KB for storage (see memory.x for details)
-const FLASH_PG_SIZE: usize = FLASH_SIZE - FLASH_BOOT_SIZE - FLASH_STORAGE_SIZE; // Remain for program FLASH size
+const FLASH_PG_SIZE: usize =
+    FLASH_SIZE - FLASH_BOOT_SIZE - FLASH_HISTORY_SIZE - FLASH_COUNTERS_SIZE - FLASH_STORAGE_SIZE; // Remain for program FLASH size
 
-const FLASH_STORAGE_START_OFFSET: usize = FLASH_BOOT_SIZE + FLASH_PG_SIZE; // Start of storage area
+const FLASH_HISTORY_START_OFFSET: usize = FLASH_BOOT_SIZE + FLASH_PG_SIZE; // Start of history area
+const FLASH_COUNTERS_START_OFFSET: usize = FLASH_HISTORY_START_OFFSET + FLASH_HISTORY_SIZE; // Start of counters area
+const FLASH_STORAGE_START_OFFSET: usize = FLASH_COUNTERS_START_OFFSET + FLASH_COUNTERS_SIZE; // Start of storage area
 const FLASH_STORAGE_END_OFFSET: usize = FLASH_STORAGE_START_OFFSET + FLASH_STORAGE_SIZE; // End of storage area
 
 // Compile-time assertions to ensure flash layout is valid
@@ -26,12 +41,28 @@ const _: () = {
         FLASH_STORAGE_SIZE.is_multiple_of(ERASE_SIZE),
         "Storage size must be multiple of erase size"
     );
+    assert!(
+        FLASH_COUNTERS_SIZE.is_multiple_of(ERASE_SIZE),
+        "Counters size must be multiple of erase size"
+    );
+    assert!(
+        FLASH_HISTORY_SIZE.is_multiple_of(ERASE_SIZE),
+        "History size must be multiple of erase size"
+    );
 
     // Ensure storage start is aligned to erase boundaries
     assert!(
         FLASH_STORAGE_START_OFFSET.is_multiple_of(ERASE_SIZE),
         "Storage start must be erase-aligned"
     );
+    assert!(
+        FLASH_COUNTERS_START_OFFSET.is_multiple_of(ERASE_SIZE),
+        "Counters start must be erase-aligned"
+    );
+    assert!(
+        FLASH_HISTORY_START_OFFSET.is_multiple_of(ERASE_SIZE),
+        "History start must be erase-aligned"
+    );
     // Ensure storage start is aligned to async read boundaries
     assert!(
         FLASH_STORAGE_START_OFFSET.is_multiple_of(ASYNC_READ_SIZE),
@@ -53,10 +84,22 @@ const _: () = {
 
 type FlashType<'a> = Flash<'a, FLASH, Async, FLASH_SIZE>;
 
-pub struct Storage<'a> {
-    flash: FlashType<'a>,
+/// The flash peripheral shared by the storage areas
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, FlashType<'static>>;
+
+/// An area of the flash. The blocking accesses never wait for the flash: they run to the end
+/// without yielding, so they only find it busy while another area awaits a background read.
+pub struct FlashArea<'a, const START: usize, const SIZE: usize> {
+    flash: &'a SharedFlash,
 }
 
+/// The settings area
+pub type Storage<'a> = FlashArea<'a, FLASH_STORAGE_START_OFFSET, FLASH_STORAGE_SIZE>;
+/// The runtime counters area
+pub type CountersStorage<'a> = FlashArea<'a, FLASH_COUNTERS_START_OFFSET, FLASH_COUNTERS_SIZE>;
+/// The reading history area
+pub type HistoryStorage<'a> = FlashArea<'a, FLASH_HISTORY_START_OFFSET, FLASH_HISTORY_SIZE>;
+
 pub fn get_user_flash_start() -> u32 {
     unsafe { &_user_flash_start as *const u32 as u32 }
 }
@@ -65,65 +108,104 @@ pub fn get_user_flash_size() -> u32 {
     unsafe { &_user_flash_size as *const u32 as u32 }
 }
 
-impl<'a> Storage<'a> {
-    pub fn new(flash_peripheral: Peri<'static, FLASH>, dma: Peri<'static, impl Channel>) -> Self {
-        let flash = FlashType::new(flash_peripheral, dma);
-        log::info!("Flash storage capacity:  size={:#X}", flash.capacity());
+pub fn get_counters_flash_start() -> u32 {
+    unsafe { &_counters_flash_start as *const u32 as u32 }
+}
+
+pub fn get_counters_flash_size() -> u32 {
+    unsafe { &_counters_flash_size as *const u32 as u32 }
+}
+
+pub fn get_history_flash_start() -> u32 {
+    unsafe { &_history_flash_start as *const u32 as u32 }
+}
+
+pub fn get_history_flash_size() -> u32 {
+    unsafe { &_history_flash_size as *const u32 as u32 }
+}
+
+pub fn create_shared_flash(flash_peripheral: Peri<'static, FLASH>, dma: Peri<'static, impl Channel>) -> SharedFlash {
+    let flash = FlashType::new(flash_peripheral, dma);
+    log::info!("Flash storage capacity:  size={:#X}", flash.capacity());
+    Mutex::new(flash)
+}
+
+impl<'a, const START: usize, const SIZE: usize> FlashArea<'a, START, SIZE> {
+    pub fn new(flash: &'a SharedFlash) -> Self {
         Self { flash }
     }
 
-    pub fn blocking_erase(&mut self) -> Result<(), embassy_rp::flash::Error> {
-        // Erase the entire storage area
-        for offset in (FLASH_STORAGE_START_OFFSET..FLASH_STORAGE_END_OFFSET).step_by(ERASE_SIZE) {
-            self.flash.blocking_erase(offset as u32, (offset + ERASE_SIZE) as u32)?;
+    fn blocking_flash(&self) -> Result<MutexGuard<'a, CriticalSectionRawMutex, FlashType<'static>>, FlashError> {
+        self.flash.try_lock().map_err(|_| FlashError::Busy)
+    }
+}
+
+impl From<embassy_rp::flash::Error> for FlashError {
+    fn from(error: embassy_rp::flash::Error) -> Self {
+        match error {
+            embassy_rp::flash::Error::OutOfBounds => FlashError::OutOfBounds,
+            embassy_rp::flash::Error::Unaligned => FlashError::Unaligned,
+            _ => FlashError::Other,
+        }
+    }
+}
+
+impl<'a, const START: usize, const SIZE: usize> FlashStorage for FlashArea<'a, START, SIZE> {
+    const SIZE: usize = SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn blocking_erase_range(&mut self, from: usize, to: usize) -> Result<(), FlashError> {
+        if from > to || to > SIZE {
+            return Err(FlashError::OutOfBounds);
+        }
+        if !from.is_multiple_of(ERASE_SIZE) || !to.is_multiple_of(ERASE_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+
+        let mut flash = self.blocking_flash()?;
+        for offset in (START + from..START + to).step_by(ERASE_SIZE) {
+            flash.blocking_erase(offset as u32, (offset + ERASE_SIZE) as u32)?;
         }
         Ok(())
     }
 
-    pub fn blocking_write(&mut self, offset: usize, data: &[u8]) -> Result<(), embassy_rp::flash::Error> {
+    fn blocking_write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
         // Ensure offset and data length are within bounds
-        if offset + data.len() > FLASH_STORAGE_SIZE {
-            return Err(embassy_rp::flash::Error::OutOfBounds);
+        if offset + data.len() > SIZE {
+            return Err(FlashError::OutOfBounds);
         }
 
-        self.flash
-            .blocking_write((FLASH_STORAGE_START_OFFSET + offset) as u32, data)?;
+        self.blocking_flash()?.blocking_write((START + offset) as u32, data)?;
 
         Ok(())
     }
 
-    pub async fn background_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), embassy_rp::flash::Error> {
+    async fn background_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
         // Ensure offset and buffer length are within bounds
-        if offset + buffer.len() > FLASH_STORAGE_SIZE {
-            return Err(embassy_rp::flash::Error::OutOfBounds);
+        if offset + buffer.len() > SIZE {
+            return Err(FlashError::OutOfBounds);
         }
         // Check alignment
         if !offset.is_multiple_of(ASYNC_READ_SIZE) || !(buffer.as_ptr() as usize).is_multiple_of(ASYNC_READ_SIZE) {
-            return Err(embassy_rp::flash::Error::Unaligned);
+            return Err(FlashError::Unaligned);
         }
 
         let u32_buffer = bytemuck::cast_slice_mut::<u8, u32>(buffer);
 
-        self.flash
-            .background_read((FLASH_STORAGE_START_OFFSET + offset) as u32, u32_buffer)?
-            .await;
+        let mut flash = self.flash.lock().await;
+        flash.background_read((START + offset) as u32, u32_buffer)?.await;
 
         Ok(())
     }
 
-    pub fn blocking_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), embassy_rp::flash::Error> {
+    fn blocking_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
         // Ensure offset and buffer length are within bounds
-        if offset + buffer.len() > FLASH_STORAGE_SIZE {
-            return Err(embassy_rp::flash::Error::OutOfBounds);
+        if offset + buffer.len() > SIZE {
+            return Err(FlashError::OutOfBounds);
         }
 
-        self.flash
-            .blocking_read((FLASH_STORAGE_START_OFFSET + offset) as u32, buffer)?;
+        self.blocking_flash()?.blocking_read((START + offset) as u32, buffer)?;
 
         Ok(())
     }
-
-    pub const fn storage_size() -> usize {
-        FLASH_STORAGE_SIZE
-    }
 }
@@ -1,7 +1,13 @@
 mod configuration_storage;
+mod counters_store;
+mod flash;
+#[cfg(not(feature = "sim"))]
 mod flash_storage;
 mod settings;
 
 pub use configuration_storage::*;
+pub use counters_store::*;
+pub use flash::*;
+#[cfg(not(feature = "sim"))]
 pub use flash_storage::*;
 pub use settings::*;
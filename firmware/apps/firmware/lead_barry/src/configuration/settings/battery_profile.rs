@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_CAPACITY_AH: f32 = 100.0; // Ampere-hours
+
+/// Lead-acid battery chemistries supported by the charge profiles.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum BatteryChemistry {
+    Flooded,
+    Agm,
+    Gel,
+    Calcium,
+}
+
+impl BatteryChemistry {
+    pub const ALL: [BatteryChemistry; 4] = [
+        BatteryChemistry::Flooded,
+        BatteryChemistry::Agm,
+        BatteryChemistry::Gel,
+        BatteryChemistry::Calcium,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            BatteryChemistry::Flooded => "Flooded",
+            BatteryChemistry::Agm => "AGM",
+            BatteryChemistry::Gel => "Gel",
+            BatteryChemistry::Calcium => "Calcium",
+        }
+    }
+}
+
+/// Charge and protection setpoints of a 12V lead-acid battery at 25°C.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct BatteryProfile {
+    pub chemistry: BatteryChemistry,
+    /// Nominal capacity in ampere-hours
+    pub capacity_ah: f32,
+    /// Absorption (bulk charge) voltage in volts
+    pub absorption_voltage: f32,
+    /// Float voltage in volts
+    pub float_voltage: f32,
+    /// Equalization voltage in volts. `None` for the sealed batteries which must not be equalized.
+    pub equalize_voltage: Option<f32>,
+    /// Days between the scheduled equalizations, 0 to equalize only on request
+    pub equalize_interval_days: u16,
+    /// How long the equalization voltage is held, in minutes
+    pub equalize_duration_min: u16,
+    /// Low-voltage disconnect voltage in volts
+    pub lvd_voltage: f32,
+}
+
+impl BatteryProfile {
+    pub const fn new() -> Self {
+        Self::for_chemistry(BatteryChemistry::Flooded)
+    }
+
+    /// Returns the typical setpoints of the given chemistry for a battery of the default capacity.
+    pub const fn for_chemistry(chemistry: BatteryChemistry) -> Self {
+        match chemistry {
+            BatteryChemistry::Flooded => Self {
+                chemistry,
+                capacity_ah: DEFAULT_CAPACITY_AH,
+                absorption_voltage: 14.4,
+                float_voltage: 13.5,
+                equalize_voltage: Some(15.5),
+                equalize_interval_days: 30,
+                equalize_duration_min: 120,
+                lvd_voltage: 11.6,
+            },
+            BatteryChemistry::Agm => Self {
+                chemistry,
+                capacity_ah: DEFAULT_CAPACITY_AH,
+                absorption_voltage: 14.6,
+                float_voltage: 13.6,
+                equalize_voltage: None,
+                equalize_interval_days: 0,
+                equalize_duration_min: 0,
+                lvd_voltage: 11.8,
+            },
+            BatteryChemistry::Gel => Self {
+                chemistry,
+                capacity_ah: DEFAULT_CAPACITY_AH,
+                absorption_voltage: 14.1,
+                float_voltage: 13.8,
+                equalize_voltage: None,
+                equalize_interval_days: 0,
+                equalize_duration_min: 0,
+                lvd_voltage: 11.8,
+            },
+            BatteryChemistry::Calcium => Self {
+                chemistry,
+                capacity_ah: DEFAULT_CAPACITY_AH,
+                absorption_voltage: 14.8,
+                float_voltage: 13.6,
+                equalize_voltage: None,
+                equalize_interval_days: 0,
+                equalize_duration_min: 0,
+                lvd_voltage: 11.6,
+            },
+        }
+    }
+
+    pub fn with_capacity_ah(mut self, capacity_ah: f32) -> Self {
+        self.capacity_ah = capacity_ah;
+        self
+    }
+
+    /// The highest voltage the charger is expected to apply to the battery.
+    pub fn max_charge_voltage(&self) -> f32 {
+        match self.equalize_voltage {
+            Some(equalize_voltage) => equalize_voltage.max(self.absorption_voltage),
+            None => self.absorption_voltage,
+        }
+    }
+
+    /// Checks that the setpoints are ordered sensibly and lie within the 12V battery range.
+    pub fn is_valid(&self) -> bool {
+        let in_range = |voltage: f32| (9.0..=16.5).contains(&voltage);
+
+        self.capacity_ah > 0.0
+            && in_range(self.lvd_voltage)
+            && in_range(self.float_voltage)
+            && in_range(self.absorption_voltage)
+            && self.equalize_voltage.is_none_or(in_range)
+            && (self.equalize_voltage.is_none() || (1..=480).contains(&self.equalize_duration_min))
+            && self.equalize_interval_days <= 365
+            && self.lvd_voltage < self.float_voltage
+            && self.float_voltage <= self.absorption_voltage
+            && self
+                .equalize_voltage
+                .is_none_or(|voltage| voltage >= self.absorption_voltage)
+    }
+}
+
+impl Default for BatteryProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
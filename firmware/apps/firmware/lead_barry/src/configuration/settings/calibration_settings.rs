@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::{
+    VCP_CHANNELS, VcpCalibrationError, VcpCalibrationRequest, VcpChannelCalibration, VcpLinearCalibration,
+};
+
+/// The shunts and the reading corrections of the VCP channels, measured on the board
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct CalibrationSettings {
+    pub channels: [VcpChannelCalibration; VCP_CHANNELS],
+}
+
+impl CalibrationSettings {
+    pub const fn new() -> Self {
+        Self {
+            channels: [VcpChannelCalibration::new(); VCP_CHANNELS],
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.channels.iter().all(|channel| channel.is_valid())
+    }
+
+    /// The calibrations of the channels of the fitted chips
+    pub fn fitted(&self, channel_count: usize) -> &[VcpChannelCalibration] {
+        &self.channels[..channel_count.min(VCP_CHANNELS)]
+    }
+
+    /// Fits the correction of the requested quantity and stores it. Returns the new calibration of
+    /// the channel, the shunt and the other quantity are kept.
+    pub fn calibrate(&mut self, request: &VcpCalibrationRequest) -> Result<VcpChannelCalibration, VcpCalibrationError> {
+        let channel = self
+            .channels
+            .get_mut(request.channel as usize)
+            .ok_or(VcpCalibrationError::InvalidChannel)?;
+        *channel.quantity_mut(request.quantity) = VcpLinearCalibration::fit(&request.points)?;
+        Ok(*channel)
+    }
+}
+
+impl Default for CalibrationSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcp_sensors::{VcpCalibrationPoint, VcpQuantity};
+
+    fn request(channel: u8, quantity: VcpQuantity, uncalibrated: f32, reference: f32) -> VcpCalibrationRequest {
+        let mut points = heapless::Vec::new();
+        points
+            .push(VcpCalibrationPoint {
+                uncalibrated,
+                reference,
+            })
+            .unwrap();
+        VcpCalibrationRequest {
+            channel,
+            quantity,
+            points,
+        }
+    }
+
+    #[test]
+    fn test_calibrate_keeps_the_other_quantity() {
+        let mut settings = CalibrationSettings::default();
+        settings.channels[1] = settings.channels[1].with_shunt_resistance(0.05);
+
+        let calibration = settings
+            .calibrate(&request(1, VcpQuantity::Voltage, 12.0, 12.24))
+            .unwrap();
+        assert!((calibration.voltage.gain - 1.02).abs() < 1e-4);
+        assert!(calibration.current == VcpLinearCalibration::IDENTITY);
+        assert_eq!(calibration.shunt_resistance, 0.05);
+        assert!(settings.channels[1] == calibration);
+        assert!(settings.is_valid());
+    }
+
+    #[test]
+    fn test_failed_calibration_changes_nothing() {
+        let mut settings = CalibrationSettings::default();
+        assert!(matches!(
+            settings.calibrate(&request(0, VcpQuantity::Current, 1.0, 3.0)),
+            Err(VcpCalibrationError::OutOfRange)
+        ));
+        assert!(matches!(
+            settings.calibrate(&request(VCP_CHANNELS as u8, VcpQuantity::Current, 1.0, 1.0)),
+            Err(VcpCalibrationError::InvalidChannel)
+        ));
+        assert!(settings == CalibrationSettings::default());
+    }
+}
@@ -0,0 +1,233 @@
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::{ChannelNum, VCP_CHANNELS};
+
+/// The longest channel label, so it fits a screen title
+pub const CHANNEL_LABEL_SIZE: usize = 15;
+
+pub type ChannelLabel = heapless::String<CHANNEL_LABEL_SIZE>;
+
+/// What a VCP channel is wired to
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum ChannelRole {
+    Battery,
+    SolarInput,
+    ChargerInput,
+    LoadBus,
+    Poe,
+    Other,
+}
+
+impl ChannelRole {
+    pub const ALL: [ChannelRole; 6] = [
+        ChannelRole::Battery,
+        ChannelRole::SolarInput,
+        ChannelRole::ChargerInput,
+        ChannelRole::LoadBus,
+        ChannelRole::Poe,
+        ChannelRole::Other,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            ChannelRole::Battery => "Battery",
+            ChannelRole::SolarInput => "Solar input",
+            ChannelRole::ChargerInput => "Charger input",
+            ChannelRole::LoadBus => "Load bus",
+            ChannelRole::Poe => "PoE",
+            ChannelRole::Other => "Other",
+        }
+    }
+
+    /// A three letter name for the compact screens
+    pub const fn short_name(&self) -> &'static str {
+        match self {
+            ChannelRole::Battery => "BAT",
+            ChannelRole::SolarInput => "SOL",
+            ChannelRole::ChargerInput => "CHG",
+            ChannelRole::LoadBus => "LD",
+            ChannelRole::Poe => "POE",
+            ChannelRole::Other => "AUX",
+        }
+    }
+
+    /// Returns `true` for the channels which bring the charge current in
+    pub const fn is_charge_source(&self) -> bool {
+        matches!(self, ChannelRole::SolarInput | ChannelRole::ChargerInput)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChannelSettings {
+    pub role: ChannelRole,
+    /// Shown in the screen titles and the web UI
+    pub label: ChannelLabel,
+}
+
+impl ChannelSettings {
+    pub const fn new(role: ChannelRole) -> Self {
+        Self {
+            role,
+            label: heapless::String::new(),
+        }
+    }
+
+    fn with_label(mut self, label: &str) -> Self {
+        self.label = ChannelLabel::from_str(label).unwrap_or_default();
+        self
+    }
+}
+
+/// Assigns a role and a label to every VCP channel, including the channels of the chips which are
+/// not fitted
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct ChannelMap {
+    pub channels: [ChannelSettings; VCP_CHANNELS],
+}
+
+impl ChannelMap {
+    pub const fn new() -> Self {
+        let mut channels = [const { ChannelSettings::new(ChannelRole::Other) }; VCP_CHANNELS];
+        channels[0].role = ChannelRole::Battery;
+        channels[1].role = ChannelRole::SolarInput;
+        channels[2].role = ChannelRole::LoadBus;
+        Self { channels }
+    }
+
+    /// Returns the first channel with the given role
+    pub fn channel_of(&self, role: ChannelRole) -> Option<ChannelNum> {
+        self.channels
+            .iter()
+            .position(|channel| channel.role == role)
+            .map(|channel| channel as ChannelNum)
+    }
+
+    /// The channel the SoC estimation and the LVD watch
+    pub fn battery_channel(&self) -> ChannelNum {
+        self.channel_of(ChannelRole::Battery).unwrap_or(0)
+    }
+
+    pub fn role(&self, channel: ChannelNum) -> ChannelRole {
+        self.channels
+            .get(channel as usize)
+            .map_or(ChannelRole::Other, |channel| channel.role)
+    }
+
+    /// The label of the channel, or the role name when the label is empty
+    pub fn label(&self, channel: ChannelNum) -> &str {
+        match self.channels.get(channel as usize) {
+            Some(channel) if !channel.label.is_empty() => channel.label.as_str(),
+            Some(channel) => channel.role.name(),
+            None => "",
+        }
+    }
+
+    /// Tags a value of the channel with its role and label for the web API
+    pub fn labeled<T>(&self, channel: ChannelNum, value: T) -> LabeledChannel<'_, T> {
+        LabeledChannel {
+            channel,
+            role: self.role(channel),
+            label: self.label(channel),
+            value,
+        }
+    }
+
+    pub fn fitted(&self, channel_count: usize) -> FittedChannelMap<'_> {
+        FittedChannelMap {
+            channel_count,
+            channels: &self.channels,
+        }
+    }
+
+    /// Checks that exactly one channel is the battery and that it is one of the fitted channels
+    pub fn is_valid(&self, channel_count: usize) -> bool {
+        let mut batteries = self
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(_, channel)| channel.role == ChannelRole::Battery);
+        matches!((batteries.next(), batteries.next()), (Some((channel, _)), None) if channel < channel_count)
+    }
+}
+
+impl Default for ChannelMap {
+    fn default() -> Self {
+        let mut map = Self::new();
+        map.channels[0] = ChannelSettings::new(ChannelRole::Battery).with_label("Battery");
+        map.channels[1] = ChannelSettings::new(ChannelRole::SolarInput).with_label("Solar");
+        map.channels[2] = ChannelSettings::new(ChannelRole::LoadBus).with_label("Load");
+        map
+    }
+}
+
+/// The channel map along with the number of the fitted channels, which the web UI lists
+#[derive(Serialize)]
+pub struct FittedChannelMap<'a> {
+    pub channel_count: usize,
+    pub channels: &'a [ChannelSettings; VCP_CHANNELS],
+}
+
+/// A value of a channel along with the role and the label of the channel
+#[derive(Serialize)]
+pub struct LabeledChannel<'a, T> {
+    pub channel: ChannelNum,
+    pub role: ChannelRole,
+    pub label: &'a str,
+    pub value: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_channel_follows_role() {
+        let mut map = ChannelMap::default();
+        assert!(map.is_valid(3));
+        assert_eq!(map.battery_channel(), 0);
+
+        map.channels[0].role = ChannelRole::LoadBus;
+        map.channels[2].role = ChannelRole::Battery;
+        assert!(map.is_valid(3));
+        assert_eq!(map.battery_channel(), 2);
+        assert_eq!(map.channel_of(ChannelRole::SolarInput), Some(1));
+        assert_eq!(map.channel_of(ChannelRole::Poe), None);
+    }
+
+    #[test]
+    fn test_exactly_one_battery() {
+        let mut map = ChannelMap::default();
+        map.channels[1].role = ChannelRole::Battery;
+        assert!(!map.is_valid(3));
+
+        map.channels[0].role = ChannelRole::Other;
+        map.channels[1].role = ChannelRole::Other;
+        assert!(!map.is_valid(3));
+    }
+
+    #[test]
+    fn test_battery_on_a_fitted_chip() {
+        let mut map = ChannelMap::default();
+        map.channels[0].role = ChannelRole::Other;
+        map.channels[4].role = ChannelRole::Battery;
+        assert!(!map.is_valid(3));
+        assert!(map.is_valid(6));
+        assert_eq!(map.battery_channel(), 4);
+    }
+
+    #[test]
+    fn test_empty_label_falls_back_to_role_name() {
+        let mut map = ChannelMap::default();
+        assert_eq!(map.label(1), "Solar");
+        map.channels[1].label.clear();
+        assert_eq!(map.label(1), "Solar input");
+        assert_eq!(map.label(3), "Other");
+        assert_eq!(map.label(VCP_CHANNELS as ChannelNum), "");
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of the depth-of-discharge histogram bins, 10% each
+pub const DOD_BINS: usize = 10;
+
+/// How hard the battery has been cycled, checkpointed periodically
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct CycleStats {
+    /// Number of the discharges counted from a peak to a valley of the state of charge
+    pub cycles: u32,
+    /// The sum of the depths of all the discharges, in full cycles
+    pub equivalent_full_cycles: f32,
+    /// The discharges counted per depth: 0-10%, 10-20%, ..., 90-100%
+    pub dod_histogram: [u32; DOD_BINS],
+    /// The time spent below 50% state of charge in seconds
+    pub time_below_half_soc_s: u32,
+    /// The lowest state of charge ever reached in percent, `None` before the first reading
+    pub deepest_soc_percent: Option<f32>,
+    /// When the lowest state of charge was reached, in seconds since 1970 of the RTC time.
+    /// `None` if the RTC was not readable.
+    pub deepest_at: Option<i64>,
+}
+
+impl CycleStats {
+    pub const fn new() -> Self {
+        Self {
+            cycles: 0,
+            equivalent_full_cycles: 0.0,
+            dod_histogram: [0; DOD_BINS],
+            time_below_half_soc_s: 0,
+            deepest_soc_percent: None,
+            deepest_at: None,
+        }
+    }
+}
+
+impl Default for CycleStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
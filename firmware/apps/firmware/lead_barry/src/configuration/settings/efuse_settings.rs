@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::outputs::OutputId;
+use crate::vcp_sensors::VCP_CHANNELS;
+
+/// Maximum number of electronic fuses, one per VCP channel
+pub const MAX_FUSES: usize = VCP_CHANNELS;
+
+const DEFAULT_TRIP_CURRENT_A: f32 = 1.5; // Amps
+const DEFAULT_CRITICAL_CURRENT_A: f32 = 3.0; // Amps
+const DEFAULT_TRIP_DELAY_MS: u32 = 200; // Milliseconds
+const DEFAULT_COOLDOWN_S: u32 = 30; // Seconds
+const DEFAULT_MAX_RETRIES: u8 = 3;
+
+/// An electronic fuse which switches an output off when the current of its VCP channel is too high
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct FuseSettings {
+    pub enabled: bool,
+    /// The VCP channel measuring the current of the output
+    pub channel: u8,
+    /// The output switched off when the fuse trips
+    pub output: OutputId,
+    /// The current above which the channel reports overcurrent, in amps
+    pub trip_current_a: f32,
+    /// The current above which the sensor raises the critical alert and the fuse trips at once, in amps
+    pub critical_current_a: f32,
+    /// How long the overcurrent has to last before the fuse trips, in milliseconds
+    pub trip_delay_ms: u32,
+    /// How long the output stays off before a retry, in seconds
+    pub cooldown_s: u32,
+    /// Number of retries before the fuse trips permanently
+    pub max_retries: u8,
+}
+
+impl FuseSettings {
+    pub const fn new(channel: u8, output: OutputId) -> Self {
+        Self {
+            enabled: true,
+            channel,
+            output,
+            trip_current_a: DEFAULT_TRIP_CURRENT_A,
+            critical_current_a: DEFAULT_CRITICAL_CURRENT_A,
+            trip_delay_ms: DEFAULT_TRIP_DELAY_MS,
+            cooldown_s: DEFAULT_COOLDOWN_S,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+/// Electronic fuse settings
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct EFuseSettings {
+    pub fuses: heapless::Vec<FuseSettings, MAX_FUSES>,
+}
+
+impl EFuseSettings {
+    pub const fn new() -> Self {
+        Self {
+            fuses: heapless::Vec::new(),
+        }
+    }
+
+    /// Checks that every fitted channel has at most one fuse, the limits are positive and the critical
+    /// current is not below the trip current
+    pub fn is_valid(&self, channel_count: usize) -> bool {
+        let unique_channels = self
+            .fuses
+            .iter()
+            .enumerate()
+            .all(|(i, fuse)| !self.fuses[..i].iter().any(|other| other.channel == fuse.channel));
+
+        unique_channels
+            && self.fuses.iter().all(|fuse| {
+                (fuse.channel as usize) < channel_count.min(MAX_FUSES)
+                    && fuse.trip_current_a > 0.0
+                    && fuse.critical_current_a >= fuse.trip_current_a
+                    && fuse.cooldown_s > 0
+            })
+    }
+}
+
+impl Default for EFuseSettings {
+    fn default() -> Self {
+        let mut settings = Self::new();
+        // The external load channel feeds the high-current bypass
+        settings.fuses.push(FuseSettings::new(2, OutputId::Bypass)).ok();
+        settings
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::{EnergyCounters, VCP_CHANNELS};
+
+/// Lifetime charge and energy totals of the VCP channels, checkpointed periodically
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct EnergyTotals {
+    pub channels: [EnergyCounters; VCP_CHANNELS],
+}
+
+impl EnergyTotals {
+    pub const fn new() -> Self {
+        Self {
+            channels: [EnergyCounters::new(); VCP_CHANNELS],
+        }
+    }
+}
+
+impl Default for EnergyTotals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
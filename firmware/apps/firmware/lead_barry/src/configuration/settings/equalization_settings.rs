@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::outputs::OutputId;
+
+const DEFAULT_MAX_TEMPERATURE_C: f32 = 40.0; // Degrees Celsius
+const DEFAULT_MAX_CURRENT_A: f32 = 10.0; // Amps
+
+/// The safety limits and the output of the equalization charge. The voltage, the interval and the
+/// duration come from the battery profile.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct EqualizationSettings {
+    /// Start the equalization automatically when it is due
+    pub scheduled: bool,
+    /// The output switching the charger into the equalization mode, `None` until it is wired
+    pub charger_output: Option<OutputId>,
+    /// The battery temperature above which the equalization is aborted, in degrees Celsius
+    pub max_temperature_c: f32,
+    /// The charge current above which the equalization is aborted, in amps
+    pub max_current_a: f32,
+}
+
+impl EqualizationSettings {
+    pub const fn new() -> Self {
+        Self {
+            scheduled: false,
+            charger_output: None,
+            max_temperature_c: DEFAULT_MAX_TEMPERATURE_C,
+            max_current_a: DEFAULT_MAX_CURRENT_A,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (20.0..=55.0).contains(&self.max_temperature_c) && self.max_current_a > 0.0
+    }
+}
+
+impl Default for EqualizationSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How an equalization run ended
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum EqualizationResult {
+    /// The equalization voltage was held for the whole duration
+    Completed,
+    /// Stopped on request
+    Stopped,
+    /// The battery got too hot
+    OverTemperature,
+    /// The charge current was too high
+    OverCurrent,
+}
+
+impl EqualizationResult {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            EqualizationResult::Completed => "Completed",
+            EqualizationResult::Stopped => "Stopped",
+            EqualizationResult::OverTemperature => "Too hot",
+            EqualizationResult::OverCurrent => "Overcurrent",
+        }
+    }
+
+    /// The run ended on a safety limit
+    pub const fn is_abort(&self) -> bool {
+        matches!(
+            self,
+            EqualizationResult::OverTemperature | EqualizationResult::OverCurrent
+        )
+    }
+}
+
+/// The last equalization run and the next scheduled one, written to the flash after every run
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct EqualizationRecord {
+    /// When the last run started, in seconds since 1970 of the RTC time. `None` if there was no
+    /// run yet or the RTC was not readable.
+    pub last_run_at: Option<i64>,
+    /// How long the last run lasted in seconds
+    pub last_duration_s: u32,
+    pub last_result: Option<EqualizationResult>,
+    /// When the next scheduled run is due, in seconds since 1970 of the RTC time
+    pub next_due_at: Option<i64>,
+}
+
+impl EqualizationRecord {
+    pub const fn new() -> Self {
+        Self {
+            last_run_at: None,
+            last_duration_s: 0,
+            last_result: None,
+            next_due_at: None,
+        }
+    }
+}
+
+impl Default for EqualizationRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
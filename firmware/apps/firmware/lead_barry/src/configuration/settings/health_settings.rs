@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of the latest full-cycle estimates kept in the trend
+pub const HEALTH_TREND_SIZE: usize = 16;
+
+const DEFAULT_MAX_RESISTANCE_RISE_PERCENT: f32 = 100.0;
+const DEFAULT_MIN_CAPACITY_PERCENT: f32 = 80.0;
+
+/// The thresholds of the "replace battery soon" warning
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct HealthSettings {
+    pub enabled: bool,
+    /// How far the internal resistance may rise above the baseline of the new battery, in percent
+    pub max_resistance_rise_percent: f32,
+    /// The usable capacity below which the battery is worn out, in percent of the nominal capacity
+    pub min_capacity_percent: f32,
+}
+
+impl HealthSettings {
+    pub const fn new() -> Self {
+        Self {
+            enabled: true,
+            max_resistance_rise_percent: DEFAULT_MAX_RESISTANCE_RISE_PERCENT,
+            min_capacity_percent: DEFAULT_MIN_CAPACITY_PERCENT,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (10.0..=500.0).contains(&self.max_resistance_rise_percent)
+            && (10.0..=100.0).contains(&self.min_capacity_percent)
+    }
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The estimates recorded at the end of a full cycle
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HealthTrendEntry {
+    /// The number of the full cycle since the battery was installed
+    pub cycle: u16,
+    /// The internal resistance in milliohms, `None` if no load step has been seen yet
+    pub resistance_mohm: Option<f32>,
+    /// The usable capacity in ampere-hours
+    pub capacity_ah: f32,
+}
+
+/// The state-of-health trend of the installed battery, written to the flash on every new estimate
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct HealthTrend {
+    /// The first internal resistance estimate of the battery in milliohms
+    pub baseline_resistance_mohm: Option<f32>,
+    /// The number of full cycles measured so far
+    pub cycles: u16,
+    /// The latest estimates, the oldest first
+    pub entries: heapless::Vec<HealthTrendEntry, HEALTH_TREND_SIZE>,
+}
+
+impl HealthTrend {
+    pub const fn new() -> Self {
+        Self {
+            baseline_resistance_mohm: None,
+            cycles: 0,
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Records the estimates of a finished full cycle, dropping the oldest entry when the trend is full
+    pub fn record(&mut self, resistance_mohm: Option<f32>, capacity_ah: f32) {
+        self.cycles = self.cycles.saturating_add(1);
+        if self.entries.is_full() {
+            self.entries.remove(0);
+        }
+        let entry = HealthTrendEntry {
+            cycle: self.cycles,
+            resistance_mohm,
+            capacity_ah,
+        };
+        self.entries.push(entry).ok();
+    }
+
+    /// The usable capacity measured in the last full cycle
+    pub fn latest_capacity_ah(&self) -> Option<f32> {
+        self.entries.last().map(|entry| entry.capacity_ah)
+    }
+}
+
+impl Default for HealthTrend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+use crate::outputs::OutputId;
+
+/// Maximum number of loads the low-voltage disconnect can shed
+pub const MAX_SHED_LOADS: usize = 8;
+
+const DEFAULT_RECONNECT_VOLTAGE: f32 = 12.6; // Volts
+const DEFAULT_DISCONNECT_DELAY_S: u32 = 30; // Seconds
+const DEFAULT_RECONNECT_DELAY_S: u32 = 120; // Seconds
+const DEFAULT_STAGE_DELAY_S: u32 = 10; // Seconds
+/// The least important loads go first, the PoE powered network equipment stays up the longest
+const DEFAULT_SHED_ORDER: [OutputId; 5] = [
+    OutputId::Adjustable,
+    OutputId::Dc2,
+    OutputId::Dc1,
+    OutputId::Bypass,
+    OutputId::Poe,
+];
+
+/// Low-voltage disconnect settings. The disconnect threshold is the LVD voltage of the battery profile.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct LvdSettings {
+    pub enabled: bool,
+    /// The battery voltage above which the loads are reconnected, in volts
+    pub reconnect_voltage: f32,
+    /// How long the battery has to stay low before a load is shed, in seconds
+    pub disconnect_delay_s: u32,
+    /// How long the battery has to stay above the reconnect voltage before the loads are restored, in seconds
+    pub reconnect_delay_s: u32,
+    /// The delay between shedding or restoring two consecutive loads, in seconds
+    pub stage_delay_s: u32,
+    /// The outputs in the order they are shed
+    pub shed_order: heapless::Vec<OutputId, MAX_SHED_LOADS>,
+}
+
+impl LvdSettings {
+    pub const fn new() -> Self {
+        Self {
+            enabled: true,
+            reconnect_voltage: DEFAULT_RECONNECT_VOLTAGE,
+            disconnect_delay_s: DEFAULT_DISCONNECT_DELAY_S,
+            reconnect_delay_s: DEFAULT_RECONNECT_DELAY_S,
+            stage_delay_s: DEFAULT_STAGE_DELAY_S,
+            shed_order: heapless::Vec::new(),
+        }
+    }
+
+    /// Checks that the reconnect voltage leaves a hysteresis above the disconnect voltage
+    /// and that no load appears twice in the shed order.
+    pub fn is_valid(&self, disconnect_voltage: f32) -> bool {
+        let unique_loads = self
+            .shed_order
+            .iter()
+            .enumerate()
+            .all(|(i, load)| !self.shed_order[..i].contains(load));
+
+        self.reconnect_voltage > disconnect_voltage && unique_loads
+    }
+}
+
+impl Default for LvdSettings {
+    fn default() -> Self {
+        let mut settings = Self::new();
+        settings.shed_order = heapless::Vec::from_slice(&DEFAULT_SHED_ORDER).unwrap_or_default();
+        settings
+    }
+}
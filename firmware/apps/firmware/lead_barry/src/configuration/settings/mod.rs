@@ -1,18 +1,48 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+mod battery_profile;
+mod calibration_settings;
+mod channel_map;
+mod cycle_stats;
+mod efuse_settings;
+mod energy_totals;
+mod equalization_settings;
+mod health_settings;
+mod lvd_settings;
 mod network_settings;
+mod outputs_settings;
+mod poe_watchdog_settings;
 mod static_ip_config;
+mod temperature_settings;
+mod vcp_settings;
 mod wifi_ap_settings;
 mod wifi_settings;
 
 use serde::{Deserialize, Serialize};
 
+pub use battery_profile::*;
+pub use calibration_settings::*;
+pub use channel_map::*;
+pub use cycle_stats::*;
+pub use efuse_settings::*;
+pub use energy_totals::*;
+pub use equalization_settings::*;
+pub use health_settings::*;
+pub use lvd_settings::*;
 pub use network_settings::*;
+pub use outputs_settings::*;
+pub use poe_watchdog_settings::*;
 pub use static_ip_config::*;
+pub use temperature_settings::*;
+pub use vcp_settings::*;
 pub use wifi_ap_settings::*;
 pub use wifi_settings::*;
 
+/// The version of the settings layout. Bump it whenever a field of the settings is added, removed
+/// or changed, so the settings saved by another firmware are migrated instead of being lost.
+pub const SETTINGS_VERSION: u32 = 14;
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[defmt_or_log::derive_format_or_debug]
 #[non_exhaustive]
@@ -20,14 +50,46 @@ pub struct Settings {
     pub network_settings: NetworkSettings,
     pub settings_version: u32,
     pub fallback_ap: bool,
+    pub battery_profile: BatteryProfile,
+    pub lvd_settings: LvdSettings,
+    pub outputs_settings: OutputsSettings,
+    pub poe_watchdog_settings: PoeWatchdogSettings,
+    pub efuse_settings: EFuseSettings,
+    pub vcp_settings: VcpSettings,
+    pub channel_map: ChannelMap,
+    pub temperature_settings: TemperatureSettings,
+    pub health_settings: HealthSettings,
+    pub equalization_settings: EqualizationSettings,
+    pub calibration_settings: CalibrationSettings,
 }
 
 impl Settings {
     pub const fn new() -> Self {
         Self {
             network_settings: NetworkSettings::new(),
-            settings_version: 1,
+            settings_version: SETTINGS_VERSION,
             fallback_ap: false,
+            battery_profile: BatteryProfile::new(),
+            lvd_settings: LvdSettings::new(),
+            outputs_settings: OutputsSettings::new(),
+            poe_watchdog_settings: PoeWatchdogSettings::new(),
+            efuse_settings: EFuseSettings::new(),
+            vcp_settings: VcpSettings::new(),
+            channel_map: ChannelMap::new(),
+            temperature_settings: TemperatureSettings::new(),
+            health_settings: HealthSettings::new(),
+            equalization_settings: EqualizationSettings::new(),
+            calibration_settings: CalibrationSettings::new(),
+        }
+    }
+
+    /// Migrates the settings of another layout. Only the network settings are kept, the rest falls
+    /// back to the defaults.
+    pub(crate) fn migrate(header: SettingsHeader) -> Self {
+        Self {
+            network_settings: header.network_settings,
+            fallback_ap: header.fallback_ap,
+            ..Self::default()
         }
     }
 }
@@ -36,8 +98,27 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             network_settings: NetworkSettings::default(),
-            settings_version: 1,
+            settings_version: SETTINGS_VERSION,
             fallback_ap: false,
+            battery_profile: BatteryProfile::default(),
+            lvd_settings: LvdSettings::default(),
+            outputs_settings: OutputsSettings::default(),
+            poe_watchdog_settings: PoeWatchdogSettings::default(),
+            efuse_settings: EFuseSettings::default(),
+            vcp_settings: VcpSettings::default(),
+            channel_map: ChannelMap::default(),
+            temperature_settings: TemperatureSettings::default(),
+            health_settings: HealthSettings::default(),
+            equalization_settings: EqualizationSettings::default(),
+            calibration_settings: CalibrationSettings::default(),
         }
     }
 }
+
+/// The fields every settings layout starts with. The layout of any version can be read this far.
+#[derive(Deserialize)]
+pub(crate) struct SettingsHeader {
+    pub network_settings: NetworkSettings,
+    pub settings_version: u32,
+    pub fallback_ap: bool,
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::outputs::{OUTPUT_COUNT, OutputId, OutputVoltage};
+
+/// The state an output is switched to at boot
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct OutputDefault {
+    pub output: OutputId,
+    pub enabled: bool,
+    pub voltage: OutputVoltage,
+}
+
+impl OutputDefault {
+    pub const fn new(output: OutputId) -> Self {
+        Self {
+            output,
+            enabled: false,
+            voltage: output.default_voltage(),
+        }
+    }
+}
+
+/// The boot state of the outputs, indexed by the output id
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct OutputsSettings {
+    pub outputs: [OutputDefault; OUTPUT_COUNT],
+}
+
+impl OutputsSettings {
+    pub const fn new() -> Self {
+        Self {
+            outputs: [
+                OutputDefault::new(OutputId::Dc1),
+                OutputDefault::new(OutputId::Dc2),
+                OutputDefault::new(OutputId::Adjustable),
+                OutputDefault::new(OutputId::Poe),
+                OutputDefault::new(OutputId::Bypass),
+            ],
+        }
+    }
+
+    pub fn output(&self, output: OutputId) -> &OutputDefault {
+        &self.outputs[output.index()]
+    }
+
+    /// Checks that every output is at its own index and supports its default voltage
+    pub fn is_valid(&self) -> bool {
+        self.outputs
+            .iter()
+            .zip(OutputId::ALL)
+            .all(|(default, output)| default.output == output && output.supports(&default.voltage))
+    }
+}
+
+impl Default for OutputsSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,86 @@
+use embassy_net::Ipv4Address;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_TARGET_PORT: u16 = 80;
+const DEFAULT_PROBE_INTERVAL_S: u32 = 60; // Seconds
+const DEFAULT_PROBE_TIMEOUT_S: u32 = 5; // Seconds
+const DEFAULT_FAILURE_THRESHOLD: u8 = 5;
+const DEFAULT_POWER_OFF_S: u32 = 10; // Seconds
+const DEFAULT_BOOT_GRACE_S: u32 = 180; // Seconds
+const DEFAULT_MAX_BACKOFF_S: u32 = 3600; // Seconds
+const DEFAULT_MAX_CYCLES_PER_DAY: u8 = 6;
+
+/// How the PoE powered device is checked for being alive
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum PoeProbeMethod {
+    /// The device is alive when it accepts a TCP connection on the target port
+    Tcp,
+    /// The device is alive when it answers a UDP datagram sent to the target port (e.g. a DNS query to port 53)
+    Udp,
+}
+
+/// Settings of the watchdog which power-cycles a hung PoE powered device
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct PoeWatchdogSettings {
+    pub enabled: bool,
+    /// IPv4 address of the probed device
+    pub target_ip: u32,
+    pub target_port: u16,
+    pub probe_method: PoeProbeMethod,
+    /// The period of the probes, in seconds
+    pub probe_interval_s: u32,
+    /// How long to wait for the device to answer a probe, in seconds
+    pub probe_timeout_s: u32,
+    /// Number of consecutive failed probes which trigger a power cycle
+    pub failure_threshold: u8,
+    /// How long the PoE output stays off during a power cycle, in seconds
+    pub power_off_s: u32,
+    /// How long the device is given to boot after a power cycle, in seconds. Doubles with every unsuccessful cycle.
+    pub boot_grace_s: u32,
+    /// The upper bound of the boot grace time, in seconds
+    pub max_backoff_s: u32,
+    /// Maximum number of power cycles within 24 hours
+    pub max_cycles_per_day: u8,
+}
+
+impl PoeWatchdogSettings {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            target_ip: Ipv4Address::UNSPECIFIED.to_bits(),
+            target_port: DEFAULT_TARGET_PORT,
+            probe_method: PoeProbeMethod::Tcp,
+            probe_interval_s: DEFAULT_PROBE_INTERVAL_S,
+            probe_timeout_s: DEFAULT_PROBE_TIMEOUT_S,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            power_off_s: DEFAULT_POWER_OFF_S,
+            boot_grace_s: DEFAULT_BOOT_GRACE_S,
+            max_backoff_s: DEFAULT_MAX_BACKOFF_S,
+            max_cycles_per_day: DEFAULT_MAX_CYCLES_PER_DAY,
+        }
+    }
+
+    pub fn target(&self) -> Ipv4Address {
+        Ipv4Address::from_bits(self.target_ip)
+    }
+
+    /// Checks that an enabled watchdog has a target and that the probes fit into the probe interval
+    pub fn is_valid(&self) -> bool {
+        let has_target = !self.target().is_unspecified() && self.target_port != 0;
+
+        (!self.enabled || has_target)
+            && self.probe_timeout_s > 0
+            && self.probe_timeout_s < self.probe_interval_s
+            && self.failure_threshold > 0
+            && self.power_off_s > 0
+    }
+}
+
+impl Default for PoeWatchdogSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_COEFFICIENT_MV_PER_CELL: f32 = -4.0; // mV/°C per cell
+/// The range of the lead-acid compensation coefficients, in mV/°C per cell
+const COEFFICIENT_RANGE_MV_PER_CELL: core::ops::RangeInclusive<f32> = -6.0..=0.0;
+
+/// Where the battery temperature is measured
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum TemperatureSource {
+    /// The sensor of the DS3231 real-time clock on the board
+    Rtc,
+    /// An NTC thermistor attached to the battery. The RTC is used while the probe is disconnected.
+    Probe,
+}
+
+/// Temperature compensation of the battery voltage setpoints
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct TemperatureSettings {
+    pub enabled: bool,
+    pub source: TemperatureSource,
+    /// The setpoint shift per degree above 25°C, in millivolts per cell
+    pub coefficient_mv_per_cell: f32,
+}
+
+impl TemperatureSettings {
+    pub const fn new() -> Self {
+        Self {
+            enabled: true,
+            source: TemperatureSource::Rtc,
+            coefficient_mv_per_cell: DEFAULT_COEFFICIENT_MV_PER_CELL,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        COEFFICIENT_RANGE_MV_PER_CELL.contains(&self.coefficient_mv_per_cell)
+    }
+}
+
+impl Default for TemperatureSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
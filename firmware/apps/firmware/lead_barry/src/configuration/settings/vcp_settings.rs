@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::{MAX_VCP_CHIPS, VcpSampling};
+
+/// The address of an INA3221 with A0 tied to GND
+const DEFAULT_CHIP_ADDRESS: u8 = 0x40;
+/// The addresses an INA3221 can be strapped to
+const MIN_CHIP_ADDRESS: u8 = 0x40;
+const MAX_CHIP_ADDRESS: u8 = 0x43;
+
+/// VCP sensors settings
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+#[non_exhaustive]
+pub struct VcpSettings {
+    /// Averaging, conversion times and poll period of the sensor
+    pub sampling: VcpSampling,
+    /// The I2C addresses of the INA3221 chips. The first chip measures the channels 0-2, the second
+    /// one the channels 3-5. Applied at the next boot.
+    pub chip_addresses: heapless::Vec<u8, MAX_VCP_CHIPS>,
+}
+
+impl VcpSettings {
+    pub const fn new() -> Self {
+        Self {
+            sampling: VcpSampling::new(),
+            chip_addresses: heapless::Vec::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.sampling.is_valid() && self.are_chip_addresses_valid()
+    }
+
+    /// Checks that there is at least one chip and that the addresses are INA3221 ones, each used once
+    pub fn are_chip_addresses_valid(&self) -> bool {
+        !self.chip_addresses.is_empty()
+            && self.chip_addresses.iter().enumerate().all(|(i, address)| {
+                (MIN_CHIP_ADDRESS..=MAX_CHIP_ADDRESS).contains(address) && !self.chip_addresses[..i].contains(address)
+            })
+    }
+
+    /// The addresses to set up the sensor with, the single default chip if the configured ones are not valid
+    pub fn fitted_chips(&self) -> &[u8] {
+        if self.are_chip_addresses_valid() {
+            &self.chip_addresses
+        } else {
+            &[DEFAULT_CHIP_ADDRESS]
+        }
+    }
+}
+
+impl Default for VcpSettings {
+    fn default() -> Self {
+        let mut settings = Self::new();
+        settings.chip_addresses.push(DEFAULT_CHIP_ADDRESS).ok();
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chip_addresses() {
+        let mut settings = VcpSettings::default();
+        assert!(settings.is_valid());
+        assert_eq!(settings.fitted_chips(), &[0x40]);
+
+        settings.chip_addresses.push(0x41).unwrap();
+        assert!(settings.is_valid());
+        assert_eq!(settings.fitted_chips(), &[0x40, 0x41]);
+
+        settings.chip_addresses[1] = 0x40;
+        assert!(!settings.is_valid());
+        assert_eq!(settings.fitted_chips(), &[0x40]);
+
+        settings.chip_addresses[1] = 0x44;
+        assert!(!settings.is_valid());
+    }
+
+    #[test]
+    fn test_new_settings_fall_back_to_one_chip() {
+        let settings = VcpSettings::new();
+        assert!(!settings.are_chip_addresses_valid());
+        assert_eq!(settings.fitted_chips(), &[DEFAULT_CHIP_ADDRESS]);
+    }
+}
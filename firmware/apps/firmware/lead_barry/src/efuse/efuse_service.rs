@@ -0,0 +1,337 @@
+use defmt_or_log as log;
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, Receiver, Sender},
+    mutex::Mutex,
+    pubsub::WaitResult,
+    signal::Signal,
+};
+use embassy_time::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::{EFuseSettings, MAX_FUSES};
+use crate::efuse::fuse_state_machine::*;
+use crate::outputs::{OutputId, OutputsControl};
+use crate::vcp_sensors::{ChannelNum, VcpAlertSubscriber, VcpControl, VcpReadingSubscriber};
+
+/// Number of the latest trip events kept for the web API
+pub const TRIP_LOG_SIZE: usize = 8;
+const COMMAND_QUEUE_SIZE: usize = 2;
+/// How long an output has to run without overcurrent before its retries are forgiven
+const RECOVERY_MS: u64 = 60_000;
+
+type EFuseCommandChannel = Channel<CriticalSectionRawMutex, EFuseCommand, COMMAND_QUEUE_SIZE>;
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+enum EFuseCommand {
+    Reset(OutputId),
+}
+
+/// A request to re-arm the fuse of an output
+#[derive(Copy, Clone, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct FuseReset {
+    pub output: OutputId,
+}
+
+/// A fuse trip kept in the trip log
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct FuseTripRecord {
+    pub output: OutputId,
+    pub channel: ChannelNum,
+    /// The current which tripped the fuse in amps
+    pub current: f32,
+    /// `true` if the fuse has tripped permanently
+    pub locked: bool,
+    /// Seconds since boot
+    pub uptime_s: u32,
+}
+
+/// The latest state of a single fuse
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct FuseStatus {
+    pub channel: ChannelNum,
+    pub output: OutputId,
+    pub state: FuseState,
+    /// Trips since the output last ran fine
+    pub attempts: u8,
+    /// Trips since boot
+    pub trips: u32,
+}
+
+/// The latest electronic fuse state published for the display and the web API
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EFuseStatus {
+    pub fuses: heapless::Vec<FuseStatus, MAX_FUSES>,
+    /// The latest trips, the oldest first
+    pub trips: heapless::Vec<FuseTripRecord, TRIP_LOG_SIZE>,
+}
+
+impl EFuseStatus {
+    pub const fn new() -> Self {
+        Self {
+            fuses: heapless::Vec::new(),
+            trips: heapless::Vec::new(),
+        }
+    }
+}
+
+impl Default for EFuseStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EFuseChannelConfig {
+    pub channel: ChannelNum,
+    pub output: OutputId,
+    pub fuse: FuseConfig,
+}
+
+#[derive(Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EFuseConfig {
+    /// The enabled fuses
+    pub fuses: heapless::Vec<EFuseChannelConfig, MAX_FUSES>,
+}
+
+impl EFuseConfig {
+    pub fn from_settings(settings: &EFuseSettings) -> Self {
+        let fuses = settings
+            .fuses
+            .iter()
+            .filter(|fuse| fuse.enabled)
+            .map(|fuse| EFuseChannelConfig {
+                channel: fuse.channel,
+                output: fuse.output,
+                fuse: FuseConfig {
+                    trip_delay_ms: fuse.trip_delay_ms as u64,
+                    cooldown_ms: fuse.cooldown_s as u64 * 1000,
+                    max_retries: fuse.max_retries,
+                    recovery_ms: RECOVERY_MS,
+                },
+            })
+            .collect();
+        Self { fuses }
+    }
+}
+
+pub struct EFuseServiceState {
+    status: Mutex<CriticalSectionRawMutex, EFuseStatus>,
+    commands: EFuseCommandChannel,
+    trip: Signal<CriticalSectionRawMutex, FuseTripRecord>,
+}
+
+impl EFuseServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(EFuseStatus::new()),
+            commands: EFuseCommandChannel::new(),
+            trip: Signal::new(),
+        }
+    }
+}
+
+struct ChannelFuse {
+    config: EFuseChannelConfig,
+    fuse: Fuse,
+    trips: u32,
+    /// The latest current of the channel in amps
+    current: f32,
+}
+
+pub struct EFuseRunner<'a> {
+    readings: VcpReadingSubscriber<'a>,
+    alerts: VcpAlertSubscriber<'a>,
+    state: &'a EFuseServiceState,
+    outputs_control: &'a OutputsControl<'a>,
+    command_receiver: Receiver<'a, CriticalSectionRawMutex, EFuseCommand, COMMAND_QUEUE_SIZE>,
+    fuses: heapless::Vec<ChannelFuse, MAX_FUSES>,
+}
+
+pub struct EFuseControl<'a> {
+    state: &'a EFuseServiceState,
+    command_sender: Sender<'a, CriticalSectionRawMutex, EFuseCommand, COMMAND_QUEUE_SIZE>,
+}
+
+#[allow(dead_code)]
+impl<'a> EFuseControl<'a> {
+    /// Returns the latest state of the fuses and the trip log
+    pub async fn status(&self) -> EFuseStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Re-arms the fuse of the output and switches the output back on if the fuse has tripped
+    pub async fn reset(&self, output: OutputId) {
+        self.command_sender.send(EFuseCommand::Reset(output)).await
+    }
+
+    /// Waits for the next fuse trip
+    pub async fn wait_trip(&self) -> FuseTripRecord {
+        self.state.trip.wait().await
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EFuseService(());
+
+impl EFuseService {
+    /// Creates a new electronic fuse instance fed by the readings of the VCP sensors
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        vcp_control: &'a VcpControl<'a>,
+        outputs_control: &'a OutputsControl<'a>,
+        state: &'a mut EFuseServiceState,
+        config: EFuseConfig,
+    ) -> (EFuseRunner<'a>, EFuseControl<'a>) {
+        let state: &'a EFuseServiceState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the e-fuse");
+        let alerts = vcp_control
+            .subscribe_alerts()
+            .expect("No free VCP alerts subscriber for the e-fuse");
+        let fuses = config
+            .fuses
+            .iter()
+            .map(|config| ChannelFuse {
+                config: *config,
+                fuse: Fuse::new(config.fuse),
+                trips: 0,
+                current: 0.0,
+            })
+            .collect();
+        (
+            EFuseRunner {
+                readings,
+                alerts,
+                state,
+                outputs_control,
+                command_receiver: state.commands.receiver(),
+                fuses,
+            },
+            EFuseControl {
+                state,
+                command_sender: state.commands.sender(),
+            },
+        )
+    }
+}
+
+impl<'a> EFuseRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        self.publish_status(None).await;
+        loop {
+            let event = select3(
+                self.readings.next_message(),
+                self.alerts.next_message_pure(),
+                self.command_receiver.receive(),
+            )
+            .await;
+            match event {
+                Either3::First(WaitResult::Lagged(missed)) => {
+                    log::warn!("E-fuse missed {} readings", missed);
+                }
+                Either3::First(WaitResult::Message(reading)) => {
+                    let now_ms = Instant::now().as_millis();
+                    let Some(index) = self.fuse_index(|fuse| fuse.config.channel == reading.channel) else {
+                        continue;
+                    };
+                    self.fuses[index].current = reading.current.value();
+                    let action = self.fuses[index].fuse.update(reading.current.is_high(), now_ms);
+                    if let Some(action) = action {
+                        self.apply(index, action).await;
+                    }
+                }
+                Either3::Second(flags) => {
+                    let now_ms = Instant::now().as_millis();
+                    for index in 0..self.fuses.len() {
+                        if !flags.critical[self.fuses[index].config.channel as usize] {
+                            continue;
+                        }
+                        if let Some(action) = self.fuses[index].fuse.critical_alert(now_ms) {
+                            self.apply(index, action).await;
+                        }
+                    }
+                }
+                Either3::Third(EFuseCommand::Reset(output)) => {
+                    let Some(index) = self.fuse_index(|fuse| fuse.config.output == output) else {
+                        log::warn!("No e-fuse on output {}", output.name());
+                        continue;
+                    };
+                    if self.fuses[index].fuse.reset(Instant::now().as_millis()) {
+                        log::info!("E-fuse of {} reset", output.name());
+                        self.outputs_control.set_fuse_tripped(output, false).await;
+                    }
+                    self.publish_status(None).await;
+                }
+            }
+        }
+    }
+
+    fn fuse_index(&self, predicate: impl Fn(&ChannelFuse) -> bool) -> Option<usize> {
+        self.fuses.iter().position(predicate)
+    }
+
+    /// Applies the action of the fuse to its output and records the trips
+    async fn apply(&mut self, index: usize, action: FuseAction) {
+        let fuse = &mut self.fuses[index];
+        let output = fuse.config.output;
+        let current = fuse.current;
+        match action {
+            FuseAction::Trip | FuseAction::Lock => {
+                fuse.trips += 1;
+                let record = FuseTripRecord {
+                    output,
+                    channel: fuse.config.channel,
+                    current,
+                    locked: action == FuseAction::Lock,
+                    uptime_s: Instant::now().as_secs() as u32,
+                };
+                log::warn!(
+                    "E-fuse of {} tripped at {} A (attempt {}, {})",
+                    output.name(),
+                    current,
+                    fuse.fuse.attempts(),
+                    fuse.fuse.state().name()
+                );
+                self.outputs_control.set_fuse_tripped(output, true).await;
+                self.state.trip.signal(record);
+                self.publish_status(Some(record)).await;
+            }
+            FuseAction::Retry => {
+                log::info!("E-fuse of {} retrying", output.name());
+                self.outputs_control.set_fuse_tripped(output, false).await;
+                self.publish_status(None).await;
+            }
+        }
+    }
+
+    async fn publish_status(&self, trip: Option<FuseTripRecord>) {
+        let mut status = self.state.status.lock().await;
+        status.fuses = self
+            .fuses
+            .iter()
+            .map(|fuse| FuseStatus {
+                channel: fuse.config.channel,
+                output: fuse.config.output,
+                state: fuse.fuse.state(),
+                attempts: fuse.fuse.attempts(),
+                trips: fuse.trips,
+            })
+            .collect();
+        if let Some(trip) = trip {
+            if status.trips.is_full() {
+                status.trips.remove(0);
+            }
+            status.trips.push(trip).ok();
+        }
+    }
+}
@@ -0,0 +1,240 @@
+#![allow(dead_code)]
+
+//! Electronic fuse state machine. A sustained overcurrent trips the output off for a cool-down, and
+//! after too many retries the fuse stays off until it is reset by hand.
+
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum FuseState {
+    /// The output is allowed to run
+    Armed,
+    /// The fuse has tripped and the output is off until the cool-down ends
+    CoolingDown,
+    /// The fuse has tripped too many times and the output is off until a manual reset
+    Locked,
+}
+
+impl FuseState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            FuseState::Armed => "Armed",
+            FuseState::CoolingDown => "Cooling",
+            FuseState::Locked => "Locked",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum FuseAction {
+    /// Switch the output off for the cool-down
+    Trip,
+    /// Switch the output back on after the cool-down
+    Retry,
+    /// Switch the output off for good
+    Lock,
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct FuseConfig {
+    /// How long the overcurrent has to last before the fuse trips
+    pub trip_delay_ms: u64,
+    /// How long the output stays off before a retry
+    pub cooldown_ms: u64,
+    /// Number of retries before the fuse trips permanently
+    pub max_retries: u8,
+    /// How long the output has to run without overcurrent before the retries are forgiven
+    pub recovery_ms: u64,
+}
+
+pub struct Fuse {
+    config: FuseConfig,
+    state: FuseState,
+    overcurrent_since: Option<u64>,
+    /// The time of the last trip or retry
+    changed_at_ms: u64,
+    /// Trips since the output last ran fine for the recovery time
+    attempts: u8,
+}
+
+impl Fuse {
+    pub const fn new(config: FuseConfig) -> Self {
+        Self {
+            config,
+            state: FuseState::Armed,
+            overcurrent_since: None,
+            changed_at_ms: 0,
+            attempts: 0,
+        }
+    }
+
+    pub fn state(&self) -> FuseState {
+        self.state
+    }
+
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
+    /// Processes a reading taken at `now_ms` and returns the action to apply, if any
+    pub fn update(&mut self, overcurrent: bool, now_ms: u64) -> Option<FuseAction> {
+        match self.state {
+            FuseState::Armed if overcurrent => {
+                let since = *self.overcurrent_since.get_or_insert(now_ms);
+                if now_ms.saturating_sub(since) >= self.config.trip_delay_ms {
+                    Some(self.trip(now_ms))
+                } else {
+                    None
+                }
+            }
+            FuseState::Armed => {
+                self.overcurrent_since = None;
+                if self.attempts > 0 && now_ms.saturating_sub(self.changed_at_ms) >= self.config.recovery_ms {
+                    self.attempts = 0;
+                }
+                None
+            }
+            FuseState::CoolingDown if now_ms.saturating_sub(self.changed_at_ms) >= self.config.cooldown_ms => {
+                self.state = FuseState::Armed;
+                self.changed_at_ms = now_ms;
+                Some(FuseAction::Retry)
+            }
+            FuseState::CoolingDown | FuseState::Locked => None,
+        }
+    }
+
+    /// Trips the fuse at once, e.g. on the hardware critical alert of the sensor
+    pub fn critical_alert(&mut self, now_ms: u64) -> Option<FuseAction> {
+        match self.state {
+            FuseState::Armed => Some(self.trip(now_ms)),
+            FuseState::CoolingDown | FuseState::Locked => None,
+        }
+    }
+
+    /// Re-arms a tripped or locked fuse. Returns `true` if the output has to be switched back on.
+    pub fn reset(&mut self, now_ms: u64) -> bool {
+        let was_tripped = self.state != FuseState::Armed;
+        self.state = FuseState::Armed;
+        self.overcurrent_since = None;
+        self.changed_at_ms = now_ms;
+        self.attempts = 0;
+        was_tripped
+    }
+
+    fn trip(&mut self, now_ms: u64) -> FuseAction {
+        self.overcurrent_since = None;
+        self.changed_at_ms = now_ms;
+        self.attempts = self.attempts.saturating_add(1);
+        if self.attempts > self.config.max_retries {
+            self.state = FuseState::Locked;
+            FuseAction::Lock
+        } else {
+            self.state = FuseState::CoolingDown;
+            FuseAction::Trip
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::*;
+
+    const READING_PERIOD_MS: u64 = 100;
+
+    fn config() -> FuseConfig {
+        FuseConfig {
+            trip_delay_ms: 500,
+            cooldown_ms: 5_000,
+            max_retries: 2,
+            recovery_ms: 60_000,
+        }
+    }
+
+    /// Feeds the fuse with the given overcurrent flag for the given duration and collects the actions
+    fn feed(
+        fuse: &mut Fuse,
+        now_ms: &mut u64,
+        overcurrent: bool,
+        duration_ms: u64,
+    ) -> heapless::Vec<FuseAction, MAX_STEP_RESULTS> {
+        step_for(now_ms, READING_PERIOD_MS, duration_ms, |now_ms| {
+            fuse.update(overcurrent, now_ms)
+        })
+    }
+
+    #[test]
+    fn test_short_inrush_does_not_trip() {
+        let mut fuse = Fuse::new(config());
+        let mut now_ms = 0;
+
+        assert!(feed(&mut fuse, &mut now_ms, true, 400).is_empty());
+        assert!(feed(&mut fuse, &mut now_ms, false, 100).is_empty());
+        assert!(feed(&mut fuse, &mut now_ms, true, 500).is_empty());
+        assert_eq!(fuse.state(), FuseState::Armed);
+    }
+
+    #[test]
+    fn test_sustained_overcurrent_trips_and_retries() {
+        let mut fuse = Fuse::new(config());
+        let mut now_ms = 0;
+
+        assert_eq!(feed(&mut fuse, &mut now_ms, true, 600).as_slice(), &[FuseAction::Trip]);
+        assert_eq!(fuse.state(), FuseState::CoolingDown);
+
+        // The output is off, so there is no current during the cool-down
+        assert!(feed(&mut fuse, &mut now_ms, false, 4_900).is_empty());
+        assert_eq!(
+            feed(&mut fuse, &mut now_ms, false, 100).as_slice(),
+            &[FuseAction::Retry]
+        );
+        assert_eq!(fuse.state(), FuseState::Armed);
+        assert_eq!(fuse.attempts(), 1);
+    }
+
+    #[test]
+    fn test_fuse_locks_after_max_retries() {
+        let mut fuse = Fuse::new(config());
+        let mut now_ms = 0;
+
+        for _ in 0..2 {
+            assert_eq!(feed(&mut fuse, &mut now_ms, true, 600).as_slice(), &[FuseAction::Trip]);
+            assert_eq!(
+                feed(&mut fuse, &mut now_ms, false, 5_000).as_slice(),
+                &[FuseAction::Retry]
+            );
+        }
+        assert_eq!(feed(&mut fuse, &mut now_ms, true, 600).as_slice(), &[FuseAction::Lock]);
+        assert_eq!(fuse.state(), FuseState::Locked);
+        assert!(feed(&mut fuse, &mut now_ms, false, 600_000).is_empty());
+
+        assert!(fuse.reset(now_ms));
+        assert_eq!(fuse.state(), FuseState::Armed);
+        assert_eq!(fuse.attempts(), 0);
+    }
+
+    #[test]
+    fn test_retries_are_forgiven_after_recovery() {
+        let mut fuse = Fuse::new(config());
+        let mut now_ms = 0;
+
+        feed(&mut fuse, &mut now_ms, true, 600);
+        feed(&mut fuse, &mut now_ms, false, 5_000);
+        assert_eq!(fuse.attempts(), 1);
+
+        feed(&mut fuse, &mut now_ms, false, 60_000);
+        assert_eq!(fuse.attempts(), 0);
+    }
+
+    #[test]
+    fn test_critical_alert_trips_at_once() {
+        let mut fuse = Fuse::new(config());
+
+        assert_eq!(fuse.critical_alert(100), Some(FuseAction::Trip));
+        assert_eq!(fuse.critical_alert(200), None);
+        assert_eq!(fuse.state(), FuseState::CoolingDown);
+    }
+}
@@ -0,0 +1,7 @@
+#![allow(unused_imports)]
+
+mod efuse_service;
+mod fuse_state_machine;
+
+pub use self::efuse_service::*;
+pub use self::fuse_state_machine::*;
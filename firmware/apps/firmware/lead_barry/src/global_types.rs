@@ -1,12 +1,40 @@
-use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
-use embassy_rp::{
-    i2c::{self, I2c},
-    peripherals::{I2C0, I2C1},
-};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
-
-// Global types
-pub type I2c0Bus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C0, i2c::Async>>;
-pub type I2c1Bus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C1, i2c::Async>>;
-pub type I2c0Device<'a> = I2cDevice<'a, CriticalSectionRawMutex, I2c<'a, I2C0, i2c::Async>>;
-pub type I2c1Device<'a> = I2cDevice<'a, CriticalSectionRawMutex, I2c<'a, I2C1, i2c::Async>>;
+#[cfg(not(feature = "sim"))]
+pub use self::board_types::*;
+#[cfg(feature = "sim")]
+pub use crate::sim::types::*;
+
+/// The devices of the board. The host simulation replaces them with its own ones of the same names.
+#[cfg(not(feature = "sim"))]
+mod board_types {
+    use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+    use embassy_rp::{
+        i2c::{self, I2c},
+        peripherals::{I2C0, I2C1},
+    };
+    use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+
+    use crate::configuration::{CountersStorage, HistoryStorage, Storage};
+    use crate::i2c_bus_clear::I2c0BusClear;
+    use crate::input::GpioButton;
+    use crate::outputs::ShiftRegisterOutputDriver;
+    use crate::temperature::NtcProbe;
+    use crate::ui::Ssd1306Display;
+    use crate::vcp_sensors::Ina3221Sensor;
+
+    // Global types
+    pub type I2c0Bus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C0, i2c::Async>>;
+    pub type I2c1Bus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C1, i2c::Async>>;
+    pub type I2c0Device<'a> = I2cDevice<'a, CriticalSectionRawMutex, I2c<'a, I2C0, i2c::Async>>;
+    pub type I2c1Device<'a> = I2cDevice<'a, CriticalSectionRawMutex, I2c<'a, I2C1, i2c::Async>>;
+
+    /// The I2C device of the DS3231 real-time clock
+    pub type RtcI2cDevice = I2c0Device<'static>;
+    pub type VcpSensorDevice = Ina3221Sensor<I2c0Device<'static>, I2c0BusClear>;
+    pub type DisplayDevice = Ssd1306Display<I2c1Device<'static>>;
+    pub type FlashDevice = Storage<'static>;
+    pub type CountersFlashDevice = CountersStorage<'static>;
+    pub type HistoryFlashDevice = HistoryStorage<'static>;
+    pub type OutputDriverDevice = ShiftRegisterOutputDriver;
+    pub type TemperatureProbeDevice = NtcProbe<'static>;
+    pub type ButtonInputDevice = GpioButton;
+}
@@ -0,0 +1,234 @@
+//! Downsampling of the readings into the history records.
+//!
+//! The readings of every channel are summarized into the minimum, the average and the maximum of the
+//! voltage and of the current over the intervals of the resolution. The intervals are aligned to the
+//! RTC time, so the minute records start at full minutes and the hour records at full hours. A record
+//! is finished by the first reading of the following interval.
+
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::{ChannelNum, VCP_CHANNELS};
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum HistoryResolution {
+    Minute,
+    Hour,
+}
+
+impl HistoryResolution {
+    pub const ALL: [HistoryResolution; 2] = [HistoryResolution::Minute, HistoryResolution::Hour];
+
+    /// The length of the intervals in seconds
+    pub const fn period_s(&self) -> u32 {
+        match self {
+            HistoryResolution::Minute => 60,
+            HistoryResolution::Hour => 3600,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            HistoryResolution::Minute => "minute",
+            HistoryResolution::Hour => "hour",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct MinAvgMax {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+}
+
+/// The summary of the readings of a channel over an interval
+#[derive(Copy, Clone, Default, PartialEq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HistoryRecord {
+    /// The start of the interval, seconds since 1970 by the RTC
+    pub timestamp_s: u32,
+    pub channel: ChannelNum,
+    pub voltage: MinAvgMax,
+    pub current: MinAvgMax,
+}
+
+impl HistoryRecord {
+    /// The column names of the rows written by [`HistoryRecord::write_csv_row`]
+    pub const CSV_HEADER: &'static str =
+        "timestamp_s,channel,voltage_min,voltage_avg,voltage_max,current_min,current_avg,current_max\n";
+
+    /// Writes the record as a CSV row, the values in mV and mA precision
+    pub fn write_csv_row(&self, out: &mut impl core::fmt::Write) -> core::fmt::Result {
+        writeln!(
+            out,
+            "{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            self.timestamp_s,
+            self.channel,
+            self.voltage.min,
+            self.voltage.avg,
+            self.voltage.max,
+            self.current.min,
+            self.current.avg,
+            self.current.max
+        )
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Accumulator {
+    min: f32,
+    max: f32,
+    sum: f32,
+}
+
+impl Accumulator {
+    const fn new(value: f32) -> Self {
+        Self {
+            min: value,
+            max: value,
+            sum: value,
+        }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+
+    fn summary(&self, count: u32) -> MinAvgMax {
+        MinAvgMax {
+            min: self.min,
+            avg: self.sum / count as f32,
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Interval {
+    start_s: u32,
+    count: u32,
+    voltage: Accumulator,
+    current: Accumulator,
+}
+
+/// Summarizes the readings of all the channels at one resolution
+pub struct HistoryAggregator {
+    resolution: HistoryResolution,
+    intervals: [Option<Interval>; VCP_CHANNELS],
+}
+
+impl HistoryAggregator {
+    pub const fn new(resolution: HistoryResolution) -> Self {
+        Self {
+            resolution,
+            intervals: [None; VCP_CHANNELS],
+        }
+    }
+
+    pub fn resolution(&self) -> HistoryResolution {
+        self.resolution
+    }
+
+    /// Adds the reading taken at `timestamp_s`. Returns the record of the previous interval of the
+    /// channel once the reading falls into another one.
+    pub fn add(&mut self, channel: ChannelNum, timestamp_s: u32, voltage: f32, current: f32) -> Option<HistoryRecord> {
+        let slot = self.intervals.get_mut(channel as usize)?;
+        let start_s = timestamp_s - timestamp_s % self.resolution.period_s();
+        match slot {
+            Some(interval) if interval.start_s == start_s => {
+                interval.count += 1;
+                interval.voltage.add(voltage);
+                interval.current.add(current);
+                None
+            }
+            _ => {
+                let finished = slot.map(|interval| HistoryRecord {
+                    timestamp_s: interval.start_s,
+                    channel,
+                    voltage: interval.voltage.summary(interval.count),
+                    current: interval.current.summary(interval.count),
+                });
+                *slot = Some(Interval {
+                    start_s,
+                    count: 1,
+                    voltage: Accumulator::new(voltage),
+                    current: Accumulator::new(current),
+                });
+                finished
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_the_readings_of_an_interval() {
+        let mut minutes = HistoryAggregator::new(HistoryResolution::Minute);
+        assert!(minutes.add(0, 120, 12.0, 1.0).is_none());
+        assert!(minutes.add(0, 150, 13.0, -1.0).is_none());
+        assert!(minutes.add(0, 179, 14.0, 3.0).is_none());
+
+        let record = minutes.add(0, 180, 12.5, 0.0).unwrap();
+        assert_eq!(record.timestamp_s, 120);
+        assert_eq!(record.channel, 0);
+        assert!(
+            record.voltage
+                == MinAvgMax {
+                    min: 12.0,
+                    avg: 13.0,
+                    max: 14.0
+                }
+        );
+        assert!(
+            record.current
+                == MinAvgMax {
+                    min: -1.0,
+                    avg: 1.0,
+                    max: 3.0
+                }
+        );
+    }
+
+    #[test]
+    fn channels_are_summarized_independently() {
+        let mut hours = HistoryAggregator::new(HistoryResolution::Hour);
+        assert!(hours.add(0, 3600, 12.0, 0.0).is_none());
+        assert!(hours.add(1, 3700, 5.0, 0.0).is_none());
+        assert!(hours.add(1, 7199, 6.0, 0.0).is_none());
+
+        let record = hours.add(0, 7200, 12.0, 0.0).unwrap();
+        assert_eq!((record.channel, record.timestamp_s), (0, 3600));
+        assert_eq!(record.voltage.max, 12.0);
+        assert!(hours.add(VCP_CHANNELS as ChannelNum, 7200, 0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn csv_row_matches_the_header() {
+        let record = HistoryRecord {
+            timestamp_s: 3600,
+            channel: 2,
+            voltage: MinAvgMax {
+                min: 12.0,
+                avg: 12.25,
+                max: 12.5,
+            },
+            current: MinAvgMax {
+                min: -1.5,
+                avg: 0.0,
+                max: 2.125,
+            },
+        };
+        let mut row = String::new();
+        record.write_csv_row(&mut row).unwrap();
+
+        assert_eq!(row, "3600,2,12.000,12.250,12.500,-1.500,0.000,2.125\n");
+        assert_eq!(row.split(',').count(), HistoryRecord::CSV_HEADER.split(',').count());
+    }
+}
@@ -0,0 +1,126 @@
+use defmt_or_log as log;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::Instant;
+
+use crate::configuration::FlashError;
+use crate::global_types::HistoryFlashDevice;
+use crate::history::aggregator::*;
+use crate::history::history_store::*;
+use crate::rtc::{RtcDs3231Ref, WallClock};
+use crate::vcp_sensors::{VcpControl, VcpReadingSubscriber};
+
+pub struct HistoryServiceState {
+    store: Mutex<CriticalSectionRawMutex, HistoryStore<HistoryFlashDevice>>,
+}
+
+impl HistoryServiceState {
+    /// Finds the records kept in the flash before the reboot
+    pub fn new(flash: HistoryFlashDevice) -> Self {
+        Self {
+            store: Mutex::new(HistoryStore::mount(flash)),
+        }
+    }
+}
+
+pub struct HistoryRunner<'a, I2C> {
+    readings: VcpReadingSubscriber<'a>,
+    rtc: &'a RtcDs3231Ref<I2C>,
+    state: &'a HistoryServiceState,
+    clock: WallClock,
+    aggregators: [HistoryAggregator; 2],
+}
+
+pub struct HistoryControl<'a> {
+    state: &'a HistoryServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> HistoryControl<'a> {
+    /// Reads the following records of the query into `records`, oldest first. Returns the number of
+    /// records read, fewer than fit only once the query is complete. The cursor keeps the position
+    /// for the next read.
+    pub async fn read(
+        &self,
+        query: &HistoryQuery,
+        cursor: &mut HistoryCursor,
+        records: &mut [HistoryRecord],
+    ) -> Result<usize, FlashError> {
+        let mut store = self.state.store.lock().await;
+        for (count, slot) in records.iter_mut().enumerate() {
+            match store.read_next(query, cursor)? {
+                Some(record) => *slot = record,
+                None => return Ok(count),
+            }
+        }
+        Ok(records.len())
+    }
+
+    /// The number of records of the resolution kept once the history has wrapped around
+    pub async fn capacity(&self, resolution: HistoryResolution) -> u32 {
+        self.state.store.lock().await.capacity(resolution)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HistoryService(());
+
+impl HistoryService {
+    /// Creates a new history recorder of the VCP readings, stamped with the RTC time
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a, I2C, E>(
+        vcp_control: &'a VcpControl<'a>,
+        rtc: &'a RtcDs3231Ref<I2C>,
+        state: &'a mut HistoryServiceState,
+    ) -> (HistoryRunner<'a, I2C>, HistoryControl<'a>)
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    {
+        let state: &'a HistoryServiceState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the history");
+        (
+            HistoryRunner {
+                readings,
+                rtc,
+                state,
+                clock: WallClock::new(),
+                aggregators: HistoryResolution::ALL.map(HistoryAggregator::new),
+            },
+            HistoryControl { state },
+        )
+    }
+}
+
+impl<'a, I2C, E> HistoryRunner<'a, I2C>
+where
+    I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let reading = self.readings.next_message_pure().await;
+            // The history is only kept while the RTC time is known
+            let Some(now_s) = self
+                .clock
+                .now_s(self.rtc, Instant::now())
+                .await
+                .and_then(|now_s| u32::try_from(now_s).ok())
+            else {
+                continue;
+            };
+
+            for aggregator in self.aggregators.iter_mut() {
+                let Some(record) =
+                    aggregator.add(reading.channel, now_s, reading.voltage.value(), reading.current.value())
+                else {
+                    continue;
+                };
+                let resolution = aggregator.resolution();
+                if let Err(e) = self.state.store.lock().await.append(resolution, &record) {
+                    log::error!("Failed to store the {} history: {:?}", resolution.name(), e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! The history records in the flash.
+//!
+//! Every resolution has its own ring of erase blocks in the history area. The records are written
+//! one after another with a growing sequence number, which also gives their slot in the ring. A block
+//! is erased just before its first record is written, so every block is erased once per turn of the
+//! ring and the wear is spread evenly. The oldest records are dropped a block at a time.
+//!
+//! At the boot the ring is scanned for the record with the highest sequence number and the writing
+//! continues after it. A record cut by a power loss fails its checksum and is skipped.
+
+use crc::{CRC_32_ISCSI, Crc};
+use defmt_or_log as log;
+
+use crate::configuration::{FlashError, FlashStorage};
+use crate::history::aggregator::*;
+use crate::vcp_sensors::ChannelNum;
+
+/// The sequence, the timestamp, the channel with the padding, the six values and the checksum
+const RECORD_SIZE: usize = 40;
+const CHECKSUM_OFFSET: usize = RECORD_SIZE - 4;
+/// The share of the blocks given to the hour ring, the rest holds the minute records
+const HOUR_RING_DIVIDER: usize = 4;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// The records of a channel at a resolution within a time range
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HistoryQuery {
+    pub resolution: HistoryResolution,
+    /// All the channels if `None`
+    pub channel: Option<ChannelNum>,
+    /// The first interval start included, seconds since 1970
+    pub from_s: u32,
+    /// The first interval start excluded, seconds since 1970
+    pub to_s: u32,
+}
+
+impl HistoryQuery {
+    fn matches(&self, record: &HistoryRecord) -> bool {
+        self.channel.is_none_or(|channel| channel == record.channel)
+            && (self.from_s..self.to_s).contains(&record.timestamp_s)
+    }
+}
+
+/// The position of a query in the ring, kept between the reads of a long query. Records overwritten
+/// meanwhile are skipped.
+#[derive(Copy, Clone, Default)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct HistoryCursor {
+    next_sequence: u32,
+}
+
+impl HistoryCursor {
+    pub const fn new() -> Self {
+        Self { next_sequence: 0 }
+    }
+}
+
+fn encode(sequence: u32, record: &HistoryRecord) -> [u8; RECORD_SIZE] {
+    let mut bytes = [0u8; RECORD_SIZE];
+    bytes[0..4].copy_from_slice(&sequence.to_le_bytes());
+    bytes[4..8].copy_from_slice(&record.timestamp_s.to_le_bytes());
+    bytes[8] = record.channel;
+    let values = [
+        record.voltage.min,
+        record.voltage.avg,
+        record.voltage.max,
+        record.current.min,
+        record.current.avg,
+        record.current.max,
+    ];
+    for (chunk, value) in bytes[12..CHECKSUM_OFFSET].chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    let checksum = CRC.checksum(&bytes[..CHECKSUM_OFFSET]);
+    bytes[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Returns the sequence and the record, `None` for an erased or a damaged slot
+fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<(u32, HistoryRecord)> {
+    if read_u32(bytes, CHECKSUM_OFFSET) != CRC.checksum(&bytes[..CHECKSUM_OFFSET]) {
+        return None;
+    }
+    let value = |index: usize| f32::from_le_bytes(bytes[12 + 4 * index..16 + 4 * index].try_into().unwrap());
+    let record = HistoryRecord {
+        timestamp_s: read_u32(bytes, 4),
+        channel: bytes[8],
+        voltage: MinAvgMax {
+            min: value(0),
+            avg: value(1),
+            max: value(2),
+        },
+        current: MinAvgMax {
+            min: value(3),
+            avg: value(4),
+            max: value(5),
+        },
+    };
+    Some((read_u32(bytes, 0), record))
+}
+
+/// A ring of erase blocks holding the records of one resolution
+struct HistoryRing {
+    /// The offset of the first block in the area
+    start: usize,
+    blocks: usize,
+    block_size: usize,
+    /// The sequence number of the next record, its slot is erased or starts a block
+    next_sequence: u32,
+}
+
+impl HistoryRing {
+    const fn new(start: usize, blocks: usize, block_size: usize) -> Self {
+        Self {
+            start,
+            blocks,
+            block_size,
+            next_sequence: 0,
+        }
+    }
+
+    fn records_per_block(&self) -> u32 {
+        (self.block_size / RECORD_SIZE) as u32
+    }
+
+    fn slots(&self) -> u32 {
+        self.blocks as u32 * self.records_per_block()
+    }
+
+    fn block_offset(&self, sequence: u32) -> usize {
+        let block = (sequence % self.slots() / self.records_per_block()) as usize;
+        self.start + block * self.block_size
+    }
+
+    fn offset(&self, sequence: u32) -> usize {
+        self.block_offset(sequence) + (sequence % self.records_per_block()) as usize * RECORD_SIZE
+    }
+
+    /// The oldest sequence number which has not been erased yet
+    fn oldest_sequence(&self) -> u32 {
+        let block_start = self.next_sequence - self.next_sequence % self.records_per_block();
+        (block_start + self.records_per_block()).saturating_sub(self.slots())
+    }
+
+    fn read_slot<F: FlashStorage>(&self, flash: &mut F, sequence: u32) -> Result<[u8; RECORD_SIZE], FlashError> {
+        let mut bytes = [0u8; RECORD_SIZE];
+        flash.blocking_read(self.offset(sequence), &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Finds the record written last and continues after it
+    fn mount<F: FlashStorage>(&mut self, flash: &mut F) -> Result<(), FlashError> {
+        let per_block = self.records_per_block();
+        // The first records of the blocks grow along the ring up to the block written last
+        let mut latest_block: Option<u32> = None;
+        for block in 0..self.blocks as u32 {
+            if let Some((sequence, _)) = decode(&self.read_slot(flash, block * per_block)?)
+                && sequence % self.slots() == block * per_block
+                && latest_block.is_none_or(|latest| sequence > latest)
+            {
+                latest_block = Some(sequence);
+            }
+        }
+        let Some(block_start) = latest_block else {
+            self.next_sequence = 0;
+            return Ok(());
+        };
+
+        let mut last = block_start;
+        for sequence in block_start + 1..block_start + per_block {
+            if let Some((stored, _)) = decode(&self.read_slot(flash, sequence)?)
+                && stored == sequence
+            {
+                last = sequence;
+            }
+        }
+        self.next_sequence = last + 1;
+        // A damaged slot can not be written again before its block is erased
+        while self.next_sequence % per_block != 0 {
+            let bytes = self.read_slot(flash, self.next_sequence)?;
+            if bytes.iter().all(|byte| *byte == 0xFF) {
+                break;
+            }
+            self.next_sequence += 1;
+        }
+        Ok(())
+    }
+
+    fn append<F: FlashStorage>(&mut self, flash: &mut F, record: &HistoryRecord) -> Result<(), FlashError> {
+        let sequence = self.next_sequence;
+        if sequence % self.records_per_block() == 0 {
+            let block = self.block_offset(sequence);
+            flash.blocking_erase_range(block, block + self.block_size)?;
+        }
+        // The slot is not written again even if the write fails halfway
+        self.next_sequence += 1;
+        flash.blocking_write(self.offset(sequence), &encode(sequence, record))
+    }
+
+    fn read_next<F: FlashStorage>(
+        &self,
+        flash: &mut F,
+        query: &HistoryQuery,
+        cursor: &mut HistoryCursor,
+    ) -> Result<Option<HistoryRecord>, FlashError> {
+        let mut sequence = cursor.next_sequence.max(self.oldest_sequence());
+        while sequence < self.next_sequence {
+            let slot = decode(&self.read_slot(flash, sequence)?);
+            sequence += 1;
+            cursor.next_sequence = sequence;
+            if let Some((stored, record)) = slot
+                && stored == sequence - 1
+                && query.matches(&record)
+            {
+                return Ok(Some(record));
+            }
+        }
+        cursor.next_sequence = sequence;
+        Ok(None)
+    }
+}
+
+/// The minute and the hour records in the history area of the flash
+pub struct HistoryStore<F> {
+    flash: F,
+    minutes: HistoryRing,
+    hours: HistoryRing,
+}
+
+impl<F: FlashStorage> HistoryStore<F> {
+    /// Splits the area into the rings and finds where the writing stopped before the reboot
+    pub fn mount(mut flash: F) -> Self {
+        let blocks = F::SIZE / F::ERASE_SIZE;
+        let hour_blocks = blocks / HOUR_RING_DIVIDER;
+        let mut minutes = HistoryRing::new(0, blocks - hour_blocks, F::ERASE_SIZE);
+        let mut hours = HistoryRing::new((blocks - hour_blocks) * F::ERASE_SIZE, hour_blocks, F::ERASE_SIZE);
+        for (resolution, ring) in [
+            (HistoryResolution::Minute, &mut minutes),
+            (HistoryResolution::Hour, &mut hours),
+        ] {
+            match ring.mount(&mut flash) {
+                Ok(()) => log::info!(
+                    "History of {} records continues at {}",
+                    resolution.name(),
+                    ring.next_sequence
+                ),
+                Err(e) => log::error!("Failed to scan the {} history: {:?}", resolution.name(), e),
+            }
+        }
+        Self { flash, minutes, hours }
+    }
+
+    fn ring(&self, resolution: HistoryResolution) -> &HistoryRing {
+        match resolution {
+            HistoryResolution::Minute => &self.minutes,
+            HistoryResolution::Hour => &self.hours,
+        }
+    }
+
+    /// The number of records the ring of the resolution holds once it is full
+    pub fn capacity(&self, resolution: HistoryResolution) -> u32 {
+        let ring = self.ring(resolution);
+        ring.slots() - ring.records_per_block()
+    }
+
+    pub fn append(&mut self, resolution: HistoryResolution, record: &HistoryRecord) -> Result<(), FlashError> {
+        let ring = match resolution {
+            HistoryResolution::Minute => &mut self.minutes,
+            HistoryResolution::Hour => &mut self.hours,
+        };
+        ring.append(&mut self.flash, record)
+    }
+
+    /// Returns the next record of the query after the cursor, oldest first. `None` once all the
+    /// records have been read.
+    pub fn read_next(
+        &mut self,
+        query: &HistoryQuery,
+        cursor: &mut HistoryCursor,
+    ) -> Result<Option<HistoryRecord>, FlashError> {
+        let ring = match query.resolution {
+            HistoryResolution::Minute => &self.minutes,
+            HistoryResolution::Hour => &self.hours,
+        };
+        ring.read_next(&mut self.flash, query, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::flash::MemoryFlash;
+
+    /// Four blocks, three for the minutes and one for the hours
+    type TestFlash = MemoryFlash<{ 4 * 0x1000 }>;
+    const PER_BLOCK: u32 = (0x1000 / RECORD_SIZE) as u32;
+
+    fn record(channel: ChannelNum, timestamp_s: u32) -> HistoryRecord {
+        let summary = MinAvgMax {
+            min: 12.0,
+            avg: 12.5,
+            max: 13.0,
+        };
+        HistoryRecord {
+            timestamp_s,
+            channel,
+            voltage: summary,
+            current: summary,
+        }
+    }
+
+    fn query(channel: Option<ChannelNum>, from_s: u32, to_s: u32) -> HistoryQuery {
+        HistoryQuery {
+            resolution: HistoryResolution::Minute,
+            channel,
+            from_s,
+            to_s,
+        }
+    }
+
+    fn read_all(store: &mut HistoryStore<TestFlash>, query: &HistoryQuery) -> Vec<HistoryRecord> {
+        let mut cursor = HistoryCursor::new();
+        let mut records = Vec::new();
+        while let Some(record) = store.read_next(query, &mut cursor).unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[test]
+    fn encoded_record_round_trips_and_detects_damage() {
+        let mut bytes = encode(7, &record(2, 600));
+        assert!(decode(&bytes).is_some_and(|(sequence, decoded)| sequence == 7 && decoded == record(2, 600)));
+        bytes[20] ^= 1;
+        assert!(decode(&bytes).is_none());
+        assert!(decode(&[0xFF; RECORD_SIZE]).is_none());
+    }
+
+    #[test]
+    fn queries_by_channel_and_time_range() {
+        let mut store = HistoryStore::mount(TestFlash::new());
+        for minute in 0..10 {
+            store
+                .append(HistoryResolution::Minute, &record(0, minute * 60))
+                .unwrap();
+            store
+                .append(HistoryResolution::Minute, &record(1, minute * 60))
+                .unwrap();
+        }
+        store.append(HistoryResolution::Hour, &record(0, 0)).unwrap();
+
+        let records = read_all(&mut store, &query(Some(1), 120, 300));
+        let timestamps: Vec<u32> = records.iter().map(|record| record.timestamp_s).collect();
+        assert_eq!(timestamps, [120, 180, 240]);
+        assert!(records.iter().all(|record| record.channel == 1));
+        assert_eq!(read_all(&mut store, &query(None, 0, u32::MAX)).len(), 20);
+
+        let hours = HistoryQuery {
+            resolution: HistoryResolution::Hour,
+            ..query(None, 0, u32::MAX)
+        };
+        assert_eq!(read_all(&mut store, &hours).len(), 1);
+    }
+
+    #[test]
+    fn continues_after_the_last_record_at_reboot() {
+        let mut store = HistoryStore::mount(TestFlash::new());
+        for minute in 0..PER_BLOCK + 5 {
+            store
+                .append(HistoryResolution::Minute, &record(0, minute * 60))
+                .unwrap();
+        }
+
+        let mut store = HistoryStore::mount(store.flash);
+        assert_eq!(store.minutes.next_sequence, PER_BLOCK + 5);
+        store.append(HistoryResolution::Minute, &record(0, 99_999)).unwrap();
+        let records = read_all(&mut store, &query(Some(0), 0, u32::MAX));
+        assert_eq!(records.len() as u32, PER_BLOCK + 6);
+        assert_eq!(records.last().unwrap().timestamp_s, 99_999);
+    }
+
+    #[test]
+    fn damaged_record_is_skipped() {
+        let mut store = HistoryStore::mount(TestFlash::new());
+        for minute in 0..3 {
+            store
+                .append(HistoryResolution::Minute, &record(0, minute * 60))
+                .unwrap();
+        }
+        // A write cut by a power loss leaves the slot programmed halfway
+        store.flash.blocking_write(3 * RECORD_SIZE, &[0x00; 8]).unwrap();
+
+        let mut store = HistoryStore::mount(store.flash);
+        assert_eq!(store.minutes.next_sequence, 4);
+        store.append(HistoryResolution::Minute, &record(0, 240)).unwrap();
+        let timestamps: Vec<u32> = read_all(&mut store, &query(None, 0, u32::MAX))
+            .iter()
+            .map(|record| record.timestamp_s)
+            .collect();
+        assert_eq!(timestamps, [0, 60, 120, 240]);
+    }
+
+    #[test]
+    fn wraps_around_dropping_the_oldest_block() {
+        let mut store = HistoryStore::mount(TestFlash::new());
+        let written = 3 * PER_BLOCK + 2;
+        for minute in 0..written {
+            store
+                .append(HistoryResolution::Minute, &record(0, minute * 60))
+                .unwrap();
+        }
+
+        let records = read_all(&mut store, &query(None, 0, u32::MAX));
+        // The first block has been erased for the newest records
+        assert_eq!(records.len() as u32, 2 * PER_BLOCK + 2);
+        assert_eq!(records[0].timestamp_s, PER_BLOCK * 60);
+        assert_eq!(records.last().unwrap().timestamp_s, (written - 1) * 60);
+        assert_eq!(store.capacity(HistoryResolution::Minute), 2 * PER_BLOCK);
+
+        // The cursor of a query started before the wrap skips the erased records
+        let mut store = HistoryStore::mount(store.flash);
+        assert_eq!(store.minutes.next_sequence, written);
+        let mut cursor = HistoryCursor::new();
+        let first = store
+            .read_next(&query(None, 0, u32::MAX), &mut cursor)
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.timestamp_s, PER_BLOCK * 60);
+    }
+}
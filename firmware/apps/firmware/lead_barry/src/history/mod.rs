@@ -0,0 +1,9 @@
+#![allow(unused_imports)]
+
+mod aggregator;
+mod history_service;
+mod history_store;
+
+pub use self::aggregator::*;
+pub use self::history_service::*;
+pub use self::history_store::*;
@@ -0,0 +1,109 @@
+//! Frees the I2C0 bus when a device holds SDA low after an interrupted transfer.
+//!
+//! The I2C peripheral is not able to do it by itself: the pins are switched to GPIO, SCL is clocked
+//! until the device lets SDA go and a STOP condition is generated. The peripheral is then set up
+//! again from scratch, which also clears its own stuck state.
+//!
+//! The bus mutex holds the driver for the whole run, so the pins and the peripheral are stolen for
+//! the recovery and the replaced driver is forgotten instead of dropped. It holds no buffers, only
+//! the peripheral handles, so nothing but those handles is leaked. The recoveries are still capped,
+//! a bus which keeps getting stuck needs a look at the hardware rather than more recoveries.
+
+use defmt_or_log as log;
+use embassy_rp::gpio::{Flex, Pull};
+use embassy_rp::i2c::{self, I2c};
+use embassy_rp::peripherals::{I2C0, PIN_16, PIN_17};
+use embassy_time::Timer;
+
+use crate::Irqs;
+use crate::global_types::I2c0Bus;
+use crate::vcp_sensors::I2cBusClear;
+
+/// A device in the middle of a byte releases SDA after at most 9 clocks
+const MAX_CLEAR_CLOCKS: usize = 9;
+/// Half of the SCL period of the manual clocking, 100 kHz
+const HALF_PERIOD_US: u64 = 5;
+/// The most recoveries until the next boot
+const MAX_RECOVERIES: u32 = 16;
+
+pub struct I2c0BusClear {
+    bus: &'static I2c0Bus,
+    frequency_hz: u32,
+    recoveries: u32,
+}
+
+impl I2c0BusClear {
+    /// The frequency must be the one the bus was set up with
+    pub fn new(bus: &'static I2c0Bus, frequency_hz: u32) -> Self {
+        Self {
+            bus,
+            frequency_hz,
+            recoveries: 0,
+        }
+    }
+}
+
+impl I2cBusClear for I2c0BusClear {
+    async fn clear_bus(&mut self) {
+        if self.recoveries >= MAX_RECOVERIES {
+            log::error!("I2C0 bus was recovered {} times, leaving it as it is", self.recoveries);
+            return;
+        }
+        self.recoveries += 1;
+
+        // The lock keeps the other devices of the bus away while the peripheral is replaced
+        let mut i2c0 = self.bus.lock().await;
+
+        // Safety: the pins and the peripheral belong to the I2C driver in the mutex, which is locked
+        // and replaced below without being used again.
+        let mut scl = Flex::new(unsafe { PIN_17::steal() });
+        let mut sda = Flex::new(unsafe { PIN_16::steal() });
+        scl.set_pull(Pull::Up);
+        sda.set_pull(Pull::Up);
+        scl.set_as_input();
+        sda.set_as_input();
+        // The pins are only driven low, the pull-ups raise them when they are released
+        scl.set_low();
+        sda.set_low();
+
+        let mut clocks = 0;
+        while sda.is_low() && clocks < MAX_CLEAR_CLOCKS {
+            scl.set_as_output();
+            Timer::after_micros(HALF_PERIOD_US).await;
+            scl.set_as_input();
+            Timer::after_micros(HALF_PERIOD_US).await;
+            clocks += 1;
+        }
+        if sda.is_low() {
+            log::warn!("I2C0 SDA is still held low after {} clocks", clocks);
+        }
+
+        // STOP: SDA rises while SCL is high
+        sda.set_as_output();
+        Timer::after_micros(HALF_PERIOD_US).await;
+        scl.set_as_input();
+        Timer::after_micros(HALF_PERIOD_US).await;
+        sda.set_as_input();
+        Timer::after_micros(HALF_PERIOD_US).await;
+        drop(scl);
+        drop(sda);
+
+        let mut config = i2c::Config::default();
+        config.frequency = self.frequency_hz;
+        let new_i2c0 = I2c::new_async(
+            unsafe { I2C0::steal() },
+            unsafe { PIN_17::steal() },
+            unsafe { PIN_16::steal() },
+            Irqs,
+            config,
+        );
+        // The old driver must not be dropped, it would detach the pins the new one has just taken
+        core::mem::forget(core::mem::replace(&mut *i2c0, new_i2c0));
+        log::info!(
+            "I2C0 bus cleared after {} clocks ({}/{} recoveries)",
+            clocks,
+            self.recoveries,
+            MAX_RECOVERIES
+        );
+    }
+}
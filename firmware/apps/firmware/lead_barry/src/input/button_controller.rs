@@ -2,13 +2,15 @@
 
 use core::task::Poll;
 
-use embassy_rp::gpio::Pin;
-use embassy_rp::{Peri, gpio};
+#[cfg(not(feature = "sim"))]
+use embassy_rp::{
+    Peri,
+    gpio::{Input, Level, Pin, Pull},
+};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Channel, Receiver, Sender};
 use embassy_sync::mutex::Mutex;
 use embassy_time::Ticker;
-use gpio::{Input, Level, Pull};
 
 use crate::async_infinite_stream::*;
 use crate::units::TimeExt as _;
@@ -89,36 +91,55 @@ impl<T> From<ButtonEvent<T>> for ButtonState {
     }
 }
 
-struct Button<AliasType = u8> {
+/// The input the state of a button is read from
+pub trait ButtonInput {
+    /// The button is held down
+    fn is_pressed(&self) -> bool;
+}
+
+/// A button on a GPIO pin of the board
+#[cfg(not(feature = "sim"))]
+pub struct GpioButton {
     input: Input<'static>,
-    alias: AliasType,
     pull: Pull,
 }
 
-impl<AliasType> From<&Button<AliasType>> for ButtonState {
-    fn from(button: &Button<AliasType>) -> Self {
-        match (button.pull, button.input.get_level()) {
-            (Pull::Up, Level::High) => ButtonState::Idle,
-            (Pull::Up, Level::Low) => ButtonState::Pressed,
-            (Pull::Down, Level::High) => ButtonState::Pressed,
-            (Pull::Down, Level::Low) => ButtonState::Idle,
-            (Pull::None, Level::High) => ButtonState::Idle,
-            (Pull::None, Level::Low) => ButtonState::Pressed,
+#[cfg(not(feature = "sim"))]
+impl ButtonInput for GpioButton {
+    fn is_pressed(&self) -> bool {
+        match (self.pull, self.input.get_level()) {
+            (Pull::Up, Level::High) => false,
+            (Pull::Up, Level::Low) => true,
+            (Pull::Down, Level::High) => true,
+            (Pull::Down, Level::Low) => false,
+            // Assume Pull::None means active low
+            (Pull::None, Level::High) => false,
+            (Pull::None, Level::Low) => true,
         }
     }
 }
 
-impl<AliasType: Copy> From<(Edge, &Button<AliasType>)> for ButtonEvent<AliasType> {
-    fn from(pair: (Edge, &Button<AliasType>)) -> Self {
+struct Button<AliasType, I> {
+    input: I,
+    alias: AliasType,
+}
+
+impl<AliasType, I: ButtonInput> From<&Button<AliasType, I>> for ButtonState {
+    fn from(button: &Button<AliasType, I>) -> Self {
+        if button.input.is_pressed() {
+            ButtonState::Pressed
+        } else {
+            ButtonState::Idle
+        }
+    }
+}
+
+impl<AliasType: Copy, I> From<(Edge, &Button<AliasType, I>)> for ButtonEvent<AliasType> {
+    fn from(pair: (Edge, &Button<AliasType, I>)) -> Self {
         let (edge, button) = pair;
-        match (edge, button.pull) {
-            (Edge::Falling, Pull::Up) => ButtonEvent::Pressed(button.alias),
-            (Edge::Falling, Pull::Down) => ButtonEvent::Released(button.alias),
-            (Edge::Rising, Pull::Up) => ButtonEvent::Released(button.alias),
-            (Edge::Rising, Pull::Down) => ButtonEvent::Pressed(button.alias),
-            // Default case (no pull). Assume Pull::None means active low
-            (Edge::Rising, Pull::None) => ButtonEvent::Released(button.alias),
-            (Edge::Falling, Pull::None) => ButtonEvent::Pressed(button.alias),
+        match edge {
+            Edge::Pressed => ButtonEvent::Pressed(button.alias),
+            Edge::Released => ButtonEvent::Released(button.alias),
         }
     }
 }
@@ -236,30 +257,29 @@ where
     }
 }
 
-pub struct ButtonControllerBuilder<const INPUTS: usize, AliasType = u8> {
-    buttons: heapless::Vec<Button<AliasType>, INPUTS>,
+pub struct ButtonControllerBuilder<const INPUTS: usize, AliasType, I> {
+    buttons: heapless::Vec<Button<AliasType, I>, INPUTS>,
 }
 
-impl<const INPUTS: usize, AliasType> ButtonControllerBuilder<INPUTS, AliasType> {
+impl<const INPUTS: usize, AliasType, I> ButtonControllerBuilder<INPUTS, AliasType, I>
+where
+    I: ButtonInput,
+{
     pub fn new() -> Self {
         Self {
             buttons: heapless::Vec::new(),
         }
     }
 
-    /// Binds a pin to the button controller with the specified alias and pull configuration
+    /// Binds an input to the button controller with the specified alias
     /// - alias: An identifier for the button (e.g., enum or integer)
-    /// - pin: The GPIO pin to bind
-    /// - pull: The pull configuration for the button (Pull::Up, Pull::Down, Pull::None)
-    ///   Note: Pull::None assumes active low configuration - button press pulls the line low
-    pub fn bind_pin(&mut self, alias: AliasType, pin: Peri<'static, impl Pin>, pull: Pull) {
+    /// - input: The input the button is read from
+    pub fn bind(&mut self, alias: AliasType, input: I) {
         if self.buttons.is_full() {
             log::panic!("ButtonControllerBuilder: Exceeded maximum number of buttons");
         }
 
-        let mut input = Input::new(pin, pull);
-        input.set_schmitt(true);
-        let _ = self.buttons.push(Button { input, alias, pull });
+        let _ = self.buttons.push(Button { input, alias });
     }
 
     /// Builds the ButtonController and ButtonControllerRunner
@@ -270,7 +290,7 @@ impl<const INPUTS: usize, AliasType> ButtonControllerBuilder<INPUTS, AliasType>
         state: &'a mut ButtonControllerState<AliasType, INPUTS, BUTTON_EVENT_QUEUE_SIZE>,
     ) -> (
         ButtonController<'a, AliasType, BUTTON_EVENT_QUEUE_SIZE>,
-        ButtonControllerRunner<'a, AliasType, INPUTS, BUTTON_EVENT_QUEUE_SIZE>,
+        ButtonControllerRunner<'a, AliasType, I, INPUTS, BUTTON_EVENT_QUEUE_SIZE>,
     )
     where
         AliasType: core::cmp::Eq + core::hash::Hash + Copy,
@@ -294,17 +314,28 @@ impl<const INPUTS: usize, AliasType> ButtonControllerBuilder<INPUTS, AliasType>
     }
 }
 
+#[cfg(not(feature = "sim"))]
+impl<const INPUTS: usize, AliasType> ButtonControllerBuilder<INPUTS, AliasType, GpioButton> {
+    /// Binds a pin to the button controller with the specified alias and pull configuration
+    /// - alias: An identifier for the button (e.g., enum or integer)
+    /// - pin: The GPIO pin to bind
+    /// - pull: The pull configuration for the button (Pull::Up, Pull::Down, Pull::None)
+    ///   Note: Pull::None assumes active low configuration - button press pulls the line low
+    pub fn bind_pin(&mut self, alias: AliasType, pin: Peri<'static, impl Pin>, pull: Pull) {
+        let mut input = Input::new(pin, pull);
+        input.set_schmitt(true);
+        self.bind(alias, GpioButton { input, pull });
+    }
+}
+
 enum Edge {
-    Rising,
-    Falling,
+    Pressed,
+    Released,
 }
 
-impl From<Level> for Edge {
-    fn from(level: Level) -> Self {
-        match level {
-            Level::High => Edge::Rising,
-            Level::Low => Edge::Falling,
-        }
+impl From<bool> for Edge {
+    fn from(pressed: bool) -> Self {
+        if pressed { Edge::Pressed } else { Edge::Released }
     }
 }
 
@@ -312,19 +343,19 @@ impl From<Level> for Edge {
 type Cycles = u8;
 
 struct EdgeDetector {
-    previous_level: Level,
+    previous_level: bool,
     cycles: Cycles,
 }
 
 impl EdgeDetector {
-    pub const fn new(initial_level: Level) -> Self {
+    pub const fn new(initial_level: bool) -> Self {
         Self {
             previous_level: initial_level,
             cycles: 0,
         }
     }
 
-    pub fn update_level(&mut self, level: Level) -> Option<Edge> {
+    pub fn update_level(&mut self, level: bool) -> Option<Edge> {
         let result = if self.previous_level != level {
             // Debounce logic: Only confirm the edge if the level has been stable for DEBOUNCE_CYCLES cycles
             if self.cycles < DEBOUNCE_CYCLES {
@@ -346,17 +377,19 @@ impl EdgeDetector {
     }
 }
 
-pub struct ButtonControllerRunner<'a, AliasType, const INPUTS: usize, const BUTTON_EVENT_QUEUE_SIZE: usize>
+pub struct ButtonControllerRunner<'a, AliasType, I, const INPUTS: usize, const BUTTON_EVENT_QUEUE_SIZE: usize>
 where
     AliasType: 'a,
 {
-    buttons: heapless::Vec<Button<AliasType>, INPUTS>,
+    buttons: heapless::Vec<Button<AliasType, I>, INPUTS>,
     sender: EventSender<'a, AliasType, BUTTON_EVENT_QUEUE_SIZE>,
     state: &'a dyn ButtonStateProvider<'a, AliasType>,
 }
 
-impl<'a, const INPUTS: usize, AliasType, const BUTTON_EVENT_QUEUE_SIZE: usize>
-    ButtonControllerRunner<'a, AliasType, INPUTS, BUTTON_EVENT_QUEUE_SIZE>
+impl<'a, const INPUTS: usize, AliasType, I, const BUTTON_EVENT_QUEUE_SIZE: usize>
+    ButtonControllerRunner<'a, AliasType, I, INPUTS, BUTTON_EVENT_QUEUE_SIZE>
+where
+    I: ButtonInput,
 {
     pub async fn run(&self) -> !
     where
@@ -371,7 +404,7 @@ impl<'a, const INPUTS: usize, AliasType, const BUTTON_EVENT_QUEUE_SIZE: usize>
             .buttons
             .iter()
             .map(|button| {
-                let initial_level = button.input.get_level();
+                let initial_level = button.input.is_pressed();
                 EdgeDetector::new(initial_level)
             })
             .collect::<heapless::Vec<_, INPUTS>>();
@@ -381,7 +414,7 @@ impl<'a, const INPUTS: usize, AliasType, const BUTTON_EVENT_QUEUE_SIZE: usize>
 
             // Process each button
             for (i, button) in self.buttons.iter().enumerate() {
-                let level = button.input.get_level();
+                let level = button.input.is_pressed();
                 // Update edge detector
                 if let Some(edge) = edge_detectors[i].update_level(level) {
                     let event: ButtonEvent<AliasType> = (edge, button).into();
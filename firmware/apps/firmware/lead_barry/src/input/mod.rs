@@ -11,13 +11,27 @@ pub enum Buttons {
     Blue,
 }
 
-pub use button_controller::ButtonState;
+impl Buttons {
+    /// The button of the two-button combination
+    pub const fn other(&self) -> Self {
+        match self {
+            Buttons::Yellow => Buttons::Blue,
+            Buttons::Blue => Buttons::Yellow,
+        }
+    }
+}
+
+use crate::global_types::ButtonInputDevice;
+#[cfg(not(feature = "sim"))]
+pub use button_controller::GpioButton;
+pub use button_controller::{ButtonInput, ButtonState};
 
 pub type ButtonEvent = button_controller::ButtonEvent<Buttons>;
 
 pub type ButtonControllerState =
     button_controller::ButtonControllerState<Buttons, BUTTONS_COUNT, BUTTON_EVENT_QUEUE_SIZE>;
 pub type ButtonController<'a> = button_controller::ButtonController<'a, Buttons, BUTTON_EVENT_QUEUE_SIZE>;
-pub type ButtonControllerBuilder = button_controller::ButtonControllerBuilder<BUTTONS_COUNT, Buttons>;
+pub type ButtonControllerBuilder =
+    button_controller::ButtonControllerBuilder<BUTTONS_COUNT, Buttons, ButtonInputDevice>;
 pub type ButtonControllerRunner<'a> =
-    button_controller::ButtonControllerRunner<'a, Buttons, BUTTONS_COUNT, BUTTON_EVENT_QUEUE_SIZE>;
+    button_controller::ButtonControllerRunner<'a, Buttons, ButtonInputDevice, BUTTONS_COUNT, BUTTON_EVENT_QUEUE_SIZE>;
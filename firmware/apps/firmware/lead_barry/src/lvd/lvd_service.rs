@@ -0,0 +1,201 @@
+use defmt_or_log as log;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, ReceiveFuture, Receiver, Sender},
+    mutex::Mutex,
+    pubsub::WaitResult,
+    signal::Signal,
+};
+use embassy_time::Instant;
+use serde::Serialize;
+
+use crate::configuration::LvdSettings;
+use crate::lvd::lvd_state_machine::*;
+use crate::vcp_sensors::{ChannelNum, VcpControl, VcpReadingSubscriber};
+
+const LVD_ACTION_QUEUE_SIZE: usize = 4;
+
+type LvdActionChannel = Channel<CriticalSectionRawMutex, LvdAction, LVD_ACTION_QUEUE_SIZE>;
+pub type LvdActionReceiveFuture<'a> = ReceiveFuture<'a, CriticalSectionRawMutex, LvdAction, LVD_ACTION_QUEUE_SIZE>;
+
+/// The latest low-voltage disconnect state published for the display and the web API.
+#[derive(Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct LvdStatus {
+    pub enabled: bool,
+    pub state: LvdState,
+    /// The last battery voltage in volts
+    pub voltage: f32,
+    /// The battery voltage above which the loads are reconnected, in volts
+    pub reconnect_voltage: f32,
+    /// The loads which are currently disconnected, in the order they were shed
+    pub shed_loads: heapless::Vec<LoadId, MAX_LVD_LOADS>,
+}
+
+impl LvdStatus {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            state: LvdState::Normal,
+            voltage: 0.0,
+            reconnect_voltage: 0.0,
+            shed_loads: heapless::Vec::new(),
+        }
+    }
+}
+
+impl Default for LvdStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct LvdServiceConfig {
+    /// The VCP channel the battery is connected to
+    pub channel: ChannelNum,
+    pub enabled: bool,
+    pub lvd: LvdConfig,
+}
+
+impl LvdServiceConfig {
+    pub fn from_settings(channel: ChannelNum, settings: &LvdSettings) -> Self {
+        Self {
+            channel,
+            enabled: settings.enabled,
+            lvd: LvdConfig {
+                reconnect_voltage: settings.reconnect_voltage,
+                disconnect_delay_ms: settings.disconnect_delay_s as u64 * 1000,
+                reconnect_delay_ms: settings.reconnect_delay_s as u64 * 1000,
+                stage_delay_ms: settings.stage_delay_s as u64 * 1000,
+                shed_order: settings.shed_order.iter().map(|output| *output as LoadId).collect(),
+            },
+        }
+    }
+}
+
+pub struct LvdServiceState {
+    status: Mutex<CriticalSectionRawMutex, LvdStatus>,
+    actions: LvdActionChannel,
+    reconnect_voltage: Signal<CriticalSectionRawMutex, f32>,
+}
+
+impl LvdServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(LvdStatus::new()),
+            actions: LvdActionChannel::new(),
+            reconnect_voltage: Signal::new(),
+        }
+    }
+}
+
+pub struct LvdRunner<'a> {
+    readings: VcpReadingSubscriber<'a>,
+    state: &'a LvdServiceState,
+    action_sender: Sender<'a, CriticalSectionRawMutex, LvdAction, LVD_ACTION_QUEUE_SIZE>,
+    channel: ChannelNum,
+    enabled: bool,
+    state_machine: LvdStateMachine,
+}
+
+pub struct LvdControl<'a> {
+    state: &'a LvdServiceState,
+    action_receiver: Receiver<'a, CriticalSectionRawMutex, LvdAction, LVD_ACTION_QUEUE_SIZE>,
+}
+
+#[allow(dead_code)]
+impl<'a> LvdControl<'a> {
+    /// Returns the latest low-voltage disconnect status
+    pub async fn status(&self) -> LvdStatus {
+        self.state.status.lock().await.clone()
+    }
+
+    /// Waits for the next load to shed or restore
+    pub fn receive_action(&self) -> LvdActionReceiveFuture<'_> {
+        self.action_receiver.receive()
+    }
+
+    /// Moves the reconnect voltage, e.g. for the temperature compensation. The disconnect voltage
+    /// follows the low voltage limit of the battery channel.
+    pub fn set_reconnect_voltage(&self, reconnect_voltage: f32) {
+        self.state.reconnect_voltage.signal(reconnect_voltage);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LvdService(());
+
+impl LvdService {
+    /// Creates a new low-voltage disconnect instance fed by the readings of the VCP sensors
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        vcp_control: &'a VcpControl<'a>,
+        state: &'a mut LvdServiceState,
+        config: LvdServiceConfig,
+    ) -> (LvdRunner<'a>, LvdControl<'a>) {
+        let state: &'a LvdServiceState = state;
+        let readings = vcp_control
+            .subscribe_readings()
+            .expect("No free VCP readings subscriber for the LVD");
+        (
+            LvdRunner {
+                readings,
+                state,
+                action_sender: state.actions.sender(),
+                channel: config.channel,
+                enabled: config.enabled,
+                state_machine: LvdStateMachine::new(config.lvd),
+            },
+            LvdControl {
+                state,
+                action_receiver: state.actions.receiver(),
+            },
+        )
+    }
+}
+
+impl<'a> LvdRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let reading = match select(self.readings.next_message(), self.state.reconnect_voltage.wait()).await {
+                Either::First(WaitResult::Lagged(missed)) => {
+                    log::warn!("LVD missed {} readings", missed);
+                    continue;
+                }
+                Either::First(WaitResult::Message(reading)) => reading,
+                Either::Second(reconnect_voltage) => {
+                    log::info!("LVD reconnect voltage {} V", reconnect_voltage);
+                    self.state_machine.set_reconnect_voltage(reconnect_voltage);
+                    continue;
+                }
+            };
+            if reading.channel != self.channel {
+                continue;
+            }
+
+            let lvd_reading = LvdReading {
+                voltage: reading.voltage.value(),
+                low: reading.voltage.is_low(),
+            };
+
+            if self.enabled
+                && let Some(action) = self.state_machine.update(lvd_reading, Instant::now().as_millis())
+            {
+                log::warn!("LVD {}: {}", self.state_machine.state().name(), action);
+                if self.action_sender.try_send(action).is_err() {
+                    log::error!("Failed to send LVD action");
+                }
+            }
+
+            let mut status = self.state.status.lock().await;
+            status.enabled = self.enabled;
+            status.state = self.state_machine.state();
+            status.voltage = lvd_reading.voltage;
+            status.reconnect_voltage = self.state_machine.config().reconnect_voltage;
+            status.shed_loads = heapless::Vec::from_slice(self.state_machine.shed_loads()).unwrap_or_default();
+        }
+    }
+}
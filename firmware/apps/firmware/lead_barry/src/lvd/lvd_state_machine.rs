@@ -0,0 +1,355 @@
+#![allow(dead_code)]
+
+//! Low-voltage disconnect (LVD) state machine. The loads are shed one by one while the battery stays
+//! low and restored in reverse order once it stays above the reconnect voltage.
+
+use serde::Serialize;
+
+/// Index of a load in the shed order
+pub type LoadId = u8;
+
+pub const MAX_LVD_LOADS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum LvdState {
+    /// All loads are connected and the battery is fine.
+    Normal,
+    /// The battery is low and the disconnect delay is running.
+    DisconnectPending,
+    /// Part of the loads are disconnected.
+    Shedding,
+    /// All loads are disconnected.
+    Disconnected,
+    /// The battery has recovered and the loads are being reconnected.
+    Reconnecting,
+}
+
+impl LvdState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            LvdState::Normal => "Normal",
+            LvdState::DisconnectPending => "Pending",
+            LvdState::Shedding => "Shedding",
+            LvdState::Disconnected => "Disconnected",
+            LvdState::Reconnecting => "Reconnecting",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum LvdAction {
+    /// Switch the load off
+    Shed(LoadId),
+    /// Switch the load back on
+    Restore(LoadId),
+}
+
+/// A single battery reading fed to the state machine.
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct LvdReading {
+    /// Battery voltage in volts
+    pub voltage: f32,
+    /// The reading is below the disconnect threshold (the battery channel reports `VcpState::Low`)
+    pub low: bool,
+}
+
+#[derive(Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct LvdConfig {
+    /// The battery voltage above which the loads are reconnected
+    pub reconnect_voltage: f32,
+    /// How long the battery has to stay low before a load is shed
+    pub disconnect_delay_ms: u64,
+    /// How long the battery has to stay above the reconnect voltage before the first load is restored
+    pub reconnect_delay_ms: u64,
+    /// The delay between shedding or restoring two consecutive loads
+    pub stage_delay_ms: u64,
+    /// The loads in the order they are shed. They are restored in reverse order.
+    pub shed_order: heapless::Vec<LoadId, MAX_LVD_LOADS>,
+}
+
+pub struct LvdStateMachine {
+    config: LvdConfig,
+    state: LvdState,
+    /// Number of loads from the beginning of the shed order which are currently disconnected
+    shed_count: usize,
+    /// The current condition of the battery and the time since when it holds
+    condition_since: Option<(Condition, u64)>,
+    /// The time of the last shed or restore step
+    last_step_ms: u64,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+enum Condition {
+    Low,
+    Recovered,
+    Hysteresis,
+}
+
+impl LvdStateMachine {
+    pub const fn new(config: LvdConfig) -> Self {
+        Self {
+            config,
+            state: LvdState::Normal,
+            shed_count: 0,
+            condition_since: None,
+            last_step_ms: 0,
+        }
+    }
+
+    pub fn state(&self) -> LvdState {
+        self.state
+    }
+
+    pub fn config(&self) -> &LvdConfig {
+        &self.config
+    }
+
+    /// Moves the reconnect voltage, e.g. for the temperature compensation
+    pub fn set_reconnect_voltage(&mut self, reconnect_voltage: f32) {
+        self.config.reconnect_voltage = reconnect_voltage;
+    }
+
+    /// The loads which are currently disconnected, in the order they were shed
+    pub fn shed_loads(&self) -> &[LoadId] {
+        &self.config.shed_order[..self.shed_count]
+    }
+
+    /// Processes a battery reading taken at `now_ms` and returns the action to apply, if any.
+    /// At most one load changes its state per reading.
+    pub fn update(&mut self, reading: LvdReading, now_ms: u64) -> Option<LvdAction> {
+        let condition = if reading.low {
+            Condition::Low
+        } else if reading.voltage >= self.config.reconnect_voltage {
+            Condition::Recovered
+        } else {
+            Condition::Hysteresis
+        };
+        let held_ms = self.hold_condition(condition, now_ms);
+
+        match (condition, self.state) {
+            (Condition::Low, LvdState::Normal) => {
+                self.state = LvdState::DisconnectPending;
+                None
+            }
+            (Condition::Low, LvdState::DisconnectPending) if held_ms >= self.config.disconnect_delay_ms => {
+                self.shed_next(now_ms)
+            }
+            (Condition::Low, LvdState::Reconnecting) => {
+                // A restored load pulls the battery down again
+                self.state = LvdState::Shedding;
+                self.last_step_ms = now_ms;
+                None
+            }
+            (Condition::Low, LvdState::Shedding)
+                if held_ms >= self.config.disconnect_delay_ms && self.step_elapsed(now_ms) =>
+            {
+                self.shed_next(now_ms)
+            }
+            (Condition::Recovered, LvdState::Shedding | LvdState::Disconnected)
+                if held_ms >= self.config.reconnect_delay_ms =>
+            {
+                self.state = LvdState::Reconnecting;
+                self.restore_next(now_ms)
+            }
+            (Condition::Recovered, LvdState::Reconnecting) if self.step_elapsed(now_ms) => self.restore_next(now_ms),
+            (Condition::Recovered | Condition::Hysteresis, LvdState::DisconnectPending) => {
+                self.state = LvdState::Normal;
+                None
+            }
+            (Condition::Hysteresis, LvdState::Reconnecting) => {
+                // Keep the already restored loads, but wait for the full reconnect delay again
+                self.state = LvdState::Shedding;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns how long the condition holds, restarting the timer when the condition changes
+    fn hold_condition(&mut self, condition: Condition, now_ms: u64) -> u64 {
+        match self.condition_since {
+            Some((current, since)) if current == condition => now_ms.saturating_sub(since),
+            _ => {
+                self.condition_since = Some((condition, now_ms));
+                0
+            }
+        }
+    }
+
+    fn step_elapsed(&self, now_ms: u64) -> bool {
+        now_ms.saturating_sub(self.last_step_ms) >= self.config.stage_delay_ms
+    }
+
+    fn shed_next(&mut self, now_ms: u64) -> Option<LvdAction> {
+        self.last_step_ms = now_ms;
+        let action = self.config.shed_order.get(self.shed_count).map(|load| {
+            self.shed_count += 1;
+            LvdAction::Shed(*load)
+        });
+        self.state = if self.shed_count >= self.config.shed_order.len() {
+            LvdState::Disconnected
+        } else {
+            LvdState::Shedding
+        };
+        action
+    }
+
+    fn restore_next(&mut self, now_ms: u64) -> Option<LvdAction> {
+        self.last_step_ms = now_ms;
+        let action = if self.shed_count > 0 {
+            self.shed_count -= 1;
+            Some(LvdAction::Restore(self.config.shed_order[self.shed_count]))
+        } else {
+            None
+        };
+        if self.shed_count == 0 {
+            self.state = LvdState::Normal;
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::*;
+
+    const READING_PERIOD_MS: u64 = 1000;
+    const DISCONNECT_VOLTAGE: f32 = 11.8;
+
+    fn config() -> LvdConfig {
+        LvdConfig {
+            reconnect_voltage: 12.6,
+            disconnect_delay_ms: 10_000,
+            reconnect_delay_ms: 30_000,
+            stage_delay_ms: 5_000,
+            shed_order: heapless::Vec::from_slice(&[3, 1, 2]).unwrap(),
+        }
+    }
+
+    /// Feeds the state machine with the given voltage for the given duration and collects the actions
+    fn feed(
+        lvd: &mut LvdStateMachine,
+        now_ms: &mut u64,
+        voltage: f32,
+        duration_ms: u64,
+    ) -> heapless::Vec<LvdAction, MAX_STEP_RESULTS> {
+        let reading = LvdReading {
+            voltage,
+            low: voltage < DISCONNECT_VOLTAGE,
+        };
+        step_for(now_ms, READING_PERIOD_MS, duration_ms, |now_ms| {
+            lvd.update(reading, now_ms)
+        })
+    }
+
+    #[test]
+    fn test_short_dip_does_not_disconnect() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 5_000).is_empty());
+        assert_eq!(lvd.state(), LvdState::DisconnectPending);
+
+        assert!(feed(&mut lvd, &mut now_ms, 12.2, 1_000).is_empty());
+        assert_eq!(lvd.state(), LvdState::Normal);
+
+        // The delay restarts after the dip
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 9_000).is_empty());
+        assert_eq!(lvd.state(), LvdState::DisconnectPending);
+    }
+
+    #[test]
+    fn test_loads_are_shed_in_order() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+
+        let actions = feed(&mut lvd, &mut now_ms, 11.5, 11_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Shed(3)]);
+        assert_eq!(lvd.state(), LvdState::Shedding);
+
+        let actions = feed(&mut lvd, &mut now_ms, 11.5, 10_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Shed(1), LvdAction::Shed(2)]);
+        assert_eq!(lvd.state(), LvdState::Disconnected);
+        assert_eq!(lvd.shed_loads(), &[3, 1, 2]);
+
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 60_000).is_empty());
+    }
+
+    #[test]
+    fn test_shedding_stops_in_hysteresis_band() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+
+        assert_eq!(
+            feed(&mut lvd, &mut now_ms, 11.5, 11_000).as_slice(),
+            &[LvdAction::Shed(3)]
+        );
+
+        // Shedding the first load lifted the voltage above the disconnect threshold but not to the reconnect one
+        assert!(feed(&mut lvd, &mut now_ms, 12.2, 120_000).is_empty());
+        assert_eq!(lvd.state(), LvdState::Shedding);
+        assert_eq!(lvd.shed_loads(), &[3]);
+    }
+
+    #[test]
+    fn test_loads_are_restored_in_reverse_order() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+        feed(&mut lvd, &mut now_ms, 11.5, 30_000);
+        assert_eq!(lvd.state(), LvdState::Disconnected);
+
+        // Not recovered long enough
+        assert!(feed(&mut lvd, &mut now_ms, 12.8, 20_000).is_empty());
+        assert!(feed(&mut lvd, &mut now_ms, 12.4, 1_000).is_empty());
+
+        let actions = feed(&mut lvd, &mut now_ms, 12.8, 31_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Restore(2)]);
+        assert_eq!(lvd.state(), LvdState::Reconnecting);
+
+        let actions = feed(&mut lvd, &mut now_ms, 12.8, 11_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Restore(1), LvdAction::Restore(3)]);
+        assert_eq!(lvd.state(), LvdState::Normal);
+        assert!(lvd.shed_loads().is_empty());
+    }
+
+    #[test]
+    fn test_low_battery_during_reconnect_sheds_again() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+        feed(&mut lvd, &mut now_ms, 11.5, 30_000);
+        feed(&mut lvd, &mut now_ms, 12.8, 31_000);
+        assert_eq!(lvd.state(), LvdState::Reconnecting);
+        assert_eq!(lvd.shed_loads(), &[3, 1]);
+
+        // Reconnected load pulls the battery down again, it is shed once the battery stays low
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 10_000).is_empty());
+        assert_eq!(lvd.state(), LvdState::Shedding);
+        let actions = feed(&mut lvd, &mut now_ms, 11.5, 1_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Shed(2)]);
+        assert_eq!(lvd.state(), LvdState::Disconnected);
+    }
+
+    #[test]
+    fn test_single_low_reading_in_hysteresis_band_does_not_shed() {
+        let mut lvd = LvdStateMachine::new(config());
+        let mut now_ms = 0;
+        feed(&mut lvd, &mut now_ms, 11.5, 11_000);
+        feed(&mut lvd, &mut now_ms, 12.2, 60_000);
+        assert_eq!(lvd.shed_loads(), &[3]);
+
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 1_000).is_empty());
+        assert!(feed(&mut lvd, &mut now_ms, 12.2, 1_000).is_empty());
+        assert_eq!(lvd.shed_loads(), &[3]);
+
+        // The next load is shed once the battery stays low for the disconnect delay
+        assert!(feed(&mut lvd, &mut now_ms, 11.5, 10_000).is_empty());
+        let actions = feed(&mut lvd, &mut now_ms, 11.5, 1_000);
+        assert_eq!(actions.as_slice(), &[LvdAction::Shed(1)]);
+    }
+}
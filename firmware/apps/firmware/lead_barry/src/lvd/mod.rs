@@ -0,0 +1,7 @@
+#![allow(unused_imports)]
+
+mod lvd_service;
+mod lvd_state_machine;
+
+pub use self::lvd_service::*;
+pub use self::lvd_state_machine::*;
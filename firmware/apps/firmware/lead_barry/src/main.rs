@@ -1,99 +1,145 @@
 //! Blinks the LED on a Pico W board
 //!
 //! This will blink the onboard LED on a Pico W, which is controlled via the CYW43 WiFi chip.
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "sim"), no_std)]
+#![cfg_attr(not(feature = "sim"), no_main)]
 #![allow(async_fn_in_trait)]
 
+mod alarms;
+mod app;
 mod async_infinite_stream;
 mod async_stream;
+mod battery;
+#[cfg(not(feature = "sim"))]
 mod board;
+mod charger;
 mod configuration;
+mod efuse;
+#[cfg(not(feature = "sim"))]
 mod global_state;
 mod global_types;
+mod history;
+#[cfg(not(feature = "sim"))]
+mod i2c_bus_clear;
 mod input;
+mod lvd;
 mod main_logic_controller;
+mod outputs;
+mod poe_watchdog;
 mod reset;
 mod rtc;
 mod shared_resources;
+#[cfg(feature = "sim")]
+mod sim;
+mod temperature;
+#[cfg(test)]
+mod test_clock;
 mod ui;
 mod units;
 mod vcp_sensors;
+#[cfg(not(feature = "sim"))]
 mod web_server;
+#[cfg(not(feature = "sim"))]
 mod wifi;
+#[cfg(not(feature = "sim"))]
 mod ws2812b_led_controller;
 
-use board::*;
-use defmt_or_log as log;
-use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
-use embassy_executor::{Executor, Spawner};
-use embassy_rp::peripherals::{DMA_CH0, I2C1, PIO0, PIO1};
-use embassy_rp::pio::{InterruptHandler as PioInterruptHandler, Pio};
-use embassy_rp::{
-    bind_interrupts,
-    i2c::{self, I2c, InterruptHandler as I2cInterruptHandler},
-    multicore::Stack,
-    peripherals::I2C0,
+#[cfg(not(feature = "sim"))]
+use {
+    app::{AppDevices, AppRunners},
+    board::*,
+    defmt_or_log as log,
+    embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice,
+    embassy_executor::{Executor, Spawner},
+    embassy_rp::peripherals::{DMA_CH0, I2C1, PIO0, PIO1},
+    embassy_rp::pio::{InterruptHandler as PioInterruptHandler, Pio},
+    embassy_rp::{
+        adc::{self, Adc, InterruptHandler as AdcInterruptHandler},
+        bind_interrupts,
+        i2c::{self, I2c, InterruptHandler as I2cInterruptHandler},
+        multicore::Stack,
+        peripherals::I2C0,
+    },
+    embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex},
+    embassy_time::Duration,
+    global_types::*,
+    i2c_bus_clear::I2c0BusClear,
+    input::*,
+    main_logic_controller::*,
+    outputs::*,
+    shared_resources::*,
+    static_cell::StaticCell,
+    temperature::*,
+    ui::*,
+    vcp_sensors::*,
+    wifi::*,
 };
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
-use embassy_time::Duration;
 
-use static_cell::StaticCell;
-
-use crate::configuration::{ConfigurationStorageBuilder, Storage};
-
-use crate::rtc::RtcDs3231Ref;
+#[cfg(not(feature = "sim"))]
+use crate::configuration::{
+    ConfigurationStorageBuilder, CountersStorage, CountersStoreBuilder, HistoryStorage, SharedFlash, Storage,
+    TemperatureSource, create_shared_flash,
+};
+#[cfg(not(feature = "sim"))]
 use crate::units::FrequencyExt;
+#[cfg(not(feature = "sim"))]
 use crate::ws2812b_led_controller::*;
-use global_types::*;
-use input::*;
-use main_logic_controller::*;
-use shared_resources::*;
-use ui::*;
-use vcp_sensors::*;
-use wifi::*;
 
 // Configure panic behavior based on features
 #[cfg(not(any(feature = "defmt", feature = "log")))]
 use panic_halt as _;
 #[cfg(feature = "defmt")]
 use {defmt_rtt as _, panic_probe as _};
-#[cfg(all(feature = "log", not(feature = "defmt")))]
+#[cfg(all(feature = "log", not(feature = "defmt"), not(feature = "sim")))]
 use {panic_rtt_target as _, rtt_target as _};
 
 // Constants
+#[cfg(not(feature = "sim"))]
 const CORE1_STACK_SIZE: usize = 4096 * 4;
+#[cfg(not(feature = "sim"))]
+const I2C0_FREQUENCY_HZ: u32 = crate::units::freq::khz(400); // Fast I2C clk for better performance
 
 // Interrupt handlers
+#[cfg(not(feature = "sim"))]
 bind_interrupts!(struct Irqs {
+    ADC_IRQ_FIFO => AdcInterruptHandler;
     I2C0_IRQ => I2cInterruptHandler<I2C0>;
     I2C1_IRQ => I2cInterruptHandler<I2C1>;
     PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
     PIO1_IRQ_0 => PioInterruptHandler<PIO1>;
 });
 
+#[cfg(not(feature = "sim"))]
 type LedControllerRunnerType = LedControllerRunner<'static, PIO1, 0, LED_NUM>;
+#[cfg(not(feature = "sim"))]
 type LedControllerBuilderType = LedControllerBuilder<LED_NUM>;
 
 // Static resources
+#[cfg(not(feature = "sim"))]
 static BUTTON_CONTROLLER: StaticCell<ButtonControllerState> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static CORE1_STACK: StaticCell<Stack<CORE1_STACK_SIZE>> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static UI_SHARED_STATE: StaticCell<UiSharedState> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static UI_CONTROL: StaticCell<UiControl> = StaticCell::new();
-static VCP_SENSORS_STATE: StaticCell<VcpSensorsState<VCP_SENSORS_EVENT_QUEUE_SIZE>> = StaticCell::new();
-static VCP_SENSORS_CONTROL: StaticCell<VcpControl> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static I2C0_BUS: StaticCell<I2c0Bus> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static I2C1_BUS: StaticCell<I2c1Bus> = StaticCell::new();
-static SHARED_RESOURCES: StaticCell<SharedResources> = StaticCell::new();
-static RTC_DS3231: StaticCell<RtcDs3231Ref<I2c0Device<'static>>> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
+static SHARED_FLASH: StaticCell<SharedFlash> = StaticCell::new();
 
+#[cfg(not(feature = "sim"))]
 struct ResourcesCore0 {
     // Owned resources
     button_controller_builder: ButtonControllerBuilder,
 
-    vcp_runner: Option<VcpSensorsRunner<'static>>,
+    app_runners: AppRunners,
     wifi_service_builder: WiFiServiceBuilder<PIO0, DMA_CH0>,
     led_controller_runner: LedControllerRunnerType,
 
@@ -101,6 +147,7 @@ struct ResourcesCore0 {
     shared_resources: &'static SharedResources,
 }
 
+#[cfg(not(feature = "sim"))]
 struct ResourcesCore1 {
     // Owned resources
     ui_runner: Option<UiRunner<'static>>,
@@ -108,6 +155,12 @@ struct ResourcesCore1 {
     core1_stack_end: usize,
 }
 
+#[cfg(feature = "sim")]
+fn main() {
+    sim::run()
+}
+
+#[cfg(not(feature = "sim"))]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     debug_memory_layout();
@@ -135,14 +188,16 @@ fn main() -> ! {
 
     //User FLASH storage
     log::info!("Initializing FLASH storage...");
-    let storage = Storage::new(p.FLASH, p.DMA_CH1);
+    let shared_flash: &'static SharedFlash = SHARED_FLASH.init(create_shared_flash(p.FLASH, p.DMA_CH1));
+    let storage = Storage::new(shared_flash);
     let configuration_storage_builder = ConfigurationStorageBuilder::new(storage);
     let configuration_storage = configuration_storage_builder.build();
+    let counters_store = CountersStoreBuilder::new(CountersStorage::new(shared_flash)).build();
 
     // Setup I2C0 with standard frequency for sensors
     log::info!("Initializing I2C0...");
     let mut i2c0_cfg = i2c::Config::default();
-    i2c0_cfg.frequency = 400.khz(); // Fast I2C clk for better performance
+    i2c0_cfg.frequency = I2C0_FREQUENCY_HZ;
     let i2c0 = I2c::new_async(p.I2C0, p.PIN_17, p.PIN_16, Irqs, i2c0_cfg);
     let i2c0_bus: &'static Mutex<CriticalSectionRawMutex, I2c<'static, I2C0, i2c::Async>> =
         I2C0_BUS.init(Mutex::new(i2c0));
@@ -166,23 +221,54 @@ fn main() -> ! {
     let ui_shared_state = UiSharedState::new();
     let state_ref = UI_SHARED_STATE.init(ui_shared_state);
     let (ui_control, ui_runner) = UiInterface::new(
-        I2cDevice::new(i2c1_bus),
-        ssd1306::size::DisplaySize128x64,
+        ssd1306_display(I2cDevice::new(i2c1_bus)),
         state_ref,
         Some(SvWelcome::new().into()),
     );
     let ui_control: &'static UiControl = UI_CONTROL.init(ui_control);
 
-    // Initialize the VCP sensors
-    log::info!("Initializing VCP sensors...");
-    let vcp_state_ref = VCP_SENSORS_STATE.init_with(VcpSensorsState::new);
-    let mut vcp_config = VcpConfig::default();
-    vcp_config.global_pv_limit = Some(VcpPowerLimits {
-        upper_voltage: 3.0, // Volts, were 13.6 (Ok battery /power voltage)
-        lower_voltage: 1.0, // Volts, were 10.45 (Low battery voltage)
+    // Initialize the services
+    let settings = embassy_futures::block_on(configuration_storage.get_settings());
+    let counters = embassy_futures::block_on(counters_store.get_counters());
+    let outputs_driver = ShiftRegisterOutputDriver::new(OutputsHardwareConfig {
+        shift_data: p.PIN_18,
+        shift_output_enable: p.PIN_19,
+        shift_latch: p.PIN_20,
+        shift_clock: p.PIN_21,
+        shift_reset: p.PIN_22,
+        dac_slice: p.PWM_SLICE7,
+        poe_dac: p.PIN_14,
+        adjustable_dac: p.PIN_15,
     });
-    let (vcp_runner, vcp_control) = VcpSensorsService::new(I2cDevice::new(i2c0_bus), vcp_state_ref, vcp_config);
-    let vcp_control: &'static VcpControl = VCP_SENSORS_CONTROL.init(vcp_control);
+    // The NTC probe on ADC0 is only set up if it is the configured source, otherwise the DS3231
+    // sensor is used.
+    let temperature_probe = if app::temperature_settings(&settings).source == TemperatureSource::Probe {
+        let adc = Adc::new(p.ADC, Irqs, adc::Config::default());
+        let channel = adc::Channel::new_pin(p.PIN_26, embassy_rp::gpio::Pull::None);
+        Some(NtcProbe::new(adc, channel))
+    } else {
+        None
+    };
+    let devices = AppDevices {
+        vcp_sensor: Ina3221Sensor::new(
+            || I2cDevice::new(i2c0_bus),
+            I2c0BusClear::new(i2c0_bus, I2C0_FREQUENCY_HZ),
+            settings.vcp_settings.fitted_chips(),
+        ),
+        outputs_driver,
+        rtc_i2c: I2cDevice::new(i2c0_bus),
+        history_flash: HistoryStorage::new(shared_flash),
+        temperature_probe,
+        led_controller,
+    };
+    let (app_runners, shared_resources) = app::init(
+        devices,
+        &settings,
+        &counters,
+        configuration_storage,
+        counters_store,
+        ui_control,
+    );
 
     // Initialize the WiFi service builder
     log::info!("Initializing WiFi service builder...");
@@ -197,19 +283,6 @@ fn main() -> ! {
 
     let wifi_service_builder = WiFiServiceBuilder::new(wifi_cfg, Irqs);
 
-    // Initialize the RTC DS3231
-    log::info!("Initializing RTC DS3231...");
-    let rtc_ds3231 = rtc::create_rtc_ds3231(I2cDevice::new(i2c0_bus));
-    let rtc_ds3231_ref: &'static RtcDs3231Ref<I2c0Device<'static>> = RTC_DS3231.init(rtc_ds3231);
-
-    let shared_resources: &'static SharedResources = SHARED_RESOURCES.init(SharedResources {
-        rtc: rtc_ds3231_ref,
-        ui_control,
-        vcp_control,
-        configuration_storage,
-        led_controller,
-    });
-
     // Spawn core threads
     embassy_rp::multicore::spawn_core1(p.CORE1, core1_stack, move || {
         let executor1 = EXECUTOR1.init(Executor::new());
@@ -234,7 +307,7 @@ fn main() -> ! {
                 spawner,
                 ResourcesCore0 {
                     button_controller_builder,
-                    vcp_runner: Some(vcp_runner),
+                    app_runners,
                     wifi_service_builder,
                     shared_resources,
                     led_controller_runner,
@@ -244,6 +317,7 @@ fn main() -> ! {
     });
 }
 
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn core0_init(spawner: Spawner, resources: ResourcesCore0) -> ! {
     log::info!("Starting core 0 thread...");
@@ -256,18 +330,15 @@ async fn core0_init(spawner: Spawner, resources: ResourcesCore0) -> ! {
         .spawn(led_controller_task(resources.led_controller_runner))
         .unwrap();
 
-    // Spawn the VCP sensors task on Core 0
-    if let Some(vcp_runner) = resources.vcp_runner {
-        // Spawn the VCP sensors task on core 0
-        spawner.spawn(vcp_sensors_runner_task(vcp_runner)).unwrap();
-    }
+    // Spawn the services on core 0
+    app::spawn_tasks(spawner, resources.app_runners, resources.shared_resources);
 
     // Initialize button controller
     let button_controller_state = BUTTON_CONTROLLER.init(ButtonControllerState::new());
     let (button_controller, button_controller_runner) =
         resources.button_controller_builder.build(button_controller_state);
     spawner
-        .spawn(buttons_controller_task(button_controller_runner))
+        .spawn(app::buttons_controller_task(button_controller_runner))
         .unwrap();
 
     log::info!("Build wifi service");
@@ -277,6 +348,7 @@ async fn core0_init(spawner: Spawner, resources: ResourcesCore0) -> ! {
     main_logic_controller(spawner, resources.shared_resources, wifi_service, button_controller).await;
 }
 
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn core1_init(spawner: Spawner, resources: ResourcesCore1) {
     log::info!("Starting core 1 thread...");
@@ -290,10 +362,11 @@ async fn core1_init(spawner: Spawner, resources: ResourcesCore1) {
 
     // Spawn the UI task on Core 1
     if let Some(ui_runner) = resources.ui_runner {
-        spawner.spawn(ui_runner_task(ui_runner)).unwrap();
+        spawner.spawn(app::ui_runner_task(ui_runner)).unwrap();
     }
 }
 
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn core_0_stack_monitor_task() -> ! {
     log::info!("Starting core 0 stack monitor task...");
@@ -312,6 +385,7 @@ async fn core_0_stack_monitor_task() -> ! {
     }
 }
 
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn core_1_stack_monitor_task(core1_stack_base: usize, core1_stack_end: usize) -> ! {
     log::info!("Starting core 1 stack monitor task...");
@@ -330,36 +404,21 @@ async fn core_1_stack_monitor_task(core1_stack_base: usize, core1_stack_end: usi
     }
 }
 
-#[embassy_executor::task]
-async fn buttons_controller_task(button_controller_runner: ButtonControllerRunner<'static>) -> ! {
-    log::info!("Starting buttons controller task...");
-    button_controller_runner.run().await
-}
-
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn led_controller_task(led_controller_runner: LedControllerRunnerType) -> ! {
     log::info!("Starting led controller task...");
     led_controller_runner.run().await;
 }
 
-#[embassy_executor::task]
-async fn ui_runner_task(mut ui_runner: UiRunner<'static>) -> ! {
-    log::info!("Starting UI task...");
-    ui_runner.run().await;
-}
-
-#[embassy_executor::task]
-async fn vcp_sensors_runner_task(mut vcp_sensors_runner: VcpSensorsRunner<'static>) -> ! {
-    log::info!("Starting VCP sensors task...");
-    vcp_sensors_runner.run().await
-}
-
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn cyw43_task(runner: WiFiDriverRunner<PIO0, DMA_CH0>) -> ! {
     log::info!("Starting CYW43 driver task...");
     runner.run().await
 }
 
+#[cfg(not(feature = "sim"))]
 fn log_system_frequencies() {
     let sys_freq = embassy_rp::clocks::clk_sys_freq();
     let peri_freq = embassy_rp::clocks::clk_peri_freq();
@@ -378,6 +437,7 @@ fn log_system_frequencies() {
     log::info!("================================");
 }
 
+#[cfg(not(feature = "sim"))]
 fn debug_memory_layout() {
     unsafe extern "C" {
         static _ram_start: u32;
@@ -403,6 +463,7 @@ fn debug_memory_layout() {
     log::info!("Stack Size:   {} bytes", stack_size);
 }
 
+#[cfg(not(feature = "sim"))]
 fn get_core_0_stack_usage() -> (usize, usize) {
     unsafe extern "C" {
         static mut _stack_start: u32;
@@ -417,6 +478,7 @@ fn get_core_0_stack_usage() -> (usize, usize) {
     (stack_start - current_sp, &raw const _stack_size as usize)
 }
 
+#[cfg(not(feature = "sim"))]
 fn get_core_1_stack_usage(core1_stack_base: usize, core1_stack_end: usize) -> (usize, usize) {
     let current_sp = cortex_m::register::msp::read() as usize;
     log::debug_assert!(core1_stack_end > core1_stack_base);
@@ -1,62 +1,67 @@
+#[cfg(not(feature = "sim"))]
 use core::mem::MaybeUninit;
-use core::usize;
 
 use defmt_or_log as log;
 
 use ds323x::DateTimeAccess;
 use ds323x::Timelike;
-use embassy_executor::Spawner;
 use embassy_futures::select::*;
-use embassy_net::Stack;
+#[cfg(not(feature = "sim"))]
+use {
+    bump_into::BumpInto, embassy_executor::Spawner, embassy_net::Stack, embassy_rp::clocks::RoscRng,
+    embassy_sync::channel::Channel, static_cell::StaticCell,
+};
 
-use bump_into::BumpInto;
-use embassy_rp::clocks::RoscRng;
-use embassy_sync::channel::Channel;
 use embassy_sync::lazy_lock::LazyLock;
 use embassy_time::Ticker;
 use embassy_time::Timer;
-use static_cell::StaticCell;
 
+use crate::battery::SocConfidence;
 use crate::configuration::*;
+#[cfg(not(feature = "sim"))]
 use crate::global_state::*;
 use crate::input::*;
-use crate::reset::trigger_system_reset;
+use crate::outputs::OutputVoltage;
 use crate::rtc::*;
 use crate::shared_resources::*;
 use crate::ui::*;
 use crate::units::TimeExt as _;
-use crate::vcp_sensors::VcpSensorsEvents;
-use crate::web_server::HttpConfigServer;
-use crate::wifi::*;
-
+use crate::vcp_sensors::{CHANNELS_PER_CHIP, VcpChannelCalibration, VcpReading};
+#[cfg(not(feature = "sim"))]
+use crate::{reset::trigger_system_reset, web_server::HttpConfigServer, wifi::*};
+
+/// How long both buttons have to be held to start or stop the equalization
+const COMBO_HOLD_S: u64 = 3;
+/// The seconds each chip of channels is shown on the energy screen
+const ENERGY_PAGE_S: usize = 3;
+/// The gain change of a button press on the calibration screen
+const GAIN_TRIM_STEP: f32 = 0.001;
+/// The current offset change of a button press on the calibration screen
+const CURRENT_OFFSET_TRIM_STEP_A: f32 = 0.001;
+#[cfg(not(feature = "sim"))]
 const SOCKETS: usize = 3;
+#[cfg(not(feature = "sim"))]
 const HTTP_SERVER_WORKERS: usize = 1;
+#[cfg(not(feature = "sim"))]
 const HTTP_SERVER_BUFFER_SIZE: usize = HttpConfigServer::<SOCKETS>::MIN_SOCKET_POOL_BUFFER_SIZE;
 
+#[cfg(not(feature = "sim"))]
 static AP_STATUS_CHANNEL: StaticCell<ApStatusChannel> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static HTTP_SERVER_BUFFER: StaticCell<[core::mem::MaybeUninit<u8>; HTTP_SERVER_BUFFER_SIZE]> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static HTTP_SERVER_ALLOCATOR: StaticCell<BumpInto> = StaticCell::new();
+#[cfg(not(feature = "sim"))]
 static HTTP_SERVER: StaticCell<HttpConfigServer<'static, SOCKETS>> = StaticCell::new();
 
+#[cfg(not(feature = "sim"))]
 pub async fn main_logic_controller(
     spawner: Spawner,
     shared: &'static SharedResources,
     wifi_service: WifiService,
     button_controller: ButtonController<'_>,
 ) -> ! {
-    let mut is_force_ap_mode_triggered = false;
-    match detect_after_reset_actions(button_controller).await {
-        AfterResetActions::FactoryReset => {
-            do_factory_reset(shared.ui_control, shared.configuration_storage).await;
-        }
-        AfterResetActions::ApMode => {
-            log::info!("Force AP mode was triggered after reset");
-            is_force_ap_mode_triggered = true;
-        }
-        AfterResetActions::None => {
-            log::info!("No special actions after reset");
-        }
-    }
+    let is_force_ap_mode_triggered = handle_after_reset_actions(shared, button_controller).await;
 
     let set_screen = |new_screen: ScCollection| async { shared.ui_control.switch(new_screen).await };
     let settings = shared.configuration_storage.get_settings().await;
@@ -132,6 +137,7 @@ pub async fn main_logic_controller(
         if network_ready {
             log::info!("Joined WiFi network done");
             global_state().set_wifi_mode(WiFiMode::Client).await;
+            shared.poe_watchdog_control.start(net_stack);
         }
 
         Timer::after(5.s()).await;
@@ -173,37 +179,112 @@ pub async fn main_logic_controller(
         show_visit_screen(shared).await;
     }
 
+    run_screens(shared, button_controller).await
+}
+
+/// Runs the actions requested by the buttons held through the reset. Returns true if the AP mode
+/// is forced.
+pub async fn handle_after_reset_actions(
+    shared: &'static SharedResources,
+    button_controller: ButtonController<'_>,
+) -> bool {
+    match detect_after_reset_actions(button_controller).await {
+        AfterResetActions::FactoryReset => {
+            do_factory_reset(shared.ui_control, shared.configuration_storage, shared.counters_store).await;
+            false
+        }
+        AfterResetActions::ApMode => {
+            log::info!("Force AP mode was triggered after reset");
+            true
+        }
+        AfterResetActions::None => {
+            log::info!("No special actions after reset");
+            false
+        }
+    }
+}
+
+/// Switches the screens on the button presses
+pub async fn run_screens(shared: &'static SharedResources, button_controller: ButtonController<'_>) -> ! {
     let mut channel: u8 = 0;
+    let mut info_screen = InfoScreen::Time;
 
-    let mut current_screan = button_controller.map_and_filter(button_event_to_screan).next().await;
+    let mut current_screan = next_bt_action(&button_controller).await;
 
     loop {
         match current_screan {
-            ActiveScrean::TimeScreen => {
-                current_screan = do_until_bt_action(&button_controller, || async {
-                    show_time_screen(shared).await;
-                })
-                .await;
-            }
-            ActiveScrean::VoltageScreen => {
-                log::debug!("Showing voltage for channel {}", channel);
+            ActiveScrean::InfoScreen => {
                 current_screan = on_repeat(
                     &current_screan,
-                    do_until_bt_action(&button_controller, || async {
-                        show_voltage_reading(shared, channel).await;
+                    do_until_bt_action(shared, &button_controller, || async {
+                        show_info_screen(shared, info_screen).await;
                     })
                     .await,
                     || async {
-                        channel = (channel + 1) % 3;
-                        log::debug!("Switching to voltage channel {}", channel);
+                        info_screen = info_screen.next();
+                        log::debug!("Switching to info screen {}", info_screen);
                     },
                 )
                 .await;
             }
+            ActiveScrean::VoltageScreen => {
+                log::debug!("Showing voltage for channel {}", channel);
+                current_screan = do_until_bt_action(shared, &button_controller, || async {
+                    show_voltage_reading(shared, channel).await;
+                })
+                .await;
+                if current_screan == ActiveScrean::VoltageScreen {
+                    // Holding the blue button calibrates the channel, a short press shows the next one
+                    let held =
+                        match select(show_voltage_reading(shared, channel), is_combo_held(&button_controller)).await {
+                            Either::First(_) => log::unreachable!(),
+                            Either::Second(held) => held,
+                        };
+                    if held {
+                        current_screan = ActiveScrean::Calibration;
+                    } else {
+                        channel = (channel + 1) % shared.vcp_control.channel_count() as u8;
+                        log::debug!("Switching to voltage channel {}", channel);
+                    }
+                }
+            }
+            ActiveScrean::Calibration => {
+                log::info!("Calibrating channel {}", channel);
+                run_calibration_screen(shared, &button_controller, channel).await;
+                current_screan = ActiveScrean::VoltageScreen;
+            }
+            ActiveScrean::Equalization => {
+                // Keeping both buttons held starts or stops the equalization
+                let held = match select(show_equalization_screen(shared), is_combo_held(&button_controller)).await {
+                    Either::First(_) => log::unreachable!(),
+                    Either::Second(held) => held,
+                };
+                if held {
+                    let equalization_control = shared.equalization_control;
+                    if equalization_control.status().await.running {
+                        equalization_control.stop();
+                    } else {
+                        equalization_control.start();
+                    }
+                }
+                current_screan = do_until_bt_action(shared, &button_controller, || async {
+                    show_equalization_screen(shared).await;
+                })
+                .await;
+            }
+            ActiveScrean::FuseTrip => {
+                // Bring the fuse screen up, the next press of the yellow button continues from it
+                info_screen = InfoScreen::Fuses;
+                current_screan = do_until_bt_action(shared, &button_controller, || async {
+                    show_info_screen(shared, InfoScreen::Fuses).await;
+                })
+                .await;
+            }
         }
     }
 }
 
+#[cfg(not(feature = "sim"))]
 async fn do_start_ap_mode(
     shared: &'static SharedResources,
     wifi_service: &WifiService,
@@ -306,16 +387,112 @@ async fn do_start_ap_mode(
 
 fn button_event_to_screan(event: &ButtonEvent) -> Option<ActiveScrean> {
     match event {
-        ButtonEvent::Pressed(Buttons::Yellow) => Some(ActiveScrean::TimeScreen),
+        ButtonEvent::Pressed(Buttons::Yellow) => Some(ActiveScrean::InfoScreen),
         ButtonEvent::Pressed(Buttons::Blue) => Some(ActiveScrean::VoltageScreen),
         _ => None,
     }
 }
 
+/// Waits for a button press selecting a new screen. A press of one button while the other one is
+/// held brings up the equalization screen.
+async fn next_bt_action(button_controller: &ButtonController<'_>) -> ActiveScrean {
+    loop {
+        let event = button_controller.receive().await;
+        if let ButtonEvent::Pressed(button) = event
+            && button_controller.get_last_state(button.other()).await == Some(ButtonState::Pressed)
+        {
+            return ActiveScrean::Equalization;
+        }
+        if let Some(new_screan) = button_event_to_screan(&event) {
+            return new_screan;
+        }
+    }
+}
+
+/// Returns true if neither button is released within the combination hold time
+async fn is_combo_held(button_controller: &ButtonController<'_>) -> bool {
+    let released = async { while let ButtonEvent::Pressed(_) = button_controller.receive().await {} };
+    matches!(select(Timer::after(COMBO_HOLD_S.s()), released).await, Either::First(_))
+}
+
 #[derive(PartialEq)]
 enum ActiveScrean {
-    TimeScreen,
+    InfoScreen,
     VoltageScreen,
+    /// Both buttons are pressed together
+    Equalization,
+    /// An electronic fuse has just tripped
+    FuseTrip,
+    /// The blue button is held on the voltage screen
+    Calibration,
+}
+
+/// The corrections trimmed in turn on the calibration screen
+#[derive(Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+enum CalibrationStep {
+    VoltageGain,
+    CurrentOffset,
+    CurrentGain,
+}
+
+impl CalibrationStep {
+    fn next(self) -> Option<Self> {
+        match self {
+            CalibrationStep::VoltageGain => Some(CalibrationStep::CurrentOffset),
+            CalibrationStep::CurrentOffset => Some(CalibrationStep::CurrentGain),
+            CalibrationStep::CurrentGain => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CalibrationStep::VoltageGain => "V gain",
+            CalibrationStep::CurrentOffset => "I zero",
+            CalibrationStep::CurrentGain => "I gain",
+        }
+    }
+
+    /// Moves the trimmed correction by `steps` trim steps
+    fn trim(&self, mut calibration: VcpChannelCalibration, steps: f32) -> VcpChannelCalibration {
+        match self {
+            CalibrationStep::VoltageGain => calibration.voltage.gain += steps * GAIN_TRIM_STEP,
+            CalibrationStep::CurrentOffset => calibration.current.offset += steps * CURRENT_OFFSET_TRIM_STEP_A,
+            CalibrationStep::CurrentGain => calibration.current.gain += steps * GAIN_TRIM_STEP,
+        }
+        calibration
+    }
+}
+
+/// Text screens cycled by repeated presses of the yellow button
+#[derive(Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+enum InfoScreen {
+    Time,
+    Battery,
+    Health,
+    Lvd,
+    Outputs,
+    Fuses,
+    Energy,
+    Charger,
+    Sensor,
+}
+
+impl InfoScreen {
+    fn next(self) -> Self {
+        match self {
+            InfoScreen::Time => InfoScreen::Battery,
+            InfoScreen::Battery => InfoScreen::Health,
+            InfoScreen::Health => InfoScreen::Lvd,
+            InfoScreen::Lvd => InfoScreen::Outputs,
+            InfoScreen::Outputs => InfoScreen::Fuses,
+            InfoScreen::Fuses => InfoScreen::Energy,
+            InfoScreen::Energy => InfoScreen::Charger,
+            InfoScreen::Charger => InfoScreen::Sensor,
+            InfoScreen::Sensor => InfoScreen::Time,
+        }
+    }
 }
 
 async fn on_repeat<F, Fut>(old: &ActiveScrean, new: ActiveScrean, f: F) -> ActiveScrean
@@ -331,16 +508,37 @@ where
     }
 }
 
-async fn do_until_bt_action<F, Fut>(button_controller: &ButtonController<'_>, mut f: F) -> ActiveScrean
+/// Runs `f` until a button selects a new screen or an electronic fuse trips
+async fn do_until_bt_action<F, Fut>(
+    shared: &'static SharedResources,
+    button_controller: &ButtonController<'_>,
+    mut f: F,
+) -> ActiveScrean
 where
     F: FnMut() -> Fut,
     Fut: core::future::Future<Output = core::convert::Infallible>,
 {
     let res =
-        embassy_futures::select::select(button_controller.map_and_filter(button_event_to_screan).next(), f()).await;
+        embassy_futures::select::select3(next_bt_action(button_controller), shared.efuse_control.wait_trip(), f())
+            .await;
     match res {
-        Either::First(new_screan) => new_screan,
-        Either::Second(_) => log::unreachable!(),
+        Either3::First(new_screan) => new_screan,
+        Either3::Second(_) => ActiveScrean::FuseTrip,
+        Either3::Third(_) => log::unreachable!(),
+    }
+}
+
+async fn show_info_screen(shared: &'static SharedResources, screen: InfoScreen) -> ! {
+    match screen {
+        InfoScreen::Time => show_time_screen(shared).await,
+        InfoScreen::Battery => show_battery_screen(shared).await,
+        InfoScreen::Health => show_health_screen(shared).await,
+        InfoScreen::Lvd => show_lvd_screen(shared).await,
+        InfoScreen::Outputs => show_outputs_screen(shared).await,
+        InfoScreen::Fuses => show_fuses_screen(shared).await,
+        InfoScreen::Energy => show_energy_screen(shared).await,
+        InfoScreen::Charger => show_charger_screen(shared).await,
+        InfoScreen::Sensor => show_sensor_screen(shared).await,
     }
 }
 
@@ -390,34 +588,490 @@ async fn show_time_screen(shared: &'static SharedResources) -> ! {
     }
 }
 
+async fn show_battery_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut battery_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.battery_control.status().await;
+
+        battery_str.clear();
+        if status.confidence == SocConfidence::Unknown {
+            core::fmt::write(&mut battery_str, format_args!("Waiting for\nreadings...")).ok();
+        } else {
+            core::fmt::write(
+                &mut battery_str,
+                format_args!(
+                    "SoC: {:.0}%\nConfidence: {}\n{:.2}V {:.2}A",
+                    status.soc_percent,
+                    status.confidence.name(),
+                    status.voltage,
+                    status.current
+                ),
+            )
+            .ok();
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Battery"),
+            message: battery_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_health_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut health_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.health_control.status().await;
+
+        health_str.clear();
+        match status.resistance_mohm {
+            Some(resistance_mohm) => {
+                core::fmt::write(&mut health_str, format_args!("IR: {:.1}mOhm\n", resistance_mohm))
+            }
+            None => core::fmt::write(&mut health_str, format_args!("IR: -\n")),
+        }
+        .ok();
+        match status.capacity_percent {
+            Some(capacity_percent) => {
+                core::fmt::write(&mut health_str, format_args!("Cap: {:.0}%\n", capacity_percent))
+            }
+            None => core::fmt::write(&mut health_str, format_args!("Cap: -\n")),
+        }
+        .ok();
+        let verdict = if status.replace_soon { "Replace soon!" } else { "OK" };
+        core::fmt::write(&mut health_str, format_args!("{}", verdict)).ok();
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Battery health"),
+            message: health_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_lvd_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut lvd_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.lvd_control.status().await;
+
+        lvd_str.clear();
+        if status.enabled {
+            core::fmt::write(
+                &mut lvd_str,
+                format_args!(
+                    "{}\nBattery: {:.2}V\nLoads off: {}",
+                    status.state.name(),
+                    status.voltage,
+                    status.shed_loads.len()
+                ),
+            )
+            .ok();
+        } else {
+            core::fmt::write(&mut lvd_str, format_args!("Disabled")).ok();
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Low Voltage"),
+            message: lvd_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_outputs_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut outputs_str = MessageString::complimentary_str();
+    loop {
+        let statuses = shared.outputs_control.statuses().await;
+
+        outputs_str.clear();
+        for (i, status) in statuses.iter().enumerate() {
+            // Two outputs per line
+            let separator = if i % 2 == 1 { "\n" } else { " " };
+            core::fmt::write(&mut outputs_str, format_args!("{}:", status.output.name())).ok();
+            match (status.active, status.voltage) {
+                (false, _) if status.fuse_tripped => core::fmt::write(&mut outputs_str, format_args!("TRP")),
+                (false, _) if status.lvd_shed => core::fmt::write(&mut outputs_str, format_args!("LVD")),
+                (false, _) if status.watchdog_off => core::fmt::write(&mut outputs_str, format_args!("WDT")),
+                (false, _) => core::fmt::write(&mut outputs_str, format_args!("off")),
+                (true, OutputVoltage::Selectable(voltage)) => {
+                    core::fmt::write(&mut outputs_str, format_args!("{:.0}V", voltage.volts()))
+                }
+                (true, OutputVoltage::Adjustable(setpoint)) => {
+                    core::fmt::write(&mut outputs_str, format_args!("{:.1}", setpoint))
+                }
+                (true, OutputVoltage::Battery) => core::fmt::write(&mut outputs_str, format_args!("on")),
+            }
+            .ok();
+            if i + 1 < statuses.len() {
+                outputs_str.push_str(separator).ok();
+            }
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Outputs"),
+            message: outputs_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_fuses_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut fuses_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.efuse_control.status().await;
+
+        fuses_str.clear();
+        for (i, fuse) in status.fuses.iter().enumerate() {
+            let separator = if i > 0 { "\n" } else { "" };
+            core::fmt::write(
+                &mut fuses_str,
+                format_args!(
+                    "{}{}:{} {}",
+                    separator,
+                    fuse.output.name(),
+                    fuse.state.name(),
+                    fuse.trips
+                ),
+            )
+            .ok();
+        }
+        match status.trips.last() {
+            // The last line is left for the latest trip when there is room for it
+            Some(trip) if status.fuses.len() < 3 => {
+                core::fmt::write(
+                    &mut fuses_str,
+                    format_args!("\nLast:{} {:.2}A", trip.output.name(), trip.current),
+                )
+                .ok();
+            }
+            _ if status.fuses.is_empty() => {
+                core::fmt::write(&mut fuses_str, format_args!("No fuses")).ok();
+            }
+            _ => {}
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("E-Fuses"),
+            message: fuses_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_energy_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut energy_str = MessageString::complimentary_str();
+    let mut seconds: usize = 0;
+    loop {
+        let energy = shared.vcp_control.energy();
+        // The channels of one chip fit the screen, the chips take turns
+        let channel_count = shared.vcp_control.channel_count();
+        let pages = channel_count.div_ceil(CHANNELS_PER_CHIP).max(1);
+        let first = (seconds / ENERGY_PAGE_S % pages) * CHANNELS_PER_CHIP;
+        seconds = seconds.wrapping_add(1);
+
+        // The energy in and out of each channel since the trip counters were reset
+        energy_str.clear();
+        let page = energy
+            .iter()
+            .enumerate()
+            .take(channel_count)
+            .skip(first)
+            .take(CHANNELS_PER_CHIP);
+        for (i, channel) in page {
+            let separator = if i > first { "\n" } else { "" };
+            core::fmt::write(
+                &mut energy_str,
+                format_args!(
+                    "{}{}:{:.1}/{:.1}Wh",
+                    separator,
+                    shared.channel_map.role(i as u8).short_name(),
+                    channel.trip.energy_in_wh,
+                    channel.trip.energy_out_wh
+                ),
+            )
+            .ok();
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Energy"),
+            message: energy_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_charger_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut charger_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.charger_control.status().await;
+
+        charger_str.clear();
+        let minutes = status.time_in_stage_s / 60;
+        core::fmt::write(
+            &mut charger_str,
+            format_args!(
+                "{}\nFor: {}h {:02}m\n{:.2}V {:+.1}A",
+                status.stage.name(),
+                minutes / 60,
+                minutes % 60,
+                status.battery_voltage,
+                status.charge_current
+            ),
+        )
+        .ok();
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Charger"),
+            message: charger_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_sensor_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut sensor_str = MessageString::complimentary_str();
+    loop {
+        let health = shared.vcp_control.health();
+
+        sensor_str.clear();
+        core::fmt::write(
+            &mut sensor_str,
+            format_args!(
+                "{}\nErrors: {}\nRecovered: {}",
+                health.health.name(),
+                health.consecutive_errors,
+                health.recoveries
+            ),
+        )
+        .ok();
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Sensor"),
+            message: sensor_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
+async fn show_equalization_screen(shared: &'static SharedResources) -> ! {
+    let mut ticker = Ticker::every(1.s());
+
+    let mut equalization_str = MessageString::complimentary_str();
+    loop {
+        let status = shared.equalization_control.status().await;
+
+        equalization_str.clear();
+        if status.running {
+            let elapsed_min = status.elapsed_s.unwrap_or_default() / 60;
+            let duration_min = status.duration_s / 60;
+            core::fmt::write(
+                &mut equalization_str,
+                format_args!(
+                    "Run {}h{:02}m/{}h{:02}m\n{:.2}V {:+.1}A\n",
+                    elapsed_min / 60,
+                    elapsed_min % 60,
+                    duration_min / 60,
+                    duration_min % 60,
+                    status.battery_voltage,
+                    status.charge_current
+                ),
+            )
+            .ok();
+            match status.temperature_c {
+                Some(temperature_c) => {
+                    core::fmt::write(&mut equalization_str, format_args!("{:.0} C Both:stop", temperature_c))
+                }
+                None => core::fmt::write(&mut equalization_str, format_args!("Both:stop")),
+            }
+            .ok();
+        } else if status.supported {
+            let last = status.record.last_result.map(|result| result.name()).unwrap_or("none");
+            core::fmt::write(&mut equalization_str, format_args!("Idle\nLast: {}\nBoth:start", last)).ok();
+        } else {
+            core::fmt::write(
+                &mut equalization_str,
+                format_args!("Not supported\nor no charger\noutput set"),
+            )
+            .ok();
+        }
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Equalize"),
+            message: equalization_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+        ticker.next().await;
+    }
+}
+
 async fn show_voltage_reading(shared: &'static SharedResources, channel: u8) -> ! {
     let mut ticker = Ticker::every(40.ms());
 
-    // Pick a channel to monitor
-    shared.vcp_control.disable_all_channels().await;
-    shared.vcp_control.enable_channel(channel).await;
-    shared.vcp_control.flush_events();
+    // Watch the channel without touching the enables, the other consumers keep all their readings
+    let mut watcher = shared
+        .vcp_control
+        .watch_reading(channel)
+        .expect("No free VCP reading watcher for the voltage screen");
 
     static VOLTAGE: LazyLock<SharedDataModel<f32>> = LazyLock::new(|| SharedDataModel::new(0f32));
     let voltage = VOLTAGE.get();
 
     let mut title = DmVcpTitle::complimentary_str();
-    core::fmt::write(&mut title, format_args!("Channel {}", channel + 1)).ok();
+    core::fmt::write(&mut title, format_args!("{}", shared.channel_map.label(channel))).ok();
 
     let vcp = DmVcp::new(voltage, DmVcpBaseUnits::Volts, title.into());
     shared.ui_control.switch(vcp.into()).await;
 
     // Voltage update loop
     loop {
-        if let VcpSensorsEvents::Reading(reading) = shared.vcp_control.receive_event().await
-            && reading.channel == channel
-        {
-            *voltage.lock().await = reading.voltage.value();
-        }
+        let reading = watcher.changed().await;
+        *voltage.lock().await = reading.voltage.value();
         ticker.next().await;
     }
 }
 
+/// Trims the calibration of the channel against a reference meter. Yellow raises and blue lowers the
+/// correction of the step, a press of one button while the other one is held moves on to the next
+/// step. The trimmed calibration applies at once and is saved after the last step.
+async fn run_calibration_screen(
+    shared: &'static SharedResources,
+    button_controller: &ButtonController<'_>,
+    channel: u8,
+) {
+    let mut ticker = Ticker::every(500.ms());
+
+    let mut calibration = shared
+        .configuration_storage
+        .get_settings()
+        .await
+        .calibration_settings
+        .channels[channel as usize];
+    let mut step = CalibrationStep::VoltageGain;
+    let mut reading: Option<VcpReading> = shared.vcp_control.latest_reading(channel);
+    // The releases which follow a press of both buttons do not trim. The blue button held to get
+    // here is still down.
+    let mut skip_release = button_controller.get_last_state(Buttons::Blue).await == Some(ButtonState::Pressed);
+
+    let mut calibration_str = MessageString::complimentary_str();
+    loop {
+        calibration_str.clear();
+        core::fmt::write(
+            &mut calibration_str,
+            format_args!("{} {}\n", shared.channel_map.label(channel), step.name()),
+        )
+        .ok();
+        match step {
+            CalibrationStep::VoltageGain => {
+                core::fmt::write(&mut calibration_str, format_args!("x{:.4}", calibration.voltage.gain))
+            }
+            CalibrationStep::CurrentOffset => core::fmt::write(
+                &mut calibration_str,
+                format_args!("{:+.3}A", calibration.current.offset),
+            ),
+            CalibrationStep::CurrentGain => {
+                core::fmt::write(&mut calibration_str, format_args!("x{:.4}", calibration.current.gain))
+            }
+        }
+        .ok();
+        match (step, reading) {
+            (_, None) => core::fmt::write(&mut calibration_str, format_args!(" -")),
+            (CalibrationStep::VoltageGain, Some(reading)) => {
+                core::fmt::write(&mut calibration_str, format_args!(" {:.3}V", reading.voltage.value()))
+            }
+            (_, Some(reading)) => {
+                core::fmt::write(&mut calibration_str, format_args!(" {:.3}A", reading.current.value()))
+            }
+        }
+        .ok();
+        core::fmt::write(&mut calibration_str, format_args!("\nY:+ B:- Both:next")).ok();
+
+        let msg = DmMessage {
+            title: MsgTitleString::from_str("Calibration"),
+            message: calibration_str.clone().into(),
+        };
+        shared.ui_control.switch(msg.into()).await;
+
+        match select(button_controller.receive(), ticker.next()).await {
+            Either::First(ButtonEvent::Pressed(button)) => {
+                if button_controller.get_last_state(button.other()).await == Some(ButtonState::Pressed) {
+                    skip_release = true;
+                    match step.next() {
+                        Some(next) => step = next,
+                        None => {
+                            save_calibration(shared, channel, calibration).await;
+                            return;
+                        }
+                    }
+                }
+            }
+            Either::First(ButtonEvent::Released(button)) => {
+                if skip_release {
+                    skip_release = button_controller.get_last_state(button.other()).await == Some(ButtonState::Pressed);
+                } else {
+                    let steps = if button == Buttons::Yellow { 1.0 } else { -1.0 };
+                    let trimmed = step.trim(calibration, steps);
+                    if trimmed.is_valid() {
+                        calibration = trimmed;
+                        shared.vcp_control.set_calibration(channel, calibration).await;
+                    }
+                }
+            }
+            Either::Second(_) => {
+                // The coalesced latest reading, refreshed at the pace of the screen
+                reading = shared.vcp_control.latest_reading(channel);
+            }
+        }
+    }
+}
+
+/// Stores the calibration of the channel trimmed with the buttons
+async fn save_calibration(shared: &'static SharedResources, channel: u8, calibration: VcpChannelCalibration) {
+    shared
+        .configuration_storage
+        .modify_settings(|settings| {
+            settings.calibration_settings.channels[channel as usize] = calibration;
+        })
+        .await;
+    let message = match shared.configuration_storage.save().await {
+        Ok(_) => "Calibration saved",
+        Err(e) => {
+            log::error!("Failed to save calibration: {:?}", e);
+            "Failed to save calibration"
+        }
+    };
+    let msg = DmMessage {
+        title: MsgTitleString::from_str("Calibration"),
+        message: MessageString::from_str(message),
+    };
+    shared.ui_control.switch(msg.into()).await;
+    Timer::after(2.s()).await;
+}
+
+#[cfg(not(feature = "sim"))]
 async fn show_visit_screen(shared: &'static SharedResources) {
     if let Some(ip) = global_state().get_device_ip().await {
         let mut invitation = MessageString::complimentary_str();
@@ -433,6 +1087,7 @@ async fn show_visit_screen(shared: &'static SharedResources) {
 
 /// Helper function to initialize the HTTP allocator with the correct generic parameters,
 /// since Rust doesn't allow using const generics in async functions directly
+#[cfg(not(feature = "sim"))]
 fn init_http_allocator() -> &'static mut BumpInto<'static> {
     let buffer = HTTP_SERVER_BUFFER.init_with(|| bump_into::space_uninit!(HTTP_SERVER_BUFFER_SIZE));
     HTTP_SERVER_ALLOCATOR.init_with(|| BumpInto::from_slice(buffer))
@@ -440,12 +1095,14 @@ fn init_http_allocator() -> &'static mut BumpInto<'static> {
 
 /// Helper function to create the HTTP server with the correct generic parameters,
 /// since Rust doesn't allow using const generics in async functions directly
+#[cfg(not(feature = "sim"))]
 #[inline(always)]
 fn create_http_server(stack: Stack<'static>) -> &'static HttpConfigServer<'static, SOCKETS> {
     HTTP_SERVER.init_with(|| HttpConfigServer::<'static, SOCKETS>::new(init_http_allocator(), stack))
 }
 
 //HTTP configuration server task
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task(pool_size = HTTP_SERVER_WORKERS)]
 async fn start_http_config_server(
     server: &'static HttpConfigServer<'static, SOCKETS>,
@@ -459,6 +1116,7 @@ async fn start_http_config_server(
     server.run(&mut worker_buffer, spawner, shared).await;
 }
 
+#[cfg(not(feature = "sim"))]
 fn generate_random_password_uppercase() -> heapless::String<64> {
     let mut rng = RoscRng;
     let mut pwd = heapless::String::<64>::new();
@@ -475,6 +1133,7 @@ fn generate_random_password_uppercase() -> heapless::String<64> {
 }
 
 /* Tasks */
+#[cfg(not(feature = "sim"))]
 #[embassy_executor::task]
 async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
     runner.run().await
@@ -482,14 +1141,19 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
 
 async fn do_factory_reset(
     ui_control: &UiControl<'_>,
-    configuration_storage: &'static ConfigurationStorage<'static>,
+    configuration_storage: &'static ConfigurationStorage,
+    counters_store: &'static CountersStore,
 ) -> bool {
     let msg = DmMessage {
         title: MsgTitleString::from_str("Factory Reset"),
         message: MessageString::from_str("Performing factory reset..."),
     };
     ui_control.switch(msg.into()).await;
-    let res = if let Err(e) = configuration_storage.factory_reset().await {
+    let result = match configuration_storage.factory_reset().await {
+        Ok(()) => counters_store.factory_reset().await,
+        Err(e) => Err(e),
+    };
+    let res = if let Err(e) = result {
         log::error!("Factory reset failed: {:?}", e);
         let msg = DmMessage {
             title: MsgTitleString::from_str("ERROR"),
@@ -530,6 +1194,7 @@ async fn detect_after_reset_actions(button_controller: ButtonController<'_>) ->
     AfterResetActions::None
 }
 
+#[cfg(not(feature = "sim"))]
 async fn reboot_device(ui_control: &UiControl<'_>) -> ! {
     let msg = DmMessage {
         title: MsgTitleString::from_str("Rebooting"),
@@ -0,0 +1,161 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+
+pub const OUTPUT_COUNT: usize = 5;
+
+/// Lower bound of the adjustable DC/DC converters
+pub const MIN_ADJUSTABLE_VOLTAGE: f32 = 5.0; // Volts
+/// Upper bound of the adjustable DC/DC converters
+pub const MAX_ADJUSTABLE_VOLTAGE: f32 = 15.0; // Volts
+
+/// The controlled DC outputs of the board
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+#[repr(u8)]
+pub enum OutputId {
+    /// DC output 1 with selectable 9/12V
+    Dc1 = 0,
+    /// DC output 2 with selectable 9/12V
+    Dc2 = 1,
+    /// DC output 3 with digitally controlled voltage in range 5-15V
+    Adjustable = 2,
+    /// 100Base passive PoE injector with its own 5-15V converter
+    Poe = 3,
+    /// High-current bypass with the battery voltage on it
+    Bypass = 4,
+}
+
+impl OutputId {
+    pub const ALL: [OutputId; OUTPUT_COUNT] = [
+        OutputId::Dc1,
+        OutputId::Dc2,
+        OutputId::Adjustable,
+        OutputId::Poe,
+        OutputId::Bypass,
+    ];
+
+    pub const fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            OutputId::Dc1 => "DC1",
+            OutputId::Dc2 => "DC2",
+            OutputId::Adjustable => "ADJ",
+            OutputId::Poe => "PoE",
+            OutputId::Bypass => "BYP",
+        }
+    }
+
+    /// Returns the voltage the output starts with
+    pub const fn default_voltage(&self) -> OutputVoltage {
+        match self {
+            OutputId::Dc1 | OutputId::Dc2 => OutputVoltage::Selectable(SelectableVoltage::V12),
+            OutputId::Adjustable | OutputId::Poe => OutputVoltage::Adjustable(12.0),
+            OutputId::Bypass => OutputVoltage::Battery,
+        }
+    }
+
+    /// Checks that the output supports the given voltage
+    pub fn supports(&self, voltage: &OutputVoltage) -> bool {
+        match (self, voltage) {
+            (OutputId::Dc1 | OutputId::Dc2, OutputVoltage::Selectable(_)) => true,
+            (OutputId::Adjustable | OutputId::Poe, OutputVoltage::Adjustable(setpoint)) => {
+                (MIN_ADJUSTABLE_VOLTAGE..=MAX_ADJUSTABLE_VOLTAGE).contains(setpoint)
+            }
+            (OutputId::Bypass, OutputVoltage::Battery) => true,
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<u8> for OutputId {
+    type Error = OutputError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        OutputId::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(OutputError::InvalidOutput)
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum SelectableVoltage {
+    V9,
+    V12,
+}
+
+impl SelectableVoltage {
+    pub const fn volts(&self) -> f32 {
+        match self {
+            SelectableVoltage::V9 => 9.0,
+            SelectableVoltage::V12 => 12.0,
+        }
+    }
+}
+
+/// The voltage on an output
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum OutputVoltage {
+    /// The output is connected straight to the battery
+    Battery,
+    /// One of the fixed voltages of the 9/12V converters
+    Selectable(SelectableVoltage),
+    /// The setpoint of the 5-15V converters in volts
+    Adjustable(f32),
+}
+
+/// The state of a single output
+#[derive(Serialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct OutputStatus {
+    pub output: OutputId,
+    /// The output is requested to be on
+    pub enabled: bool,
+    /// The output is disconnected by the low-voltage disconnect
+    pub lvd_shed: bool,
+    /// The output is switched off by its electronic fuse
+    pub fuse_tripped: bool,
+    /// The output is switched off by the PoE watchdog to power-cycle the device
+    pub watchdog_off: bool,
+    /// The output is actually powered
+    pub active: bool,
+    pub voltage: OutputVoltage,
+}
+
+impl OutputStatus {
+    pub const fn new(output: OutputId) -> Self {
+        Self {
+            output,
+            enabled: false,
+            lvd_shed: false,
+            fuse_tripped: false,
+            watchdog_off: false,
+            active: false,
+            voltage: output.default_voltage(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum OutputError {
+    /// There is no output with such index
+    InvalidOutput,
+    /// The output does not support the requested voltage
+    UnsupportedVoltage,
+}
+
+/// A change of a single output requested over the web API. The missing fields stay unchanged.
+#[derive(Deserialize, Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct OutputChange {
+    pub output: OutputId,
+    pub enabled: Option<bool>,
+    pub voltage: Option<OutputVoltage>,
+}
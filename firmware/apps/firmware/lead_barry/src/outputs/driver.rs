@@ -0,0 +1,77 @@
+use super::data_model::OutputId;
+
+/// The control lines of the outputs. The discriminant is the bit of the line in the output shift register.
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+#[repr(u8)]
+pub enum OutputLine {
+    Dc1Enable = 0,
+    /// Switches DC output 1 from 9V to 12V
+    Dc1Select12V = 1,
+    Dc2Enable = 2,
+    /// Switches DC output 2 from 9V to 12V
+    Dc2Select12V = 3,
+    AdjustableEnable = 4,
+    BypassEnable = 5,
+    PoeEnable = 6,
+    /// Connects the battery to the board
+    BatteryEnable = 7,
+}
+
+impl OutputLine {
+    /// Returns the line which switches the given output on and off
+    pub const fn enable_line(output: OutputId) -> Self {
+        match output {
+            OutputId::Dc1 => OutputLine::Dc1Enable,
+            OutputId::Dc2 => OutputLine::Dc2Enable,
+            OutputId::Adjustable => OutputLine::AdjustableEnable,
+            OutputId::Poe => OutputLine::PoeEnable,
+            OutputId::Bypass => OutputLine::BypassEnable,
+        }
+    }
+
+    /// Returns the 9/12V selection line of the given output, if it has one
+    pub const fn select_12v_line(output: OutputId) -> Option<Self> {
+        match output {
+            OutputId::Dc1 => Some(OutputLine::Dc1Select12V),
+            OutputId::Dc2 => Some(OutputLine::Dc2Select12V),
+            _ => None,
+        }
+    }
+}
+
+/// The state of all the output control lines
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct OutputSwitches(u8);
+
+impl OutputSwitches {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_set(&self, line: OutputLine) -> bool {
+        self.0 & (1 << line as u8) != 0
+    }
+
+    pub fn set(&mut self, line: OutputLine, value: bool) {
+        if value {
+            self.0 |= 1 << line as u8;
+        } else {
+            self.0 &= !(1 << line as u8);
+        }
+    }
+}
+
+/// Hardware access of the outputs: the switch lines and the DACs of the adjustable converters.
+pub trait OutputDriver {
+    /// Applies all the control lines at once
+    fn write_switches(&mut self, switches: OutputSwitches);
+
+    /// Sets the DAC level of an adjustable converter. The level 0.0 selects the lowest setpoint and 1.0 the highest.
+    fn write_dac(&mut self, output: OutputId, level: f32);
+}
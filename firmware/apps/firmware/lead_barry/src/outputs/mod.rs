@@ -0,0 +1,18 @@
+#![allow(unused_imports)]
+
+mod data_model;
+mod driver;
+mod output_controller;
+mod outputs_service;
+#[cfg(not(feature = "sim"))]
+mod shift_register_driver;
+
+pub use self::data_model::*;
+pub use self::driver::*;
+pub use self::output_controller::*;
+pub use self::outputs_service::{OutputCommand, OutputStatusSubscriber, OutputsControl, OutputsService, OutputsState};
+#[cfg(not(feature = "sim"))]
+pub use self::shift_register_driver::*;
+use crate::global_types::OutputDriverDevice;
+
+pub type OutputsRunner<'a> = self::outputs_service::OutputsRunner<'a, OutputDriverDevice>;
@@ -0,0 +1,263 @@
+//! Output switching logic.
+//!
+//! The controller keeps the requested state of every output and translates it into the control
+//! lines and DAC levels of an [`OutputDriver`]. An output is powered only when it is enabled, not
+//! shed by the low-voltage disconnect and not switched off by its electronic fuse or the PoE watchdog.
+
+use super::data_model::*;
+use super::driver::{OutputDriver, OutputLine, OutputSwitches};
+
+pub struct OutputController<D: OutputDriver> {
+    driver: D,
+    switches: OutputSwitches,
+    statuses: [OutputStatus; OUTPUT_COUNT],
+}
+
+impl<D: OutputDriver> OutputController<D> {
+    /// Creates the controller with all the outputs off and the battery connected
+    pub fn new(driver: D) -> Self {
+        let mut controller = Self {
+            driver,
+            switches: OutputSwitches::new(),
+            statuses: OutputId::ALL.map(OutputStatus::new),
+        };
+        controller.switches.set(OutputLine::BatteryEnable, true);
+        for output in OutputId::ALL {
+            controller.apply(output);
+        }
+        controller
+    }
+
+    pub fn status(&self, output: OutputId) -> OutputStatus {
+        self.statuses[output.index()]
+    }
+
+    pub fn statuses(&self) -> &[OutputStatus; OUTPUT_COUNT] {
+        &self.statuses
+    }
+
+    pub fn switches(&self) -> OutputSwitches {
+        self.switches
+    }
+
+    pub fn set_enabled(&mut self, output: OutputId, enabled: bool) -> OutputStatus {
+        self.statuses[output.index()].enabled = enabled;
+        self.apply(output)
+    }
+
+    pub fn set_voltage(&mut self, output: OutputId, voltage: OutputVoltage) -> Result<OutputStatus, OutputError> {
+        if !output.supports(&voltage) {
+            return Err(OutputError::UnsupportedVoltage);
+        }
+        self.statuses[output.index()].voltage = voltage;
+        Ok(self.apply(output))
+    }
+
+    pub fn set_lvd_shed(&mut self, output: OutputId, shed: bool) -> OutputStatus {
+        self.statuses[output.index()].lvd_shed = shed;
+        self.apply(output)
+    }
+
+    pub fn set_fuse_tripped(&mut self, output: OutputId, tripped: bool) -> OutputStatus {
+        self.statuses[output.index()].fuse_tripped = tripped;
+        self.apply(output)
+    }
+
+    pub fn set_watchdog_off(&mut self, output: OutputId, off: bool) -> OutputStatus {
+        self.statuses[output.index()].watchdog_off = off;
+        self.apply(output)
+    }
+
+    /// Applies a change of the voltage and the enable state at once. An unsupported voltage rejects the
+    /// whole change.
+    pub fn apply_change(&mut self, change: &OutputChange) -> Result<OutputStatus, OutputError> {
+        if let Some(voltage) = change.voltage {
+            self.set_voltage(change.output, voltage)?;
+        }
+        if let Some(enabled) = change.enabled {
+            self.set_enabled(change.output, enabled);
+        }
+        Ok(self.status(change.output))
+    }
+
+    /// Writes the state of the output to the hardware. The voltage is set before the output is switched on.
+    fn apply(&mut self, output: OutputId) -> OutputStatus {
+        let status = &mut self.statuses[output.index()];
+        status.active = status.enabled && !status.lvd_shed && !status.fuse_tripped && !status.watchdog_off;
+
+        match status.voltage {
+            OutputVoltage::Selectable(voltage) => {
+                if let Some(line) = OutputLine::select_12v_line(output) {
+                    self.switches.set(line, voltage == SelectableVoltage::V12);
+                }
+            }
+            OutputVoltage::Adjustable(setpoint) => {
+                let level = (setpoint - MIN_ADJUSTABLE_VOLTAGE) / (MAX_ADJUSTABLE_VOLTAGE - MIN_ADJUSTABLE_VOLTAGE);
+                self.driver.write_dac(output, level);
+            }
+            OutputVoltage::Battery => {}
+        }
+        self.switches.set(OutputLine::enable_line(output), status.active);
+        self.driver.write_switches(self.switches);
+
+        *status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Host stand-in for the output hardware which records what was written
+    #[derive(Default)]
+    struct MockOutputDriver {
+        switches: OutputSwitches,
+        dac: [Option<f32>; OUTPUT_COUNT],
+        writes: usize,
+    }
+
+    impl OutputDriver for &mut MockOutputDriver {
+        fn write_switches(&mut self, switches: OutputSwitches) {
+            self.switches = switches;
+            self.writes += 1;
+        }
+
+        fn write_dac(&mut self, output: OutputId, level: f32) {
+            self.dac[output.index()] = Some(level);
+        }
+    }
+
+    #[test]
+    fn test_outputs_start_off_with_battery_connected() {
+        let mut driver = MockOutputDriver::default();
+        let controller = OutputController::new(&mut driver);
+
+        assert!(controller.statuses().iter().all(|status| !status.active));
+        assert!(driver.switches.is_set(OutputLine::BatteryEnable));
+        assert!(!driver.switches.is_set(OutputLine::Dc1Enable));
+        assert!(!driver.switches.is_set(OutputLine::PoeEnable));
+    }
+
+    #[test]
+    fn test_voltage_selection_sets_select_line() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        controller
+            .set_voltage(OutputId::Dc2, OutputVoltage::Selectable(SelectableVoltage::V9))
+            .unwrap();
+        let status = controller.set_enabled(OutputId::Dc2, true);
+        assert!(status.active);
+
+        assert!(driver.switches.is_set(OutputLine::Dc2Enable));
+        assert!(!driver.switches.is_set(OutputLine::Dc2Select12V));
+        assert!(!driver.switches.is_set(OutputLine::Dc1Enable));
+    }
+
+    #[test]
+    fn test_setpoint_is_written_to_dac() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        controller
+            .set_voltage(OutputId::Poe, OutputVoltage::Adjustable(10.0))
+            .unwrap();
+
+        assert_eq!(driver.dac[OutputId::Poe.index()], Some(0.5));
+    }
+
+    #[test]
+    fn test_unsupported_voltage_is_rejected() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        assert_eq!(
+            controller.set_voltage(OutputId::Adjustable, OutputVoltage::Adjustable(20.0)),
+            Err(OutputError::UnsupportedVoltage)
+        );
+        assert_eq!(
+            controller.set_voltage(OutputId::Dc1, OutputVoltage::Adjustable(9.0)),
+            Err(OutputError::UnsupportedVoltage)
+        );
+        assert_eq!(
+            controller.set_voltage(OutputId::Bypass, OutputVoltage::Selectable(SelectableVoltage::V12)),
+            Err(OutputError::UnsupportedVoltage)
+        );
+        assert_eq!(
+            controller.status(OutputId::Adjustable).voltage,
+            OutputVoltage::Adjustable(12.0)
+        );
+    }
+
+    #[test]
+    fn test_change_with_unsupported_voltage_is_rejected() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        let change = OutputChange {
+            output: OutputId::Dc1,
+            enabled: Some(true),
+            voltage: Some(OutputVoltage::Adjustable(9.0)),
+        };
+        assert_eq!(controller.apply_change(&change), Err(OutputError::UnsupportedVoltage));
+        assert!(!controller.status(OutputId::Dc1).enabled);
+
+        let change = OutputChange {
+            voltage: Some(OutputVoltage::Selectable(SelectableVoltage::V9)),
+            ..change
+        };
+        let status = controller.apply_change(&change).unwrap();
+        assert!(status.active);
+        assert_eq!(status.voltage, OutputVoltage::Selectable(SelectableVoltage::V9));
+    }
+
+    #[test]
+    fn test_lvd_shed_overrides_enable() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        controller.set_enabled(OutputId::Bypass, true);
+        let status = controller.set_lvd_shed(OutputId::Bypass, true);
+        assert!(status.enabled);
+        assert!(!status.active);
+        assert!(!controller.switches().is_set(OutputLine::BypassEnable));
+
+        // The user can't switch a shed output back on
+        assert!(!controller.set_enabled(OutputId::Bypass, true).active);
+
+        let status = controller.set_lvd_shed(OutputId::Bypass, false);
+        assert!(status.active);
+        assert!(driver.switches.is_set(OutputLine::BypassEnable));
+    }
+
+    #[test]
+    fn test_fuse_and_lvd_are_independent() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        controller.set_enabled(OutputId::Dc1, true);
+        controller.set_fuse_tripped(OutputId::Dc1, true);
+        controller.set_lvd_shed(OutputId::Dc1, true);
+
+        // The LVD restores the output while the fuse still holds it off
+        assert!(!controller.set_lvd_shed(OutputId::Dc1, false).active);
+        assert!(controller.set_fuse_tripped(OutputId::Dc1, false).active);
+        assert!(driver.switches.is_set(OutputLine::Dc1Enable));
+    }
+
+    #[test]
+    fn test_watchdog_restore_keeps_user_off() {
+        let mut driver = MockOutputDriver::default();
+        let mut controller = OutputController::new(&mut driver);
+
+        controller.set_enabled(OutputId::Poe, true);
+        assert!(!controller.set_watchdog_off(OutputId::Poe, true).active);
+
+        // The user switches the output off during the power cycle, the watchdog must not switch it back on
+        controller.set_enabled(OutputId::Poe, false);
+        let status = controller.set_watchdog_off(OutputId::Poe, false);
+        assert!(!status.enabled);
+        assert!(!status.active);
+        assert!(!driver.switches.is_set(OutputLine::PoeEnable));
+    }
+}
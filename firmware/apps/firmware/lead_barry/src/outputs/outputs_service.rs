@@ -0,0 +1,216 @@
+use defmt_or_log as log;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, Receiver, Sender},
+    mutex::Mutex,
+    pubsub::{ImmediatePublisher, PubSubChannel, Subscriber},
+    signal::Signal,
+};
+
+use crate::configuration::OutputsSettings;
+use crate::outputs::data_model::*;
+use crate::outputs::driver::OutputDriver;
+use crate::outputs::output_controller::OutputController;
+
+const OUTPUT_COMMAND_QUEUE_SIZE: usize = 4;
+const OUTPUT_STATUS_QUEUE_SIZE: usize = 8;
+const OUTPUT_STATUS_SUBSCRIBERS: usize = 2;
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum OutputCommand {
+    SetEnabled(OutputId, bool),
+    SetVoltage(OutputId, OutputVoltage),
+    SetLvdShed(OutputId, bool),
+    SetFuseTripped(OutputId, bool),
+    SetWatchdogOff(OutputId, bool),
+    /// A change requested over the web API, answered through the change result
+    Apply(OutputChange),
+}
+
+type OutputCommandChannel = Channel<CriticalSectionRawMutex, OutputCommand, OUTPUT_COMMAND_QUEUE_SIZE>;
+type OutputStatusChannel =
+    PubSubChannel<CriticalSectionRawMutex, OutputStatus, OUTPUT_STATUS_QUEUE_SIZE, OUTPUT_STATUS_SUBSCRIBERS, 0>;
+type OutputStatusPublisher<'a> = ImmediatePublisher<
+    'a,
+    CriticalSectionRawMutex,
+    OutputStatus,
+    OUTPUT_STATUS_QUEUE_SIZE,
+    OUTPUT_STATUS_SUBSCRIBERS,
+    0,
+>;
+/// Receives the new status of an output every time it changes
+pub type OutputStatusSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, OutputStatus, OUTPUT_STATUS_QUEUE_SIZE, OUTPUT_STATUS_SUBSCRIBERS, 0>;
+
+pub struct OutputsState {
+    statuses: Mutex<CriticalSectionRawMutex, [OutputStatus; OUTPUT_COUNT]>,
+    status_changes: OutputStatusChannel,
+    commands: OutputCommandChannel,
+    /// Serializes the changes waiting for their result
+    change_lock: Mutex<CriticalSectionRawMutex, ()>,
+    change_result: Signal<CriticalSectionRawMutex, Result<OutputStatus, OutputError>>,
+}
+
+impl OutputsState {
+    pub const fn new() -> Self {
+        Self {
+            statuses: Mutex::new([
+                OutputStatus::new(OutputId::Dc1),
+                OutputStatus::new(OutputId::Dc2),
+                OutputStatus::new(OutputId::Adjustable),
+                OutputStatus::new(OutputId::Poe),
+                OutputStatus::new(OutputId::Bypass),
+            ]),
+            status_changes: OutputStatusChannel::new(),
+            commands: OutputCommandChannel::new(),
+            change_lock: Mutex::new(()),
+            change_result: Signal::new(),
+        }
+    }
+}
+
+pub struct OutputsRunner<'a, Driver: OutputDriver> {
+    controller: OutputController<Driver>,
+    state: &'a OutputsState,
+    status_publisher: OutputStatusPublisher<'a>,
+    command_receiver: Receiver<'a, CriticalSectionRawMutex, OutputCommand, OUTPUT_COMMAND_QUEUE_SIZE>,
+}
+
+pub struct OutputsControl<'a> {
+    state: &'a OutputsState,
+    command_sender: Sender<'a, CriticalSectionRawMutex, OutputCommand, OUTPUT_COMMAND_QUEUE_SIZE>,
+}
+
+#[allow(dead_code)]
+impl<'a> OutputsControl<'a> {
+    /// Returns the latest status of the output
+    pub async fn status(&self, output: OutputId) -> OutputStatus {
+        self.state.statuses.lock().await[output.index()]
+    }
+
+    /// Returns the latest status of all the outputs
+    pub async fn statuses(&self) -> [OutputStatus; OUTPUT_COUNT] {
+        *self.state.statuses.lock().await
+    }
+
+    /// Subscribes to the output status changes.
+    /// Returns `None` if all the subscriber slots are already taken.
+    pub fn subscribe_status(&self) -> Option<OutputStatusSubscriber<'a>> {
+        self.state.status_changes.subscriber().ok()
+    }
+
+    pub async fn set_enabled(&self, output: OutputId, enabled: bool) {
+        self.command_sender
+            .send(OutputCommand::SetEnabled(output, enabled))
+            .await
+    }
+
+    pub async fn set_voltage(&self, output: OutputId, voltage: OutputVoltage) -> Result<(), OutputError> {
+        if !output.supports(&voltage) {
+            return Err(OutputError::UnsupportedVoltage);
+        }
+        self.command_sender
+            .send(OutputCommand::SetVoltage(output, voltage))
+            .await;
+        Ok(())
+    }
+
+    /// Applies the change and returns the resulting status of the output
+    pub async fn apply_change(&self, change: OutputChange) -> Result<OutputStatus, OutputError> {
+        let _lock = self.state.change_lock.lock().await;
+        self.state.change_result.reset();
+        self.command_sender.send(OutputCommand::Apply(change)).await;
+        self.state.change_result.wait().await
+    }
+
+    /// Disconnects (`shed = true`) or restores the output on behalf of the low-voltage disconnect
+    pub async fn set_lvd_shed(&self, output: OutputId, shed: bool) {
+        self.command_sender.send(OutputCommand::SetLvdShed(output, shed)).await
+    }
+
+    /// Disconnects (`tripped = true`) or restores the output on behalf of its electronic fuse
+    pub async fn set_fuse_tripped(&self, output: OutputId, tripped: bool) {
+        self.command_sender
+            .send(OutputCommand::SetFuseTripped(output, tripped))
+            .await
+    }
+
+    /// Switches the output off (`off = true`) or restores it on behalf of the PoE watchdog
+    pub async fn set_watchdog_off(&self, output: OutputId, off: bool) {
+        self.command_sender
+            .send(OutputCommand::SetWatchdogOff(output, off))
+            .await
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct OutputsService(());
+
+impl OutputsService {
+    /// Creates a new outputs instance and switches the outputs to their default state
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a, Driver: OutputDriver>(
+        driver: Driver,
+        state: &'a mut OutputsState,
+        settings: &OutputsSettings,
+    ) -> (OutputsRunner<'a, Driver>, OutputsControl<'a>) {
+        let state: &'a OutputsState = state;
+        let mut controller = OutputController::new(driver);
+        for default in settings.outputs.iter() {
+            if let Err(error) = controller.set_voltage(default.output, default.voltage) {
+                log::error!("Invalid default voltage of {}: {}", default.output.name(), error);
+            }
+            controller.set_enabled(default.output, default.enabled);
+        }
+
+        (
+            OutputsRunner {
+                controller,
+                state,
+                status_publisher: state.status_changes.immediate_publisher(),
+                command_receiver: state.commands.receiver(),
+            },
+            OutputsControl {
+                state,
+                command_sender: state.commands.sender(),
+            },
+        )
+    }
+}
+
+impl<'a, Driver: OutputDriver> OutputsRunner<'a, Driver> {
+    pub async fn run(&mut self) -> ! {
+        *self.state.statuses.lock().await = *self.controller.statuses();
+
+        loop {
+            let command = self.command_receiver.receive().await;
+            let status = match command {
+                OutputCommand::SetEnabled(output, enabled) => self.controller.set_enabled(output, enabled),
+                OutputCommand::SetVoltage(output, voltage) => match self.controller.set_voltage(output, voltage) {
+                    Ok(status) => status,
+                    Err(error) => {
+                        log::error!("Failed to set {} voltage: {}", output.name(), error);
+                        continue;
+                    }
+                },
+                OutputCommand::SetLvdShed(output, shed) => self.controller.set_lvd_shed(output, shed),
+                OutputCommand::SetFuseTripped(output, tripped) => self.controller.set_fuse_tripped(output, tripped),
+                OutputCommand::SetWatchdogOff(output, off) => self.controller.set_watchdog_off(output, off),
+                OutputCommand::Apply(change) => {
+                    let result = self.controller.apply_change(&change);
+                    self.state.change_result.signal(result);
+                    match result {
+                        Ok(status) => status,
+                        // The caller reports the rejected change, nothing has changed
+                        Err(_) => continue,
+                    }
+                }
+            };
+            log::info!("Output {}: {}", status.output.name(), status);
+
+            self.state.statuses.lock().await[status.output.index()] = status;
+            self.status_publisher.publish_immediate(status);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use defmt_or_log as log;
+use embassy_rp::Peri;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::peripherals::{PIN_14, PIN_15, PIN_18, PIN_19, PIN_20, PIN_21, PIN_22, PWM_SLICE7};
+use embassy_rp::pwm::{Config, Pwm, PwmOutput, SetDutyCycle};
+
+use super::data_model::OutputId;
+use super::driver::{OutputDriver, OutputSwitches};
+
+const DAC_PWM_FREQUENCY_HZ: u32 = 50_000;
+
+pub struct OutputsHardwareConfig {
+    /// 74HC595 serial data input (DS)
+    pub shift_data: Peri<'static, PIN_18>,
+    /// 74HC595 output enable (~OE), active low
+    pub shift_output_enable: Peri<'static, PIN_19>,
+    /// 74HC595 storage register clock (STCP)
+    pub shift_latch: Peri<'static, PIN_20>,
+    /// 74HC595 shift register clock (SHCP)
+    pub shift_clock: Peri<'static, PIN_21>,
+    /// 74HC595 master reset (~MR), active low
+    pub shift_reset: Peri<'static, PIN_22>,
+
+    pub dac_slice: Peri<'static, PWM_SLICE7>,
+    /// Filtered PWM which sets the PoE converter voltage
+    pub poe_dac: Peri<'static, PIN_14>,
+    /// Filtered PWM which sets the adjustable converter voltage
+    pub adjustable_dac: Peri<'static, PIN_15>,
+}
+
+/// Output driver of the board: the switch lines sit on a 74HC595 shift register and the adjustable
+/// converters are set by RC-filtered PWM.
+pub struct ShiftRegisterOutputDriver {
+    data: Output<'static>,
+    output_enable: Output<'static>,
+    latch: Output<'static>,
+    clock: Output<'static>,
+    _reset: Output<'static>,
+    poe_dac: PwmOutput<'static>,
+    adjustable_dac: PwmOutput<'static>,
+}
+
+impl ShiftRegisterOutputDriver {
+    pub fn new(config: OutputsHardwareConfig) -> Self {
+        // Keep the register outputs disabled until the first state is latched
+        let output_enable = Output::new(config.shift_output_enable, Level::High);
+        let reset = Output::new(config.shift_reset, Level::High);
+
+        let clock_freq_hz = embassy_rp::clocks::clk_sys_freq();
+        let mut pwm_config = Config::default();
+        pwm_config.top = (clock_freq_hz / DAC_PWM_FREQUENCY_HZ) as u16 - 1;
+
+        let pwm = Pwm::new_output_ab(config.dac_slice, config.poe_dac, config.adjustable_dac, pwm_config);
+        let (poe_dac, adjustable_dac) = pwm.split();
+
+        Self {
+            data: Output::new(config.shift_data, Level::Low),
+            output_enable,
+            latch: Output::new(config.shift_latch, Level::Low),
+            clock: Output::new(config.shift_clock, Level::Low),
+            _reset: reset,
+            poe_dac: poe_dac.unwrap(),
+            adjustable_dac: adjustable_dac.unwrap(),
+        }
+    }
+}
+
+impl OutputDriver for ShiftRegisterOutputDriver {
+    fn write_switches(&mut self, switches: OutputSwitches) {
+        // Q7 is shifted in first
+        for bit in (0..8).rev() {
+            self.data.set_level(Level::from(switches.bits() & (1 << bit) != 0));
+            self.clock.set_high();
+            self.clock.set_low();
+        }
+        self.latch.set_high();
+        self.latch.set_low();
+        self.output_enable.set_low();
+    }
+
+    fn write_dac(&mut self, output: OutputId, level: f32) {
+        let dac = match output {
+            OutputId::Poe => &mut self.poe_dac,
+            OutputId::Adjustable => &mut self.adjustable_dac,
+            _ => return,
+        };
+        let duty = (dac.max_duty_cycle() as f32 * level.clamp(0.0, 1.0)) as u16;
+        if let Err(error) = dac.set_duty_cycle(duty) {
+            log::error!("Failed to set {} DAC: {}", output.name(), error);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+#![allow(unused_imports)]
+
+mod poe_watchdog_service;
+mod watchdog_policy;
+
+pub use self::poe_watchdog_service::*;
+pub use self::watchdog_policy::*;
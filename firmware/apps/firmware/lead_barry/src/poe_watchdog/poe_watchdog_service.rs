@@ -0,0 +1,281 @@
+use defmt_or_log as log;
+use embassy_net::{
+    IpAddress, IpEndpoint, Stack,
+    tcp::TcpSocket,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker, Timer, with_timeout};
+use serde::Serialize;
+
+use crate::configuration::{PoeProbeMethod, PoeWatchdogSettings};
+use crate::outputs::{OutputId, OutputsControl};
+use crate::poe_watchdog::watchdog_policy::*;
+
+const PROBE_BUFFER_SIZE: usize = 128;
+const SOCKET_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// A DNS query for the root name servers. Any DNS resolver answers it, so the UDP probe works on port 53 of
+/// most routers without any setup.
+const UDP_PROBE_PAYLOAD: [u8; 17] = [
+    0x4c, 0x42, // Transaction id
+    0x01, 0x00, // Standard query, recursion desired
+    0x00, 0x01, // One question
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // No answer, authority and additional records
+    0x00, // Root name
+    0x00, 0x02, // Type NS
+    0x00, 0x01, // Class IN
+];
+
+/// The latest PoE watchdog state published for the web API
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct PoeWatchdogStatus {
+    pub state: PoeWatchdogState,
+    /// The result of the last probe, `None` until the first probe
+    pub last_probe_ok: Option<bool>,
+    pub consecutive_failures: u8,
+    /// Power cycles within the current 24 hour window
+    pub cycles_today: u8,
+    /// Power cycles since boot
+    pub total_cycles: u32,
+}
+
+impl PoeWatchdogStatus {
+    pub const fn new() -> Self {
+        Self {
+            state: PoeWatchdogState::Disabled,
+            last_probe_ok: None,
+            consecutive_failures: 0,
+            cycles_today: 0,
+            total_cycles: 0,
+        }
+    }
+}
+
+impl Default for PoeWatchdogStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct PoeWatchdogConfig {
+    pub enabled: bool,
+    pub target: IpEndpoint,
+    pub probe_method: PoeProbeMethod,
+    pub probe_interval: Duration,
+    pub probe_timeout: Duration,
+    pub policy: WatchdogPolicyConfig,
+}
+
+impl PoeWatchdogConfig {
+    pub fn from_settings(settings: &PoeWatchdogSettings) -> Self {
+        Self {
+            enabled: settings.enabled && settings.is_valid(),
+            target: IpEndpoint::new(IpAddress::Ipv4(settings.target()), settings.target_port),
+            probe_method: settings.probe_method,
+            probe_interval: Duration::from_secs(settings.probe_interval_s as u64),
+            probe_timeout: Duration::from_secs(settings.probe_timeout_s as u64),
+            policy: WatchdogPolicyConfig {
+                failure_threshold: settings.failure_threshold,
+                power_off_ms: settings.power_off_s as u64 * 1000,
+                boot_grace_ms: settings.boot_grace_s as u64 * 1000,
+                max_backoff_ms: settings.max_backoff_s as u64 * 1000,
+                max_cycles_per_day: settings.max_cycles_per_day,
+            },
+        }
+    }
+}
+
+pub struct PoeWatchdogServiceState {
+    status: Mutex<CriticalSectionRawMutex, PoeWatchdogStatus>,
+    network: Signal<CriticalSectionRawMutex, Stack<'static>>,
+}
+
+impl PoeWatchdogServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(PoeWatchdogStatus::new()),
+            network: Signal::new(),
+        }
+    }
+}
+
+pub struct PoeWatchdogRunner<'a> {
+    state: &'a PoeWatchdogServiceState,
+    outputs_control: &'a OutputsControl<'a>,
+    config: PoeWatchdogConfig,
+    policy: WatchdogPolicy,
+    last_probe_ok: Option<bool>,
+}
+
+pub struct PoeWatchdogControl<'a> {
+    state: &'a PoeWatchdogServiceState,
+}
+
+impl<'a> PoeWatchdogControl<'a> {
+    /// Returns the latest PoE watchdog status
+    pub async fn status(&self) -> PoeWatchdogStatus {
+        *self.state.status.lock().await
+    }
+
+    /// Starts probing through the given network stack once the device has joined the network
+    pub fn start(&self, net_stack: Stack<'static>) {
+        self.state.network.signal(net_stack);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PoeWatchdogService(());
+
+impl PoeWatchdogService {
+    /// Creates a new PoE watchdog instance which power-cycles the PoE output through the outputs control
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(
+        outputs_control: &'a OutputsControl<'a>,
+        state: &'a mut PoeWatchdogServiceState,
+        config: PoeWatchdogConfig,
+    ) -> (PoeWatchdogRunner<'a>, PoeWatchdogControl<'a>) {
+        let state: &'a PoeWatchdogServiceState = state;
+        (
+            PoeWatchdogRunner {
+                state,
+                outputs_control,
+                config,
+                policy: WatchdogPolicy::new(config.policy),
+                last_probe_ok: None,
+            },
+            PoeWatchdogControl { state },
+        )
+    }
+}
+
+impl<'a> PoeWatchdogRunner<'a> {
+    pub async fn run(&mut self) -> ! {
+        if !self.config.enabled {
+            log::info!("PoE watchdog is disabled");
+            loop {
+                core::future::pending::<()>().await;
+            }
+        }
+
+        self.update_status(PoeWatchdogState::WaitingForNetwork).await;
+        let net_stack = self.state.network.wait().await;
+        log::info!("PoE watchdog probes {}", self.config.target);
+        self.update_status(PoeWatchdogState::Monitoring).await;
+
+        let mut ticker = Ticker::every(self.config.probe_interval);
+        loop {
+            ticker.next().await;
+
+            // The device is intentionally off (by the user or the LVD), nothing to watch
+            if !self.outputs_control.status(OutputId::Poe).await.active {
+                continue;
+            }
+
+            // A lost link or DHCP lease says nothing about the device, so it doesn't count as a failure
+            if !net_stack.is_config_up() {
+                self.update_status(PoeWatchdogState::WaitingForNetwork).await;
+                continue;
+            }
+
+            let now_ms = Instant::now().as_millis();
+            if self.policy.should_probe(now_ms) {
+                let reachable = self.probe(net_stack).await;
+                self.last_probe_ok = Some(reachable);
+                if !reachable {
+                    log::warn!("PoE device {} is not reachable", self.config.target);
+                }
+
+                if self.policy.on_probe(reachable, now_ms) {
+                    self.update_status(self.policy.state()).await;
+                    self.power_cycle().await;
+                    ticker.reset();
+                } else if self.policy.state() == PoeWatchdogState::LimitReached {
+                    log::error!("PoE device is hung, but the daily power cycle limit is reached");
+                }
+            }
+
+            self.update_status(self.policy.state()).await;
+        }
+    }
+
+    async fn power_cycle(&mut self) {
+        log::warn!(
+            "Power-cycling the PoE device ({} today, {} since boot)",
+            self.policy.cycles_today(),
+            self.policy.total_cycles()
+        );
+        // The watchdog holds the output off without touching the user's request, so an output switched off
+        // in the meantime stays off
+        self.outputs_control.set_watchdog_off(OutputId::Poe, true).await;
+        Timer::after_millis(self.config.policy.power_off_ms).await;
+        self.outputs_control.set_watchdog_off(OutputId::Poe, false).await;
+    }
+
+    async fn probe(&self, net_stack: Stack<'static>) -> bool {
+        match self.config.probe_method {
+            PoeProbeMethod::Tcp => self.probe_tcp(net_stack).await,
+            PoeProbeMethod::Udp => self.probe_udp(net_stack).await,
+        }
+    }
+
+    async fn probe_tcp(&self, net_stack: Stack<'static>) -> bool {
+        let mut rx_buffer = [0u8; PROBE_BUFFER_SIZE];
+        let mut tx_buffer = [0u8; PROBE_BUFFER_SIZE];
+        let mut socket = TcpSocket::new(net_stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(self.config.probe_timeout));
+
+        let connected = matches!(
+            with_timeout(self.config.probe_timeout, socket.connect(self.config.target)).await,
+            Ok(Ok(()))
+        );
+
+        socket.abort();
+        with_timeout(SOCKET_CLOSE_TIMEOUT, socket.flush()).await.ok();
+        connected
+    }
+
+    async fn probe_udp(&self, net_stack: Stack<'static>) -> bool {
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0u8; PROBE_BUFFER_SIZE];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0u8; PROBE_BUFFER_SIZE];
+        let mut socket = UdpSocket::new(net_stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+
+        if let Err(e) = socket.bind(0) {
+            log::error!("PoE watchdog UDP bind error: {:?}", e);
+            return false;
+        }
+        if let Err(e) = socket.send_to(&UDP_PROBE_PAYLOAD, self.config.target).await {
+            log::error!("PoE watchdog UDP send error: {:?}", e);
+            return false;
+        }
+
+        let mut answer = [0u8; PROBE_BUFFER_SIZE];
+        let deadline = Instant::now() + self.config.probe_timeout;
+        loop {
+            match with_timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                socket.recv_from(&mut answer),
+            )
+            .await
+            {
+                Ok(Ok((_, meta))) if meta.endpoint.addr == self.config.target.addr => return true,
+                // Ignore strays from other hosts
+                Ok(Ok(_)) => continue,
+                Ok(Err(_)) | Err(_) => return false,
+            }
+        }
+    }
+
+    async fn update_status(&self, state: PoeWatchdogState) {
+        let mut status = self.state.status.lock().await;
+        status.state = state;
+        status.last_probe_ok = self.last_probe_ok;
+        status.consecutive_failures = self.policy.consecutive_failures();
+        status.cycles_today = self.policy.cycles_today();
+        status.total_cycles = self.policy.total_cycles();
+    }
+}
@@ -0,0 +1,242 @@
+#![allow(dead_code)]
+
+//! PoE watchdog decision logic. The device is power-cycled after a run of failed probes, with a growing
+//! back-off between the power cycles and a limit on their number per day.
+
+use serde::Serialize;
+
+pub const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum PoeWatchdogState {
+    /// The watchdog is switched off in the settings
+    Disabled,
+    /// The watchdog waits for the network to come up
+    WaitingForNetwork,
+    /// The device is probed periodically
+    Monitoring,
+    /// The device has been power-cycled and is given time to boot
+    BackingOff,
+    /// The daily power cycle limit is reached, the device is left alone until the limit expires
+    LimitReached,
+}
+
+impl PoeWatchdogState {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            PoeWatchdogState::Disabled => "Disabled",
+            PoeWatchdogState::WaitingForNetwork => "No network",
+            PoeWatchdogState::Monitoring => "Monitoring",
+            PoeWatchdogState::BackingOff => "Backing off",
+            PoeWatchdogState::LimitReached => "Limit reached",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct WatchdogPolicyConfig {
+    /// Number of consecutive failed probes which trigger a power cycle
+    pub failure_threshold: u8,
+    /// How long the output stays off during a power cycle
+    pub power_off_ms: u64,
+    /// How long the device is given to boot after the first power cycle
+    pub boot_grace_ms: u64,
+    /// The upper bound of the doubled boot grace time
+    pub max_backoff_ms: u64,
+    /// Maximum number of power cycles within 24 hours
+    pub max_cycles_per_day: u8,
+}
+
+pub struct WatchdogPolicy {
+    config: WatchdogPolicyConfig,
+    state: PoeWatchdogState,
+    consecutive_failures: u8,
+    /// Power cycles since the device answered last time
+    consecutive_cycles: u32,
+    /// Power cycles within the current 24 hour window
+    cycles_today: u8,
+    day_start_ms: u64,
+    resume_at_ms: u64,
+    total_cycles: u32,
+}
+
+impl WatchdogPolicy {
+    pub const fn new(config: WatchdogPolicyConfig) -> Self {
+        Self {
+            config,
+            state: PoeWatchdogState::Monitoring,
+            consecutive_failures: 0,
+            consecutive_cycles: 0,
+            cycles_today: 0,
+            day_start_ms: 0,
+            resume_at_ms: 0,
+            total_cycles: 0,
+        }
+    }
+
+    pub fn state(&self) -> PoeWatchdogState {
+        self.state
+    }
+
+    pub fn consecutive_failures(&self) -> u8 {
+        self.consecutive_failures
+    }
+
+    pub fn cycles_today(&self) -> u8 {
+        self.cycles_today
+    }
+
+    pub fn total_cycles(&self) -> u32 {
+        self.total_cycles
+    }
+
+    /// Checks whether the device should be probed at `now_ms`. Updates the back-off and the daily limit state.
+    pub fn should_probe(&mut self, now_ms: u64) -> bool {
+        if self.cycles_today > 0 && now_ms.saturating_sub(self.day_start_ms) >= DAY_MS {
+            self.cycles_today = 0;
+        }
+
+        match self.state {
+            PoeWatchdogState::BackingOff | PoeWatchdogState::LimitReached if now_ms < self.resume_at_ms => false,
+            PoeWatchdogState::LimitReached if self.cycles_today >= self.config.max_cycles_per_day => false,
+            _ => {
+                self.state = PoeWatchdogState::Monitoring;
+                true
+            }
+        }
+    }
+
+    /// Processes the result of a probe taken at `now_ms`. Returns `true` if the device has to be power-cycled now.
+    pub fn on_probe(&mut self, reachable: bool, now_ms: u64) -> bool {
+        if reachable {
+            self.consecutive_failures = 0;
+            self.consecutive_cycles = 0;
+            return false;
+        }
+
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if self.consecutive_failures < self.config.failure_threshold {
+            return false;
+        }
+
+        if self.cycles_today >= self.config.max_cycles_per_day {
+            // Wait until the oldest window expires
+            self.state = PoeWatchdogState::LimitReached;
+            self.resume_at_ms = self.day_start_ms + DAY_MS;
+            return false;
+        }
+
+        if self.cycles_today == 0 {
+            self.day_start_ms = now_ms;
+        }
+        self.cycles_today += 1;
+        self.total_cycles += 1;
+        self.consecutive_failures = 0;
+
+        let backoff_ms = self
+            .config
+            .boot_grace_ms
+            .saturating_mul(1 << self.consecutive_cycles.min(16))
+            .min(self.config.max_backoff_ms);
+        self.consecutive_cycles += 1;
+        self.state = PoeWatchdogState::BackingOff;
+        self.resume_at_ms = now_ms + self.config.power_off_ms + backoff_ms;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::*;
+
+    const PROBE_PERIOD_MS: u64 = 60_000;
+
+    fn config() -> WatchdogPolicyConfig {
+        WatchdogPolicyConfig {
+            failure_threshold: 3,
+            power_off_ms: 10_000,
+            boot_grace_ms: 120_000,
+            max_backoff_ms: 600_000,
+            max_cycles_per_day: 3,
+        }
+    }
+
+    /// Probes a device with the given reachability for the given duration and returns the times of
+    /// the power cycles
+    fn run(
+        policy: &mut WatchdogPolicy,
+        now_ms: &mut u64,
+        reachable: bool,
+        duration_ms: u64,
+    ) -> heapless::Vec<u64, MAX_STEP_RESULTS> {
+        step_for(now_ms, PROBE_PERIOD_MS, duration_ms, |now_ms| {
+            (policy.should_probe(now_ms) && policy.on_probe(reachable, now_ms)).then_some(now_ms)
+        })
+    }
+
+    #[test]
+    fn test_reachable_device_is_not_cycled() {
+        let mut policy = WatchdogPolicy::new(config());
+        let mut now_ms = 0;
+
+        assert!(run(&mut policy, &mut now_ms, true, DAY_MS).is_empty());
+        assert_eq!(policy.state(), PoeWatchdogState::Monitoring);
+    }
+
+    #[test]
+    fn test_cycle_after_consecutive_failures() {
+        let mut policy = WatchdogPolicy::new(config());
+        let mut now_ms = 0;
+
+        assert!(run(&mut policy, &mut now_ms, false, 2 * PROBE_PERIOD_MS).is_empty());
+        // A single answer restarts the failure count
+        assert!(run(&mut policy, &mut now_ms, true, PROBE_PERIOD_MS).is_empty());
+        assert!(run(&mut policy, &mut now_ms, false, 2 * PROBE_PERIOD_MS).is_empty());
+
+        let cycles = run(&mut policy, &mut now_ms, false, PROBE_PERIOD_MS);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(policy.state(), PoeWatchdogState::BackingOff);
+        assert_eq!(policy.cycles_today(), 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles_until_device_answers() {
+        let mut policy = WatchdogPolicy::new(WatchdogPolicyConfig {
+            max_cycles_per_day: 10,
+            ..config()
+        });
+        let mut now_ms = 0;
+
+        let cycles = run(&mut policy, &mut now_ms, false, 60 * PROBE_PERIOD_MS);
+        // Every power cycle is followed by 10s off, the boot grace of 2, 4, 8, 10 (capped) minutes and 3 failed probes
+        assert_eq!(
+            cycles.as_slice(),
+            &[180_000, 480_000, 900_000, 1_560_000, 2_340_000, 3_120_000]
+        );
+
+        // The device came back, the next hang starts with the short back-off again
+        run(&mut policy, &mut now_ms, true, 20 * PROBE_PERIOD_MS);
+        let cycles = run(&mut policy, &mut now_ms, false, 10 * PROBE_PERIOD_MS);
+        assert_eq!(cycles.len(), 2);
+        assert_eq!(cycles[1] - cycles[0], 300_000);
+    }
+
+    #[test]
+    fn test_daily_limit() {
+        let mut policy = WatchdogPolicy::new(config());
+        let mut now_ms = 0;
+
+        let cycles = run(&mut policy, &mut now_ms, false, 12 * 60 * PROBE_PERIOD_MS);
+        assert_eq!(cycles.len(), 3);
+        assert_eq!(policy.state(), PoeWatchdogState::LimitReached);
+
+        // The limit expires 24 hours after the first power cycle of the window and the still
+        // unreachable device is power-cycled right away
+        let cycles = run(&mut policy, &mut now_ms, false, DAY_MS);
+        assert_eq!(cycles.first().copied(), Some(180_000 + DAY_MS));
+        assert_eq!(policy.total_cycles(), 6);
+    }
+}
@@ -1,14 +1,23 @@
 #![allow(dead_code)]
 
+#[cfg(not(feature = "sim"))]
 use cortex_m::peripheral::SCB;
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 
+#[cfg(not(feature = "sim"))]
 pub fn trigger_system_reset() -> ! {
     cortex_m::interrupt::disable();
     SCB::sys_reset();
 }
 
+/// The simulation has no one to boot it again, a reset ends it
+#[cfg(feature = "sim")]
+pub fn trigger_system_reset() -> ! {
+    defmt_or_log::info!("System reset, stopping the simulation");
+    std::process::exit(0)
+}
+
 #[embassy_executor::task]
 async fn deferred_system_reset_task(delay: Duration) {
     Timer::after(delay).await;
@@ -22,6 +31,7 @@ pub fn deferred_system_reset(spawner: Spawner, delay: Duration) {
 }
 
 // For more advanced reset scenarios
+#[cfg(not(feature = "sim"))]
 pub fn reset_to_bootloader() -> ! {
     // Set magic value in RAM that bootloader can detect
     unsafe {
@@ -31,6 +41,11 @@ pub fn reset_to_bootloader() -> ! {
     cortex_m::peripheral::SCB::sys_reset();
 }
 
+#[cfg(feature = "sim")]
+pub fn reset_to_bootloader() -> ! {
+    trigger_system_reset()
+}
+
 pub fn deferred_reset_to_bootloader(spawner: Spawner, delay: Duration) {
     // Implement a deferred reset mechanism if needed
     // For example, setting a flag to reset later
@@ -1,8 +1,10 @@
+use defmt_or_log as log;
 use ds323x::Ds323xAsync;
 use ds323x::ic::DS3231;
 use ds323x::*;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
+use embassy_time::Instant;
 
 pub use ds323x::{DateTimeAccess, Datelike, NaiveDateTime, Rtcc};
 
@@ -15,3 +17,37 @@ where
 {
     Mutex::new(Ds323xAsync::new_ds3231(i2c_device))
 }
+
+/// How often the wall clock is re-read from the RTC
+const CLOCK_SYNC_PERIOD_S: u64 = 3600;
+
+/// The RTC time at a known uptime, so the time can be stamped without reading the RTC every update
+#[derive(Copy, Clone, Default)]
+pub struct WallClock {
+    synced: Option<(i64, Instant)>,
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self { synced: None }
+    }
+
+    /// The RTC time in seconds since 1970, re-synchronized periodically. `None` until the RTC
+    /// has been read successfully.
+    pub async fn now_s<I2C, E>(&mut self, rtc: &RtcDs3231Ref<I2C>, now: Instant) -> Option<i64>
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+        E: core::fmt::Debug,
+    {
+        let stale = self
+            .synced
+            .is_none_or(|(_, at)| (now - at).as_secs() >= CLOCK_SYNC_PERIOD_S);
+        if stale {
+            match rtc.lock().await.datetime().await {
+                Ok(datetime) => self.synced = Some((datetime.and_utc().timestamp(), now)),
+                Err(e) => log::error!("RTC datetime read error: {:?}", defmt_or_log::Debug2Format(&e)),
+            }
+        }
+        self.synced.map(|(rtc_s, at)| rtc_s + (now - at).as_secs() as i64)
+    }
+}
@@ -1,6 +1,15 @@
-use crate::global_types::I2c0Device;
+use crate::global_types::RtcI2cDevice;
 
-use crate::configuration::ConfigurationStorage;
+use crate::battery::{BatteryMonitorControl, CycleControl, HealthControl};
+use crate::charger::{ChargerControl, EqualizationControl};
+use crate::configuration::{BatteryProfile, ChannelMap, ConfigurationStorage, CountersStore};
+use crate::efuse::EFuseControl;
+use crate::history::HistoryControl;
+use crate::lvd::LvdControl;
+use crate::outputs::OutputsControl;
+use crate::poe_watchdog::PoeWatchdogControl;
+use crate::temperature::TemperatureControl;
+#[cfg(not(feature = "sim"))]
 pub use crate::ws2812b_led_controller::LedController;
 
 use crate::rtc::RtcDs3231Ref;
@@ -11,8 +20,24 @@ use crate::vcp_sensors::VcpControl;
 pub struct SharedResources {
     pub ui_control: &'static UiControl<'static>,
     pub vcp_control: &'static VcpControl<'static>,
-    pub rtc: &'static RtcDs3231Ref<I2c0Device<'static>>,
-    pub configuration_storage: &'static ConfigurationStorage<'static>,
-
+    pub battery_control: &'static BatteryMonitorControl<'static>,
+    pub health_control: &'static HealthControl<'static>,
+    pub cycle_control: &'static CycleControl<'static>,
+    pub history_control: &'static HistoryControl<'static>,
+    pub lvd_control: &'static LvdControl<'static>,
+    pub outputs_control: &'static OutputsControl<'static>,
+    pub poe_watchdog_control: &'static PoeWatchdogControl<'static>,
+    pub efuse_control: &'static EFuseControl<'static>,
+    pub charger_control: &'static ChargerControl<'static>,
+    pub temperature_control: &'static TemperatureControl<'static>,
+    pub equalization_control: &'static EqualizationControl<'static>,
+    /// The battery profile applied at boot
+    pub battery_profile: BatteryProfile,
+    /// The channel map applied at boot
+    pub channel_map: &'static ChannelMap,
+    pub rtc: &'static RtcDs3231Ref<RtcI2cDevice>,
+    pub configuration_storage: &'static ConfigurationStorage,
+    pub counters_store: &'static CountersStore,
+    #[cfg(not(feature = "sim"))]
     pub led_controller: LedController,
 }
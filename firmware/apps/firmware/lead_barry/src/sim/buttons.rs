@@ -0,0 +1,37 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::input::{ButtonInput, Buttons};
+
+static YELLOW_PRESSED: AtomicBool = AtomicBool::new(false);
+static BLUE_PRESSED: AtomicBool = AtomicBool::new(false);
+
+const fn pressed_flag(button: Buttons) -> &'static AtomicBool {
+    match button {
+        Buttons::Yellow => &YELLOW_PRESSED,
+        Buttons::Blue => &BLUE_PRESSED,
+    }
+}
+
+/// Presses or releases a button, the button controller picks the change up on its next poll
+pub fn set_pressed(button: Buttons, pressed: bool) {
+    pressed_flag(button).store(pressed, Ordering::Relaxed);
+}
+
+/// A button pressed and released by the scenario
+pub struct SimButton {
+    pressed: &'static AtomicBool,
+}
+
+impl SimButton {
+    pub const fn new(button: Buttons) -> Self {
+        Self {
+            pressed: pressed_flag(button),
+        }
+    }
+}
+
+impl ButtonInput for SimButton {
+    fn is_pressed(&self) -> bool {
+        self.pressed.load(Ordering::Relaxed)
+    }
+}
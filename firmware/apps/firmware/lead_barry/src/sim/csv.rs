@@ -0,0 +1,101 @@
+//! The comma separated scripts of the scenarios. Empty lines and lines starting with `#` are
+//! skipped, so is the header line if it starts with `time_s`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct CsvError {
+    path: PathBuf,
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+pub struct CsvRow {
+    path: PathBuf,
+    /// The line number, starting from 1
+    line: usize,
+    fields: Vec<String>,
+}
+
+impl CsvRow {
+    pub fn error(&self, message: impl Into<String>) -> CsvError {
+        CsvError {
+            path: self.path.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn all_fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Returns exactly N fields
+    pub fn fields<const N: usize>(&self) -> Result<[&str; N], CsvError> {
+        if self.fields.len() != N {
+            return Err(self.error(format!("expected {} fields, found {}", N, self.fields.len())));
+        }
+        Ok(core::array::from_fn(|i| self.fields[i].as_str()))
+    }
+}
+
+pub fn parse_f32(row: &CsvRow, field: &str) -> Result<f32, CsvError> {
+    field
+        .parse()
+        .map_err(|_| row.error(format!("invalid number '{}'", field)))
+}
+
+pub fn read_rows(path: &Path) -> Result<Vec<CsvRow>, CsvError> {
+    let text = std::fs::read_to_string(path).map_err(|e| CsvError {
+        path: path.to_path_buf(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    Ok(parse_rows(path, &text))
+}
+
+pub fn parse_rows(path: &Path, text: &str) -> Vec<CsvRow> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#') && !line.starts_with("time_s"))
+        .map(|(line, text)| CsvRow {
+            path: path.to_path_buf(),
+            line,
+            fields: text.split(',').map(|field| field.trim().to_string()).collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_header_comments_and_empty_lines() {
+        let rows = parse_rows(Path::new("test.csv"), "time_s,a\n# comment\n\n1.5, press ,yellow\n");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].line, 4);
+        assert_eq!(rows[0].all_fields(), ["1.5", "press", "yellow"]);
+    }
+
+    #[test]
+    fn checks_the_field_count() {
+        let rows = parse_rows(Path::new("test.csv"), "1,2,3\n");
+        assert!(rows[0].fields::<3>().is_ok());
+        assert!(rows[0].fields::<4>().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use embedded_graphics::{
+    Pixel,
+    draw_target::DrawTarget,
+    geometry::{OriginDimensions, Size},
+    pixelcolor::BinaryColor,
+};
+
+use crate::ui::Display;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 64;
+/// The screenshots are scaled up to be readable
+const SCREENSHOT_SCALE: usize = 4;
+
+type Frame = [[bool; WIDTH]; HEIGHT];
+
+/// The last frame flushed to the display
+static FLUSHED_FRAME: Mutex<Frame> = Mutex::new([[false; WIDTH]; HEIGHT]);
+
+/// The 128x64 monochrome display of the board drawn into memory
+pub struct FramebufferDisplay {
+    frame: Frame,
+}
+
+impl FramebufferDisplay {
+    pub const fn new() -> Self {
+        Self {
+            frame: [[false; WIDTH]; HEIGHT],
+        }
+    }
+}
+
+impl Default for FramebufferDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for FramebufferDisplay {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for FramebufferDisplay {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y))
+                && x < WIDTH
+                && y < HEIGHT
+            {
+                self.frame[y][x] = color.is_on();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for FramebufferDisplay {
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        *FLUSHED_FRAME.lock().unwrap() = self.frame;
+        Ok(())
+    }
+}
+
+/// Writes the last flushed frame to a PNG file, white on black like the OLED
+pub fn save_screenshot(path: &Path) -> Result<(), png::EncodingError> {
+    let frame = *FLUSHED_FRAME.lock().unwrap();
+    let width = WIDTH * SCREENSHOT_SCALE;
+    let height = HEIGHT * SCREENSHOT_SCALE;
+    let mut image = Vec::with_capacity(width * height);
+    for row in frame.iter() {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|on| [if *on { 0xFF } else { 0x00 }; SCREENSHOT_SCALE])
+            .collect();
+        for _ in 0..SCREENSHOT_SCALE {
+            image.extend_from_slice(&line);
+        }
+    }
+
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image)
+}
@@ -0,0 +1,228 @@
+//! The DS3231 emulated at the register level, so the RTC driver of the firmware runs unchanged
+
+use embassy_time::Instant;
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, I2c, Operation, SevenBitAddress};
+
+use crate::sim::temperature::ambient_temperature;
+
+const DS3231_ADDRESS: u8 = 0x68;
+const REGISTERS: usize = 0x13;
+const TIME_REGISTERS: usize = 7;
+const CONTROL: usize = 0x0E;
+const STATUS: usize = 0x0F;
+const TEMPERATURE_MSB: usize = 0x11;
+const TEMPERATURE_LSB: usize = 0x12;
+const CONTROL_CONV: u8 = 1 << 5;
+/// The oscillator was stopped, so the time is not valid
+const STATUS_OSF: u8 = 1 << 7;
+const STATUS_EN32KHZ: u8 = 1 << 3;
+const STATUS_FLAGS: u8 = STATUS_OSF | 0x03;
+
+pub struct SimDs3231 {
+    registers: [u8; REGISTERS],
+    /// The register the next access starts at
+    pointer: usize,
+    /// The RTC time in seconds since 1970 at the uptime zero
+    base_s: i64,
+}
+
+impl SimDs3231 {
+    /// Creates the RTC running from the given time in seconds since 1970
+    pub fn new(start_s: i64) -> Self {
+        let mut registers = [0u8; REGISTERS];
+        registers[CONTROL] = 0x1C;
+        registers[STATUS] = STATUS_EN32KHZ;
+        Self {
+            registers,
+            pointer: 0,
+            base_s: start_s,
+        }
+    }
+
+    fn now_s(&self) -> i64 {
+        self.base_s + Instant::now().as_secs() as i64
+    }
+
+    fn read_register(&mut self, register: usize) -> u8 {
+        match register {
+            0..TIME_REGISTERS => encode_time(self.now_s())[register],
+            TEMPERATURE_MSB => {
+                // A conversion completes immediately
+                self.registers[CONTROL] &= !CONTROL_CONV;
+                let quarters = (ambient_temperature() * 4.0).round() as i16;
+                (quarters >> 2) as u8
+            }
+            TEMPERATURE_LSB => {
+                let quarters = (ambient_temperature() * 4.0).round() as i16;
+                ((quarters & 0x03) << 6) as u8
+            }
+            _ => self.registers[register],
+        }
+    }
+
+    fn write_registers(&mut self, start: usize, data: &[u8]) -> Result<(), ErrorKind> {
+        let mut time = encode_time(self.now_s());
+        let mut time_written = false;
+        for (i, value) in data.iter().enumerate() {
+            let register = (start + i) % REGISTERS;
+            match register {
+                0..TIME_REGISTERS => {
+                    time[register] = *value;
+                    time_written = true;
+                }
+                STATUS => {
+                    // The alarm and the oscillator flags are cleared by writing zeros, the busy flag
+                    // is read-only and the conversions never keep it set
+                    self.registers[STATUS] = (self.registers[STATUS] & value & STATUS_FLAGS) | (value & STATUS_EN32KHZ);
+                }
+                TEMPERATURE_MSB | TEMPERATURE_LSB => return Err(ErrorKind::Other),
+                _ => self.registers[register] = *value,
+            }
+        }
+        if time_written {
+            let time_s = decode_time(&time).ok_or(ErrorKind::Other)?;
+            self.base_s = time_s - Instant::now().as_secs() as i64;
+            self.registers[STATUS] &= !STATUS_OSF;
+        }
+        Ok(())
+    }
+}
+
+impl ErrorType for SimDs3231 {
+    type Error = ErrorKind;
+}
+
+impl I2c<SevenBitAddress> for SimDs3231 {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if address != DS3231_ADDRESS {
+            return Err(ErrorKind::NoAcknowledge(
+                embedded_hal_async::i2c::NoAcknowledgeSource::Address,
+            ));
+        }
+        for operation in operations {
+            match operation {
+                Operation::Write(data) => {
+                    let Some((register, values)) = data.split_first() else {
+                        continue;
+                    };
+                    self.pointer = *register as usize % REGISTERS;
+                    self.write_registers(self.pointer, values)?;
+                    self.pointer = (self.pointer + values.len()) % REGISTERS;
+                }
+                Operation::Read(buffer) => {
+                    for value in buffer.iter_mut() {
+                        *value = self.read_register(self.pointer);
+                        self.pointer = (self.pointer + 1) % REGISTERS;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u32 {
+    ((value >> 4) * 10 + (value & 0x0F)) as u32
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of the days since 1970-01-01 as (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The time registers in the 24-hour mode
+fn encode_time(time_s: i64) -> [u8; TIME_REGISTERS] {
+    let days = time_s.div_euclid(86_400);
+    let seconds = time_s.rem_euclid(86_400) as u32;
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday, the weekday register counts from 1 on Sunday
+    let weekday = (days + 4).rem_euclid(7) as u32 + 1;
+    let century = if year >= 2100 { 0x80 } else { 0 };
+    [
+        to_bcd(seconds % 60),
+        to_bcd(seconds / 60 % 60),
+        to_bcd(seconds / 3600),
+        to_bcd(weekday),
+        to_bcd(day),
+        to_bcd(month) | century,
+        to_bcd((year.rem_euclid(100)) as u32),
+    ]
+}
+
+fn decode_time(registers: &[u8; TIME_REGISTERS]) -> Option<i64> {
+    let seconds = from_bcd(registers[0] & 0x7F);
+    let minutes = from_bcd(registers[1] & 0x7F);
+    let hours = if registers[2] & 0x40 != 0 {
+        // The 12-hour mode, bit 5 selects PM
+        let hour = from_bcd(registers[2] & 0x1F) % 12;
+        hour + if registers[2] & 0x20 != 0 { 12 } else { 0 }
+    } else {
+        from_bcd(registers[2] & 0x3F)
+    };
+    let day = from_bcd(registers[4] & 0x3F);
+    let month = from_bcd(registers[5] & 0x1F);
+    let century = if registers[5] & 0x80 != 0 { 2100 } else { 2000 };
+    let year = century + from_bcd(registers[6]) as i64;
+    if seconds > 59 || minutes > 59 || hours > 23 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + (hours * 3600 + minutes * 60 + seconds) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in [-1, 0, 365, 10_957, 11_016, 20_000, 47_482] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        // 2000-02-29, a leap day
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn time_registers_round_trip() {
+        // 2025-06-15 13:45:30, a Sunday
+        let time_s = 1_749_995_130;
+        let registers = encode_time(time_s);
+        assert_eq!(registers, [0x30, 0x45, 0x13, 0x01, 0x15, 0x06, 0x25]);
+        assert_eq!(decode_time(&registers), Some(time_s));
+    }
+
+    #[test]
+    fn rejects_invalid_time_registers() {
+        assert_eq!(decode_time(&[0x00, 0x00, 0x00, 0x01, 0x01, 0x13, 0x25]), None);
+    }
+}
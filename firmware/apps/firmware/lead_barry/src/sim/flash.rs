@@ -0,0 +1,129 @@
+use crate::configuration::{FlashError, FlashStorage};
+
+/// The same size as the user flash of the board
+const MEMORY_FLASH_SIZE: usize = 0x1000;
+/// The same size as the counters flash of the board
+pub const COUNTERS_FLASH_SIZE: usize = 0x2000;
+/// The same size as the history flash of the board
+pub const HISTORY_FLASH_SIZE: usize = 0x80000;
+const ASYNC_READ_SIZE: usize = 4;
+const ERASE_SIZE: usize = 0x1000;
+/// The flash reads 0xFF after an erase
+const ERASED: u8 = 0xFF;
+
+/// The flash kept in memory. Every simulation starts from an erased flash, that is from the
+/// default settings.
+pub struct MemoryFlash<const SIZE: usize = MEMORY_FLASH_SIZE> {
+    data: Vec<u8>,
+}
+
+impl<const SIZE: usize> MemoryFlash<SIZE> {
+    pub fn new() -> Self {
+        Self {
+            data: vec![ERASED; SIZE],
+        }
+    }
+
+    fn range(offset: usize, len: usize) -> Result<core::ops::Range<usize>, FlashError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= SIZE => Ok(offset..end),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for MemoryFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> FlashStorage for MemoryFlash<SIZE> {
+    const SIZE: usize = SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn blocking_erase_range(&mut self, from: usize, to: usize) -> Result<(), FlashError> {
+        if from > to {
+            return Err(FlashError::OutOfBounds);
+        }
+        let range = Self::range(from, to - from)?;
+        if !from.is_multiple_of(ERASE_SIZE) || !to.is_multiple_of(ERASE_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+        self.data[range].fill(ERASED);
+        Ok(())
+    }
+
+    fn blocking_write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        let range = Self::range(offset, data.len())?;
+        // Programming only clears bits, like on the real flash
+        for (cell, value) in self.data[range].iter_mut().zip(data) {
+            *cell &= value;
+        }
+        Ok(())
+    }
+
+    async fn background_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
+        // The board reads words, the memory has no such constraint but keeps the offset check
+        if !offset.is_multiple_of(ASYNC_READ_SIZE) {
+            return Err(FlashError::Unaligned);
+        }
+        self.blocking_read(offset, buffer)
+    }
+
+    fn blocking_read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
+        let range = Self::range(offset, buffer.len())?;
+        buffer.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_without_erase_only_clears_bits() {
+        let mut flash: MemoryFlash = MemoryFlash::new();
+        flash.blocking_write(0, &[0x0F]).unwrap();
+        flash.blocking_write(0, &[0xF1]).unwrap();
+        let mut buffer = [0u8; 1];
+        flash.blocking_read(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x01]);
+        flash.blocking_erase().unwrap();
+        flash.blocking_read(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [ERASED]);
+    }
+
+    #[test]
+    fn erase_range_erases_whole_blocks() {
+        let mut flash: MemoryFlash<{ 2 * ERASE_SIZE }> = MemoryFlash::new();
+        flash.blocking_write(0, &[0]).unwrap();
+        flash.blocking_write(ERASE_SIZE, &[0]).unwrap();
+        assert!(matches!(
+            flash.blocking_erase_range(0, ERASE_SIZE / 2),
+            Err(FlashError::Unaligned)
+        ));
+
+        flash.blocking_erase_range(ERASE_SIZE, 2 * ERASE_SIZE).unwrap();
+        let mut buffer = [0u8; 1];
+        flash.blocking_read(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [0]);
+        flash.blocking_read(ERASE_SIZE, &mut buffer).unwrap();
+        assert_eq!(buffer, [ERASED]);
+    }
+
+    #[test]
+    fn rejects_access_out_of_bounds() {
+        let mut flash: MemoryFlash = MemoryFlash::new();
+        let mut buffer = [0u8; 2];
+        assert!(matches!(
+            flash.blocking_read(MEMORY_FLASH_SIZE - 1, &mut buffer),
+            Err(FlashError::OutOfBounds)
+        ));
+        assert!(matches!(
+            flash.blocking_write(MEMORY_FLASH_SIZE, &[0]),
+            Err(FlashError::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+//! The host simulation of the controller. The services of the firmware run on the std executor
+//! with the devices of the board replaced by simulated ones:
+//! - the VCP sensor replays the profiles of `sensors.csv`
+//! - the DS3231 is emulated at the register level
+//! - the flash is kept in memory
+//! - the display draws into a framebuffer which is saved as PNG screenshots
+//! - the buttons, the temperature and the expectations follow `events.csv`
+//!
+//! The WiFi chip and the web server are not simulated.
+//!
+//! Run with `cargo lead_barry_sim -- --scenario <dir> [--out <dir>] [--speed <factor>]`.
+
+mod buttons;
+mod csv;
+mod display;
+mod ds3231;
+mod flash;
+mod outputs;
+mod scenario;
+mod temperature;
+mod time_driver;
+pub mod types;
+mod vcp_sensor;
+
+use std::path::PathBuf;
+
+use defmt_or_log as log;
+use embassy_executor::{Executor, Spawner};
+use static_cell::StaticCell;
+
+use crate::app::{self, AppDevices};
+use crate::configuration::{ConfigurationStorageBuilder, CountersStoreBuilder, TemperatureSource};
+use crate::input::{ButtonControllerBuilder, ButtonControllerState, Buttons};
+use crate::main_logic_controller::{handle_after_reset_actions, run_screens};
+use crate::ui::{SvWelcome, UiControl, UiInterface, UiSharedState};
+
+use self::buttons::SimButton;
+use self::display::FramebufferDisplay;
+use self::ds3231::SimDs3231;
+use self::flash::MemoryFlash;
+use self::outputs::SimOutputDriver;
+use self::scenario::{Scenario, scenario_task};
+use self::temperature::SimTemperatureProbe;
+use self::vcp_sensor::CsvVcpSensor;
+
+/// The RTC starts at 2025-01-01 00:00:00 unless the scenario sets another time
+const DEFAULT_START_S: i64 = 1_735_689_600;
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+static BUTTON_CONTROLLER: StaticCell<ButtonControllerState> = StaticCell::new();
+static UI_SHARED_STATE: StaticCell<UiSharedState> = StaticCell::new();
+static UI_CONTROL: StaticCell<UiControl> = StaticCell::new();
+
+struct SimArgs {
+    scenario_dir: PathBuf,
+    out_dir: PathBuf,
+    speed: u32,
+    start_s: i64,
+}
+
+impl SimArgs {
+    fn parse() -> Result<Self, String> {
+        let mut scenario_dir = None;
+        let mut out_dir = None;
+        let mut speed = 1;
+        let mut start_s = DEFAULT_START_S;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--scenario" => scenario_dir = Some(PathBuf::from(value()?)),
+                "--out" => out_dir = Some(PathBuf::from(value()?)),
+                "--speed" => speed = value()?.parse().map_err(|_| "--speed needs a whole number")?,
+                "--start" => start_s = value()?.parse().map_err(|_| "--start needs seconds since 1970")?,
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        let scenario_dir = scenario_dir.ok_or("--scenario is required")?;
+        Ok(Self {
+            out_dir: out_dir.unwrap_or_else(|| scenario_dir.join("out")),
+            scenario_dir,
+            speed,
+            start_s,
+        })
+    }
+}
+
+/// The devices set up from the scenario before the executor starts
+struct SimSetup {
+    vcp_sensor: CsvVcpSensor,
+    scenario: Scenario,
+    start_s: i64,
+}
+
+pub fn run() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = match SimArgs::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: lead_barry --scenario <dir> [--out <dir>] [--speed <factor>] [--start <unix time>]");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&args.out_dir) {
+        eprintln!("Failed to create {}: {}", args.out_dir.display(), e);
+        std::process::exit(2);
+    }
+    let setup = CsvVcpSensor::from_file(&args.scenario_dir.join("sensors.csv")).and_then(|vcp_sensor| {
+        Ok(SimSetup {
+            vcp_sensor,
+            scenario: Scenario::from_file(&args.scenario_dir.join("events.csv"), args.out_dir.clone())?,
+            start_s: args.start_s,
+        })
+    });
+    let setup = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    log::info!("Simulating {} at {}x speed", args.scenario_dir.display(), args.speed);
+    time_driver::set_speed(args.speed);
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(move |spawner| {
+        spawner.spawn(sim_init(spawner, setup)).unwrap();
+    });
+}
+
+#[embassy_executor::task]
+async fn sim_init(spawner: Spawner, setup: SimSetup) -> ! {
+    let configuration_storage = ConfigurationStorageBuilder::new(MemoryFlash::new()).build();
+    let counters_store = CountersStoreBuilder::new(MemoryFlash::new()).build();
+
+    let ui_state = UI_SHARED_STATE.init(UiSharedState::new());
+    let (ui_control, ui_runner) = UiInterface::new(FramebufferDisplay::new(), ui_state, Some(SvWelcome::new().into()));
+    let ui_control: &'static UiControl = UI_CONTROL.init(ui_control);
+    spawner.spawn(app::ui_runner_task(ui_runner)).unwrap();
+
+    let settings = configuration_storage.get_settings().await;
+    let counters = counters_store.get_counters().await;
+    let temperature_probe =
+        (app::temperature_settings(&settings).source == TemperatureSource::Probe).then_some(SimTemperatureProbe);
+    let devices = AppDevices {
+        vcp_sensor: setup.vcp_sensor,
+        outputs_driver: SimOutputDriver::new(),
+        rtc_i2c: SimDs3231::new(setup.start_s),
+        history_flash: MemoryFlash::new(),
+        temperature_probe,
+    };
+    let (app_runners, shared_resources) = app::init(
+        devices,
+        &settings,
+        &counters,
+        configuration_storage,
+        counters_store,
+        ui_control,
+    );
+    app::spawn_tasks(spawner, app_runners, shared_resources);
+
+    let mut button_controller_builder = ButtonControllerBuilder::new();
+    button_controller_builder.bind(Buttons::Yellow, SimButton::new(Buttons::Yellow));
+    button_controller_builder.bind(Buttons::Blue, SimButton::new(Buttons::Blue));
+    let (button_controller, button_controller_runner) =
+        button_controller_builder.build(BUTTON_CONTROLLER.init(ButtonControllerState::new()));
+    spawner
+        .spawn(app::buttons_controller_task(button_controller_runner))
+        .unwrap();
+
+    spawner.spawn(scenario_task(setup.scenario, shared_resources)).unwrap();
+
+    handle_after_reset_actions(shared_resources, button_controller).await;
+    run_screens(shared_resources, button_controller).await
+}
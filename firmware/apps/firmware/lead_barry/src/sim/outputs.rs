@@ -0,0 +1,47 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt_or_log as log;
+
+use crate::outputs::{OutputDriver, OutputId, OutputLine, OutputSwitches};
+
+/// The control lines as the shift register of the board would hold them
+static SWITCHES: AtomicU8 = AtomicU8::new(0);
+
+/// Returns true if the line switching the output on is set
+pub fn is_output_enabled(output: OutputId) -> bool {
+    let switches = SWITCHES.load(Ordering::Relaxed);
+    switches & (1 << OutputLine::enable_line(output) as u8) != 0
+}
+
+/// The output lines and DACs of the board, logged and kept for the scenario checks
+pub struct SimOutputDriver {
+    switches: OutputSwitches,
+}
+
+impl SimOutputDriver {
+    pub const fn new() -> Self {
+        Self {
+            switches: OutputSwitches::new(),
+        }
+    }
+}
+
+impl Default for SimOutputDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputDriver for SimOutputDriver {
+    fn write_switches(&mut self, switches: OutputSwitches) {
+        if switches != self.switches {
+            log::info!("Output lines: {:08b}", switches.bits());
+        }
+        self.switches = switches;
+        SWITCHES.store(switches.bits(), Ordering::Relaxed);
+    }
+
+    fn write_dac(&mut self, output: OutputId, level: f32) {
+        log::info!("Output {:?} DAC level: {}", output, level);
+    }
+}
@@ -0,0 +1,253 @@
+//! The timeline of a scenario from `events.csv`. The rows are `time_s,action,arguments...` with
+//! the actions:
+//! - `press,<yellow|blue>` and `release,<yellow|blue>`
+//! - `temperature,<celsius>` sets the temperature of the battery and the RTC
+//! - `screenshot,<name>` writes the display to `<name>.png` in the output directory
+//! - `expect,<key>,<value>` checks the state of the controller, the keys are `charge_stage`,
+//!   `lvd`, `output.<output>` with `on` or `off`, `soc_above` and `soc_below`
+//! - `end` finishes the scenario, otherwise it finishes after the last event
+//!
+//! The simulation exits with the status 1 if an expectation failed.
+
+use std::path::{Path, PathBuf};
+
+use defmt_or_log as log;
+use embassy_time::{Instant, Timer};
+
+use crate::input::Buttons;
+use crate::outputs::OutputId;
+use crate::shared_resources::SharedResources;
+use crate::sim::csv::{CsvError, CsvRow, parse_f32, read_rows};
+use crate::sim::{buttons, display, outputs, temperature};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// The name of the charge stage
+    ChargeStage(String),
+    /// The name of the LVD state
+    Lvd(String),
+    Output(OutputId, bool),
+    SocAbove(f32),
+    SocBelow(f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Press(Buttons),
+    Release(Buttons),
+    Temperature(f32),
+    Screenshot(String),
+    Expect(Expectation),
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScenarioEvent {
+    pub time_s: f32,
+    pub action: Action,
+    /// The line of the event in the script, for the reports
+    pub line: usize,
+}
+
+pub struct Scenario {
+    events: Vec<ScenarioEvent>,
+    out_dir: PathBuf,
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path, out_dir: PathBuf) -> Result<Self, CsvError> {
+        let events = parse_events(&read_rows(path)?)?;
+        Ok(Self { events, out_dir })
+    }
+}
+
+fn parse_events(rows: &[CsvRow]) -> Result<Vec<ScenarioEvent>, CsvError> {
+    let mut events = rows.iter().map(parse_event).collect::<Result<Vec<_>, _>>()?;
+    events.sort_by(|a, b| a.time_s.total_cmp(&b.time_s));
+    Ok(events)
+}
+
+fn parse_button(row: &CsvRow, name: &str) -> Result<Buttons, CsvError> {
+    match name {
+        "yellow" => Ok(Buttons::Yellow),
+        "blue" => Ok(Buttons::Blue),
+        _ => Err(row.error(format!("unknown button '{}'", name))),
+    }
+}
+
+fn parse_output(row: &CsvRow, name: &str) -> Result<OutputId, CsvError> {
+    (0u8..)
+        .map_while(|id| OutputId::try_from(id).ok())
+        .find(|output| format!("{:?}", output).eq_ignore_ascii_case(name))
+        .ok_or_else(|| row.error(format!("unknown output '{}'", name)))
+}
+
+fn parse_expectation(row: &CsvRow, key: &str, value: &str) -> Result<Expectation, CsvError> {
+    if let Some(output) = key.strip_prefix("output.") {
+        let enabled = match value {
+            "on" => true,
+            "off" => false,
+            _ => return Err(row.error(format!("expected on or off, found '{}'", value))),
+        };
+        return Ok(Expectation::Output(parse_output(row, output)?, enabled));
+    }
+    match key {
+        "charge_stage" => Ok(Expectation::ChargeStage(value.to_string())),
+        "lvd" => Ok(Expectation::Lvd(value.to_string())),
+        "soc_above" => Ok(Expectation::SocAbove(parse_f32(row, value)?)),
+        "soc_below" => Ok(Expectation::SocBelow(parse_f32(row, value)?)),
+        _ => Err(row.error(format!("unknown expectation '{}'", key))),
+    }
+}
+
+fn parse_event(row: &CsvRow) -> Result<ScenarioEvent, CsvError> {
+    let fields = row.all_fields();
+    if fields.len() < 2 {
+        return Err(row.error("expected the time and the action"));
+    }
+    let time_s = parse_f32(row, &fields[0])?;
+    let action = match fields[1..].iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["press", button] => Action::Press(parse_button(row, button)?),
+        ["release", button] => Action::Release(parse_button(row, button)?),
+        ["temperature", celsius] => Action::Temperature(parse_f32(row, celsius)?),
+        ["screenshot", name] => Action::Screenshot(name.to_string()),
+        ["expect", key, value] => Action::Expect(parse_expectation(row, key, value)?),
+        ["end"] => Action::End,
+        _ => return Err(row.error(format!("invalid action '{}'", fields[1..].join(",")))),
+    };
+    Ok(ScenarioEvent {
+        time_s,
+        action,
+        line: row.line(),
+    })
+}
+
+/// Returns a description of the mismatch if the controller is not in the expected state
+async fn check(shared: &'static SharedResources, expectation: &Expectation) -> Result<(), String> {
+    match expectation {
+        Expectation::ChargeStage(expected) => {
+            let stage = format!("{:?}", shared.charger_control.status().await.stage);
+            if stage.eq_ignore_ascii_case(expected) {
+                Ok(())
+            } else {
+                Err(format!("charge stage is {}, expected {}", stage, expected))
+            }
+        }
+        Expectation::Lvd(expected) => {
+            let state = format!("{:?}", shared.lvd_control.status().await.state);
+            if state.eq_ignore_ascii_case(expected) {
+                Ok(())
+            } else {
+                Err(format!("LVD state is {}, expected {}", state, expected))
+            }
+        }
+        Expectation::Output(output, expected) => {
+            let enabled = outputs::is_output_enabled(*output);
+            if enabled == *expected {
+                Ok(())
+            } else {
+                Err(format!("output {:?} is {}", output, if enabled { "on" } else { "off" }))
+            }
+        }
+        Expectation::SocAbove(percent) => {
+            let soc = shared.battery_control.status().await.soc_percent;
+            if soc > *percent {
+                Ok(())
+            } else {
+                Err(format!("SoC is {:.1}%, expected above {}%", soc, percent))
+            }
+        }
+        Expectation::SocBelow(percent) => {
+            let soc = shared.battery_control.status().await.soc_percent;
+            if soc < *percent {
+                Ok(())
+            } else {
+                Err(format!("SoC is {:.1}%, expected below {}%", soc, percent))
+            }
+        }
+    }
+}
+
+fn finish(failures: usize) -> ! {
+    if failures == 0 {
+        log::info!("Scenario passed");
+        std::process::exit(0)
+    }
+    log::error!("Scenario failed: {} expectation(s) not met", failures);
+    std::process::exit(1)
+}
+
+/// Plays the events of the scenario at their times and exits the simulation after the last one
+#[embassy_executor::task]
+pub async fn scenario_task(scenario: Scenario, shared: &'static SharedResources) -> ! {
+    let mut failures = 0;
+    for event in scenario.events {
+        Timer::at(Instant::from_millis((event.time_s * 1000.0) as u64)).await;
+        log::debug!("{}s: {:?}", event.time_s, event.action);
+        match event.action {
+            Action::Press(button) => buttons::set_pressed(button, true),
+            Action::Release(button) => buttons::set_pressed(button, false),
+            Action::Temperature(celsius) => temperature::set_ambient_temperature(celsius),
+            Action::Screenshot(name) => {
+                let path = scenario.out_dir.join(format!("{}.png", name));
+                if let Err(e) = display::save_screenshot(&path) {
+                    log::error!("Failed to write {}: {}", path.display(), e);
+                    failures += 1;
+                }
+            }
+            Action::Expect(expectation) => {
+                if let Err(mismatch) = check(shared, &expectation).await {
+                    log::error!("Line {} at {}s: {}", event.line, event.time_s, mismatch);
+                    failures += 1;
+                }
+            }
+            Action::End => break,
+        }
+    }
+    finish(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::csv::parse_rows;
+
+    fn events(script: &str) -> Result<Vec<ScenarioEvent>, CsvError> {
+        parse_events(&parse_rows(Path::new("events.csv"), script))
+    }
+
+    #[test]
+    fn parses_and_orders_the_events() {
+        let events = events(
+            "time_s,action\n\
+             10,expect,output.Dc1,off\n\
+             1.5,press,yellow\n\
+             2,release,yellow\n\
+             5,expect,soc_above,80\n\
+             6,screenshot,battery\n\
+             20,end\n",
+        )
+        .unwrap();
+        let actions: Vec<_> = events.iter().map(|event| event.action.clone()).collect();
+        assert_eq!(
+            actions,
+            [
+                Action::Press(Buttons::Yellow),
+                Action::Release(Buttons::Yellow),
+                Action::Expect(Expectation::SocAbove(80.0)),
+                Action::Screenshot("battery".to_string()),
+                Action::Expect(Expectation::Output(OutputId::Dc1, false)),
+                Action::End,
+            ]
+        );
+        assert_eq!(events[0].line, 3);
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        assert!(events("1,jump\n").is_err());
+        assert!(events("1,press,green\n").is_err());
+        assert!(events("1,expect,output.Dc9,on\n").is_err());
+        assert!(events("1,expect,soc_above,high\n").is_err());
+    }
+}
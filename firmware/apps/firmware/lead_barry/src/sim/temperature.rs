@@ -0,0 +1,37 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::temperature::TemperatureProbe;
+
+/// 25°C until the scenario changes it
+static AMBIENT_TEMPERATURE_BITS: AtomicU32 = AtomicU32::new(0x41C8_0000);
+
+/// The temperature of the battery and of the board in degrees Celsius
+pub fn ambient_temperature() -> f32 {
+    f32::from_bits(AMBIENT_TEMPERATURE_BITS.load(Ordering::Relaxed))
+}
+
+pub fn set_ambient_temperature(temperature_c: f32) {
+    AMBIENT_TEMPERATURE_BITS.store(temperature_c.to_bits(), Ordering::Relaxed);
+}
+
+/// The battery probe, always at the ambient temperature
+pub struct SimTemperatureProbe;
+
+impl TemperatureProbe for SimTemperatureProbe {
+    async fn temperature(&mut self) -> Option<f32> {
+        Some(ambient_temperature())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ambient_temperature_follows_the_scenario() {
+        assert_eq!(ambient_temperature(), 25.0);
+        set_ambient_temperature(-12.5);
+        assert_eq!(ambient_temperature(), -12.5);
+        set_ambient_temperature(25.0);
+    }
+}
@@ -0,0 +1,74 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Waker;
+use std::sync::{Condvar, Mutex, Once, OnceLock};
+use std::time::{Duration, Instant};
+
+use embassy_time_driver::{Driver, time_driver_impl};
+use embassy_time_queue_utils::Queue;
+
+/// The embassy time of the simulation. It runs `speed` times faster than the wall clock, so the
+/// scenarios covering hours of battery operation finish in minutes.
+struct ScaledTimeDriver {
+    started: OnceLock<Instant>,
+    speed: AtomicU32,
+    queue: Mutex<Queue>,
+    /// Wakes the alarm thread up when an earlier alarm is scheduled
+    rescheduled: Condvar,
+    alarm_thread: Once,
+}
+
+time_driver_impl!(static DRIVER: ScaledTimeDriver = ScaledTimeDriver {
+    started: OnceLock::new(),
+    speed: AtomicU32::new(1),
+    queue: Mutex::new(Queue::new()),
+    rescheduled: Condvar::new(),
+    alarm_thread: Once::new(),
+});
+
+/// Sets how many times faster than the wall clock the simulation runs. Must be called before the
+/// executor starts.
+pub fn set_speed(speed: u32) {
+    DRIVER.speed.store(speed.max(1), Ordering::Relaxed);
+}
+
+impl ScaledTimeDriver {
+    fn speed(&self) -> u64 {
+        self.speed.load(Ordering::Relaxed) as u64
+    }
+
+    /// Converts the ticks of the simulation to the wall clock time
+    fn wall_duration(&self, ticks: u64) -> Duration {
+        let us = ticks.saturating_mul(1_000_000) / embassy_time_driver::TICK_HZ / self.speed();
+        Duration::from_micros(us)
+    }
+
+    fn run_alarms(&'static self) -> ! {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let next = queue.next_expiration(self.now());
+            queue = if next == u64::MAX {
+                self.rescheduled.wait(queue).unwrap()
+            } else {
+                let timeout = self.wall_duration(next.saturating_sub(self.now()));
+                self.rescheduled.wait_timeout(queue, timeout).unwrap().0
+            };
+        }
+    }
+}
+
+impl Driver for ScaledTimeDriver {
+    fn now(&self) -> u64 {
+        let elapsed = self.started.get_or_init(Instant::now).elapsed();
+        let ticks = elapsed.as_micros() as u64 * embassy_time_driver::TICK_HZ / 1_000_000;
+        ticks * self.speed()
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        self.alarm_thread.call_once(|| {
+            std::thread::spawn(|| DRIVER.run_alarms());
+        });
+        if self.queue.lock().unwrap().schedule_wake(at, waker) {
+            self.rescheduled.notify_one();
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use crate::sim::buttons::SimButton;
+use crate::sim::display::FramebufferDisplay;
+use crate::sim::ds3231::SimDs3231;
+use crate::sim::flash::{COUNTERS_FLASH_SIZE, HISTORY_FLASH_SIZE, MemoryFlash};
+use crate::sim::outputs::SimOutputDriver;
+use crate::sim::temperature::SimTemperatureProbe;
+use crate::sim::vcp_sensor::CsvVcpSensor;
+
+/// The I2C device of the DS3231 real-time clock
+pub type RtcI2cDevice = SimDs3231;
+pub type VcpSensorDevice = CsvVcpSensor;
+pub type DisplayDevice = FramebufferDisplay;
+pub type FlashDevice = MemoryFlash;
+pub type CountersFlashDevice = MemoryFlash<COUNTERS_FLASH_SIZE>;
+pub type HistoryFlashDevice = MemoryFlash<HISTORY_FLASH_SIZE>;
+pub type OutputDriverDevice = SimOutputDriver;
+pub type TemperatureProbeDevice = SimTemperatureProbe;
+pub type ButtonInputDevice = SimButton;
@@ -0,0 +1,228 @@
+use std::path::Path;
+
+use embassy_time::Instant;
+
+use crate::sim::csv::{CsvError, parse_f32, read_rows};
+use crate::vcp_sensors::{
+    CHANNELS_PER_CHIP, ChannelNum, VCP_CHANNELS, VcpAlertFlags, VcpConfig, VcpError, VcpPowerLimits, VcpSampling,
+    VcpSensor,
+};
+
+const CHANNELS: usize = VCP_CHANNELS;
+
+/// A point of the scripted profile of a channel
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ProfilePoint {
+    time_s: f32,
+    voltage: f32,
+    current: f32,
+}
+
+/// The voltage and the current of a channel over time, linearly interpolated between the points
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelProfile {
+    points: Vec<ProfilePoint>,
+}
+
+impl ChannelProfile {
+    /// Returns the voltage and the current at the time. The profile holds the first point before
+    /// it and the last one after it.
+    pub fn sample(&self, time_s: f32) -> (f32, f32) {
+        let Some(first) = self.points.first() else {
+            return (0.0, 0.0);
+        };
+        let after = self.points.partition_point(|point| point.time_s <= time_s);
+        if after == 0 {
+            return (first.voltage, first.current);
+        }
+        let a = self.points[after - 1];
+        let Some(b) = self.points.get(after) else {
+            return (a.voltage, a.current);
+        };
+        let t = (time_s - a.time_s) / (b.time_s - a.time_s);
+        (
+            a.voltage + (b.voltage - a.voltage) * t,
+            a.current + (b.current - a.current) * t,
+        )
+    }
+}
+
+/// The VCP sensor replaying the profiles of `sensors.csv`. The rows are
+/// `time_s,channel,voltage_v,current_a`, the current is positive out of the battery. A second chip
+/// is fitted if the file has a profile of any of its channels.
+pub struct CsvVcpSensor {
+    profiles: [ChannelProfile; CHANNELS],
+    channel_count: usize,
+    shunt_resistance: [f32; CHANNELS],
+    enabled: [bool; CHANNELS],
+    power_valid_limit: Option<VcpPowerLimits>,
+    /// The critical and the warning limits as shunt voltages
+    alert_limits: [(f32, f32); CHANNELS],
+    summation_limit: Option<(f32, [bool; CHANNELS])>,
+}
+
+impl CsvVcpSensor {
+    pub fn from_file(path: &Path) -> Result<Self, CsvError> {
+        let mut profiles: [ChannelProfile; CHANNELS] = Default::default();
+        for row in read_rows(path)? {
+            let [time_s, channel, voltage, current] = row.fields()?;
+            let channel: usize = channel.parse().map_err(|_| row.error("invalid channel"))?;
+            let profile = profiles
+                .get_mut(channel)
+                .ok_or_else(|| row.error("channel out of range"))?;
+            profile.points.push(ProfilePoint {
+                time_s: parse_f32(&row, time_s)?,
+                voltage: parse_f32(&row, voltage)?,
+                current: parse_f32(&row, current)?,
+            });
+        }
+        for profile in profiles.iter_mut() {
+            profile.points.sort_by(|a, b| a.time_s.total_cmp(&b.time_s));
+        }
+        Ok(Self::new(profiles))
+    }
+
+    pub fn new(profiles: [ChannelProfile; CHANNELS]) -> Self {
+        let chips = profiles
+            .iter()
+            .rposition(|profile| !profile.points.is_empty())
+            .map_or(1, |channel| channel / CHANNELS_PER_CHIP + 1);
+        Self {
+            profiles,
+            channel_count: chips * CHANNELS_PER_CHIP,
+            shunt_resistance: [0.1; CHANNELS],
+            enabled: [false; CHANNELS],
+            power_valid_limit: None,
+            alert_limits: [(f32::MAX, f32::MAX); CHANNELS],
+            summation_limit: None,
+        }
+    }
+
+    fn sample(&self, channel: ChannelNum) -> Result<(f32, f32), VcpError> {
+        if channel as usize >= self.channel_count {
+            return Err(VcpError::InvalidChannel);
+        }
+        let profile = &self.profiles[channel as usize];
+        Ok(profile.sample(Instant::now().as_millis() as f32 / 1000.0))
+    }
+
+    fn shunt_voltage(&self, channel: ChannelNum) -> Result<f32, VcpError> {
+        let (_, current) = self.sample(channel)?;
+        Ok(current * self.shunt_resistance[channel as usize])
+    }
+}
+
+impl VcpSensor for CsvVcpSensor {
+    fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    async fn configure(&mut self, config: &VcpConfig) -> Result<(), VcpError> {
+        self.shunt_resistance = config.shunt_resistances();
+        self.enabled = config.enabled_channels;
+        self.power_valid_limit = config.global_pv_limit;
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), VcpError> {
+        Ok(())
+    }
+
+    async fn set_channel_enabled(&mut self, channel: ChannelNum, enabled: bool) -> Result<(), VcpError> {
+        *self.enabled.get_mut(channel as usize).ok_or(VcpError::I2c)? = enabled;
+        Ok(())
+    }
+
+    async fn read_bus_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        self.sample(channel).map(|(voltage, _)| voltage)
+    }
+
+    async fn read_shunt_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        self.shunt_voltage(channel)
+    }
+
+    async fn set_sampling(&mut self, _sampling: &VcpSampling) -> Result<(), VcpError> {
+        Ok(())
+    }
+
+    async fn set_alert_limits(&mut self, channel: ChannelNum, critical: f32, warning: f32) -> Result<(), VcpError> {
+        *self.alert_limits.get_mut(channel as usize).ok_or(VcpError::I2c)? = (critical, warning);
+        Ok(())
+    }
+
+    async fn set_summation_limit(&mut self, limit: f32, channels: [bool; VCP_CHANNELS]) -> Result<(), VcpError> {
+        self.summation_limit = Some((limit, channels));
+        Ok(())
+    }
+
+    async fn read_alert_flags(&mut self) -> Result<VcpAlertFlags, VcpError> {
+        let mut flags = VcpAlertFlags::new();
+        let mut power_valid = true;
+        for channel in 0..self.channel_count {
+            let (voltage, _) = self.sample(channel as ChannelNum)?;
+            let shunt_voltage = self.shunt_voltage(channel as ChannelNum)?;
+            let (critical, warning) = self.alert_limits[channel];
+            flags.critical[channel] = self.enabled[channel] && shunt_voltage > critical;
+            flags.warning[channel] = self.enabled[channel] && shunt_voltage > warning;
+            if let Some(limit) = &self.power_valid_limit {
+                power_valid &= voltage >= limit.lower_voltage && voltage <= limit.upper_voltage;
+            }
+        }
+        if let Some((limit, channels)) = self.summation_limit {
+            let mut sum = 0.0;
+            for (channel, _) in channels.iter().enumerate().filter(|(_, selected)| **selected) {
+                sum += self.shunt_voltage(channel as ChannelNum).unwrap_or(0.0);
+            }
+            flags.summation = sum > limit;
+        }
+        flags.power_valid = power_valid;
+        Ok(flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(points: &[(f32, f32, f32)]) -> ChannelProfile {
+        ChannelProfile {
+            points: points
+                .iter()
+                .map(|&(time_s, voltage, current)| ProfilePoint {
+                    time_s,
+                    voltage,
+                    current,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn empty_profile_reads_zero() {
+        assert_eq!(ChannelProfile::default().sample(10.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn profile_interpolates_between_points() {
+        let profile = profile(&[(0.0, 12.0, 1.0), (10.0, 13.0, -1.0)]);
+        let (voltage, current) = profile.sample(5.0);
+        assert!((voltage - 12.5).abs() < 1e-6);
+        assert!(current.abs() < 1e-6);
+    }
+
+    #[test]
+    fn channel_count_covers_the_chips_of_the_profiles() {
+        let mut profiles: [ChannelProfile; CHANNELS] = Default::default();
+        assert_eq!(CsvVcpSensor::new(profiles.clone()).channel_count(), CHANNELS_PER_CHIP);
+
+        profiles[CHANNELS_PER_CHIP] = profile(&[(0.0, 5.0, 0.5)]);
+        assert_eq!(CsvVcpSensor::new(profiles).channel_count(), 2 * CHANNELS_PER_CHIP);
+    }
+
+    #[test]
+    fn profile_holds_outside_points() {
+        let profile = profile(&[(5.0, 12.0, 1.0), (10.0, 13.0, -1.0)]);
+        assert_eq!(profile.sample(0.0), (12.0, 1.0));
+        assert_eq!(profile.sample(100.0), (13.0, -1.0));
+    }
+}
@@ -0,0 +1,162 @@
+#![allow(dead_code)]
+
+//! Temperature compensation of the battery voltage setpoints.
+//!
+//! The charge voltages of a lead-acid battery are specified at 25°C. A cold battery needs a higher
+//! voltage to charge fully and a warm one a lower voltage not to gas, so all the setpoints move by
+//! the compensation coefficient per cell and degree. The LVD thresholds move with them, since the
+//! voltage of a cold battery at the same state of charge is higher under charge and a cold battery
+//! must not be discharged as deep.
+//!
+//! The temperature is clamped to the range the compensation is specified for, so a broken probe
+//! cannot push the setpoints out of the safe range.
+
+use serde::Serialize;
+
+use crate::configuration::BatteryProfile;
+
+/// The temperature the battery profile setpoints are specified at
+pub const REFERENCE_TEMPERATURE_C: f32 = 25.0;
+/// Number of cells of a 12V battery
+pub const CELLS: u8 = 6;
+const MIN_COMPENSATION_TEMPERATURE_C: f32 = -20.0;
+const MAX_COMPENSATION_TEMPERATURE_C: f32 = 50.0;
+
+/// The battery voltage setpoints in volts, either at 25°C or compensated for the battery temperature
+#[derive(Copy, Clone, PartialEq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct CompensatedSetpoints {
+    /// The shift from the 25°C setpoints in volts
+    pub offset_v: f32,
+    pub absorption_voltage: f32,
+    pub float_voltage: f32,
+    pub equalize_voltage: Option<f32>,
+    pub lvd_voltage: f32,
+    pub reconnect_voltage: f32,
+}
+
+impl CompensatedSetpoints {
+    pub const fn new() -> Self {
+        Self {
+            offset_v: 0.0,
+            absorption_voltage: 0.0,
+            float_voltage: 0.0,
+            equalize_voltage: None,
+            lvd_voltage: 0.0,
+            reconnect_voltage: 0.0,
+        }
+    }
+
+    /// The uncompensated setpoints of the profile and the LVD reconnect voltage
+    pub fn from_profile(profile: &BatteryProfile, reconnect_voltage: f32) -> Self {
+        Self {
+            offset_v: 0.0,
+            absorption_voltage: profile.absorption_voltage,
+            float_voltage: profile.float_voltage,
+            equalize_voltage: profile.equalize_voltage,
+            lvd_voltage: profile.lvd_voltage,
+            reconnect_voltage,
+        }
+    }
+
+    /// The highest voltage the charger is expected to apply to the battery
+    pub fn max_charge_voltage(&self) -> f32 {
+        match self.equalize_voltage {
+            Some(equalize_voltage) => equalize_voltage.max(self.absorption_voltage),
+            None => self.absorption_voltage,
+        }
+    }
+
+    /// The profile with the compensated voltages
+    pub fn apply_to(&self, profile: &BatteryProfile) -> BatteryProfile {
+        let mut profile = *profile;
+        profile.absorption_voltage = self.absorption_voltage;
+        profile.float_voltage = self.float_voltage;
+        profile.equalize_voltage = self.equalize_voltage;
+        profile.lvd_voltage = self.lvd_voltage;
+        profile
+    }
+
+    fn shifted(&self, offset_v: f32) -> Self {
+        Self {
+            offset_v: self.offset_v + offset_v,
+            absorption_voltage: self.absorption_voltage + offset_v,
+            float_voltage: self.float_voltage + offset_v,
+            equalize_voltage: self.equalize_voltage.map(|voltage| voltage + offset_v),
+            lvd_voltage: self.lvd_voltage + offset_v,
+            reconnect_voltage: self.reconnect_voltage + offset_v,
+        }
+    }
+}
+
+impl Default for CompensatedSetpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct TemperatureCompensation {
+    /// The setpoints at the reference temperature
+    pub reference: CompensatedSetpoints,
+    /// The setpoint shift per degree above the reference temperature, in millivolts per cell
+    pub coefficient_mv_per_cell: f32,
+    pub cells: u8,
+}
+
+impl TemperatureCompensation {
+    /// The setpoint shift in volts at the given battery temperature
+    pub fn offset_v(&self, temperature_c: f32) -> f32 {
+        let temperature_c = temperature_c.clamp(MIN_COMPENSATION_TEMPERATURE_C, MAX_COMPENSATION_TEMPERATURE_C);
+        (temperature_c - REFERENCE_TEMPERATURE_C) * self.coefficient_mv_per_cell * self.cells as f32 / 1000.0
+    }
+
+    /// The setpoints at the given battery temperature
+    pub fn setpoints(&self, temperature_c: f32) -> CompensatedSetpoints {
+        self.reference.shifted(self.offset_v(temperature_c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compensation() -> TemperatureCompensation {
+        TemperatureCompensation {
+            reference: CompensatedSetpoints::from_profile(&BatteryProfile::new(), 12.6),
+            coefficient_mv_per_cell: -4.0,
+            cells: CELLS,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_no_shift_at_reference_temperature() {
+        let compensation = compensation();
+        assert_eq!(compensation.setpoints(REFERENCE_TEMPERATURE_C), compensation.reference);
+    }
+
+    #[test]
+    fn test_cold_battery_raises_setpoints() {
+        // 20 degrees below the reference: +4 mV * 6 cells * 20 = +0.48 V
+        let setpoints = compensation().setpoints(5.0);
+        assert_close(setpoints.offset_v, 0.48);
+        assert_close(setpoints.absorption_voltage, 14.88);
+        assert_close(setpoints.float_voltage, 13.98);
+        assert_close(setpoints.equalize_voltage.unwrap(), 15.98);
+        assert_close(setpoints.lvd_voltage, 12.08);
+        assert_close(setpoints.reconnect_voltage, 13.08);
+    }
+
+    #[test]
+    fn test_temperature_is_clamped() {
+        let compensation = compensation();
+        assert_eq!(compensation.setpoints(85.0), compensation.setpoints(50.0));
+        assert_eq!(compensation.setpoints(-40.0), compensation.setpoints(-20.0));
+        assert_close(compensation.setpoints(50.0).offset_v, -0.6);
+    }
+}
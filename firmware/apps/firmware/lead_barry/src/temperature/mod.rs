@@ -0,0 +1,9 @@
+#![allow(unused_imports)]
+
+mod compensation;
+mod ntc_probe;
+mod temperature_service;
+
+pub use self::compensation::*;
+pub use self::ntc_probe::*;
+pub use self::temperature_service::*;
@@ -0,0 +1,80 @@
+#![allow(dead_code)]
+
+//! Optional battery temperature probe: a 10k NTC thermistor between the ADC pin and the ground with
+//! a 10k pull-up to the 3.3V ADC reference.
+
+#[cfg(not(feature = "sim"))]
+use defmt_or_log as log;
+#[cfg(not(feature = "sim"))]
+use embassy_rp::adc::{Adc, Async, Channel};
+
+#[cfg(not(feature = "sim"))]
+use crate::temperature::temperature_service::TemperatureProbe;
+
+const ADC_FULL_SCALE: f32 = 4095.0;
+const PULL_UP_OHMS: f32 = 10_000.0;
+const NTC_NOMINAL_OHMS: f32 = 10_000.0;
+const NTC_NOMINAL_KELVIN: f32 = 298.15;
+const NTC_BETA: f32 = 3950.0;
+const KELVIN_OFFSET: f32 = 273.15;
+/// Readings this close to the rails mean an open or a shorted probe
+const RAIL_MARGIN: u16 = 16;
+
+/// Converts a raw 12-bit ADC reading of the divider into degrees Celsius. Returns `None` when the
+/// probe is disconnected or shorted.
+pub fn ntc_temperature_c(raw: u16) -> Option<f32> {
+    if raw < RAIL_MARGIN || raw > ADC_FULL_SCALE as u16 - RAIL_MARGIN {
+        return None;
+    }
+    let raw = raw as f32;
+    let resistance = PULL_UP_OHMS * raw / (ADC_FULL_SCALE - raw);
+    let inverse_kelvin = 1.0 / NTC_NOMINAL_KELVIN + libm::logf(resistance / NTC_NOMINAL_OHMS) / NTC_BETA;
+    Some(1.0 / inverse_kelvin - KELVIN_OFFSET)
+}
+
+#[cfg(not(feature = "sim"))]
+pub struct NtcProbe<'a> {
+    adc: Adc<'a, Async>,
+    channel: Channel<'a>,
+}
+
+#[cfg(not(feature = "sim"))]
+impl<'a> NtcProbe<'a> {
+    pub fn new(adc: Adc<'a, Async>, channel: Channel<'a>) -> Self {
+        Self { adc, channel }
+    }
+}
+
+#[cfg(not(feature = "sim"))]
+impl<'a> TemperatureProbe for NtcProbe<'a> {
+    async fn temperature(&mut self) -> Option<f32> {
+        match self.adc.read(&mut self.channel).await {
+            Ok(raw) => ntc_temperature_c(raw),
+            Err(e) => {
+                log::error!("Temperature probe ADC error: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntc_conversion() {
+        // Mid scale is the nominal resistance
+        let temperature = ntc_temperature_c(2048).unwrap();
+        assert!((temperature - 25.0).abs() < 0.1, "{}", temperature);
+        // A colder thermistor has a higher resistance
+        assert!(ntc_temperature_c(3000).unwrap() < 10.0);
+        assert!(ntc_temperature_c(1000).unwrap() > 40.0);
+    }
+
+    #[test]
+    fn test_disconnected_probe() {
+        assert_eq!(ntc_temperature_c(4095), None);
+        assert_eq!(ntc_temperature_c(0), None);
+    }
+}
@@ -0,0 +1,223 @@
+use defmt_or_log as log;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Ticker, Timer};
+use serde::Serialize;
+
+use crate::configuration::{BatteryProfile, TemperatureSettings, TemperatureSource};
+use crate::rtc::RtcDs3231Ref;
+use crate::temperature::compensation::*;
+use crate::units::TimeExt as _;
+
+const SAMPLE_PERIOD_S: u64 = 30;
+/// The DS3231 needs up to 200 ms for a forced temperature conversion
+const RTC_CONVERSION_TIME_MS: u64 = 250;
+/// Setpoint changes smaller than this are not passed on to the consumers
+const MIN_SETPOINT_CHANGE_V: f32 = 0.005;
+
+/// A battery temperature sensor besides the one of the RTC
+pub trait TemperatureProbe {
+    /// Reads the probe temperature in degrees Celsius, `None` if the probe is missing
+    async fn temperature(&mut self) -> Option<f32>;
+}
+
+/// The latest battery temperature and the setpoints compensated for it
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct TemperatureStatus {
+    pub enabled: bool,
+    /// The battery temperature in degrees Celsius, `None` until the first successful reading
+    pub temperature_c: Option<f32>,
+    /// The sensor the temperature comes from
+    pub source: Option<TemperatureSource>,
+    /// The setpoints at 25°C
+    pub reference: CompensatedSetpoints,
+    pub compensated: CompensatedSetpoints,
+}
+
+impl TemperatureStatus {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            temperature_c: None,
+            source: None,
+            reference: CompensatedSetpoints::new(),
+            compensated: CompensatedSetpoints::new(),
+        }
+    }
+}
+
+impl Default for TemperatureStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct TemperatureConfig {
+    pub enabled: bool,
+    pub source: TemperatureSource,
+    pub compensation: TemperatureCompensation,
+}
+
+impl TemperatureConfig {
+    pub fn from_settings(settings: &TemperatureSettings, profile: &BatteryProfile, reconnect_voltage: f32) -> Self {
+        Self {
+            enabled: settings.enabled,
+            source: settings.source,
+            compensation: TemperatureCompensation {
+                reference: CompensatedSetpoints::from_profile(profile, reconnect_voltage),
+                coefficient_mv_per_cell: settings.coefficient_mv_per_cell,
+                cells: CELLS,
+            },
+        }
+    }
+}
+
+pub struct TemperatureServiceState {
+    status: Mutex<CriticalSectionRawMutex, TemperatureStatus>,
+    setpoints: Signal<CriticalSectionRawMutex, CompensatedSetpoints>,
+}
+
+impl TemperatureServiceState {
+    pub const fn new() -> Self {
+        Self {
+            status: Mutex::new(TemperatureStatus::new()),
+            setpoints: Signal::new(),
+        }
+    }
+}
+
+pub struct TemperatureRunner<'a, I2C, Probe> {
+    rtc: &'a RtcDs3231Ref<I2C>,
+    probe: Option<Probe>,
+    state: &'a TemperatureServiceState,
+    config: TemperatureConfig,
+    /// The setpoints last passed on to the consumers
+    applied: CompensatedSetpoints,
+}
+
+pub struct TemperatureControl<'a> {
+    state: &'a TemperatureServiceState,
+}
+
+#[allow(dead_code)]
+impl<'a> TemperatureControl<'a> {
+    /// Returns the latest temperature and the compensated setpoints
+    pub async fn status(&self) -> TemperatureStatus {
+        *self.state.status.lock().await
+    }
+
+    /// Waits until the compensated setpoints move
+    pub async fn wait_setpoints(&self) -> CompensatedSetpoints {
+        self.state.setpoints.wait().await
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TemperatureService(());
+
+impl TemperatureService {
+    /// Creates a new temperature compensation instance. The probe is only read if it is the configured source.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a, I2C, E, Probe>(
+        rtc: &'a RtcDs3231Ref<I2C>,
+        probe: Option<Probe>,
+        state: &'a mut TemperatureServiceState,
+        config: TemperatureConfig,
+    ) -> (TemperatureRunner<'a, I2C, Probe>, TemperatureControl<'a>)
+    where
+        I2C: embedded_hal_async::i2c::I2c<Error = E>,
+        Probe: TemperatureProbe,
+    {
+        let state: &'a TemperatureServiceState = state;
+        (
+            TemperatureRunner {
+                rtc,
+                probe,
+                state,
+                config,
+                applied: config.compensation.reference,
+            },
+            TemperatureControl { state },
+        )
+    }
+}
+
+impl<'a, I2C, E, Probe> TemperatureRunner<'a, I2C, Probe>
+where
+    I2C: embedded_hal_async::i2c::I2c<Error = E>,
+    E: core::fmt::Debug,
+    Probe: TemperatureProbe,
+{
+    pub async fn run(&mut self) -> ! {
+        let mut ticker = Ticker::every(SAMPLE_PERIOD_S.s());
+        loop {
+            let (temperature_c, source) = match self.read_temperature().await {
+                Some((temperature_c, source)) => (Some(temperature_c), Some(source)),
+                None => {
+                    log::warn!("No battery temperature, keeping the last setpoints");
+                    (None, None)
+                }
+            };
+
+            let compensated = match temperature_c {
+                Some(temperature_c) if self.config.enabled => self.config.compensation.setpoints(temperature_c),
+                Some(_) => self.config.compensation.reference,
+                None => self.applied,
+            };
+            if (compensated.offset_v - self.applied.offset_v).abs() >= MIN_SETPOINT_CHANGE_V {
+                log::info!(
+                    "Battery at {} C, setpoints shifted by {} V",
+                    temperature_c.unwrap_or_default(),
+                    compensated.offset_v
+                );
+                self.applied = compensated;
+                self.state.setpoints.signal(compensated);
+            }
+
+            *self.state.status.lock().await = TemperatureStatus {
+                enabled: self.config.enabled,
+                temperature_c,
+                source,
+                reference: self.config.compensation.reference,
+                compensated: self.applied,
+            };
+            ticker.next().await;
+        }
+    }
+
+    /// Reads the configured sensor and falls back to the RTC when the probe is missing
+    async fn read_temperature(&mut self) -> Option<(f32, TemperatureSource)> {
+        if self.config.source == TemperatureSource::Probe {
+            match self.probe.as_mut() {
+                Some(probe) => match probe.temperature().await {
+                    Some(temperature_c) => return Some((temperature_c, TemperatureSource::Probe)),
+                    None => log::warn!("Temperature probe disconnected, using the RTC sensor"),
+                },
+                None => log::warn!("No temperature probe, using the RTC sensor"),
+            }
+        }
+        self.read_rtc_temperature()
+            .await
+            .map(|temperature_c| (temperature_c, TemperatureSource::Rtc))
+    }
+
+    async fn read_rtc_temperature(&mut self) -> Option<f32> {
+        {
+            let mut rtc = self.rtc.lock().await;
+            // A conversion is already running when the RTC is busy, its result is read below
+            if let Ok(false) = rtc.busy().await {
+                rtc.convert_temperature().await.ok();
+            }
+        }
+        Timer::after(RTC_CONVERSION_TIME_MS.ms()).await;
+        match self.rtc.lock().await.temperature().await {
+            Ok(temperature_c) => Some(temperature_c),
+            Err(e) => {
+                log::error!("RTC temperature error: {:?}", defmt_or_log::Debug2Format(&e));
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Steps the time of the unit tests of the state machines, which take the time of every update in
+//! milliseconds.
+
+/// The most values a single run collects
+pub const MAX_STEP_RESULTS: usize = 32;
+
+/// Advances `now_ms` by `period_ms` until `duration_ms` have passed. `step` is called with the time of
+/// every step and the values it returns are collected.
+pub fn step_for<T>(
+    now_ms: &mut u64,
+    period_ms: u64,
+    duration_ms: u64,
+    mut step: impl FnMut(u64) -> Option<T>,
+) -> heapless::Vec<T, MAX_STEP_RESULTS> {
+    let mut results = heapless::Vec::new();
+    let end_ms = *now_ms + duration_ms;
+    while *now_ms < end_ms {
+        *now_ms += period_ms;
+        if let Some(result) = step(*now_ms) {
+            assert!(results.push(result).is_ok(), "Too many results for a single run");
+        }
+    }
+    results
+}
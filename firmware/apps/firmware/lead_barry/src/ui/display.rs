@@ -0,0 +1,45 @@
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::BinaryColor};
+#[cfg(not(feature = "sim"))]
+use ssd1306::I2CDisplayInterface;
+use ssd1306::Ssd1306Async;
+use ssd1306::mode::BufferedGraphicsModeAsync;
+use ssd1306::prelude::*;
+
+/// The display the screens are drawn on. The drawing goes into a buffer which `flush` sends out.
+pub trait Display: DrawTarget<Color = BinaryColor, Error: core::fmt::Debug> {
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    async fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+/// The 128x64 SSD1306 OLED of the board
+#[cfg(not(feature = "sim"))]
+pub type Ssd1306Display<I2C> =
+    Ssd1306Async<I2CInterface<I2C>, DisplaySize128x64, BufferedGraphicsModeAsync<DisplaySize128x64>>;
+
+#[cfg(not(feature = "sim"))]
+pub fn ssd1306_display<I2C>(i2c_dev: I2C) -> Ssd1306Display<I2C>
+where
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    Ssd1306Async::new(
+        I2CDisplayInterface::new(i2c_dev),
+        DisplaySize128x64,
+        DisplayRotation::Rotate0,
+    )
+    .into_buffered_graphics_mode()
+}
+
+impl<DI, SIZE> Display for Ssd1306Async<DI, SIZE, BufferedGraphicsModeAsync<SIZE>>
+where
+    DI: display_interface::AsyncWriteOnlyDataCommand,
+    SIZE: DisplaySizeAsync,
+{
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ssd1306Async::init(self).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ssd1306Async::flush(self).await
+    }
+}
@@ -1,18 +1,21 @@
 // Display driver imports
 mod data_model;
+mod display;
 mod screen_view;
 
 mod screens;
 mod ui_interface;
 
 pub use self::data_model::SharedDataModel;
+pub use self::display::Display;
+#[cfg(not(feature = "sim"))]
+pub use self::display::{Ssd1306Display, ssd1306_display};
 pub use self::screen_view::ScreenView;
-use crate::global_types::I2c1Device;
+use crate::global_types::DisplayDevice;
 
 pub use self::screens::*;
 pub use self::ui_interface::UiInterface;
 
 pub type UiSharedState = self::ui_interface::UiSharedState<ScCollection>;
-pub type UiRunner<'a> =
-    self::ui_interface::UiRunner<'a, I2c1Device<'a>, ssd1306::size::DisplaySize128x64, ScCollection>;
+pub type UiRunner<'a> = self::ui_interface::UiRunner<'a, DisplayDevice, ScCollection>;
 pub type UiControl<'a> = self::ui_interface::UiControl<'a, ScCollection>;
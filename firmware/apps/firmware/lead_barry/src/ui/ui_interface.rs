@@ -3,12 +3,9 @@ use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::{Channel, Receiver, SendFuture, Sender};
 use embassy_time::Ticker;
 
+use crate::ui::display::Display;
 use crate::ui::screen_view::ScreenView;
 
-use ssd1306::I2CDisplayInterface;
-use ssd1306::Ssd1306Async;
-use ssd1306::prelude::*;
-
 use crate::units::TimeExt;
 use defmt_or_log as log;
 
@@ -26,9 +23,8 @@ impl<ScreenSet> UiSharedState<ScreenSet> {
     }
 }
 
-pub struct UiRunner<'a, SharedI2cDevice, DisplaySize, ScreenSet> {
-    i2c_dev: Option<SharedI2cDevice>,
-    display_size: Option<DisplaySize>,
+pub struct UiRunner<'a, D, ScreenSet> {
+    display: D,
     screen_receiver: Receiver<'a, CriticalSectionRawMutex, ScreenSet, 1>,
     active_screen: &'a mut Option<ScreenSet>,
 }
@@ -53,30 +49,24 @@ impl<'a, ScreenSet> UiControl<'a, ScreenSet> {
 pub struct UiInterface(());
 
 impl UiInterface {
-    /// Creates a new UI instance with SSD1306 display
+    /// Creates a new UI instance drawing on the given display
     ///
-    /// This is a factory method that returns `Ui<Interface, Size>`, not `Self`.
+    /// This is a factory method that returns `Ui<Display>`, not `Self`.
     /// The marker struct pattern is intentionally used here for namespace organization.
     #[allow(clippy::new_ret_no_self)]
-    pub fn new<'a, SharedI2cDevice, DisplaySize, ScreenSet>(
-        i2c_dev: SharedI2cDevice,
-        display_size: DisplaySize,
+    pub fn new<'a, D, ScreenSet>(
+        display: D,
         state: &'a mut UiSharedState<ScreenSet>,
         initial_screen: Option<ScreenSet>,
-    ) -> (
-        UiControl<'a, ScreenSet>,
-        UiRunner<'a, SharedI2cDevice, DisplaySize, ScreenSet>,
-    )
+    ) -> (UiControl<'a, ScreenSet>, UiRunner<'a, D, ScreenSet>)
     where
-        SharedI2cDevice: embedded_hal_async::i2c::I2c,
-        DisplaySize: ssd1306::size::DisplaySizeAsync,
+        D: Display,
     {
         state.active_screen = initial_screen;
         (
             UiControl::new(state.screen_channel.sender()),
             UiRunner {
-                i2c_dev: Some(i2c_dev),
-                display_size: Some(display_size),
+                display,
                 screen_receiver: state.screen_channel.receiver(),
                 active_screen: &mut state.active_screen,
             },
@@ -84,58 +74,48 @@ impl UiInterface {
     }
 }
 
-impl<'a, SharedI2cDevice, DisplaySize, ScreenSet> UiRunner<'a, SharedI2cDevice, DisplaySize, ScreenSet>
+impl<'a, D, ScreenSet> UiRunner<'a, D, ScreenSet>
 where
-    DisplaySize: ssd1306::size::DisplaySizeAsync + Copy,
-    SharedI2cDevice: embedded_hal_async::i2c::I2c,
+    D: Display,
 {
     pub async fn run(&mut self) -> !
     where
         ScreenSet: ScreenView,
     {
-        let i2c_dev = self.i2c_dev.take().expect("I2C device already taken");
-        let display_size = self.display_size.take().expect("Display size already taken");
-
-        let interface = I2CDisplayInterface::new(i2c_dev);
-        let mut display: Ssd1306Async<
-            I2CInterface<SharedI2cDevice>,
-            DisplaySize,
-            ssd1306::mode::BufferedGraphicsModeAsync<DisplaySize>,
-        > = Ssd1306Async::new(interface, display_size, ssd1306::prelude::DisplayRotation::Rotate0)
-            .into_buffered_graphics_mode();
+        let display = &mut self.display;
 
         log::debug!("Initializing the display ...");
         display.init().await.unwrap_or_else(|e| {
-            log::error!("Init error: {:?}", e);
+            log::error!("Init error: {:?}", defmt_or_log::Debug2Format(&e));
         });
         display.flush().await.unwrap_or_else(|e| {
-            log::error!("Flush error: {:?}", e);
+            log::error!("Flush error: {:?}", defmt_or_log::Debug2Format(&e));
         });
 
         // Frame rate ticker for 25 FPS
         let mut ticker = Ticker::every((1000 / 25).ms());
 
         if let Some(active_screen) = self.active_screen {
-            active_screen.enter(&mut display);
+            active_screen.enter(display);
         }
 
         loop {
             if let Ok(mut new_screen) = self.screen_receiver.try_receive() {
                 log::trace!("Switching to new screen ...");
                 if let Some(old_screen) = self.active_screen {
-                    old_screen.exit(&mut display);
+                    old_screen.exit(display);
                 }
 
-                new_screen.enter(&mut display);
+                new_screen.enter(display);
                 self.active_screen.replace(new_screen);
 
                 log::trace!("Switching to new screen complete");
             } else if let Some(active_screen) = self.active_screen {
-                active_screen.redraw(&mut display);
+                active_screen.redraw(display);
             }
 
             display.flush().await.unwrap_or_else(|e| {
-                log::error!("Flush error: {:?}", e);
+                log::error!("Flush error: {:?}", defmt_or_log::Debug2Format(&e));
             });
             ticker.next().await;
         }
@@ -0,0 +1,155 @@
+#![allow(dead_code)]
+
+//! INA3221 alert limits and flags.
+//!
+//! The chip compares every shunt conversion with a per-channel critical and warning limit and the
+//! sum of the selected shunt voltages with the summation limit. With the latching enabled the flags
+//! stay set in the mask/enable register until it is read, so an over-limit condition lasting less
+//! than a poll period is still reported.
+//!
+//! The ALERT pins of the chip are not wired to the MCU, so the flags are read over I2C.
+
+use serde::Serialize;
+
+use crate::vcp_sensors::data_model::{CHANNELS_PER_CHIP, VCP_CHANNELS};
+
+/// Resolution of the shunt voltage limits
+const SHUNT_LSB_VOLTS: f32 = 40e-6;
+/// The largest value of a 13-bit shunt voltage limit
+const MAX_SHUNT_LIMIT: i32 = 0x0FFF;
+/// The largest value of a 15-bit summation limit
+const MAX_SUMMATION_LIMIT: i32 = 0x3FFF;
+
+// Mask/enable register bits
+const SCC_SHIFT: u16 = 12;
+const WARNING_LATCH_ENABLE: u16 = 1 << 11;
+const CRITICAL_LATCH_ENABLE: u16 = 1 << 10;
+const CRITICAL_FLAG_SHIFT: u16 = 7;
+const SUMMATION_FLAG: u16 = 1 << 6;
+const WARNING_FLAG_SHIFT: u16 = 3;
+const POWER_VALID_FLAG: u16 = 1 << 2;
+
+/// The alert flags of the mask/enable registers of all the chips, by global channel
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpAlertFlags {
+    pub critical: [bool; VCP_CHANNELS],
+    pub warning: [bool; VCP_CHANNELS],
+    /// The summation limit of any chip is exceeded
+    pub summation: bool,
+    /// All the bus voltages of every chip are within the power-valid window
+    pub power_valid: bool,
+}
+
+impl VcpAlertFlags {
+    pub const fn new() -> Self {
+        Self {
+            critical: [false; VCP_CHANNELS],
+            warning: [false; VCP_CHANNELS],
+            summation: false,
+            power_valid: false,
+        }
+    }
+
+    /// Decodes the mask/enable register of the chip into the flags of its channels
+    pub fn from_mask_enable(chip: usize, register: u16) -> Self {
+        // The channel 1 flag is the most significant one
+        let flag = |shift: u16, channel: usize| register & (1 << (shift + 2 - channel as u16)) != 0;
+        let first = chip * CHANNELS_PER_CHIP;
+        let mut flags = Self::new();
+        for channel in 0..CHANNELS_PER_CHIP.min(VCP_CHANNELS.saturating_sub(first)) {
+            flags.critical[first + channel] = flag(CRITICAL_FLAG_SHIFT, channel);
+            flags.warning[first + channel] = flag(WARNING_FLAG_SHIFT, channel);
+        }
+        flags.summation = register & SUMMATION_FLAG != 0;
+        flags.power_valid = register & POWER_VALID_FLAG != 0;
+        flags
+    }
+
+    /// Combines the flags of two chips. The power is valid only if it is valid for both.
+    pub fn merge(mut self, other: Self) -> Self {
+        for channel in 0..VCP_CHANNELS {
+            self.critical[channel] |= other.critical[channel];
+            self.warning[channel] |= other.warning[channel];
+        }
+        self.summation |= other.summation;
+        self.power_valid &= other.power_valid;
+        self
+    }
+
+    /// Returns `true` if any of the over-limit flags is set
+    pub fn any_alert(&self) -> bool {
+        self.summation || self.critical.iter().chain(self.warning.iter()).any(|flag| *flag)
+    }
+}
+
+/// Encodes a shunt voltage limit in volts into the critical or warning limit register
+pub fn shunt_limit_register(volts: f32) -> u16 {
+    let steps = libm::roundf(volts / SHUNT_LSB_VOLTS) as i32;
+    (steps.clamp(-MAX_SHUNT_LIMIT - 1, MAX_SHUNT_LIMIT) << 3) as u16
+}
+
+/// Encodes a shunt voltage sum limit in volts into the summation limit register
+pub fn summation_limit_register(volts: f32) -> u16 {
+    let steps = libm::roundf(volts / SHUNT_LSB_VOLTS) as i32;
+    (steps.clamp(-MAX_SUMMATION_LIMIT - 1, MAX_SUMMATION_LIMIT) << 1) as u16
+}
+
+/// Builds the mask/enable register which latches both alerts and sums the selected channels
+pub fn mask_enable_register(summation_channels: [bool; 3]) -> u16 {
+    let scc = summation_channels
+        .iter()
+        .enumerate()
+        .filter(|(_, selected)| **selected)
+        .fold(0u16, |scc, (channel, _)| scc | (1 << (2 - channel)));
+    (scc << SCC_SHIFT) | WARNING_LATCH_ENABLE | CRITICAL_LATCH_ENABLE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shunt_limit_encoding() {
+        // 1 A through 0.1 Ohm is 100 mV, i.e. 2500 steps of 40 uV in bits 15..3
+        assert_eq!(shunt_limit_register(0.1), 2500 << 3);
+        // Saturates at the largest positive limit
+        assert_eq!(shunt_limit_register(1.0), 0x7FF8);
+        assert_eq!(summation_limit_register(0.3), 7500 << 1);
+        assert_eq!(summation_limit_register(1.0), 0x7FFE);
+    }
+
+    #[test]
+    fn test_mask_enable_encoding() {
+        assert_eq!(mask_enable_register([false; 3]), 0x0C00);
+        assert_eq!(mask_enable_register([true, false, true]), 0x5C00);
+    }
+
+    #[test]
+    fn test_alert_flags_decoding() {
+        // Critical on channel 3, warning on channel 1, power valid
+        let flags = VcpAlertFlags::from_mask_enable(0, 0x0C00 | (1 << 7) | (1 << 5) | (1 << 2));
+        assert_eq!(flags.critical[..3], [false, false, true]);
+        assert_eq!(flags.warning[..3], [true, false, false]);
+        assert!(!flags.summation);
+        assert!(flags.power_valid);
+        assert!(flags.any_alert());
+
+        let flags = VcpAlertFlags::from_mask_enable(0, 0x0C00 | (1 << 2));
+        assert!(!flags.any_alert());
+    }
+
+    #[test]
+    fn test_alert_flags_of_the_second_chip() {
+        let first = VcpAlertFlags::from_mask_enable(0, 0x0C00 | (1 << 2));
+        // Critical on channel 1 of the second chip, its power is not valid
+        let second = VcpAlertFlags::from_mask_enable(1, 0x0C00 | (1 << 9));
+        assert_eq!(second.critical[..3], [false; 3]);
+        assert_eq!(second.critical[3..6], [true, false, false]);
+
+        let flags = first.merge(second);
+        assert!(flags.critical[3]);
+        assert!(flags.any_alert());
+        assert!(!flags.power_valid);
+    }
+}
@@ -0,0 +1,276 @@
+//! Per-channel calibration of the VCP readings.
+//!
+//! Every channel has its own shunt resistance and a linear correction of the voltage and of the
+//! current: `value = gain * uncalibrated + offset`. The uncalibrated voltage is the bus voltage of
+//! the chip, the uncalibrated current is the shunt voltage divided by the shunt resistance.
+//!
+//! The correction is fitted from one or two points, each an uncalibrated reading taken while a known
+//! reference voltage or current is applied. One point only corrects the gain, two points correct the
+//! gain and the offset.
+
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::data_model::ChannelNum;
+
+pub const DEFAULT_SHUNT_RESISTANCE: f32 = 0.1; // Ohms
+/// The shunt resistances the boards are fitted with, in ohms
+pub const SHUNT_RESISTANCE_RANGE: core::ops::RangeInclusive<f32> = 0.000_1..=10.0;
+/// A correction beyond these gains is a wrong divider or shunt rather than a tolerance
+pub const GAIN_RANGE: core::ops::RangeInclusive<f32> = 0.5..=1.5;
+/// The largest offset of the voltage in volts and of the current in amps
+pub const MAX_OFFSET: f32 = 1.0;
+/// The closest two points can be and still give a usable gain, in volts or amps
+const MIN_POINT_DISTANCE: f32 = 0.01;
+pub const MAX_CALIBRATION_POINTS: usize = 2;
+
+/// The measured quantity a correction applies to
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum VcpQuantity {
+    Voltage,
+    Current,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum VcpCalibrationError {
+    /// The channel belongs to a chip which is not fitted
+    InvalidChannel,
+    /// Neither of the points is given
+    NoPoints,
+    /// The point is too close to zero or the two points are too close to each other
+    PointsTooClose,
+    /// The fitted gain or offset is beyond the correctable range
+    OutOfRange,
+}
+
+impl VcpCalibrationError {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            VcpCalibrationError::InvalidChannel => "Invalid channel",
+            VcpCalibrationError::NoPoints => "No calibration points",
+            VcpCalibrationError::PointsTooClose => "Calibration points are too close",
+            VcpCalibrationError::OutOfRange => "Calibration is out of range",
+        }
+    }
+}
+
+/// An uncalibrated reading taken while the reference value is applied
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpCalibrationPoint {
+    pub uncalibrated: f32,
+    pub reference: f32,
+}
+
+/// The linear correction of a quantity
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpLinearCalibration {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl VcpLinearCalibration {
+    /// The correction which keeps the readings as they are
+    pub const IDENTITY: Self = Self { gain: 1.0, offset: 0.0 };
+
+    pub const fn new(gain: f32, offset: f32) -> Self {
+        Self { gain, offset }
+    }
+
+    pub fn apply(&self, uncalibrated: f32) -> f32 {
+        self.gain * uncalibrated + self.offset
+    }
+
+    pub fn is_valid(&self) -> bool {
+        GAIN_RANGE.contains(&self.gain) && self.offset.abs() <= MAX_OFFSET
+    }
+
+    /// Fits the correction to the points. The points beyond [`MAX_CALIBRATION_POINTS`] are ignored.
+    pub fn fit(points: &[VcpCalibrationPoint]) -> Result<Self, VcpCalibrationError> {
+        let calibration = match points {
+            [] => return Err(VcpCalibrationError::NoPoints),
+            [point] => {
+                if point.uncalibrated.abs() < MIN_POINT_DISTANCE {
+                    return Err(VcpCalibrationError::PointsTooClose);
+                }
+                Self::new(point.reference / point.uncalibrated, 0.0)
+            }
+            [first, second, ..] => {
+                let span = second.uncalibrated - first.uncalibrated;
+                if span.abs() < MIN_POINT_DISTANCE {
+                    return Err(VcpCalibrationError::PointsTooClose);
+                }
+                let gain = (second.reference - first.reference) / span;
+                Self::new(gain, first.reference - gain * first.uncalibrated)
+            }
+        };
+        if calibration.is_valid() {
+            Ok(calibration)
+        } else {
+            Err(VcpCalibrationError::OutOfRange)
+        }
+    }
+}
+
+impl Default for VcpLinearCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// The shunt and the corrections of a channel
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpChannelCalibration {
+    /// Ohms
+    pub shunt_resistance: f32,
+    pub voltage: VcpLinearCalibration,
+    pub current: VcpLinearCalibration,
+}
+
+impl VcpChannelCalibration {
+    /// The nominal shunt without any correction
+    pub const fn new() -> Self {
+        Self {
+            shunt_resistance: DEFAULT_SHUNT_RESISTANCE,
+            voltage: VcpLinearCalibration::IDENTITY,
+            current: VcpLinearCalibration::IDENTITY,
+        }
+    }
+
+    pub fn with_shunt_resistance(mut self, shunt_resistance: f32) -> Self {
+        self.shunt_resistance = shunt_resistance;
+        self
+    }
+
+    pub fn is_valid(&self) -> bool {
+        SHUNT_RESISTANCE_RANGE.contains(&self.shunt_resistance) && self.voltage.is_valid() && self.current.is_valid()
+    }
+
+    pub fn quantity(&self, quantity: VcpQuantity) -> &VcpLinearCalibration {
+        match quantity {
+            VcpQuantity::Voltage => &self.voltage,
+            VcpQuantity::Current => &self.current,
+        }
+    }
+
+    pub fn quantity_mut(&mut self, quantity: VcpQuantity) -> &mut VcpLinearCalibration {
+        match quantity {
+            VcpQuantity::Voltage => &mut self.voltage,
+            VcpQuantity::Current => &mut self.current,
+        }
+    }
+
+    /// The current through the shunt before the correction
+    pub fn uncalibrated_current(&self, shunt_voltage: f32) -> f32 {
+        shunt_voltage / self.shunt_resistance
+    }
+
+    pub fn voltage(&self, bus_voltage: f32) -> f32 {
+        self.voltage.apply(bus_voltage)
+    }
+
+    pub fn current(&self, shunt_voltage: f32) -> f32 {
+        self.current.apply(self.uncalibrated_current(shunt_voltage))
+    }
+
+    /// The shunt voltage at which the corrected current is `current`, used to program the alert limits
+    pub fn shunt_voltage(&self, current: f32) -> f32 {
+        (current - self.current.offset) / self.current.gain * self.shunt_resistance
+    }
+}
+
+impl Default for VcpChannelCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The latest readings of a channel before the correction, the base of the calibration points
+#[derive(Serialize, Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpUncalibratedReading {
+    pub voltage: f32,
+    pub current: f32,
+}
+
+/// A request to fit the correction of a quantity of a channel to the points
+#[derive(Clone, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpCalibrationRequest {
+    pub channel: ChannelNum,
+    pub quantity: VcpQuantity,
+    pub points: heapless::Vec<VcpCalibrationPoint, MAX_CALIBRATION_POINTS>,
+}
+
+/// A calibration of a channel entered by hand
+#[derive(Copy, Clone, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpCalibrationChange {
+    pub channel: ChannelNum,
+    pub calibration: VcpChannelCalibration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(uncalibrated: f32, reference: f32) -> VcpCalibrationPoint {
+        VcpCalibrationPoint {
+            uncalibrated,
+            reference,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn one_point_corrects_the_gain() {
+        let calibration = VcpLinearCalibration::fit(&[point(12.0, 12.12)]).unwrap();
+        assert_close(calibration.gain, 1.01);
+        assert_close(calibration.offset, 0.0);
+    }
+
+    #[test]
+    fn two_points_correct_the_gain_and_the_offset() {
+        let calibration = VcpLinearCalibration::fit(&[point(0.05, 0.0), point(2.05, 2.1)]).unwrap();
+        assert_close(calibration.gain, 1.05);
+        assert_close(calibration.offset, -0.0525);
+        assert_close(calibration.apply(1.05), 1.05);
+    }
+
+    #[test]
+    fn unusable_points_are_rejected() {
+        assert!(matches!(
+            VcpLinearCalibration::fit(&[]),
+            Err(VcpCalibrationError::NoPoints)
+        ));
+        assert!(matches!(
+            VcpLinearCalibration::fit(&[point(0.001, 1.0)]),
+            Err(VcpCalibrationError::PointsTooClose)
+        ));
+        assert!(matches!(
+            VcpLinearCalibration::fit(&[point(1.0, 1.0), point(1.005, 2.0)]),
+            Err(VcpCalibrationError::PointsTooClose)
+        ));
+        assert!(matches!(
+            VcpLinearCalibration::fit(&[point(1.0, 2.0)]),
+            Err(VcpCalibrationError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn the_alert_shunt_voltage_inverts_the_current_correction() {
+        let calibration = VcpChannelCalibration::new().with_shunt_resistance(0.05);
+        assert_close(calibration.current(0.1), 2.0);
+
+        let mut calibration = calibration;
+        calibration.current = VcpLinearCalibration::new(1.02, 0.03);
+        let shunt_voltage = calibration.shunt_voltage(5.0);
+        assert_close(calibration.current(shunt_voltage), 5.0);
+    }
+}
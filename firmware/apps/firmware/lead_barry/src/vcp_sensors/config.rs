@@ -1,7 +1,10 @@
 #![allow(dead_code)]
 
-use crate::vcp_sensors::data_model::ChannelNum;
+use crate::vcp_sensors::calibration::VcpChannelCalibration;
+use crate::vcp_sensors::data_model::{ChannelNum, VCP_CHANNELS};
+use crate::vcp_sensors::energy::EnergyCounters;
 use defmt_or_log as log;
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone)]
 #[defmt_or_log::derive_format_or_debug]
@@ -10,13 +13,118 @@ pub struct VcpLimits {
     pub max_voltage: f32,
     pub min_current: f32,
     pub max_current: f32,
+    /// The current programmed into the critical alert limit of the sensor. `None` disables the alert.
+    pub critical_current: Option<f32>,
+    /// The current programmed into the warning alert limit of the sensor. `None` disables the alert.
+    pub warning_current: Option<f32>,
 }
 
-const DEFAULT_SHUNT_RESISTANCE: [f32; 3] = [0.1; 3]; // Ohms
 const DEFAULT_MIN_VOLTAGE: f32 = 0.0; // Volts
 const DEFAULT_MAX_VOLTAGE: f32 = 5.0; // Volts
 const DEFAULT_MIN_CURRENT: f32 = 0.0; // Amps
 const DEFAULT_MAX_CURRENT: f32 = 2.0; // Amps
+const DEFAULT_POLL_PERIOD_MS: u32 = 40; // Milliseconds
+pub const MIN_POLL_PERIOD_MS: u32 = 10; // Milliseconds
+pub const MAX_POLL_PERIOD_MS: u32 = 10_000; // Milliseconds
+
+/// Number of the conversions averaged by the sensor. The discriminant is the value of the register field.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+#[repr(u8)]
+pub enum VcpAveraging {
+    X1 = 0,
+    X4,
+    X16,
+    X64,
+    X128,
+    X256,
+    X512,
+    X1024,
+}
+
+impl VcpAveraging {
+    pub const fn samples(&self) -> u32 {
+        match self {
+            VcpAveraging::X1 => 1,
+            VcpAveraging::X4 => 4,
+            VcpAveraging::X16 => 16,
+            VcpAveraging::X64 => 64,
+            VcpAveraging::X128 => 128,
+            VcpAveraging::X256 => 256,
+            VcpAveraging::X512 => 512,
+            VcpAveraging::X1024 => 1024,
+        }
+    }
+}
+
+/// Duration of a single bus or shunt conversion. The discriminant is the value of the register field.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+#[repr(u8)]
+pub enum VcpConversionTime {
+    Us140 = 0,
+    Us204,
+    Us332,
+    Us588,
+    Us1100,
+    Us2116,
+    Us4156,
+    Us8244,
+}
+
+impl VcpConversionTime {
+    pub const fn micros(&self) -> u32 {
+        match self {
+            VcpConversionTime::Us140 => 140,
+            VcpConversionTime::Us204 => 204,
+            VcpConversionTime::Us332 => 332,
+            VcpConversionTime::Us588 => 588,
+            VcpConversionTime::Us1100 => 1100,
+            VcpConversionTime::Us2116 => 2116,
+            VcpConversionTime::Us4156 => 4156,
+            VcpConversionTime::Us8244 => 8244,
+        }
+    }
+}
+
+/// How the sensor converts and how often the runner polls it
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpSampling {
+    pub averaging: VcpAveraging,
+    pub bus_conversion_time: VcpConversionTime,
+    pub shunt_conversion_time: VcpConversionTime,
+    pub poll_period_ms: u32,
+}
+
+impl VcpSampling {
+    /// The power-on defaults of the sensor polled every 40 ms
+    pub const fn new() -> Self {
+        Self {
+            averaging: VcpAveraging::X1,
+            bus_conversion_time: VcpConversionTime::Us1100,
+            shunt_conversion_time: VcpConversionTime::Us1100,
+            poll_period_ms: DEFAULT_POLL_PERIOD_MS,
+        }
+    }
+
+    /// How long the sensor takes to produce a fresh averaged value of every enabled channel
+    pub const fn update_period_us(&self, enabled_channels: u32) -> u32 {
+        self.averaging.samples()
+            * (self.bus_conversion_time.micros() + self.shunt_conversion_time.micros())
+            * enabled_channels
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.poll_period_ms >= MIN_POLL_PERIOD_MS && self.poll_period_ms <= MAX_POLL_PERIOD_MS
+    }
+}
+
+impl Default for VcpSampling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Copy, Clone)]
 #[defmt_or_log::derive_format_or_debug]
@@ -24,12 +132,30 @@ pub struct VcpPowerLimits {
     pub upper_voltage: f32,
     pub lower_voltage: f32,
 }
+
+/// The limit of the sum of the shunt voltages of the selected channels. The channels must share the same
+/// shunt resistance for the sum to be proportional to the total current. Every chip sums up its own
+/// channels, so the selected channels should belong to one chip.
+#[derive(Copy, Clone)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpSummationLimit {
+    pub channels: [bool; VCP_CHANNELS],
+    pub max_current: f32,
+}
+
 #[defmt_or_log::derive_format_or_debug]
 pub struct VcpConfig {
-    pub limits: [VcpLimits; 3],
-    shunt_resistance: &'static [f32; 3],
-    pub enabled_channels: [bool; 3],
+    pub limits: [VcpLimits; VCP_CHANNELS],
+    /// The shunts and the corrections of the readings
+    pub calibration: [VcpChannelCalibration; VCP_CHANNELS],
+    pub enabled_channels: [bool; VCP_CHANNELS],
+    /// The channels the protections rely on. They are always polled, disabling them has no effect.
+    pub pinned_channels: [bool; VCP_CHANNELS],
     pub global_pv_limit: Option<VcpPowerLimits>,
+    pub summation_limit: Option<VcpSummationLimit>,
+    pub sampling: VcpSampling,
+    /// The lifetime energy totals of the channels restored from the flash
+    pub lifetime_energy: [EnergyCounters; VCP_CHANNELS],
 }
 
 impl VcpLimits {
@@ -39,6 +165,8 @@ impl VcpLimits {
             max_voltage,
             min_current,
             max_current,
+            critical_current: None,
+            warning_current: None,
         }
     }
 
@@ -62,6 +190,16 @@ impl VcpLimits {
         self
     }
 
+    pub fn with_critical_current(mut self, critical_current: Option<f32>) -> Self {
+        self.critical_current = critical_current;
+        self
+    }
+
+    pub fn with_warning_current(mut self, warning_current: Option<f32>) -> Self {
+        self.warning_current = warning_current;
+        self
+    }
+
     pub const fn const_default() -> Self {
         Self::new(
             DEFAULT_MIN_VOLTAGE,
@@ -74,26 +212,19 @@ impl VcpLimits {
 
 impl VcpConfig {
     pub const fn new(
-        limits: [VcpLimits; 3],
-        enabled_channels: [bool; 3],
-        shunt_resistance: &'static [f32; 3],
+        limits: [VcpLimits; VCP_CHANNELS],
+        enabled_channels: [bool; VCP_CHANNELS],
         global_pv_limit: Option<VcpPowerLimits>,
     ) -> Self {
-        if shunt_resistance[0] <= 0.0 {
-            panic!("Shunt 0 resistance values must be positive and non-zero");
-        }
-        if shunt_resistance[1] <= 0.0 {
-            panic!("Shunt 1 resistance values must be positive and non-zero");
-        }
-        if shunt_resistance[2] <= 0.0 {
-            panic!("Shunt 2 resistance values must be positive and non-zero");
-        }
-
         Self {
             limits,
-            shunt_resistance,
+            calibration: [VcpChannelCalibration::new(); VCP_CHANNELS],
             enabled_channels,
+            pinned_channels: [false; VCP_CHANNELS],
             global_pv_limit,
+            summation_limit: None,
+            sampling: VcpSampling::new(),
+            lifetime_energy: [EnergyCounters::new(); VCP_CHANNELS],
         }
     }
 
@@ -106,37 +237,48 @@ impl VcpConfig {
         self
     }
 
-    pub fn with_shunt_resistance(mut self, shunt_resistance: &'static [f32; 3]) -> Self {
-        if shunt_resistance[0] <= 0.0 {
-            log::panic!("Shunt 0 resistance values must be positive and non-zero");
-        }
-        if shunt_resistance[1] <= 0.0 {
-            log::panic!("Shunt 1 resistance values must be positive and non-zero");
-        }
-        if shunt_resistance[2] <= 0.0 {
-            log::panic!("Shunt 2 resistance values must be positive and non-zero");
+    /// Keeps the channel polled whatever the consumers of the readings enable
+    pub fn with_pinned(mut self, channel: ChannelNum) -> Self {
+        self.enabled_channels[channel as usize] = true;
+        self.pinned_channels[channel as usize] = true;
+        self
+    }
+
+    pub fn with_summation_limit(mut self, summation_limit: Option<VcpSummationLimit>) -> Self {
+        self.summation_limit = summation_limit;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: VcpSampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn with_lifetime_energy(mut self, lifetime_energy: [EnergyCounters; VCP_CHANNELS]) -> Self {
+        self.lifetime_energy = lifetime_energy;
+        self
+    }
+
+    pub fn with_calibration(mut self, calibration: [VcpChannelCalibration; VCP_CHANNELS]) -> Self {
+        if let Some(channel) = calibration.iter().position(|channel| channel.shunt_resistance <= 0.0) {
+            log::panic!("Shunt {} resistance values must be positive and non-zero", channel);
         }
 
-        self.shunt_resistance = shunt_resistance;
+        self.calibration = calibration;
         self
     }
 
     /// Const version of default
     pub const fn const_default() -> Self {
-        Self::new(
-            [VcpLimits::const_default(); 3],
-            [true; 3],
-            &DEFAULT_SHUNT_RESISTANCE,
-            None,
-        )
+        Self::new([VcpLimits::const_default(); VCP_CHANNELS], [true; VCP_CHANNELS], None)
     }
 
     pub fn shunt_resistance(&self, channel: ChannelNum) -> f32 {
-        self.shunt_resistance[channel as usize]
+        self.calibration[channel as usize].shunt_resistance
     }
 
-    pub fn shunt_resistances(&self) -> &'_ [f32; 3] {
-        self.shunt_resistance
+    pub fn shunt_resistances(&self) -> [f32; VCP_CHANNELS] {
+        self.calibration.map(|channel| channel.shunt_resistance)
     }
 }
 
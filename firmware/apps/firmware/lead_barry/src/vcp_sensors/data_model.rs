@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use serde::Serialize;
+
 #[derive(Copy, Clone)]
 #[defmt_or_log::derive_format_or_debug]
 pub enum VcpState {
@@ -8,8 +10,25 @@ pub enum VcpState {
     High(f32),
 }
 
+/// A global channel index. Channel `n` is the channel `n % 3` of the chip `n / 3`.
 pub type ChannelNum = u8;
 
+/// The channels of one INA3221
+pub const CHANNELS_PER_CHIP: usize = 3;
+/// The most INA3221 chips on the bus
+pub const MAX_VCP_CHIPS: usize = 2;
+/// The most channels of the sensor. The arrays of the service and of the settings are this long, the
+/// channels of the chips which are not fitted are never enabled.
+pub const VCP_CHANNELS: usize = MAX_VCP_CHIPS * CHANNELS_PER_CHIP;
+
+/// The chip and its own channel number of a global channel
+pub const fn chip_channel(channel: ChannelNum) -> (usize, ChannelNum) {
+    (
+        channel as usize / CHANNELS_PER_CHIP,
+        (channel as usize % CHANNELS_PER_CHIP) as ChannelNum,
+    )
+}
+
 #[derive(Copy, Clone)]
 #[defmt_or_log::derive_format_or_debug]
 pub struct VcpReading {
@@ -43,3 +62,29 @@ impl VcpState {
         matches!(self, VcpState::High(_))
     }
 }
+
+/// The values of a reading as sent by the web API
+#[derive(Copy, Clone, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpReadingValues {
+    pub channel: ChannelNum,
+    /// Volts
+    pub voltage: f32,
+    /// Amps
+    pub current: f32,
+    /// Watts
+    pub power: f32,
+}
+
+impl From<VcpReading> for VcpReadingValues {
+    fn from(reading: VcpReading) -> Self {
+        let voltage = reading.voltage.value();
+        let current = reading.current.value();
+        Self {
+            channel: reading.channel,
+            voltage,
+            current,
+            power: voltage * current,
+        }
+    }
+}
@@ -0,0 +1,251 @@
+#![allow(dead_code)]
+
+//! Charge and energy accumulators of the VCP channels.
+//!
+//! Every reading is integrated over the time since the previous reading of the same channel. The
+//! positive current, i.e. the current flowing through the shunt in its measurement direction, is
+//! counted as "in", the negative one as "out". Gaps longer than a few poll periods, e.g. while the
+//! channel is disabled, are not integrated.
+//!
+//! The accumulators use `f64`, since the `f32` mantissa is too short to add a 40 ms step to a
+//! lifetime total.
+
+use serde::{Deserialize, Serialize};
+
+use crate::vcp_sensors::data_model::ChannelNum;
+
+/// The shortest gap limit, so the short poll periods still integrate across a sensor recovery
+pub const MIN_INTEGRATION_GAP_MS: u64 = 2_000;
+/// The number of poll periods a gap may last to be integrated
+const GAP_POLL_PERIODS: u64 = 3;
+const MS_PER_HOUR: f64 = 3_600_000.0;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Default)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EnergyCounters {
+    pub charge_in_ah: f64,
+    pub charge_out_ah: f64,
+    pub energy_in_wh: f64,
+    pub energy_out_wh: f64,
+}
+
+impl EnergyCounters {
+    pub const fn new() -> Self {
+        Self {
+            charge_in_ah: 0.0,
+            charge_out_ah: 0.0,
+            energy_in_wh: 0.0,
+            energy_out_wh: 0.0,
+        }
+    }
+
+    /// Adds the charge and the energy of the given voltage and current held for `hours`
+    pub fn add(&mut self, voltage: f32, current: f32, hours: f64) {
+        let charge_ah = current as f64 * hours;
+        let energy_wh = voltage as f64 * charge_ah;
+        if current >= 0.0 {
+            self.charge_in_ah += charge_ah;
+            self.energy_in_wh += energy_wh;
+        } else {
+            self.charge_out_ah -= charge_ah;
+            self.energy_out_wh -= energy_wh;
+        }
+    }
+
+    /// The net charge in ampere-hours, positive when more charge came in than went out
+    pub fn net_charge_ah(&self) -> f64 {
+        self.charge_in_ah - self.charge_out_ah
+    }
+
+    /// The net energy in watt-hours, positive when more energy came in than went out
+    pub fn net_energy_wh(&self) -> f64 {
+        self.energy_in_wh - self.energy_out_wh
+    }
+}
+
+/// The accumulators of a single channel
+#[derive(Serialize, Copy, Clone, PartialEq, Default)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct ChannelEnergy {
+    /// Counters since the last reset or boot
+    pub trip: EnergyCounters,
+    /// Counters since the first boot, restored from the flash at boot
+    pub lifetime: EnergyCounters,
+}
+
+impl ChannelEnergy {
+    pub const fn new() -> Self {
+        Self {
+            trip: EnergyCounters::new(),
+            lifetime: EnergyCounters::new(),
+        }
+    }
+}
+
+/// A request to reset the trip counters of a channel
+#[derive(Copy, Clone, Deserialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct EnergyTripReset {
+    pub channel: ChannelNum,
+}
+
+pub struct EnergyAccumulator<const CHANNELS: usize> {
+    channels: [ChannelEnergy; CHANNELS],
+    last_reading_ms: [Option<u64>; CHANNELS],
+    max_gap_ms: u64,
+}
+
+/// The longest time between two readings polled every `poll_period_ms` which is still integrated
+pub const fn max_integration_gap_ms(poll_period_ms: u32) -> u64 {
+    let gap_ms = GAP_POLL_PERIODS * poll_period_ms as u64;
+    if gap_ms > MIN_INTEGRATION_GAP_MS {
+        gap_ms
+    } else {
+        MIN_INTEGRATION_GAP_MS
+    }
+}
+
+impl<const CHANNELS: usize> EnergyAccumulator<CHANNELS> {
+    pub const fn new(lifetime: [EnergyCounters; CHANNELS], poll_period_ms: u32) -> Self {
+        let mut channels = [ChannelEnergy::new(); CHANNELS];
+        let mut i = 0;
+        while i < CHANNELS {
+            channels[i].lifetime = lifetime[i];
+            i += 1;
+        }
+        Self {
+            channels,
+            last_reading_ms: [None; CHANNELS],
+            max_gap_ms: max_integration_gap_ms(poll_period_ms),
+        }
+    }
+
+    /// Follows a new poll period of the channels
+    pub fn set_poll_period(&mut self, poll_period_ms: u32) {
+        self.max_gap_ms = max_integration_gap_ms(poll_period_ms);
+    }
+
+    /// Integrates a reading of the channel taken at `now_ms`
+    pub fn update(&mut self, channel: usize, voltage: f32, current: f32, now_ms: u64) {
+        let Some(last_reading_ms) = self.last_reading_ms.get_mut(channel) else {
+            return;
+        };
+        if let Some(last_ms) = last_reading_ms.replace(now_ms) {
+            let elapsed_ms = now_ms.saturating_sub(last_ms);
+            if elapsed_ms <= self.max_gap_ms {
+                let hours = elapsed_ms as f64 / MS_PER_HOUR;
+                self.channels[channel].trip.add(voltage, current, hours);
+                self.channels[channel].lifetime.add(voltage, current, hours);
+            }
+        }
+    }
+
+    pub fn channel(&self, channel: usize) -> ChannelEnergy {
+        self.channels[channel]
+    }
+
+    pub fn channels(&self) -> &[ChannelEnergy; CHANNELS] {
+        &self.channels
+    }
+
+    pub fn lifetime(&self) -> [EnergyCounters; CHANNELS] {
+        self.channels.map(|channel| channel.lifetime)
+    }
+
+    pub fn reset_trip(&mut self, channel: usize) {
+        if let Some(channel) = self.channels.get_mut(channel) {
+            channel.trip = EnergyCounters::new();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READING_PERIOD_MS: u64 = 40;
+    const HOUR_MS: u64 = 3_600_000;
+
+    /// Feeds the channel with a constant reading for the given duration
+    fn feed(
+        accumulator: &mut EnergyAccumulator<3>,
+        now_ms: &mut u64,
+        channel: usize,
+        voltage: f32,
+        current: f32,
+        duration_ms: u64,
+    ) {
+        let end_ms = *now_ms + duration_ms;
+        while *now_ms < end_ms {
+            *now_ms += READING_PERIOD_MS;
+            accumulator.update(channel, voltage, current, *now_ms);
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_charge_and_discharge_are_counted_separately() {
+        let mut accumulator = EnergyAccumulator::new([EnergyCounters::new(); 3], READING_PERIOD_MS as u32);
+        let mut now_ms = 0;
+        accumulator.update(0, 12.0, 2.0, now_ms);
+
+        feed(&mut accumulator, &mut now_ms, 0, 12.0, 2.0, HOUR_MS);
+        feed(&mut accumulator, &mut now_ms, 0, 12.5, -1.0, HOUR_MS / 2);
+
+        let trip = accumulator.channel(0).trip;
+        assert_close(trip.charge_in_ah, 2.0);
+        assert_close(trip.energy_in_wh, 24.0);
+        assert_close(trip.charge_out_ah, 0.5);
+        assert_close(trip.energy_out_wh, 6.25);
+        assert_close(trip.net_charge_ah(), 1.5);
+        assert_eq!(accumulator.channel(1), ChannelEnergy::new());
+    }
+
+    #[test]
+    fn test_gaps_are_not_integrated() {
+        let mut accumulator = EnergyAccumulator::new([EnergyCounters::new(); 3], READING_PERIOD_MS as u32);
+        accumulator.update(1, 12.0, 1.0, 0);
+        // The channel was disabled for a minute
+        accumulator.update(1, 12.0, 1.0, 60_000);
+        assert_eq!(accumulator.channel(1).trip, EnergyCounters::new());
+
+        accumulator.update(1, 12.0, 1.0, 61_800);
+        assert_close(accumulator.channel(1).trip.charge_in_ah, 0.0005);
+    }
+
+    #[test]
+    fn test_gap_limit_follows_the_poll_period() {
+        let mut accumulator = EnergyAccumulator::new([EnergyCounters::new(); 3], 10_000);
+        let mut now_ms = 0;
+        accumulator.update(0, 12.0, 1.0, now_ms);
+        // Polled every 10 s for an hour
+        while now_ms < HOUR_MS {
+            now_ms += 10_000;
+            accumulator.update(0, 12.0, 1.0, now_ms);
+        }
+        assert_close(accumulator.channel(0).trip.charge_in_ah, 1.0);
+
+        // A faster poll period shortens the limit again
+        accumulator.set_poll_period(40);
+        accumulator.update(0, 12.0, 1.0, now_ms + 10_000);
+        assert_close(accumulator.channel(0).trip.charge_in_ah, 1.0);
+    }
+
+    #[test]
+    fn test_trip_reset_keeps_lifetime() {
+        let mut lifetime = [EnergyCounters::new(); 3];
+        lifetime[2].energy_out_wh = 1000.0;
+        let mut accumulator = EnergyAccumulator::new(lifetime, READING_PERIOD_MS as u32);
+        let mut now_ms = 0;
+        accumulator.update(2, 12.0, -1.0, now_ms);
+        feed(&mut accumulator, &mut now_ms, 2, 12.0, -1.0, HOUR_MS);
+
+        accumulator.reset_trip(2);
+
+        assert_eq!(accumulator.channel(2).trip, EnergyCounters::new());
+        assert_close(accumulator.lifetime()[2].energy_out_wh, 1012.0);
+    }
+}
@@ -3,4 +3,6 @@
 pub enum VcpError {
     I2c,
     Timeout,
+    /// The channel belongs to a chip which is not fitted
+    InvalidChannel,
 }
@@ -0,0 +1,178 @@
+use defmt_or_log as log;
+use ina3221_async::*;
+
+use crate::vcp_sensors::alerts::VcpAlertFlags;
+use crate::vcp_sensors::config::{VcpConfig, VcpSampling};
+use crate::vcp_sensors::data_model::{CHANNELS_PER_CHIP, ChannelNum, MAX_VCP_CHIPS, VCP_CHANNELS, chip_channel};
+use crate::vcp_sensors::error::VcpError;
+use crate::vcp_sensors::registers::Ina3221Registers;
+use crate::vcp_sensors::sensor::VcpSensor;
+
+/// Frees an I2C bus which a slave holds low after a transfer was interrupted
+pub trait I2cBusClear {
+    async fn clear_bus(&mut self);
+}
+
+/// One INA3221 on the bus. The measurement driver and the raw register access share the chip
+/// through two devices of the same bus.
+struct Ina3221Chip<SharedI2cDevice> {
+    ina: INA3221Async<SharedI2cDevice>,
+    registers: Ina3221Registers<SharedI2cDevice>,
+}
+
+/// The INA3221 chips of the board. The chip `n` holds the global channels `3n` to `3n + 2`.
+pub struct Ina3221Sensor<SharedI2cDevice, BusClear> {
+    chips: heapless::Vec<Ina3221Chip<SharedI2cDevice>, MAX_VCP_CHIPS>,
+    bus_clear: BusClear,
+}
+
+impl<SharedI2cDevice, BusClear> Ina3221Sensor<SharedI2cDevice, BusClear>
+where
+    SharedI2cDevice: embedded_hal_async::i2c::I2c,
+    BusClear: I2cBusClear,
+{
+    /// Creates the sensor of the chips at the addresses, in the order of their channels. Every chip
+    /// takes two devices of the bus from `i2c_dev`. The addresses beyond [`MAX_VCP_CHIPS`] are ignored.
+    pub fn new(mut i2c_dev: impl FnMut() -> SharedI2cDevice, bus_clear: BusClear, addresses: &[u8]) -> Self {
+        if addresses.len() > MAX_VCP_CHIPS {
+            log::warn!(
+                "Only {} of {} INA3221 chips are supported",
+                MAX_VCP_CHIPS,
+                addresses.len()
+            );
+        }
+        let chips = addresses
+            .iter()
+            .take(MAX_VCP_CHIPS)
+            .map(|address| Ina3221Chip {
+                ina: INA3221Async::new(i2c_dev(), *address),
+                registers: Ina3221Registers::new(i2c_dev(), *address),
+            })
+            .collect();
+        Self { chips, bus_clear }
+    }
+
+    /// The chip of the global channel and the channel number within the chip
+    fn chip(&mut self, channel: ChannelNum) -> Result<(&mut Ina3221Chip<SharedI2cDevice>, ChannelNum), VcpError> {
+        let (chip, chip_channel) = chip_channel(channel);
+        match self.chips.get_mut(chip) {
+            Some(chip) => Ok((chip, chip_channel)),
+            None => Err(VcpError::InvalidChannel),
+        }
+    }
+}
+
+impl<SharedI2cDevice, BusClear> VcpSensor for Ina3221Sensor<SharedI2cDevice, BusClear>
+where
+    SharedI2cDevice: embedded_hal_async::i2c::I2c,
+    BusClear: I2cBusClear,
+{
+    fn channel_count(&self) -> usize {
+        self.chips.len() * CHANNELS_PER_CHIP
+    }
+
+    async fn configure(&mut self, config: &VcpConfig) -> Result<(), VcpError> {
+        for chip in self.chips.iter_mut() {
+            // Set operating mode to continuous
+            chip.ina.set_mode(OperatingMode::Continuous).await.map_err(|e| {
+                log::error!("INA3221 set mode error: {:?}", defmt_or_log::Debug2Format(&e));
+                VcpError::I2c
+            })?;
+
+            if let Some(power_valid_limit) = &config.global_pv_limit {
+                chip.ina
+                    .set_power_valid_limits(
+                        Voltage::from_micro_volts((power_valid_limit.lower_voltage * 1_000_000.0) as i32),
+                        Voltage::from_micro_volts((power_valid_limit.upper_voltage * 1_000_000.0) as i32),
+                    )
+                    .await
+                    .map_err(|e| {
+                        log::error!(
+                            "INA3221 set power valid limits error: {:?}",
+                            defmt_or_log::Debug2Format(&e)
+                        );
+                        VcpError::I2c
+                    })?;
+            }
+        }
+
+        // Enable selected channels
+        for (i, enable) in config.enabled_channels.iter().take(self.channel_count()).enumerate() {
+            self.set_channel_enabled(i as ChannelNum, *enable).await?;
+        }
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), VcpError> {
+        self.bus_clear.clear_bus().await;
+        for chip in self.chips.iter_mut() {
+            chip.registers.soft_reset().await?;
+        }
+        Ok(())
+    }
+
+    async fn set_channel_enabled(&mut self, channel: ChannelNum, enabled: bool) -> Result<(), VcpError> {
+        let (chip, chip_channel) = self.chip(channel)?;
+        chip.ina.set_channel_enabled(chip_channel, enabled).await.map_err(|e| {
+            log::error!(
+                "INA3221 set channel {} enabled error: {:?}",
+                channel,
+                defmt_or_log::Debug2Format(&e)
+            );
+            VcpError::I2c
+        })
+    }
+
+    async fn read_bus_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        let (chip, chip_channel) = self.chip(channel)?;
+        match chip.ina.get_bus_voltage(chip_channel).await {
+            Ok(voltage) => Ok(voltage.volts()),
+            Err(e) => {
+                log::error!("INA3221 bus voltage read error: {:?}", defmt_or_log::Debug2Format(&e));
+                Err(VcpError::I2c)
+            }
+        }
+    }
+
+    async fn read_shunt_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        let (chip, chip_channel) = self.chip(channel)?;
+        match chip.ina.get_shunt_voltage(chip_channel).await {
+            Ok(shunt_voltage) => Ok(shunt_voltage.volts()),
+            Err(e) => {
+                log::error!("INA3221 shunt voltage read error: {:?}", defmt_or_log::Debug2Format(&e));
+                Err(VcpError::I2c)
+            }
+        }
+    }
+
+    async fn set_sampling(&mut self, sampling: &VcpSampling) -> Result<(), VcpError> {
+        for chip in self.chips.iter_mut() {
+            chip.registers.set_sampling(sampling).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_alert_limits(&mut self, channel: ChannelNum, critical: f32, warning: f32) -> Result<(), VcpError> {
+        let (chip, chip_channel) = self.chip(channel)?;
+        chip.registers.set_critical_limit(chip_channel, critical).await?;
+        chip.registers.set_warning_limit(chip_channel, warning).await
+    }
+
+    async fn set_summation_limit(&mut self, limit: f32, channels: [bool; VCP_CHANNELS]) -> Result<(), VcpError> {
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            let chip_channels = core::array::from_fn(|channel| channels[i * CHANNELS_PER_CHIP + channel]);
+            chip.registers.set_summation_limit(limit).await?;
+            chip.registers.set_mask_enable(chip_channels).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_alert_flags(&mut self) -> Result<VcpAlertFlags, VcpError> {
+        let mut flags: Option<VcpAlertFlags> = None;
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            let chip_flags = chip.registers.read_alert_flags(i).await?;
+            flags = Some(flags.map_or(chip_flags, |flags| flags.merge(chip_flags)));
+        }
+        Ok(flags.unwrap_or_default())
+    }
+}
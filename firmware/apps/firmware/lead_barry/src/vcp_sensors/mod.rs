@@ -1,20 +1,36 @@
 #![allow(unused_imports)]
 
+mod alerts;
+mod calibration;
 mod config;
 mod data_model;
+mod energy;
 mod error;
-mod events;
+#[cfg(not(feature = "sim"))]
+mod ina3221_sensor;
+mod recovery;
+#[cfg_attr(feature = "sim", allow(dead_code))]
+mod registers;
+#[cfg(test)]
+mod scripted_sensor;
+mod sensor;
 mod sensor_service;
 
-pub use crate::global_types::I2c0Device;
+pub use crate::global_types::VcpSensorDevice;
 
+pub use self::alerts::VcpAlertFlags;
+pub use self::calibration::*;
 pub use self::config::*;
-pub use self::data_model::{ChannelNum, VcpReading};
+pub use self::data_model::{CHANNELS_PER_CHIP, ChannelNum, MAX_VCP_CHIPS, VCP_CHANNELS, VcpReading, VcpReadingValues};
+pub use self::energy::{ChannelEnergy, EnergyCounters, EnergyTripReset};
 pub use self::error::VcpError;
-pub use self::events::VcpSensorsEvents;
-pub use self::sensor_service::{VcpSensorsService, VcpSensorsState};
-pub const VCP_SENSORS_EVENT_QUEUE_SIZE: usize = 8;
+#[cfg(not(feature = "sim"))]
+pub use self::ina3221_sensor::{I2cBusClear, Ina3221Sensor};
+pub use self::recovery::{VcpHealth, VcpHealthStatus};
+pub use self::sensor::VcpSensor;
+pub use self::sensor_service::{
+    VcpAlertSubscriber, VcpControl, VcpErrorSubscriber, VcpReadingSubscriber, VcpReadingWatcher, VcpSensorsService,
+    VcpSensorsState,
+};
 
-pub type VcpSensorsRunner<'a> =
-    self::sensor_service::VcpSensorsRunner<'a, I2c0Device<'a>, VCP_SENSORS_EVENT_QUEUE_SIZE>;
-pub type VcpControl<'a> = self::sensor_service::VcpControl<'a, VCP_SENSORS_EVENT_QUEUE_SIZE>;
+pub type VcpSensorsRunner<'a> = self::sensor_service::VcpSensorsRunner<'a, VcpSensorDevice>;
@@ -0,0 +1,208 @@
+//! Recovery of the sensor after I2C faults.
+//!
+//! A poll with a failed transfer only degrades the sensor. After a run of consecutive failed polls
+//! the runner clears the bus, resets the chip and configures it again. While the recovery keeps failing
+//! the sensor is offline and the attempts are spaced by an exponentially growing back-off, so a
+//! disconnected chip does not keep the shared bus busy.
+
+use serde::Serialize;
+
+/// Consecutive failed polls after which the sensor is recovered
+pub const RECOVER_AFTER_ERRORS: u32 = 5;
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 60_000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum VcpHealth {
+    /// The latest poll succeeded
+    Ok,
+    /// Polls are failing or the sensor has just been recovered
+    Degraded,
+    /// The recovery failed, the sensor is not polled until the next attempt
+    Offline,
+}
+
+impl VcpHealth {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            VcpHealth::Ok => "OK",
+            VcpHealth::Degraded => "Degraded",
+            VcpHealth::Offline => "Offline",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize)]
+#[defmt_or_log::derive_format_or_debug]
+pub struct VcpHealthStatus {
+    pub health: VcpHealth,
+    /// Consecutive polls with a failed transfer
+    pub consecutive_errors: u32,
+    /// Failed recovery attempts since the sensor last worked
+    pub failed_recoveries: u32,
+    /// Successful recoveries since the boot
+    pub recoveries: u32,
+}
+
+impl VcpHealthStatus {
+    pub const fn new() -> Self {
+        Self {
+            health: VcpHealth::Ok,
+            consecutive_errors: 0,
+            failed_recoveries: 0,
+            recoveries: 0,
+        }
+    }
+}
+
+impl Default for VcpHealthStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct RecoveryPolicy {
+    status: VcpHealthStatus,
+    next_attempt_ms: u64,
+}
+
+impl RecoveryPolicy {
+    pub const fn new() -> Self {
+        Self {
+            status: VcpHealthStatus::new(),
+            next_attempt_ms: 0,
+        }
+    }
+
+    pub fn status(&self) -> VcpHealthStatus {
+        self.status
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.status.health == VcpHealth::Offline
+    }
+
+    pub fn on_success(&mut self) {
+        self.status.consecutive_errors = 0;
+        self.status.failed_recoveries = 0;
+        self.status.health = VcpHealth::Ok;
+    }
+
+    pub fn on_error(&mut self) {
+        self.status.consecutive_errors = self.status.consecutive_errors.saturating_add(1);
+        if self.status.health == VcpHealth::Ok {
+            self.status.health = VcpHealth::Degraded;
+        }
+    }
+
+    /// Returns true if the sensor has failed long enough and the back-off has elapsed
+    pub fn recovery_due(&self, now_ms: u64) -> bool {
+        self.status.consecutive_errors >= RECOVER_AFTER_ERRORS && now_ms >= self.next_attempt_ms
+    }
+
+    /// The sensor is configured again. It stays degraded until the next successful reading.
+    pub fn on_recovered(&mut self) {
+        self.status.consecutive_errors = 0;
+        self.status.failed_recoveries = 0;
+        self.status.recoveries = self.status.recoveries.saturating_add(1);
+        self.status.health = VcpHealth::Degraded;
+        self.next_attempt_ms = 0;
+    }
+
+    /// The recovery or the initial configuration failed, the next attempt waits for the back-off
+    pub fn on_recovery_failed(&mut self, now_ms: u64) {
+        self.status.failed_recoveries = self.status.failed_recoveries.saturating_add(1);
+        self.status.consecutive_errors = self.status.consecutive_errors.max(RECOVER_AFTER_ERRORS);
+        self.status.health = VcpHealth::Offline;
+        self.next_attempt_ms = now_ms + backoff_ms(self.status.failed_recoveries);
+    }
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The delay before the next recovery attempt, doubling with every failed one
+fn backoff_ms(failed_recoveries: u32) -> u64 {
+    let doublings = failed_recoveries.saturating_sub(1).min(16);
+    (INITIAL_BACKOFF_MS << doublings).min(MAX_BACKOFF_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail(policy: &mut RecoveryPolicy, count: u32) {
+        for _ in 0..count {
+            policy.on_error();
+        }
+    }
+
+    #[test]
+    fn single_error_degrades() {
+        let mut policy = RecoveryPolicy::new();
+        policy.on_error();
+        assert_eq!(policy.status().health, VcpHealth::Degraded);
+        assert!(!policy.recovery_due(0));
+
+        policy.on_success();
+        assert_eq!(policy.status(), VcpHealthStatus::new());
+    }
+
+    #[test]
+    fn recovers_after_consecutive_errors() {
+        let mut policy = RecoveryPolicy::new();
+        fail(&mut policy, RECOVER_AFTER_ERRORS - 1);
+        policy.on_success();
+        fail(&mut policy, RECOVER_AFTER_ERRORS - 1);
+        assert!(!policy.recovery_due(0));
+
+        policy.on_error();
+        assert!(policy.recovery_due(0));
+
+        policy.on_recovered();
+        let status = policy.status();
+        assert_eq!(status.health, VcpHealth::Degraded);
+        assert_eq!(status.consecutive_errors, 0);
+        assert_eq!(status.recoveries, 1);
+        assert!(!policy.recovery_due(0));
+    }
+
+    #[test]
+    fn failed_recoveries_back_off_exponentially() {
+        let mut policy = RecoveryPolicy::new();
+        fail(&mut policy, RECOVER_AFTER_ERRORS);
+
+        policy.on_recovery_failed(1_000);
+        assert!(policy.is_offline());
+        assert!(!policy.recovery_due(1_499));
+        assert!(policy.recovery_due(1_500));
+
+        policy.on_recovery_failed(1_500);
+        assert!(!policy.recovery_due(2_499));
+        assert!(policy.recovery_due(2_500));
+
+        policy.on_recovery_failed(2_500);
+        assert!(policy.recovery_due(4_500));
+        assert_eq!(policy.status().failed_recoveries, 3);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff_ms(1), INITIAL_BACKOFF_MS);
+        assert_eq!(backoff_ms(2), 2 * INITIAL_BACKOFF_MS);
+        assert_eq!(backoff_ms(8), MAX_BACKOFF_MS);
+        assert_eq!(backoff_ms(u32::MAX), MAX_BACKOFF_MS);
+    }
+
+    #[test]
+    fn failed_configuration_at_boot_goes_offline() {
+        let mut policy = RecoveryPolicy::new();
+        policy.on_recovery_failed(0);
+        assert!(policy.is_offline());
+        assert!(policy.recovery_due(INITIAL_BACKOFF_MS));
+    }
+}
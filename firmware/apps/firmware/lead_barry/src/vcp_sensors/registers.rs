@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+//! Raw access to the INA3221 registers which the measurement driver does not cover: the alert
+//! limits, the mask/enable register and the averaging and conversion time fields of the
+//! configuration register.
+
+use defmt_or_log as log;
+
+use crate::vcp_sensors::alerts::*;
+use crate::vcp_sensors::config::VcpSampling;
+use crate::vcp_sensors::data_model::ChannelNum;
+use crate::vcp_sensors::error::VcpError;
+
+const REG_CONFIGURATION: u8 = 0x00;
+const REG_CRITICAL_LIMIT: [u8; 3] = [0x07, 0x09, 0x0B];
+const REG_WARNING_LIMIT: [u8; 3] = [0x08, 0x0A, 0x0C];
+const REG_SUMMATION_LIMIT: u8 = 0x0E;
+const REG_MASK_ENABLE: u8 = 0x0F;
+
+// Configuration register fields
+const RESET: u16 = 1 << 15;
+const AVERAGING_SHIFT: u16 = 9;
+const BUS_CONVERSION_TIME_SHIFT: u16 = 6;
+const SHUNT_CONVERSION_TIME_SHIFT: u16 = 3;
+const SAMPLING_MASK: u16 = 0b111_111_111 << SHUNT_CONVERSION_TIME_SHIFT;
+
+/// Replaces the averaging and conversion time fields of the configuration register, keeping the
+/// channel enables and the operating mode
+pub fn configuration_register(current: u16, sampling: &VcpSampling) -> u16 {
+    let fields = ((sampling.averaging as u16) << AVERAGING_SHIFT)
+        | ((sampling.bus_conversion_time as u16) << BUS_CONVERSION_TIME_SHIFT)
+        | ((sampling.shunt_conversion_time as u16) << SHUNT_CONVERSION_TIME_SHIFT);
+    (current & !SAMPLING_MASK) | fields
+}
+
+pub struct Ina3221Registers<I2c> {
+    i2c: I2c,
+    address: u8,
+}
+
+impl<I2c> Ina3221Registers<I2c>
+where
+    I2c: embedded_hal_async::i2c::I2c,
+{
+    pub const fn new(i2c: I2c, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    pub async fn set_critical_limit(&mut self, channel: ChannelNum, shunt_volts: f32) -> Result<(), VcpError> {
+        self.write_register(REG_CRITICAL_LIMIT[channel as usize], shunt_limit_register(shunt_volts))
+            .await
+    }
+
+    pub async fn set_warning_limit(&mut self, channel: ChannelNum, shunt_volts: f32) -> Result<(), VcpError> {
+        self.write_register(REG_WARNING_LIMIT[channel as usize], shunt_limit_register(shunt_volts))
+            .await
+    }
+
+    pub async fn set_summation_limit(&mut self, shunt_volts: f32) -> Result<(), VcpError> {
+        self.write_register(REG_SUMMATION_LIMIT, summation_limit_register(shunt_volts))
+            .await
+    }
+
+    pub async fn set_mask_enable(&mut self, summation_channels: [bool; 3]) -> Result<(), VcpError> {
+        self.write_register(REG_MASK_ENABLE, mask_enable_register(summation_channels))
+            .await
+    }
+
+    /// Reads the alert flags of the chip. Reading the register clears the latched flags.
+    pub async fn read_alert_flags(&mut self, chip: usize) -> Result<VcpAlertFlags, VcpError> {
+        let register = self.read_register(REG_MASK_ENABLE).await?;
+        Ok(VcpAlertFlags::from_mask_enable(chip, register))
+    }
+
+    /// Resets all the registers to their power-on values
+    pub async fn soft_reset(&mut self) -> Result<(), VcpError> {
+        self.write_register(REG_CONFIGURATION, RESET).await
+    }
+
+    /// Sets the averaging and the conversion times
+    pub async fn set_sampling(&mut self, sampling: &VcpSampling) -> Result<(), VcpError> {
+        let current = self.read_register(REG_CONFIGURATION).await?;
+        self.write_register(REG_CONFIGURATION, configuration_register(current, sampling))
+            .await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u16, VcpError> {
+        let mut buffer = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)
+            .await
+            .map_err(|e| {
+                log::error!(
+                    "INA3221 register 0x{:02x} read error: {:?}",
+                    register,
+                    defmt_or_log::Debug2Format(&e)
+                );
+                VcpError::I2c
+            })?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    async fn write_register(&mut self, register: u8, value: u16) -> Result<(), VcpError> {
+        let [high, low] = value.to_be_bytes();
+        self.i2c.write(self.address, &[register, high, low]).await.map_err(|e| {
+            log::error!(
+                "INA3221 register 0x{:02x} write error: {:?}",
+                register,
+                defmt_or_log::Debug2Format(&e)
+            );
+            VcpError::I2c
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcp_sensors::config::{VcpAveraging, VcpConversionTime};
+
+    #[test]
+    fn test_configuration_register_keeps_enables_and_mode() {
+        let sampling = VcpSampling {
+            averaging: VcpAveraging::X64,
+            bus_conversion_time: VcpConversionTime::Us2116,
+            shunt_conversion_time: VcpConversionTime::Us8244,
+            poll_period_ms: 100,
+        };
+        // Power-on default: all channels on, 1 sample, 1.1 ms conversions, continuous shunt and bus
+        let register = configuration_register(0x7127, &sampling);
+        assert_eq!(register, 0x7000 | (0b011 << 9) | (0b101 << 6) | (0b111 << 3) | 0b111);
+    }
+}
@@ -0,0 +1,163 @@
+//! A fake sensor for the unit tests of the service. Every channel plays a script of steps, one step
+//! per reading: a voltage/current sample, an I2C error or a bus hang the service has to time out.
+
+use core::future::pending;
+
+use crate::vcp_sensors::alerts::VcpAlertFlags;
+use crate::vcp_sensors::config::{VcpConfig, VcpSampling};
+use crate::vcp_sensors::data_model::{CHANNELS_PER_CHIP, ChannelNum, VCP_CHANNELS};
+use crate::vcp_sensors::error::VcpError;
+use crate::vcp_sensors::sensor::VcpSensor;
+
+pub const MAX_SCRIPT_STEPS: usize = 16;
+const CHANNELS: usize = VCP_CHANNELS;
+
+#[derive(Copy, Clone, PartialEq)]
+#[defmt_or_log::derive_format_or_debug]
+pub enum ScriptStep {
+    /// The bus voltage in volts and the current in amps of one reading
+    Sample(f32, f32),
+    /// The chip does not acknowledge the reading
+    I2cError,
+    /// The bus hangs, the reading never completes
+    Hang,
+}
+
+pub struct ScriptedSensor {
+    scripts: [heapless::Deque<ScriptStep, MAX_SCRIPT_STEPS>; CHANNELS],
+    /// The current of the latest sample, returned by the shunt reading that follows the bus reading
+    current: [f32; CHANNELS],
+    shunt_resistance: [f32; CHANNELS],
+    alert_flags: Result<VcpAlertFlags, VcpError>,
+    reset_result: Result<(), VcpError>,
+    /// The alert limits as programmed by the service
+    pub alert_limits: [(f32, f32); CHANNELS],
+    pub enabled: [bool; CHANNELS],
+    pub sampling: Option<VcpSampling>,
+    pub configured: bool,
+    pub resets: u32,
+    channel_count: usize,
+}
+
+impl ScriptedSensor {
+    pub fn new() -> Self {
+        Self {
+            scripts: Default::default(),
+            current: [0.0; CHANNELS],
+            shunt_resistance: [0.1; CHANNELS],
+            alert_flags: Ok(VcpAlertFlags::new()),
+            reset_result: Ok(()),
+            alert_limits: [(f32::MAX, f32::MAX); CHANNELS],
+            enabled: [false; CHANNELS],
+            sampling: None,
+            configured: false,
+            resets: 0,
+            channel_count: CHANNELS_PER_CHIP,
+        }
+    }
+
+    /// Sets the number of channels of the fitted chips, one chip by default
+    pub fn with_channel_count(mut self, channel_count: usize) -> Self {
+        self.channel_count = channel_count;
+        self
+    }
+
+    /// Appends the steps to the script of the channel. The last sample is held once the script runs out.
+    pub fn with_script(mut self, channel: ChannelNum, steps: &[ScriptStep]) -> Self {
+        for step in steps {
+            self.scripts[channel as usize]
+                .push_back(*step)
+                .expect("The script is too long");
+        }
+        self
+    }
+
+    /// Sets the result of the following alert flag readings
+    pub fn with_alert_flags(mut self, alert_flags: Result<VcpAlertFlags, VcpError>) -> Self {
+        self.alert_flags = alert_flags;
+        self
+    }
+
+    /// Sets the result of the following resets
+    pub fn with_reset_result(mut self, reset_result: Result<(), VcpError>) -> Self {
+        self.reset_result = reset_result;
+        self
+    }
+
+    fn next_step(&mut self, channel: ChannelNum) -> Result<ScriptStep, VcpError> {
+        if channel as usize >= self.channel_count {
+            return Err(VcpError::InvalidChannel);
+        }
+        let script = &mut self.scripts[channel as usize];
+        let step = match script.len() {
+            0 => ScriptStep::Sample(0.0, 0.0),
+            1 => *script.front().unwrap(),
+            _ => script.pop_front().unwrap(),
+        };
+        Ok(step)
+    }
+}
+
+impl Default for ScriptedSensor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VcpSensor for ScriptedSensor {
+    fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    async fn configure(&mut self, config: &VcpConfig) -> Result<(), VcpError> {
+        self.shunt_resistance = config.shunt_resistances();
+        self.enabled = config.enabled_channels;
+        self.configured = true;
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), VcpError> {
+        self.resets += 1;
+        self.configured = false;
+        self.reset_result
+    }
+
+    async fn set_channel_enabled(&mut self, channel: ChannelNum, enabled: bool) -> Result<(), VcpError> {
+        *self.enabled.get_mut(channel as usize).ok_or(VcpError::I2c)? = enabled;
+        Ok(())
+    }
+
+    async fn read_bus_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        match self.next_step(channel)? {
+            ScriptStep::Sample(voltage, current) => {
+                self.current[channel as usize] = current;
+                Ok(voltage)
+            }
+            ScriptStep::I2cError => Err(VcpError::I2c),
+            ScriptStep::Hang => pending().await,
+        }
+    }
+
+    async fn read_shunt_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError> {
+        let current = self.current.get(channel as usize).ok_or(VcpError::I2c)?;
+        Ok(current * self.shunt_resistance[channel as usize])
+    }
+
+    async fn set_sampling(&mut self, sampling: &VcpSampling) -> Result<(), VcpError> {
+        self.sampling = Some(*sampling);
+        Ok(())
+    }
+
+    async fn set_alert_limits(&mut self, channel: ChannelNum, critical: f32, warning: f32) -> Result<(), VcpError> {
+        *self.alert_limits.get_mut(channel as usize).ok_or(VcpError::I2c)? = (critical, warning);
+        Ok(())
+    }
+
+    async fn set_summation_limit(&mut self, _limit: f32, _channels: [bool; VCP_CHANNELS]) -> Result<(), VcpError> {
+        Ok(())
+    }
+
+    async fn read_alert_flags(&mut self) -> Result<VcpAlertFlags, VcpError> {
+        self.alert_flags
+    }
+}
@@ -0,0 +1,40 @@
+use crate::vcp_sensors::alerts::VcpAlertFlags;
+use crate::vcp_sensors::config::{VcpConfig, VcpSampling};
+use crate::vcp_sensors::data_model::{ChannelNum, VCP_CHANNELS};
+use crate::vcp_sensors::error::VcpError;
+
+/// The hardware access of the voltage/current sensor. The service classifies, accumulates and
+/// publishes the readings; an implementation only talks to the chips.
+///
+/// The channels are numbered globally across the chips, see [`ChannelNum`].
+pub trait VcpSensor {
+    /// The number of channels of the fitted chips, at most [`VCP_CHANNELS`]
+    fn channel_count(&self) -> usize;
+
+    /// Starts the continuous conversion of the enabled channels and programs the power-valid window
+    async fn configure(&mut self, config: &VcpConfig) -> Result<(), VcpError>;
+
+    /// Frees the bus and returns the chip to its power-on state. The service configures it again afterwards.
+    async fn reset(&mut self) -> Result<(), VcpError>;
+
+    async fn set_channel_enabled(&mut self, channel: ChannelNum, enabled: bool) -> Result<(), VcpError>;
+
+    /// Reads the bus voltage of the channel in volts
+    async fn read_bus_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError>;
+
+    /// Reads the voltage across the shunt of the channel in volts
+    async fn read_shunt_voltage(&mut self, channel: ChannelNum) -> Result<f32, VcpError>;
+
+    /// Programs the averaging and the conversion times
+    async fn set_sampling(&mut self, sampling: &VcpSampling) -> Result<(), VcpError>;
+
+    /// Programs the critical and the warning alert limits of the channel as shunt voltages
+    async fn set_alert_limits(&mut self, channel: ChannelNum, critical: f32, warning: f32) -> Result<(), VcpError>;
+
+    /// Programs the summation alert limit as a shunt voltage and the channels it sums up. Every chip
+    /// sums up its own selected channels only.
+    async fn set_summation_limit(&mut self, limit: f32, channels: [bool; VCP_CHANNELS]) -> Result<(), VcpError>;
+
+    /// Reads the alert flags latched by the chips
+    async fn read_alert_flags(&mut self) -> Result<VcpAlertFlags, VcpError>;
+}
@@ -1,24 +1,35 @@
+use core::cell::Cell;
+
 use defmt_or_log as log;
 use embassy_futures::*;
 use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex,
+    blocking_mutex::{Mutex as BlockingMutex, raw::CriticalSectionRawMutex},
     channel::{Channel, Receiver, SendFuture, Sender},
-    priority_channel::{
-        Max as MaxPriorityOrdering, PriorityChannel, ReceiveFuture, Receiver as PriorityReceiver,
-        Sender as PrioritySender,
-    },
+    pubsub::{ImmediatePublisher, PubSubChannel, Subscriber},
+    watch::{Receiver as WatchReceiver, Watch},
 };
 
-use embassy_time::{Ticker, with_timeout};
-use ina3221_async::*;
+use embassy_time::{Instant, Ticker, with_timeout};
 use postcard::fixint::le;
 
 use crate::{
-    units::TimeExt, vcp_sensors::config::*, vcp_sensors::data_model::*, vcp_sensors::error::*, vcp_sensors::events::*,
+    units::TimeExt, vcp_sensors::alerts::*, vcp_sensors::calibration::*, vcp_sensors::config::*,
+    vcp_sensors::data_model::*, vcp_sensors::energy::*, vcp_sensors::error::*, vcp_sensors::recovery::*,
+    vcp_sensors::sensor::VcpSensor,
 };
 
-const POLL_TIMEOUT_MS: u64 = 40;
 const HARDWARE_RESPONSE_TIMEOUT_MS: u64 = 100;
+/// The bus clear, the reset and the configuration together
+const RECOVERY_TIMEOUT_MS: u64 = 1_000;
+/// Two full polls of all the channels, so a subscriber busy for a poll period does not lag
+const READINGS_QUEUE_SIZE: usize = 2 * VCP_CHANNELS;
+const READING_SUBSCRIBERS: usize = 8;
+/// The waiting receivers of the latest reading of a channel, the plain reads are not limited
+const LATEST_READING_RECEIVERS: usize = 4;
+const ALERTS_QUEUE_SIZE: usize = 2;
+const ALERT_SUBSCRIBERS: usize = 2;
+const ERRORS_QUEUE_SIZE: usize = 4;
+const ERROR_SUBSCRIBERS: usize = 2;
 
 #[defmt_or_log::derive_format_or_debug]
 pub enum VcpCommand {
@@ -26,153 +37,305 @@ pub enum VcpCommand {
     DisableChannel(ChannelNum),
     EnableAllChannels,
     DisableAllChannels,
+    SetSampling(VcpSampling),
+    ResetEnergyTrip(ChannelNum),
+    /// Moves the low and the high voltage thresholds of the channel
+    SetVoltageLimits(ChannelNum, f32, f32),
+    /// Replaces the shunt and the corrections of the channel
+    SetCalibration(ChannelNum, VcpChannelCalibration),
 }
 
-type VcpEventChannel<const EVENT_QUEUE_SIZE: usize> =
-    PriorityChannel<CriticalSectionRawMutex, VcpSensorsEvents, MaxPriorityOrdering, EVENT_QUEUE_SIZE>;
-pub type VcpEventReceiver<'a, const EVENT_QUEUE_SIZE: usize> =
-    PriorityReceiver<'a, CriticalSectionRawMutex, VcpSensorsEvents, MaxPriorityOrdering, EVENT_QUEUE_SIZE>;
-pub type VcpEventReceiveFuture<'a, const EVENT_QUEUE_SIZE: usize> =
-    ReceiveFuture<'a, CriticalSectionRawMutex, VcpSensorsEvents, MaxPriorityOrdering, EVENT_QUEUE_SIZE>;
+type VcpReadingChannel =
+    PubSubChannel<CriticalSectionRawMutex, VcpReading, READINGS_QUEUE_SIZE, READING_SUBSCRIBERS, 0>;
+type VcpReadingPublisher<'a> =
+    ImmediatePublisher<'a, CriticalSectionRawMutex, VcpReading, READINGS_QUEUE_SIZE, READING_SUBSCRIBERS, 0>;
+/// Receives every successful reading independently of the other subscribers. A subscriber which falls
+/// behind by more than the queue loses the oldest readings, it never holds the others back.
+pub type VcpReadingSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, VcpReading, READINGS_QUEUE_SIZE, READING_SUBSCRIBERS, 0>;
+
+type VcpLatestReading = Watch<CriticalSectionRawMutex, VcpReading, LATEST_READING_RECEIVERS>;
+/// Waits for the next reading of a channel. The readings in between are coalesced into the latest one.
+pub type VcpReadingWatcher<'a> = WatchReceiver<'a, CriticalSectionRawMutex, VcpReading, LATEST_READING_RECEIVERS>;
+
+type VcpAlertChannel = PubSubChannel<CriticalSectionRawMutex, VcpAlertFlags, ALERTS_QUEUE_SIZE, ALERT_SUBSCRIBERS, 0>;
+type VcpAlertPublisher<'a> =
+    ImmediatePublisher<'a, CriticalSectionRawMutex, VcpAlertFlags, ALERTS_QUEUE_SIZE, ALERT_SUBSCRIBERS, 0>;
+/// Receives the hardware alerts latched by the sensor
+pub type VcpAlertSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, VcpAlertFlags, ALERTS_QUEUE_SIZE, ALERT_SUBSCRIBERS, 0>;
+
+type VcpErrorChannel = PubSubChannel<CriticalSectionRawMutex, VcpError, ERRORS_QUEUE_SIZE, ERROR_SUBSCRIBERS, 0>;
+type VcpErrorPublisher<'a> =
+    ImmediatePublisher<'a, CriticalSectionRawMutex, VcpError, ERRORS_QUEUE_SIZE, ERROR_SUBSCRIBERS, 0>;
+/// Receives the failed transfers and timeouts of the polls, the configuration and the recoveries
+pub type VcpErrorSubscriber<'a> =
+    Subscriber<'a, CriticalSectionRawMutex, VcpError, ERRORS_QUEUE_SIZE, ERROR_SUBSCRIBERS, 0>;
 
-type VcpEventSender<'a, const EVENT_QUEUE_SIZE: usize> =
-    PrioritySender<'a, CriticalSectionRawMutex, VcpSensorsEvents, MaxPriorityOrdering, EVENT_QUEUE_SIZE>;
 type VcpCommandChannel = Channel<CriticalSectionRawMutex, VcpCommand, 1>;
 type VcpCommandSendFuture<'a> = SendFuture<'a, CriticalSectionRawMutex, VcpCommand, 1>;
 
-pub struct VcpSensorsState<const EVENT_QUEUE_SIZE: usize> {
-    events: VcpEventChannel<EVENT_QUEUE_SIZE>,
+pub struct VcpSensorsState {
+    readings: VcpReadingChannel,
+    latest: [VcpLatestReading; VCP_CHANNELS],
+    alerts: VcpAlertChannel,
+    errors: VcpErrorChannel,
+    alert_flags: BlockingMutex<CriticalSectionRawMutex, Cell<VcpAlertFlags>>,
+    energy: BlockingMutex<CriticalSectionRawMutex, Cell<[ChannelEnergy; VCP_CHANNELS]>>,
+    uncalibrated: BlockingMutex<CriticalSectionRawMutex, Cell<[Option<VcpUncalibratedReading>; VCP_CHANNELS]>>,
+    health: BlockingMutex<CriticalSectionRawMutex, Cell<VcpHealthStatus>>,
     control: VcpCommandChannel,
 }
 
-impl<const EVENT_QUEUE_SIZE: usize> VcpSensorsState<EVENT_QUEUE_SIZE> {
+impl VcpSensorsState {
     pub const fn new() -> Self {
         Self {
-            events: VcpEventChannel::new(),
+            readings: VcpReadingChannel::new(),
+            latest: [const { VcpLatestReading::new() }; VCP_CHANNELS],
+            alerts: VcpAlertChannel::new(),
+            errors: VcpErrorChannel::new(),
+            alert_flags: BlockingMutex::new(Cell::new(VcpAlertFlags::new())),
+            energy: BlockingMutex::new(Cell::new([ChannelEnergy::new(); VCP_CHANNELS])),
+            uncalibrated: BlockingMutex::new(Cell::new([None; VCP_CHANNELS])),
+            health: BlockingMutex::new(Cell::new(VcpHealthStatus::new())),
             control: VcpCommandChannel::new(),
         }
     }
 }
 
-pub struct VcpSensorsRunner<'a, SharedI2cDevice, const EVENT_QUEUE_SIZE: usize> {
-    i2c_dev: Option<SharedI2cDevice>,
-    event_sender: VcpEventSender<'a, EVENT_QUEUE_SIZE>,
+pub struct VcpSensorsRunner<'a, Sensor> {
+    sensor: Sensor,
+    reading_publisher: VcpReadingPublisher<'a>,
+    latest: &'a [VcpLatestReading; VCP_CHANNELS],
+    alert_publisher: VcpAlertPublisher<'a>,
+    error_publisher: VcpErrorPublisher<'a>,
+    alert_flags: &'a BlockingMutex<CriticalSectionRawMutex, Cell<VcpAlertFlags>>,
+    energy_snapshot: &'a BlockingMutex<CriticalSectionRawMutex, Cell<[ChannelEnergy; VCP_CHANNELS]>>,
+    energy: EnergyAccumulator<VCP_CHANNELS>,
+    uncalibrated: &'a BlockingMutex<CriticalSectionRawMutex, Cell<[Option<VcpUncalibratedReading>; VCP_CHANNELS]>>,
+    health_snapshot: &'a BlockingMutex<CriticalSectionRawMutex, Cell<VcpHealthStatus>>,
+    recovery: RecoveryPolicy,
     command_sender: Receiver<'a, CriticalSectionRawMutex, VcpCommand, 1>,
     config: VcpConfig,
+    /// The channels of the fitted chips
+    channel_count: usize,
 }
 
-pub struct VcpControl<'a, const EVENT_QUEUE_SIZE: usize> {
-    event_receiver: VcpEventReceiver<'a, EVENT_QUEUE_SIZE>,
+pub struct VcpControl<'a> {
+    readings: &'a VcpReadingChannel,
+    latest: &'a [VcpLatestReading; VCP_CHANNELS],
+    alerts: &'a VcpAlertChannel,
+    errors: &'a VcpErrorChannel,
+    alert_flags: &'a BlockingMutex<CriticalSectionRawMutex, Cell<VcpAlertFlags>>,
+    energy: &'a BlockingMutex<CriticalSectionRawMutex, Cell<[ChannelEnergy; VCP_CHANNELS]>>,
+    uncalibrated: &'a BlockingMutex<CriticalSectionRawMutex, Cell<[Option<VcpUncalibratedReading>; VCP_CHANNELS]>>,
+    health: &'a BlockingMutex<CriticalSectionRawMutex, Cell<VcpHealthStatus>>,
     command_receiver: Sender<'a, CriticalSectionRawMutex, VcpCommand, 1>,
+    channel_count: usize,
 }
 
 #[allow(dead_code)]
-impl<'a, const EVENT_QUEUE_SIZE: usize> VcpControl<'a, EVENT_QUEUE_SIZE> {
-    pub fn receive_event(&self) -> VcpEventReceiveFuture<'_, EVENT_QUEUE_SIZE> {
-        self.event_receiver.receive()
+impl<'a> VcpControl<'a> {
+    /// The number of channels of the fitted chips. The channels are numbered from 0 across the chips.
+    pub fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    /// Subscribes to the readings of all enabled channels.
+    /// Returns `None` if all the subscriber slots are already taken.
+    pub fn subscribe_readings(&self) -> Option<VcpReadingSubscriber<'a>> {
+        self.readings.subscriber().ok()
+    }
+
+    /// Returns the latest reading of the channel, `None` until the channel is read successfully
+    pub fn latest_reading(&self, channel: ChannelNum) -> Option<VcpReading> {
+        self.latest.get(channel as usize)?.try_get()
+    }
+
+    /// Watches the latest reading of the channel. Returns `None` if the channel does not exist or all
+    /// the receiver slots of the channel are already taken. The slot is freed when the watcher is dropped.
+    pub fn watch_reading(&self, channel: ChannelNum) -> Option<VcpReadingWatcher<'a>> {
+        self.latest.get(channel as usize)?.receiver()
+    }
+
+    /// Subscribes to the critical, warning and summation alerts latched by the sensor.
+    /// Returns `None` if all the subscriber slots are already taken.
+    pub fn subscribe_alerts(&self) -> Option<VcpAlertSubscriber<'a>> {
+        self.alerts.subscriber().ok()
     }
 
-    pub fn flush_events(&self) {
-        while self.event_receiver.try_receive().is_ok() {}
+    /// Subscribes to the errors of the sensor.
+    /// Returns `None` if all the subscriber slots are already taken.
+    pub fn subscribe_errors(&self) -> Option<VcpErrorSubscriber<'a>> {
+        self.errors.subscriber().ok()
     }
 
+    /// Returns the alert flags read from the mask/enable register at the latest poll
+    pub fn alert_flags(&self) -> VcpAlertFlags {
+        self.alert_flags.lock(|flags| flags.get())
+    }
+
+    /// Returns the charge and energy accumulators of all the channels
+    pub fn energy(&self) -> [ChannelEnergy; VCP_CHANNELS] {
+        self.energy.lock(|energy| energy.get())
+    }
+
+    /// Returns the latest readings of the channel before the calibration is applied, `None` until
+    /// the channel is polled
+    pub fn uncalibrated_reading(&self, channel: ChannelNum) -> Option<VcpUncalibratedReading> {
+        self.uncalibrated
+            .lock(|uncalibrated| uncalibrated.get().get(channel as usize).copied().flatten())
+    }
+
+    /// Returns whether the sensor responds and how its recovery went
+    pub fn health(&self) -> VcpHealthStatus {
+        self.health.lock(|health| health.get())
+    }
+
+    /// Resets the trip counters of the channel. The lifetime totals are kept.
+    pub fn reset_energy_trip(&self, channel: ChannelNum) -> VcpCommandSendFuture<'_> {
+        self.command_receiver.send(VcpCommand::ResetEnergyTrip(channel))
+    }
+
+    /// Starts polling the channel. The enables are shared by all the consumers of the readings, a consumer
+    /// interested in a single channel watches it instead.
     pub fn enable_channel(&self, channel: ChannelNum) -> VcpCommandSendFuture<'_> {
         self.command_receiver.send(VcpCommand::EnableChannel(channel))
     }
 
+    /// Stops polling the channel unless it is pinned
     pub fn disable_channel(&self, channel: ChannelNum) -> VcpCommandSendFuture<'_> {
         self.command_receiver.send(VcpCommand::DisableChannel(channel))
     }
     pub fn enable_all_channels(&self) -> impl Future<Output = ()> + '_ {
         self.command_receiver.send(VcpCommand::EnableAllChannels)
     }
+    /// Stops polling all the channels but the pinned ones
     pub fn disable_all_channels(&self) -> impl Future<Output = ()> + '_ {
         self.command_receiver.send(VcpCommand::DisableAllChannels)
     }
+
+    /// Moves the voltage thresholds of the channel readings, e.g. for the temperature compensation
+    pub fn set_voltage_limits(
+        &self,
+        channel: ChannelNum,
+        min_voltage: f32,
+        max_voltage: f32,
+    ) -> VcpCommandSendFuture<'_> {
+        self.command_receiver
+            .send(VcpCommand::SetVoltageLimits(channel, min_voltage, max_voltage))
+    }
+
+    /// Applies the shunt and the corrections of the channel to the following readings
+    pub fn set_calibration(&self, channel: ChannelNum, calibration: VcpChannelCalibration) -> VcpCommandSendFuture<'_> {
+        self.command_receiver
+            .send(VcpCommand::SetCalibration(channel, calibration))
+    }
+
+    /// Changes the averaging, the conversion times and the poll period without a restart
+    pub fn set_sampling(&self, sampling: VcpSampling) -> VcpCommandSendFuture<'_> {
+        self.command_receiver.send(VcpCommand::SetSampling(sampling))
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct VcpSensorsService(());
 
 impl VcpSensorsService {
-    /// Creates a new VCP sensors instance
+    /// Creates a new VCP sensors instance polling the given sensor
     #[allow(clippy::new_ret_no_self)]
-    pub fn new<'a, SharedI2cDevice, const EVENT_QUEUE_SIZE: usize>(
-        i2c_dev: SharedI2cDevice,
-        state: &'a mut VcpSensorsState<{ EVENT_QUEUE_SIZE }>,
-        config: VcpConfig,
-    ) -> (
-        VcpSensorsRunner<'a, SharedI2cDevice, { EVENT_QUEUE_SIZE }>,
-        VcpControl<'a, { EVENT_QUEUE_SIZE }>,
-    ) {
+    pub fn new<'a, Sensor>(
+        sensor: Sensor,
+        state: &'a mut VcpSensorsState,
+        mut config: VcpConfig,
+    ) -> (VcpSensorsRunner<'a, Sensor>, VcpControl<'a>)
+    where
+        Sensor: VcpSensor,
+    {
+        let channel_count = sensor.channel_count().min(VCP_CHANNELS);
+        // The channels of the chips which are not fitted are never polled
+        config.enabled_channels[channel_count..].fill(false);
+        config.pinned_channels[channel_count..].fill(false);
         (
             VcpSensorsRunner {
-                i2c_dev: Some(i2c_dev),
-                event_sender: state.events.sender(),
+                sensor,
+                reading_publisher: state.readings.immediate_publisher(),
+                latest: &state.latest,
+                alert_publisher: state.alerts.immediate_publisher(),
+                error_publisher: state.errors.immediate_publisher(),
+                alert_flags: &state.alert_flags,
+                energy_snapshot: &state.energy,
+                energy: EnergyAccumulator::new(config.lifetime_energy, config.sampling.poll_period_ms),
+                uncalibrated: &state.uncalibrated,
+                health_snapshot: &state.health,
+                recovery: RecoveryPolicy::new(),
                 command_sender: state.control.receiver(),
                 config,
+                channel_count,
             },
             VcpControl {
-                event_receiver: state.events.receiver(),
+                readings: &state.readings,
+                latest: &state.latest,
+                alerts: &state.alerts,
+                errors: &state.errors,
+                alert_flags: &state.alert_flags,
+                energy: &state.energy,
+                uncalibrated: &state.uncalibrated,
+                health: &state.health,
                 command_receiver: state.control.sender(),
+                channel_count,
             },
         )
     }
 }
 
-impl<'a, SharedI2cDevice, const EVENT_QUEUE_SIZE: usize> VcpSensorsRunner<'a, SharedI2cDevice, EVENT_QUEUE_SIZE>
+impl<'a, Sensor> VcpSensorsRunner<'a, Sensor>
 where
-    SharedI2cDevice: embedded_hal_async::i2c::I2c,
+    Sensor: VcpSensor,
 {
-    async fn read_bus_voltage(
-        &mut self,
-        ina: &INA3221Async<SharedI2cDevice>,
-        channel: u8,
-    ) -> Result<VcpState, VcpError> {
-        match ina.get_bus_voltage(channel).await {
-            Err(e) => {
-                log::error!("INA3221 bus voltage read error: {:?}", defmt_or_log::Debug2Format(&e));
-                Err(VcpError::I2c)
-            }
-            Ok(voltage) => {
-                if voltage.volts() < self.config.limits[channel as usize].min_voltage {
-                    Ok(VcpState::Low(voltage.volts()))
-                } else if voltage.volts() > self.config.limits[channel as usize].max_voltage {
-                    Ok(VcpState::High(voltage.volts()))
-                } else {
-                    Ok(VcpState::Normal(voltage.volts()))
-                }
-            }
+    async fn read_bus_voltage(&mut self, channel: u8) -> Result<VcpState, VcpError> {
+        let bus_voltage = self.sensor.read_bus_voltage(channel).await?;
+        self.record_uncalibrated(channel, |uncalibrated| uncalibrated.voltage = bus_voltage);
+        let voltage = self.config.calibration[channel as usize].voltage(bus_voltage);
+        if voltage < self.config.limits[channel as usize].min_voltage {
+            Ok(VcpState::Low(voltage))
+        } else if voltage > self.config.limits[channel as usize].max_voltage {
+            Ok(VcpState::High(voltage))
+        } else {
+            Ok(VcpState::Normal(voltage))
         }
     }
 
-    async fn read_shunt_voltage(
-        &mut self,
-        ina: &INA3221Async<SharedI2cDevice>,
-        channel: u8,
-    ) -> Result<VcpState, VcpError> {
-        match ina.get_shunt_voltage(channel).await {
-            Err(e) => {
-                log::error!("INA3221 shunt voltage read error: {:?}", defmt_or_log::Debug2Format(&e));
-                Err(VcpError::I2c)
-            }
-            Ok(shunt_voltage) => {
-                let shunt_resistance = self.config.shunt_resistance(channel);
-                let shunt_current = shunt_voltage.volts() / shunt_resistance;
-                if shunt_current < self.config.limits[channel as usize].min_current {
-                    Ok(VcpState::Low(shunt_current))
-                } else if shunt_current > self.config.limits[channel as usize].max_current {
-                    Ok(VcpState::High(shunt_current))
-                } else {
-                    Ok(VcpState::Normal(shunt_current))
-                }
-            }
+    async fn read_shunt_voltage(&mut self, channel: u8) -> Result<VcpState, VcpError> {
+        let shunt_voltage = self.sensor.read_shunt_voltage(channel).await?;
+        let calibration = self.config.calibration[channel as usize];
+        let uncalibrated_current = calibration.uncalibrated_current(shunt_voltage);
+        self.record_uncalibrated(channel, |uncalibrated| uncalibrated.current = uncalibrated_current);
+        let shunt_current = calibration.current(shunt_voltage);
+        if shunt_current < self.config.limits[channel as usize].min_current {
+            Ok(VcpState::Low(shunt_current))
+        } else if shunt_current > self.config.limits[channel as usize].max_current {
+            Ok(VcpState::High(shunt_current))
+        } else {
+            Ok(VcpState::Normal(shunt_current))
         }
     }
 
-    async fn read_channel(&mut self, ina: &INA3221Async<SharedI2cDevice>, channel: u8) -> Result<VcpReading, VcpError> {
-        let voltage = self.read_bus_voltage(ina, channel).await?;
-        let current = self.read_shunt_voltage(ina, channel).await?;
+    fn record_uncalibrated(&self, channel: ChannelNum, update: impl FnOnce(&mut VcpUncalibratedReading)) {
+        self.uncalibrated.lock(|uncalibrated| {
+            let mut readings = uncalibrated.get();
+            let reading = readings[channel as usize].get_or_insert(VcpUncalibratedReading {
+                voltage: 0.0,
+                current: 0.0,
+            });
+            update(reading);
+            uncalibrated.set(readings);
+        });
+    }
+
+    async fn read_channel(&mut self, channel: u8) -> Result<VcpReading, VcpError> {
+        let voltage = self.read_bus_voltage(channel).await?;
+        let current = self.read_shunt_voltage(channel).await?;
         Ok(VcpReading {
             voltage,
             current,
@@ -180,46 +343,81 @@ where
         })
     }
 
-    async fn configure(&mut self, ina: &mut INA3221Async<SharedI2cDevice>) -> Result<(), VcpError> {
-        // Set operating mode to continuous
-        ina.set_mode(OperatingMode::Continuous).await.map_err(|e| {
-            log::error!("INA3221 set mode error: {:?}", defmt_or_log::Debug2Format(&e));
-            VcpError::I2c
-        })?;
+    /// Programs the alert limits. The disabled limits are set to the largest value, so they never fire.
+    async fn configure_alerts(&mut self) -> Result<(), VcpError> {
+        for channel in 0..self.channel_count as ChannelNum {
+            let limits = self.config.limits[channel as usize];
+            let calibration = self.config.calibration[channel as usize];
+            let critical = limits
+                .critical_current
+                .map_or(f32::MAX, |current| calibration.shunt_voltage(current));
+            let warning = limits
+                .warning_current
+                .map_or(f32::MAX, |current| calibration.shunt_voltage(current));
+            self.sensor.set_alert_limits(channel, critical, warning).await?;
+        }
 
-        // Enable selected channels
-        for (i, enable) in self.config.enabled_channels.iter().enumerate() {
-            ina.set_channel_enabled(i as u8, *enable).await.map_err(|e| {
-                log::error!(
-                    "INA3221 set channel {} enabled error: {:?}",
-                    i,
-                    defmt_or_log::Debug2Format(&e)
-                );
-                VcpError::I2c
-            })?;
+        match &self.config.summation_limit {
+            Some(summation_limit) => {
+                // The shunts are required to be equal, so any selected one converts the current
+                let limit = (0..self.channel_count)
+                    .find(|channel| summation_limit.channels[*channel])
+                    .map_or(f32::MAX, |channel| {
+                        self.config.calibration[channel].shunt_voltage(summation_limit.max_current)
+                    });
+                self.sensor.set_summation_limit(limit, summation_limit.channels).await
+            }
+            None => self.sensor.set_summation_limit(f32::MAX, [false; VCP_CHANNELS]).await,
         }
+    }
 
-        if let Some(power_valid_limit) = &self.config.global_pv_limit {
-            ina.set_power_valid_limits(
-                Voltage::from_micro_volts((power_valid_limit.lower_voltage * 1_000_000.0) as i32),
-                Voltage::from_micro_volts((power_valid_limit.upper_voltage * 1_000_000.0) as i32),
-            )
-            .await
-            .map_err(|e| {
-                log::error!(
-                    "INA3221 set power valid limits error: {:?}",
-                    defmt_or_log::Debug2Format(&e)
-                );
-                VcpError::I2c
-            })?;
+    /// Reads the latched alert flags and reports the alerts. Returns false if the reading failed.
+    async fn poll_alerts(&mut self) -> bool {
+        let flags = match with_timeout(HARDWARE_RESPONSE_TIMEOUT_MS.ms(), self.sensor.read_alert_flags()).await {
+            Err(_) => {
+                log::error!("Timeout reading alert flags");
+                self.publish_error(VcpError::Timeout);
+                return false;
+            }
+            Ok(Err(e)) => {
+                log::error!("Error reading alert flags: {:?}", e);
+                self.publish_error(e);
+                return false;
+            }
+            Ok(Ok(flags)) => flags,
+        };
+
+        self.alert_flags.lock(|latest| latest.set(flags));
+        if flags.any_alert() {
+            log::warn!("INA3221 alert: {}", flags);
+            self.alert_publisher.publish_immediate(flags);
         }
-        Ok(())
+        true
+    }
+
+    /// Programs the averaging and the conversion times of the sampling configuration
+    async fn apply_sampling(&mut self) -> Result<(), VcpError> {
+        let sampling = self.config.sampling;
+        let enabled_channels = self.config.enabled_channels.iter().filter(|enabled| **enabled).count() as u32;
+        let update_period_us = sampling.update_period_us(enabled_channels);
+        if update_period_us > sampling.poll_period_ms * 1000 {
+            log::warn!(
+                "VCP poll period {} ms is shorter than the sensor update period {} us",
+                sampling.poll_period_ms,
+                update_period_us
+            );
+        }
+        self.sensor.set_sampling(&sampling).await
+    }
+
+    fn poll_period(&self) -> embassy_time::Duration {
+        (self.config.sampling.poll_period_ms as u64).ms()
     }
 
-    fn handle_command(&mut self, _: &mut INA3221Async<SharedI2cDevice>, command: VcpCommand) {
+    async fn handle_command(&mut self, command: VcpCommand) {
         match command {
             VcpCommand::EnableChannel(channel) => {
-                if (channel as usize) < self.config.enabled_channels.len() {
+                if (channel as usize) < self.channel_count {
                     self.config.enabled_channels[channel as usize] = true;
                     log::info!("Enabled channel {}", channel);
                 } else {
@@ -227,7 +425,9 @@ where
                 }
             }
             VcpCommand::DisableChannel(channel) => {
-                if (channel as usize) < self.config.enabled_channels.len() {
+                if self.config.pinned_channels.get(channel as usize) == Some(&true) {
+                    log::info!("Channel {} is pinned, keeping it enabled", channel);
+                } else if (channel as usize) < self.channel_count {
                     self.config.enabled_channels[channel as usize] = false;
                     log::info!("Disabled channel {}", channel);
                 } else {
@@ -235,72 +435,583 @@ where
                 }
             }
             VcpCommand::EnableAllChannels => {
-                for ch in 0u8..3u8 {
-                    self.config.enabled_channels[ch as usize] = true;
-                }
+                self.config.enabled_channels[..self.channel_count].fill(true);
                 log::info!("Enabled all channels");
             }
             VcpCommand::DisableAllChannels => {
-                for ch in 0u8..3u8 {
-                    self.config.enabled_channels[ch as usize] = false;
+                self.config.enabled_channels = self.config.pinned_channels;
+                log::info!("Disabled all channels but the pinned ones");
+            }
+            VcpCommand::ResetEnergyTrip(channel) => {
+                self.energy.reset_trip(channel as usize);
+                self.publish_energy();
+                log::info!("Reset energy trip counters of channel {}", channel);
+            }
+            VcpCommand::SetVoltageLimits(channel, min_voltage, max_voltage) => {
+                if let Some(limits) = self.config.limits.get_mut(channel as usize) {
+                    limits.min_voltage = min_voltage;
+                    limits.max_voltage = max_voltage;
+                    log::info!("Channel {} voltage limits {} - {} V", channel, min_voltage, max_voltage);
+                }
+            }
+            VcpCommand::SetCalibration(channel, calibration) => {
+                if (channel as usize) >= self.channel_count || !calibration.is_valid() {
+                    log::warn!("Invalid calibration of channel {}", channel);
+                    return;
                 }
-                log::info!("Disabled all channels");
+                self.config.calibration[channel as usize] = calibration;
+                // The alert limits are shunt voltages, they follow the new shunt and current correction
+                match self.configure_sensor().await {
+                    Ok(()) => log::info!("Applied calibration of channel {}", channel),
+                    Err(e) => self.publish_error(e),
+                }
+            }
+            VcpCommand::SetSampling(sampling) => {
+                self.config.sampling = sampling;
+                self.energy.set_poll_period(sampling.poll_period_ms);
+                match self.apply_sampling().await {
+                    Ok(()) => log::info!("Applied sampling {}", sampling),
+                    Err(e) => {
+                        log::error!("Failed to apply sampling: {:?}", e);
+                        self.publish_error(e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn publish_energy(&self) {
+        self.energy_snapshot.lock(|energy| energy.set(*self.energy.channels()));
+    }
+
+    /// Publishes the reading to the subscribers and as the latest reading of its channel
+    fn publish_reading(&self, reading: VcpReading) {
+        self.reading_publisher.publish_immediate(reading);
+        self.latest[reading.channel as usize].sender().send(reading);
+    }
+
+    fn publish_error(&self, error: VcpError) {
+        // A lagging subscriber loses the oldest errors, the health keeps the count
+        self.error_publisher.publish_immediate(error);
+    }
+
+    /// Configures the sensor, the sampling and the alert limits. Stops at the first failure.
+    async fn configure_sensor(&mut self) -> Result<(), VcpError> {
+        self.sensor.configure(&self.config).await.inspect_err(|e| {
+            log::error!("Failed to configure VCP sensor: {:?}", e);
+        })?;
+        self.apply_sampling().await.inspect_err(|e| {
+            log::error!("Failed to configure VCP sensor sampling: {:?}", e);
+        })?;
+        self.configure_alerts().await.inspect_err(|e| {
+            log::error!("Failed to configure VCP sensor alerts: {:?}", e);
+        })
+    }
+
+    /// Configures the sensor, first clearing the bus and resetting the chip if it is a recovery
+    async fn bring_up(&mut self, reset: bool) -> Result<(), VcpError> {
+        let bring_up = async {
+            if reset {
+                self.sensor.reset().await?;
+            }
+            self.configure_sensor().await
+        };
+        with_timeout(RECOVERY_TIMEOUT_MS.ms(), bring_up)
+            .await
+            .unwrap_or(Err(VcpError::Timeout))
+    }
+
+    /// Resets and configures the sensor again after a run of failures
+    async fn recover(&mut self) {
+        log::warn!(
+            "Recovering VCP sensor after {} errors",
+            self.recovery.status().consecutive_errors
+        );
+        match self.bring_up(true).await {
+            Ok(()) => {
+                log::info!("VCP sensor recovered");
+                self.recovery.on_recovered();
+            }
+            Err(e) => {
+                self.recovery.on_recovery_failed(Instant::now().as_millis());
+                log::error!(
+                    "VCP sensor recovery failed: {:?}, {} attempts",
+                    e,
+                    self.recovery.status().failed_recoveries
+                );
+                self.publish_error(e);
             }
         }
     }
 
-    fn push_event(&mut self, event: VcpSensorsEvents) {
-        if self.event_sender.is_full() {
-            // If data queue is full, clear it to make space for new readings
-            self.event_sender.clear();
+    fn publish_health(&self) {
+        self.health_snapshot.lock(|health| health.set(self.recovery.status()));
+    }
+
+    /// Reads all the enabled channels and publishes the readings, the errors and the alerts
+    async fn poll(&mut self) {
+        let mut failed = false;
+        for ch in 0..self.channel_count as ChannelNum {
+            if !self.config.enabled_channels[ch as usize] {
+                continue;
+            }
+            let reading = with_timeout(HARDWARE_RESPONSE_TIMEOUT_MS.ms(), self.read_channel(ch)).await;
+            match reading {
+                Err(_) => {
+                    log::error!("Timeout reading channel {}", ch);
+                    failed = true;
+                    self.publish_error(VcpError::Timeout);
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    log::error!("Error reading channel {}: {:?}", ch, e);
+                    failed = true;
+                    self.publish_error(e);
+                    continue;
+                }
+                Ok(Ok(reading)) => {
+                    self.energy.update(
+                        ch as usize,
+                        reading.voltage.value(),
+                        reading.current.value(),
+                        Instant::now().as_millis(),
+                    );
+                    self.publish_reading(reading);
+                }
+            };
+        }
+        self.publish_energy();
+        let alerts_read = self.poll_alerts().await;
+        if failed || !alerts_read {
+            self.recovery.on_error();
+        } else {
+            self.recovery.on_success();
+        }
+    }
+
+    /// Recovers the sensor if it has failed long enough, then polls it unless it is offline
+    async fn poll_or_recover(&mut self) {
+        if self.recovery.recovery_due(Instant::now().as_millis()) {
+            self.recover().await;
         }
-        if self.event_sender.try_send(event).is_err() {
-            log::error!("Failed to send VCP event");
+        if !self.recovery.is_offline() {
+            self.poll().await;
         }
+        self.publish_health();
     }
 
     pub async fn run(&mut self) -> ! {
-        let i2c_dev = self.i2c_dev.take().expect("I2C device already taken");
-        // Initialize the sensors here using self.i2c_dev
-        let mut ina: INA3221Async<SharedI2cDevice> = INA3221Async::new(i2c_dev, 0x40);
-
-        // Configure the INA3221
-        if let Err(e) = self.configure(&mut ina).await {
-            log::error!("Failed to configure INA3221: {:?}", e);
-            self.event_sender.send(VcpSensorsEvents::Error(e)).await;
+        if let Err(e) = self.bring_up(false).await {
+            // Retried with a reset after the back-off
+            self.recovery.on_recovery_failed(Instant::now().as_millis());
+            self.publish_error(e);
         }
+        self.publish_health();
 
-        let mut ticker = Ticker::every(POLL_TIMEOUT_MS.ms());
+        let mut ticker = Ticker::every(self.poll_period());
 
         loop {
             match select::select(self.command_sender.receive(), ticker.next()).await {
                 select::Either::First(command) => {
                     // Handle incoming command
                     log::debug!("Handled VCP command: {}", command);
-                    self.handle_command(&mut ina, command);
+                    let resync = matches!(command, VcpCommand::SetSampling(_));
+                    self.handle_command(command).await;
+                    if resync {
+                        // Start the new poll period from now
+                        ticker = Ticker::every(self.poll_period());
+                    }
                 }
                 select::Either::Second(_) => {}
             }
-            // The sensor reading and processing logic here
-            for ch in 0u8..3u8 {
-                if !self.config.enabled_channels[ch as usize] {
-                    continue;
-                }
-                let reading = with_timeout(HARDWARE_RESPONSE_TIMEOUT_MS.ms(), self.read_channel(&ina, ch)).await;
-                match reading {
-                    Err(_) => {
-                        log::error!("Timeout reading channel {}", ch);
-                        self.push_event(VcpSensorsEvents::Error(VcpError::Timeout));
-                        continue;
-                    }
-                    Ok(Err(e)) => {
-                        log::error!("Error reading channel {}: {:?}", ch, e);
-                        self.push_event(VcpSensorsEvents::Error(e));
-                        continue;
-                    }
-                    Ok(Ok(reading)) => self.push_event(VcpSensorsEvents::Reading(reading)),
-                };
-            }
+            self.poll_or_recover().await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcp_sensors::data_model::CHANNELS_PER_CHIP;
+    use crate::vcp_sensors::scripted_sensor::{ScriptStep, ScriptedSensor};
+    use embassy_futures::block_on;
+    use embassy_sync::pubsub::WaitResult;
+
+    fn config() -> VcpConfig {
+        VcpConfig::default().with_limits(0, VcpLimits::new(11.0, 15.0, -5.0, 5.0))
+    }
+
+    fn reading_channels(readings: &mut VcpReadingSubscriber<'_>) -> heapless::Vec<ChannelNum, READINGS_QUEUE_SIZE> {
+        let mut channels = heapless::Vec::new();
+        while let Some(reading) = readings.try_next_message_pure() {
+            channels.push(reading.channel).unwrap();
+        }
+        channels.sort_unstable();
+        channels
+    }
+
+    fn drain_errors(errors: &mut VcpErrorSubscriber<'_>) -> heapless::Vec<VcpError, ERRORS_QUEUE_SIZE> {
+        let mut drained = heapless::Vec::new();
+        while let Some(error) = errors.try_next_message_pure() {
+            drained.push(error).unwrap();
+        }
+        drained
+    }
+
+    #[test]
+    fn classifies_the_voltage_against_the_limits() {
+        let sensor = ScriptedSensor::new().with_script(
+            0,
+            &[
+                ScriptStep::Sample(10.5, 0.0),
+                ScriptStep::Sample(12.0, 0.0),
+                ScriptStep::Sample(15.5, 0.0),
+            ],
+        );
+        let mut state = VcpSensorsState::new();
+        let (mut runner, _control) = VcpSensorsService::new(sensor, &mut state, config());
+
+        block_on(async {
+            assert!(matches!(runner.read_bus_voltage(0).await, Ok(VcpState::Low(v)) if v == 10.5));
+            assert!(matches!(runner.read_bus_voltage(0).await, Ok(VcpState::Normal(v)) if v == 12.0));
+            assert!(matches!(runner.read_bus_voltage(0).await, Ok(VcpState::High(v)) if v == 15.5));
+        });
+    }
+
+    #[test]
+    fn classifies_the_current_from_the_shunt_voltage() {
+        let sensor = ScriptedSensor::new().with_script(
+            0,
+            &[
+                ScriptStep::Sample(12.0, -6.0),
+                ScriptStep::Sample(12.0, 1.0),
+                ScriptStep::Sample(12.0, 6.0),
+            ],
+        );
+        let mut state = VcpSensorsState::new();
+        let (mut runner, _control) = VcpSensorsService::new(sensor, &mut state, config());
+
+        block_on(async {
+            let current = runner.read_channel(0).await.unwrap().current;
+            assert!(current.is_low() && (current.value() + 6.0).abs() < 1e-4);
+            let current = runner.read_channel(0).await.unwrap().current;
+            assert!(current.is_normal() && (current.value() - 1.0).abs() < 1e-4);
+            let current = runner.read_channel(0).await.unwrap().current;
+            assert!(current.is_high() && (current.value() - 6.0).abs() < 1e-4);
+        });
+    }
+
+    #[test]
+    fn configures_the_sensor_and_the_alert_limits() {
+        let config = config().with_limits(
+            0,
+            VcpLimits::new(11.0, 15.0, -5.0, 5.0).with_critical_current(Some(3.0)),
+        );
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(ScriptedSensor::new(), &mut state, config);
+        let mut errors = control.subscribe_errors().unwrap();
+
+        block_on(runner.configure_sensor()).unwrap();
+        assert!(runner.sensor.configured);
+        assert!(runner.sensor.sampling == Some(VcpSampling::new()));
+        let (critical, warning) = runner.sensor.alert_limits[0];
+        assert!((critical - 0.3).abs() < 1e-6);
+        assert_eq!(warning, f32::MAX);
+        assert_eq!(runner.sensor.alert_limits[1], (f32::MAX, f32::MAX));
+        assert!(drain_errors(&mut errors).is_empty());
+    }
+
+    #[test]
+    fn poll_publishes_the_readings_of_the_enabled_channels() {
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::Sample(12.5, 1.0)])
+            .with_script(1, &[ScriptStep::Sample(18.0, 2.0)])
+            .with_script(2, &[ScriptStep::Sample(5.0, 0.5)]);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config().with_enabled(1, false));
+        let mut subscriber = control.subscribe_readings().unwrap();
+
+        block_on(runner.poll());
+
+        let first = subscriber.try_next_message_pure().unwrap();
+        assert!(first.channel == 0 && first.voltage.is_normal() && first.voltage.value() == 12.5);
+        let second = subscriber.try_next_message_pure().unwrap();
+        assert!(second.channel == 2 && second.voltage.value() == 5.0);
+        assert!(subscriber.try_next_message_pure().is_none());
+        assert!(
+            control
+                .latest_reading(0)
+                .is_some_and(|reading| reading.voltage.value() == 12.5)
+        );
+        assert!(control.latest_reading(1).is_none());
+    }
+
+    #[test]
+    fn applies_the_calibration_to_the_readings() {
+        let mut calibration = [VcpChannelCalibration::new(); VCP_CHANNELS];
+        calibration[0].voltage = VcpLinearCalibration::new(1.01, 0.0);
+        calibration[0].current = VcpLinearCalibration::new(1.0, 0.05);
+        let sensor = ScriptedSensor::new().with_script(0, &[ScriptStep::Sample(12.0, 1.0)]);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config().with_calibration(calibration));
+        let mut subscriber = control.subscribe_readings().unwrap();
+        assert!(control.uncalibrated_reading(0).is_none());
+
+        block_on(runner.poll());
+        let reading = subscriber.try_next_message_pure().unwrap();
+        assert!((reading.voltage.value() - 12.12).abs() < 1e-4);
+        assert!((reading.current.value() - 1.05).abs() < 1e-4);
+        let uncalibrated = control.uncalibrated_reading(0).unwrap();
+        assert!((uncalibrated.voltage - 12.0).abs() < 1e-4);
+        assert!((uncalibrated.current - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn new_calibration_reprograms_the_alert_limits() {
+        let config = config().with_limits(
+            0,
+            VcpLimits::new(11.0, 15.0, -5.0, 5.0).with_critical_current(Some(3.0)),
+        );
+        let mut state = VcpSensorsState::new();
+        let (mut runner, _control) = VcpSensorsService::new(ScriptedSensor::new(), &mut state, config);
+
+        let calibration = VcpChannelCalibration::new().with_shunt_resistance(0.05);
+        block_on(runner.handle_command(VcpCommand::SetCalibration(0, calibration)));
+        let (critical, _) = runner.sensor.alert_limits[0];
+        assert!((critical - 0.15).abs() < 1e-6);
+
+        // An invalid calibration is ignored
+        let invalid = VcpChannelCalibration::new().with_shunt_resistance(0.0);
+        block_on(runner.handle_command(VcpCommand::SetCalibration(0, invalid)));
+        assert_eq!(runner.config.shunt_resistance(0), 0.05);
+    }
+
+    #[test]
+    fn polls_the_channels_of_the_second_chip() {
+        let sensor = ScriptedSensor::new()
+            .with_channel_count(2 * CHANNELS_PER_CHIP)
+            .with_script(4, &[ScriptStep::Sample(5.0, 0.0)]);
+        let config = config()
+            .with_enabled(1, false)
+            .with_enabled(2, false)
+            .with_enabled(3, false)
+            .with_enabled(5, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut readings = control.subscribe_readings().unwrap();
+        assert_eq!(control.channel_count(), 6);
+
+        block_on(runner.poll());
+        assert_eq!(reading_channels(&mut readings), [0, 4]);
+        assert!(
+            control
+                .latest_reading(4)
+                .is_some_and(|reading| reading.voltage.value() == 5.0)
+        );
+    }
+
+    #[test]
+    fn channels_of_missing_chips_are_never_polled() {
+        let sensor = ScriptedSensor::new();
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config());
+        let mut readings = control.subscribe_readings().unwrap();
+        assert_eq!(control.channel_count(), 3);
+
+        block_on(runner.handle_command(VcpCommand::EnableChannel(4)));
+        block_on(runner.handle_command(VcpCommand::EnableAllChannels));
+        block_on(runner.poll());
+        assert_eq!(reading_channels(&mut readings), [0, 1, 2]);
+        assert!(control.latest_reading(4).is_none());
+        assert_eq!(runner.sensor.enabled[3..], [false; 3]);
+    }
+
+    #[test]
+    fn pinned_channels_stay_polled() {
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::Sample(12.0, 0.0)])
+            .with_script(2, &[ScriptStep::Sample(5.0, 0.0)]);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config().with_pinned(0));
+        let mut readings = control.subscribe_readings().unwrap();
+
+        block_on(runner.handle_command(VcpCommand::DisableAllChannels));
+        block_on(runner.handle_command(VcpCommand::EnableChannel(2)));
+        block_on(runner.poll());
+        assert_eq!(reading_channels(&mut readings), [0, 2]);
+
+        block_on(runner.handle_command(VcpCommand::DisableChannel(0)));
+        block_on(runner.poll());
+        assert_eq!(reading_channels(&mut readings), [0, 2]);
+    }
+
+    #[test]
+    fn poll_reports_i2c_errors_and_timeouts() {
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::I2cError, ScriptStep::Sample(12.0, 0.0)])
+            .with_script(1, &[ScriptStep::Hang])
+            .with_script(2, &[ScriptStep::Sample(4.0, 0.0)]);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config());
+        let mut readings = control.subscribe_readings().unwrap();
+        let mut errors = control.subscribe_errors().unwrap();
+
+        block_on(runner.poll());
+        assert!(matches!(
+            drain_errors(&mut errors).as_slice(),
+            [VcpError::I2c, VcpError::Timeout]
+        ));
+        assert_eq!(reading_channels(&mut readings), [2]);
+
+        // The channel recovers at the next poll, the hung bus keeps timing out
+        block_on(runner.poll());
+        assert_eq!(reading_channels(&mut readings), [0, 2]);
+        assert!(matches!(drain_errors(&mut errors).as_slice(), [VcpError::Timeout]));
+    }
+
+    #[test]
+    fn poll_publishes_the_latched_alerts() {
+        let mut flags = VcpAlertFlags::new();
+        flags.critical[0] = true;
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::Sample(12.0, 4.0)])
+            .with_alert_flags(Ok(flags));
+        let config = config().with_enabled(1, false).with_enabled(2, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut alerts = control.subscribe_alerts().unwrap();
+        let mut readings = control.subscribe_readings().unwrap();
+
+        block_on(runner.poll());
+
+        assert_eq!(reading_channels(&mut readings), [0]);
+        assert!(control.alert_flags() == flags);
+        assert!(alerts.try_next_message_pure() == Some(flags));
+    }
+
+    #[test]
+    fn failed_alert_reading_keeps_the_latest_flags() {
+        let sensor = ScriptedSensor::new().with_alert_flags(Err(VcpError::I2c));
+        let config = config()
+            .with_enabled(0, false)
+            .with_enabled(1, false)
+            .with_enabled(2, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut errors = control.subscribe_errors().unwrap();
+
+        block_on(runner.poll());
+
+        assert!(matches!(drain_errors(&mut errors).as_slice(), [VcpError::I2c]));
+        assert!(control.alert_flags() == VcpAlertFlags::new());
+    }
+
+    #[test]
+    fn recovers_the_sensor_after_consecutive_failed_polls() {
+        let mut steps = [ScriptStep::I2cError; RECOVER_AFTER_ERRORS as usize + 1];
+        steps[RECOVER_AFTER_ERRORS as usize] = ScriptStep::Sample(12.0, 0.0);
+        let sensor = ScriptedSensor::new().with_script(0, &steps);
+        let config = config().with_enabled(1, false).with_enabled(2, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut readings = control.subscribe_readings().unwrap();
+
+        for _ in 0..RECOVER_AFTER_ERRORS {
+            block_on(runner.poll_or_recover());
+        }
+        assert_eq!(control.health().health, VcpHealth::Degraded);
+        assert_eq!(control.health().consecutive_errors, RECOVER_AFTER_ERRORS);
+        assert_eq!(runner.sensor.resets, 0);
+        assert!(reading_channels(&mut readings).is_empty());
+
+        block_on(runner.poll_or_recover());
+        assert_eq!(runner.sensor.resets, 1);
+        assert!(runner.sensor.configured);
+        let health = control.health();
+        assert_eq!(health.health, VcpHealth::Ok);
+        assert_eq!(health.recoveries, 1);
+        assert_eq!(reading_channels(&mut readings), [0]);
+    }
+
+    #[test]
+    fn failed_recovery_takes_the_sensor_offline() {
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::I2cError])
+            .with_reset_result(Err(VcpError::I2c));
+        let config = config().with_enabled(1, false).with_enabled(2, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut errors = control.subscribe_errors().unwrap();
+
+        for _ in 0..=RECOVER_AFTER_ERRORS {
+            block_on(runner.poll_or_recover());
+        }
+        assert_eq!(runner.sensor.resets, 1);
+        let health = control.health();
+        assert_eq!(health.health, VcpHealth::Offline);
+        assert_eq!(health.failed_recoveries, 1);
+        drain_errors(&mut errors);
+
+        // The offline sensor is left alone until the back-off elapses
+        block_on(runner.poll_or_recover());
+        assert_eq!(runner.sensor.resets, 1);
+        assert!(drain_errors(&mut errors).is_empty());
+    }
+
+    #[test]
+    fn lagging_subscriber_does_not_hold_back_the_others() {
+        let steps: [ScriptStep; READINGS_QUEUE_SIZE + 1] =
+            core::array::from_fn(|step| ScriptStep::Sample(step as f32, 0.0));
+        let sensor = ScriptedSensor::new().with_script(0, &steps);
+        let config = config().with_enabled(1, false).with_enabled(2, false);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config);
+        let mut prompt = control.subscribe_readings().unwrap();
+        let mut lagging = control.subscribe_readings().unwrap();
+
+        for step in 0..steps.len() {
+            block_on(runner.poll());
+            let reading = prompt.try_next_message_pure().unwrap();
+            assert_eq!(reading.voltage.value(), step as f32);
+        }
+
+        // The lagging subscriber loses the oldest reading and gets the rest in order
+        assert!(matches!(lagging.try_next_message(), Some(WaitResult::Lagged(1))));
+        let first = lagging.try_next_message_pure().unwrap();
+        assert_eq!(first.voltage.value(), 1.0);
+        assert_eq!(lagging.available(), READINGS_QUEUE_SIZE as u64 - 1);
+    }
+
+    #[test]
+    fn watchers_get_the_latest_reading_of_their_channel() {
+        let sensor = ScriptedSensor::new()
+            .with_script(0, &[ScriptStep::Sample(12.0, 0.0), ScriptStep::Sample(12.5, 0.0)])
+            .with_script(1, &[ScriptStep::Sample(5.0, 0.0)]);
+        let mut state = VcpSensorsState::new();
+        let (mut runner, control) = VcpSensorsService::new(sensor, &mut state, config());
+        let mut first = control.watch_reading(0).unwrap();
+        let mut second = control.watch_reading(0).unwrap();
+        assert!(control.watch_reading(VCP_CHANNELS as ChannelNum).is_none());
+
+        block_on(runner.poll());
+        assert!(
+            first
+                .try_changed()
+                .is_some_and(|reading| reading.voltage.value() == 12.0)
+        );
+
+        // The readings the watcher has not taken yet are coalesced into the latest one
+        block_on(runner.poll());
+        assert!(
+            first
+                .try_changed()
+                .is_some_and(|reading| reading.voltage.value() == 12.5)
+        );
+        assert!(
+            second
+                .try_changed()
+                .is_some_and(|reading| reading.voltage.value() == 12.5)
+        );
+        assert!(first.try_changed().is_none());
+    }
+}
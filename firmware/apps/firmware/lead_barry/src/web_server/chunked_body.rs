@@ -0,0 +1,78 @@
+use core::fmt::Write as _;
+
+use nanofish::{Error, HttpWriteSocket};
+
+/// The size of the chunks the body is sent in
+const CHUNK_SIZE: usize = 1024;
+
+/// A response body of unknown length sent with `Transfer-Encoding: chunked`. Only one chunk is
+/// buffered at a time, so the body does not have to fit into the worker buffer. The response closes
+/// the connection, so a body dropped without [`ChunkedBody::finish`] ends without the final chunk
+/// and the client sees it as incomplete.
+pub struct ChunkedBody<'s, HttpSocket: HttpWriteSocket> {
+    http_socket: &'s mut HttpSocket,
+    buffer: [u8; CHUNK_SIZE],
+    len: usize,
+}
+
+impl<'s, HttpSocket: HttpWriteSocket> ChunkedBody<'s, HttpSocket> {
+    /// Sends the headers of a successful response offering the body as a file download
+    pub async fn start(
+        http_socket: &'s mut HttpSocket,
+        content_type: &str,
+        file_name: &str,
+    ) -> Result<ChunkedBody<'s, HttpSocket>, Error> {
+        // The response builder only sends bodies of a known length, so the headers are written here
+        for part in [
+            "HTTP/1.1 200 OK\r\nContent-Type: ",
+            content_type,
+            "\r\nContent-Disposition: attachment; filename=\"",
+            file_name,
+            "\"\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n",
+        ] {
+            http_socket.write_all(part.as_bytes()).await?;
+        }
+
+        Ok(Self {
+            http_socket,
+            buffer: [0; CHUNK_SIZE],
+            len: 0,
+        })
+    }
+
+    /// Appends the data to the body, sending the chunks as they fill up
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let count = data.len().min(CHUNK_SIZE - self.len);
+            self.buffer[self.len..self.len + count].copy_from_slice(&data[..count]);
+            self.len += count;
+            data = &data[count..];
+            if self.len == CHUNK_SIZE {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the rest of the body and the final empty chunk
+    pub async fn finish(mut self) -> Result<(), Error> {
+        self.flush().await?;
+        self.http_socket.write_all(b"0\r\n\r\n").await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if self.len == 0 {
+            // An empty chunk would end the body
+            return Ok(());
+        }
+
+        let mut size_line = heapless::String::<12>::new();
+        // The hexadecimal size of a chunk always fits
+        let _ = write!(size_line, "{:X}\r\n", self.len);
+        self.http_socket.write_all(size_line.as_bytes()).await?;
+        self.http_socket.write_all(&self.buffer[..self.len]).await?;
+        self.http_socket.write_all(b"\r\n").await?;
+        self.len = 0;
+        Ok(())
+    }
+}
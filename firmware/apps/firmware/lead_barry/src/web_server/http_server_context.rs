@@ -1,7 +1,19 @@
 use embassy_executor::Spawner;
 
 use crate::{
-    configuration::ConfigurationStorage, global_types::I2c0Device, rtc::RtcDs3231Ref, shared_resources::SharedResources,
+    battery::{BatteryMonitorControl, CycleControl, HealthControl},
+    charger::{ChargerControl, EqualizationControl},
+    configuration::{ChannelMap, ConfigurationStorage},
+    efuse::EFuseControl,
+    global_types::RtcI2cDevice,
+    history::HistoryControl,
+    lvd::LvdControl,
+    outputs::OutputsControl,
+    poe_watchdog::PoeWatchdogControl,
+    rtc::RtcDs3231Ref,
+    shared_resources::SharedResources,
+    temperature::TemperatureControl,
+    vcp_sensors::VcpControl,
 };
 
 pub struct HttpServerContext {
@@ -21,7 +33,7 @@ impl HttpServerContext {
     }
 
     #[inline(always)]
-    pub const fn configuration_storage(&self) -> &'static ConfigurationStorage<'static> {
+    pub const fn configuration_storage(&self) -> &'static ConfigurationStorage {
         self.shared.configuration_storage
     }
 
@@ -29,7 +41,59 @@ impl HttpServerContext {
         self.shared
     }
 
-    pub const fn rtc(&self) -> &'static RtcDs3231Ref<I2c0Device<'static>> {
+    pub const fn vcp_control(&self) -> &'static VcpControl<'static> {
+        self.shared.vcp_control
+    }
+
+    pub const fn history_control(&self) -> &'static HistoryControl<'static> {
+        self.shared.history_control
+    }
+
+    pub const fn rtc(&self) -> &'static RtcDs3231Ref<RtcI2cDevice> {
         self.shared.rtc
     }
+
+    pub const fn battery_control(&self) -> &'static BatteryMonitorControl<'static> {
+        self.shared.battery_control
+    }
+
+    pub const fn health_control(&self) -> &'static HealthControl<'static> {
+        self.shared.health_control
+    }
+
+    pub const fn cycle_control(&self) -> &'static CycleControl<'static> {
+        self.shared.cycle_control
+    }
+
+    pub const fn equalization_control(&self) -> &'static EqualizationControl<'static> {
+        self.shared.equalization_control
+    }
+
+    pub const fn lvd_control(&self) -> &'static LvdControl<'static> {
+        self.shared.lvd_control
+    }
+
+    pub const fn outputs_control(&self) -> &'static OutputsControl<'static> {
+        self.shared.outputs_control
+    }
+
+    pub const fn poe_watchdog_control(&self) -> &'static PoeWatchdogControl<'static> {
+        self.shared.poe_watchdog_control
+    }
+
+    pub const fn charger_control(&self) -> &'static ChargerControl<'static> {
+        self.shared.charger_control
+    }
+
+    pub const fn temperature_control(&self) -> &'static TemperatureControl<'static> {
+        self.shared.temperature_control
+    }
+
+    pub const fn channel_map(&self) -> &'static ChannelMap {
+        self.shared.channel_map
+    }
+
+    pub const fn efuse_control(&self) -> &'static EFuseControl<'static> {
+        self.shared.efuse_control
+    }
 }
@@ -1,4 +1,6 @@
+mod chunked_body;
 mod http_server_context;
+mod telemetry;
 
 use core::mem::MaybeUninit;
 use core::str::FromStr;
@@ -6,20 +8,38 @@ use core::str::FromStr;
 use bump_into::BumpInto;
 use defmt_or_log::{self as log};
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
 use embassy_net::Stack;
+use embassy_time::{Instant, Timer};
 use nanofish::{
     Error, HttpHandler, HttpMethod, HttpRequest, HttpResponseBuilder, HttpServer, HttpWriteSocket, ServerTimeouts,
     SocketBuffers, StatusCode, WebSocket, WebSocketRead,
 };
 use prefix_arena::PrefixArena;
 
+use crate::alarms::AlarmSources;
 use crate::board::*;
-use crate::configuration::WiFiSettings;
+use crate::configuration::{
+    BatteryChemistry, BatteryProfile, ChannelMap, EFuseSettings, EqualizationSettings, HealthSettings, LvdSettings,
+    OutputsSettings, PoeWatchdogSettings, TemperatureSettings, VcpSettings, WiFiSettings,
+};
+use crate::efuse::FuseReset;
+use crate::history::{HistoryCursor, HistoryQuery, HistoryRecord, HistoryResolution};
+use crate::outputs::OutputChange;
 use crate::rtc::*;
 use crate::shared_resources::SharedResources;
+use crate::vcp_sensors::{
+    ChannelNum, EnergyTripReset, VCP_CHANNELS, VcpCalibrationChange, VcpCalibrationError, VcpCalibrationRequest,
+    VcpChannelCalibration, VcpReadingValues,
+};
 use crate::ws2812b_led_controller::*;
 use crate::{reset, units::TimeExt as _};
+use chunked_body::ChunkedBody;
 use http_server_context::HttpServerContext;
+use telemetry::{
+    MAX_TELEMETRY_SESSIONS, TELEMETRY_IDLE_TIMEOUT_S, TelemetryFrame, TelemetryRequest, TelemetrySession,
+    TelemetrySubscription,
+};
 
 // Get version from Cargo.toml at compile time
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -35,6 +55,16 @@ const SOCKET_TX_SIZE: usize = 256;
 // Maximum request/response size for a single HTTP server worker
 const WORKER_BUFFER_SIZE: usize = 8192;
 
+// Number of history records read from the flash at a time
+const HISTORY_READ_BATCH: usize = 16;
+
+// Maximum size of a telemetry frame and of a subscription message
+const TELEMETRY_FRAME_SIZE: usize = 2048;
+const TELEMETRY_REQUEST_SIZE: usize = 128;
+
+// Maximum size of the JSON body of an error or a confirmation response
+const MESSAGE_BODY_SIZE: usize = 128;
+
 // Port for the HTTP server to listen on
 const HTTP_SERVER_PORT: u16 = 80;
 
@@ -97,16 +127,12 @@ impl<'a> HttpWebAPIHandler<'a> {
 
     async fn api_version<HttpSocket: HttpWriteSocket>(
         &mut self,
-        _allocator: &mut PrefixArena<'_>,
+        allocator: &mut PrefixArena<'_>,
         _request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
         log::debug!("Serving version info");
-        HttpResponseBuilder::new(http_socket)
-            .with_status(StatusCode::Ok)
-            .await?
-            .with_plain_text_body(VERSION)
-            .await
+        send_serialized_type(allocator, http_socket, &VersionBody { version: VERSION }).await
     }
 
     async fn api_reboot<HttpSocket: HttpWriteSocket>(
@@ -118,11 +144,7 @@ impl<'a> HttpWebAPIHandler<'a> {
         log::info!("Serving reboot request");
         reset::deferred_system_reset(self.context.spawner(), 1.s());
         // The reset function does not return, but we provide a response for completeness
-        HttpResponseBuilder::new(http_socket)
-            .with_status(StatusCode::Ok)
-            .await?
-            .with_plain_text_body("System is resetting...")
-            .await
+        send_ok(http_socket, "System is resetting...").await
     }
 
     async fn api_wifi_config<HttpSocket: HttpWriteSocket>(
@@ -156,7 +178,9 @@ impl<'a> HttpWebAPIHandler<'a> {
     ) -> Result<(), Error> {
         log::debug!("Serving set configuration request");
         //TODO: Implement data integrity checks
-        let mut wifi_settings: WiFiSettings = from_request(request)?;
+        let Some(mut wifi_settings) = from_request::<WiFiSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
         if wifi_settings.password.is_none() {
             // Preserve existing password if not provided
             let current_settings = self
@@ -175,48 +199,56 @@ impl<'a> HttpWebAPIHandler<'a> {
             })
             .await;
         match self.context.configuration_storage().save().await {
-            Ok(_) => {
-                HttpResponseBuilder::new(http_socket)
-                    .with_status(StatusCode::Ok)
-                    .await?
-                    .with_plain_text_body("WiFi configuration updated")
-                    .await
-            }
+            Ok(_) => send_ok(http_socket, "WiFi configuration updated").await,
             Err(e) => {
                 log::error!("Failed to save configuration: {:?}", e);
-                HttpResponseBuilder::new(http_socket)
-                    .with_status(StatusCode::InternalServerError)
-                    .await?
-                    .with_plain_text_body("Failed to save WiFi configuration")
-                    .await
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save WiFi configuration",
+                )
+                .await
             }
         }
     }
 
     async fn api_date_time<HttpSocket: HttpWriteSocket>(
         &mut self,
-        _allocator: &mut PrefixArena<'_>,
+        allocator: &mut PrefixArena<'_>,
         _request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
         log::debug!("Serving date_time request");
 
         let mut rtc = self.context.rtc().lock().await;
-        let datetime = rtc.datetime().await.map_err(|e| {
-            log::error!("RTC datetime read error: {}", e);
-            Error::ServerError
-        })?;
+        let datetime = match rtc.datetime().await {
+            Ok(datetime) => datetime,
+            Err(e) => {
+                log::error!("RTC datetime read error: {}", e);
+                return send_error(http_socket, StatusCode::InternalServerError, "Failed to read the RTC").await;
+            }
+        };
 
         let mut date_time_str = heapless::String::<64>::new();
 
         //ISO 8601 format could be used as well
         //"1995-12-17T03:24:00Z"
-        core::fmt::write(&mut date_time_str, format_args!("{}", datetime)).map_err(|_| Error::ServerError)?;
-        HttpResponseBuilder::new(http_socket)
-            .with_status(StatusCode::Ok)
-            .await?
-            .with_plain_text_body(&date_time_str)
-            .await
+        if core::fmt::write(&mut date_time_str, format_args!("{}", datetime)).is_err() {
+            return send_error(
+                http_socket,
+                StatusCode::InternalServerError,
+                "Failed to format the date and time",
+            )
+            .await;
+        }
+        send_serialized_type(
+            allocator,
+            http_socket,
+            &DateTimeBody {
+                date_time: &date_time_str,
+            },
+        )
+        .await
     }
 
     async fn api_set_date_time<HttpSocket: HttpWriteSocket>(
@@ -226,153 +258,1282 @@ impl<'a> HttpWebAPIHandler<'a> {
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
         log::debug!("Serving set_date_time request");
-        let date_time_str = core::str::from_utf8(request.body).map_err(|_| {
+        let Ok(date_time_str) = core::str::from_utf8(request.body) else {
             log::error!("Invalid UTF-8 in request body");
-            Error::ServerError
-        })?;
-        let date_time = NaiveDateTime::from_str(date_time_str).map_err(|_| {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        let Ok(date_time) = NaiveDateTime::from_str(date_time_str) else {
             log::error!("Invalid date time format: {}", date_time_str);
-            Error::ServerError
-        })?;
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid date and time").await;
+        };
 
         let mut rtc = self.context.rtc().lock().await;
-        rtc.set_datetime(&date_time).await.map_err(|e| {
+        if let Err(e) = rtc.set_datetime(&date_time).await {
             log::error!("RTC datetime set error: {}", e);
-            Error::ServerError
-        })?;
+            return send_error(http_socket, StatusCode::InternalServerError, "Failed to set the RTC").await;
+        }
 
-        HttpResponseBuilder::new(http_socket)
-            .with_status(StatusCode::Ok)
-            .await?
-            .with_plain_text_body("Date and time updated")
-            .await
+        send_ok(http_socket, "Date and time updated").await
     }
 
-    async fn api_not_found<HttpSocket: HttpWriteSocket>(
+    async fn api_battery<HttpSocket: HttpWriteSocket>(
         &mut self,
-        _allocator: &mut PrefixArena<'_>,
+        allocator: &mut PrefixArena<'_>,
         _request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
-        HttpResponseBuilder::new(http_socket)
-            .with_status(StatusCode::NotFound)
-            .await?
-            .with_plain_text_body("Not Found")
+        log::debug!("Serving battery request");
+        let status = self.context.battery_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_battery_profile<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving battery profile request");
+        let battery_profile = self
+            .context
+            .configuration_storage()
+            .get_settings()
             .await
+            .battery_profile;
+        send_serialized_type(allocator, http_socket, &battery_profile).await
     }
 
-    async fn handle_rest_api<HttpSocket: HttpWriteSocket>(
+    async fn api_battery_profile_presets<HttpSocket: HttpWriteSocket>(
         &mut self,
         allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving battery profile presets request");
+        let presets = BatteryChemistry::ALL.map(BatteryProfile::for_chemistry);
+        send_serialized_type(allocator, http_socket, &presets).await
+    }
+
+    async fn api_set_battery_profile<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
         request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
-        api: &str,
     ) -> Result<(), Error> {
-        match (request.method, api) {
-            (HttpMethod::GET, "version") => self.api_version(allocator, request, http_socket).await,
-            (HttpMethod::GET, "reboot") => self.api_reboot(allocator, request, http_socket).await,
-            (HttpMethod::GET, "wifi_config") => self.api_wifi_config(allocator, request, http_socket).await,
-            (HttpMethod::POST, "set_wifi_config") => self.api_set_wifi_config(allocator, request, http_socket).await,
-            (HttpMethod::GET, "date_time") => self.api_date_time(allocator, request, http_socket).await,
-            (HttpMethod::POST, "set_date_time") => self.api_set_date_time(allocator, request, http_socket).await,
-            _ => self.api_not_found(allocator, request, http_socket).await,
+        log::debug!("Serving set battery profile request");
+        let Some(battery_profile) = from_request::<BatteryProfile>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !battery_profile.is_valid() {
+            log::error!("Invalid battery profile: {:?}", battery_profile);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid battery profile").await;
+        }
+
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.battery_profile = battery_profile;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Battery profile updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save battery profile",
+                )
+                .await
+            }
         }
     }
 
-    async fn handle_request_impl<HttpSocket: HttpWriteSocket>(
+    async fn api_lvd<HttpSocket: HttpWriteSocket>(
         &mut self,
         allocator: &mut PrefixArena<'_>,
-        request: &HttpRequest<'_>,
+        _request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
-        if request.path == "/" {
-            // Show main page
-            log::debug!("Serving main configuration page");
+        log::debug!("Serving LVD request");
+        let status = self.context.lvd_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
 
-            return HttpResponseBuilder::new(http_socket)
-                .with_status(StatusCode::Ok)
-                .await?
-                .with_compressed_page(MAIN_CONFIGURATION_HTML_GZ)
-                .await;
-        }
+    async fn api_lvd_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving LVD settings request");
+        let lvd_settings = self.context.configuration_storage().get_settings().await.lvd_settings;
+        send_serialized_type(allocator, http_socket, &lvd_settings).await
+    }
 
-        let Some(api) = request.path.strip_prefix("/api/") else {
-            return HttpResponseBuilder::new(http_socket)
-                .with_status(StatusCode::NotFound)
-                .await?
-                .with_plain_text_body("Not Found")
-                .await;
+    async fn api_set_lvd_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set LVD settings request");
+        let Some(lvd_settings) = from_request::<LvdSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
         };
+        let disconnect_voltage = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .battery_profile
+            .lvd_voltage;
+        if !lvd_settings.is_valid(disconnect_voltage) {
+            log::error!("Invalid LVD settings: {:?}", lvd_settings);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid LVD settings").await;
+        }
 
-        if request.method == HttpMethod::OPTIONS {
-            log::debug!("Serving {} preflight request", api);
-            trace_headers(request);
-            //TODO: Implement more strict header checking
-            return HttpResponseBuilder::new(http_socket).preflight_response().await;
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.lvd_settings = lvd_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "LVD settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save LVD settings",
+                )
+                .await
+            }
         }
+    }
 
-        self.handle_rest_api(allocator, request, http_socket, api).await
+    async fn api_outputs<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving outputs request");
+        let statuses = self.context.outputs_control().statuses().await;
+        send_serialized_type(allocator, http_socket, &statuses).await
     }
 
-    async fn handle_websocket_connection_impl<'h>(
+    async fn api_set_output<HttpSocket: HttpWriteSocket>(
         &mut self,
+        allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set output request");
+        let Some(change) = from_request::<OutputChange>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        match self.context.outputs_control().apply_change(change).await {
+            Ok(status) => send_serialized_type(allocator, http_socket, &status).await,
+            Err(e) => {
+                log::error!("Invalid output change {:?}: {:?}", change, e);
+                send_error(http_socket, StatusCode::BadRequest, "Unsupported output voltage").await
+            }
+        }
+    }
+
+    async fn api_output_defaults<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
         _request: &HttpRequest<'_>,
-        web_socket: &mut WebSocket<'h, '_>,
-    ) -> Result<(), ()> {
-        let mut buffer = [0u8; 128];
-        let len = web_socket.read(&mut buffer).await.map_err(|_| ())?;
-        let str = core::str::from_utf8(&buffer[..len]).unwrap_or("<invalid utf-8>");
-        log::info!("Received WebSocket frame: {}", str);
-        web_socket.write_all(b"Hello from WebSocket!").await.map_err(|_| ())?;
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving output defaults request");
+        let outputs_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .outputs_settings;
+        send_serialized_type(allocator, http_socket, &outputs_settings).await
+    }
 
-        web_socket.close().await.map_err(|_| ())?;
+    async fn api_set_output_defaults<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set output defaults request");
+        let Some(outputs_settings) = from_request::<OutputsSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !outputs_settings.is_valid() {
+            log::error!("Invalid output defaults: {:?}", outputs_settings);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid output defaults").await;
+        }
 
-        Ok(()) // Close the connection immediately
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.outputs_settings = outputs_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Output defaults updated. They are applied at boot.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save output defaults",
+                )
+                .await
+            }
+        }
     }
-}
 
-fn trace_headers(request: &HttpRequest<'_>) {
-    log::debug!("Request header");
-    for header in request.headers.iter() {
-        log::debug!(" - : {}: {}", header.name, header.value);
+    async fn api_poe_watchdog<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving PoE watchdog request");
+        let status = self.context.poe_watchdog_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
     }
-}
 
-impl<'a> HttpHandler for HttpWebAPIHandler<'a> {
-    async fn handle_request<HttpSocket: HttpWriteSocket>(
+    async fn api_poe_watchdog_settings<HttpSocket: HttpWriteSocket>(
         &mut self,
         allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving PoE watchdog settings request");
+        let poe_watchdog_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .poe_watchdog_settings;
+        send_serialized_type(allocator, http_socket, &poe_watchdog_settings).await
+    }
+
+    async fn api_set_poe_watchdog_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
         request: &HttpRequest<'_>,
         http_socket: &mut HttpSocket,
     ) -> Result<(), Error> {
+        log::debug!("Serving set PoE watchdog settings request");
+        let Some(poe_watchdog_settings) = from_request::<PoeWatchdogSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !poe_watchdog_settings.is_valid() {
+            log::error!("Invalid PoE watchdog settings: {:?}", poe_watchdog_settings);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid PoE watchdog settings").await;
+        }
+
         self.context
-            .shared_resources()
-            .led_controller
-            .set_animation(LED_1, LedAnimation::Decay(color::ORANGE, 300))
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.poe_watchdog_settings = poe_watchdog_settings;
+            })
             .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "PoE watchdog settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save PoE watchdog settings",
+                )
+                .await
+            }
+        }
+    }
 
-        self.handle_request_impl(allocator, request, http_socket).await
+    async fn api_efuse<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving e-fuse request");
+        let status = self.context.efuse_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
     }
 
-    async fn handle_websocket_connection<'h>(
+    async fn api_efuse_settings<HttpSocket: HttpWriteSocket>(
         &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving e-fuse settings request");
+        let efuse_settings = self.context.configuration_storage().get_settings().await.efuse_settings;
+        send_serialized_type(allocator, http_socket, &efuse_settings).await
+    }
+
+    async fn api_set_efuse_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
         request: &HttpRequest<'_>,
-        web_socket: &mut WebSocket<'h, '_>,
-    ) -> Result<(), ()> {
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set e-fuse settings request");
+        let Some(efuse_settings) = from_request::<EFuseSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !efuse_settings.is_valid(self.context.vcp_control().channel_count()) {
+            log::error!("Invalid e-fuse settings: {:?}", efuse_settings);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid e-fuse settings").await;
+        }
+
         self.context
-            .shared_resources()
-            .led_controller
-            .set_animation(LED_1, LedAnimation::Decay(color::ORANGE, 300))
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.efuse_settings = efuse_settings;
+            })
             .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "E-fuse settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save e-fuse settings",
+                )
+                .await
+            }
+        }
+    }
 
-        let res = self.handle_websocket_connection_impl(request, web_socket).await;
+    async fn api_reset_fuse<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving reset fuse request");
+        let Some(reset) = from_request::<FuseReset>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        self.context.efuse_control().reset(reset.output).await;
 
-        res
+        send_ok(http_socket, "Fuse reset").await
     }
-}
 
-async fn send_serialized_type<T, WriteSocket: HttpWriteSocket>(
-    allocator: &mut PrefixArena<'_>,
+    async fn api_vcp_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving VCP settings request");
+        let vcp_settings = self.context.configuration_storage().get_settings().await.vcp_settings;
+        send_serialized_type(allocator, http_socket, &vcp_settings).await
+    }
+
+    async fn api_set_vcp_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set VCP settings request");
+        let Some(vcp_settings) = from_request::<VcpSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !vcp_settings.is_valid() {
+            log::error!("Invalid VCP settings: {:?}", vcp_settings);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid VCP settings").await;
+        }
+
+        // The sampling is applied at once, the chips are set up at the next boot
+        self.context.vcp_control().set_sampling(vcp_settings.sampling).await;
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.vcp_settings = vcp_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "VCP settings applied and saved").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "VCP settings applied but not saved",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_readings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving readings request");
+        let vcp_control = self.context.vcp_control();
+        let channel_map = self.context.channel_map();
+        // The channels which have not been read yet are left out
+        let readings: heapless::Vec<_, VCP_CHANNELS> = (0..vcp_control.channel_count())
+            .filter_map(|channel| vcp_control.latest_reading(channel as ChannelNum))
+            .map(|reading| channel_map.labeled(reading.channel, VcpReadingValues::from(reading)))
+            .collect();
+        send_serialized_type(allocator, http_socket, &readings).await
+    }
+
+    async fn api_alarms<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving alarms request");
+        let vcp_control = self.context.vcp_control();
+        let alarms = AlarmSources {
+            lvd: &self.context.lvd_control().status().await,
+            efuse: &self.context.efuse_control().status().await,
+            vcp_alerts: vcp_control.alert_flags(),
+            vcp_health: vcp_control.health().health,
+            temperature: &self.context.temperature_control().status().await,
+            battery_health: &self.context.health_control().status().await,
+            poe_watchdog: &self.context.poe_watchdog_control().status().await,
+            channel_count: vcp_control.channel_count(),
+        }
+        .active_alarms();
+        send_serialized_type(allocator, http_socket, &alarms).await
+    }
+
+    async fn api_energy<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving energy request");
+        let vcp_control = self.context.vcp_control();
+        let energy = vcp_control.energy();
+        let channel_map = self.context.channel_map();
+        let energy: heapless::Vec<_, VCP_CHANNELS> = (0..vcp_control.channel_count())
+            .map(|channel| channel_map.labeled(channel as ChannelNum, energy[channel]))
+            .collect();
+        send_serialized_type(allocator, http_socket, &energy).await
+    }
+
+    async fn api_vcp_health<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving VCP health request");
+        let health = self.context.vcp_control().health();
+        send_serialized_type(allocator, http_socket, &health).await
+    }
+
+    async fn api_vcp_calibration<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving VCP calibration request");
+        let calibration_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .calibration_settings;
+        let channel_count = self.context.vcp_control().channel_count();
+        send_serialized_type(allocator, http_socket, &calibration_settings.fitted(channel_count)).await
+    }
+
+    async fn api_vcp_uncalibrated<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving uncalibrated VCP readings request");
+        let vcp_control = self.context.vcp_control();
+        let readings: heapless::Vec<_, VCP_CHANNELS> = (0..vcp_control.channel_count())
+            .map(|channel| vcp_control.uncalibrated_reading(channel as ChannelNum))
+            .collect();
+        send_serialized_type(allocator, http_socket, &readings).await
+    }
+
+    async fn api_set_vcp_calibration<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set VCP calibration request");
+        let Some(change) = from_request::<VcpCalibrationChange>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if change.channel as usize >= self.context.vcp_control().channel_count() || !change.calibration.is_valid() {
+            log::error!("Invalid VCP calibration: {:?}", change);
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid calibration").await;
+        }
+
+        self.save_vcp_calibration(http_socket, change.channel, change.calibration)
+            .await
+    }
+
+    async fn api_calibrate_vcp<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving calibrate VCP request");
+        let Some(calibration_request) = from_request::<VcpCalibrationRequest>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        let mut calibration_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .calibration_settings;
+        let calibration = if calibration_request.channel as usize >= self.context.vcp_control().channel_count() {
+            Err(VcpCalibrationError::InvalidChannel)
+        } else {
+            calibration_settings.calibrate(&calibration_request)
+        };
+        match calibration {
+            Ok(calibration) => {
+                self.save_vcp_calibration(http_socket, calibration_request.channel, calibration)
+                    .await
+            }
+            Err(e) => {
+                log::error!("VCP calibration failed: {:?}", e);
+                send_error(http_socket, StatusCode::BadRequest, e.name()).await
+            }
+        }
+    }
+
+    /// Applies the calibration of the channel to the readings and stores it
+    async fn save_vcp_calibration<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        http_socket: &mut HttpSocket,
+        channel: ChannelNum,
+        calibration: VcpChannelCalibration,
+    ) -> Result<(), Error> {
+        self.context.vcp_control().set_calibration(channel, calibration).await;
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.calibration_settings.channels[channel as usize] = calibration;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Calibration applied and saved").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Calibration applied but not saved",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_reset_energy<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving reset energy request");
+        let Some(reset) = from_request::<EnergyTripReset>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if reset.channel as usize >= self.context.vcp_control().channel_count() {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid channel").await;
+        }
+        self.context.vcp_control().reset_energy_trip(reset.channel).await;
+
+        send_ok(http_socket, "Energy trip counters reset").await
+    }
+
+    async fn api_history<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving history request");
+        let channel_count = self.context.vcp_control().channel_count();
+        let Some((query, format)) = parse_history_request(query_string(request), channel_count) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid history query").await;
+        };
+
+        // The first batch is read before the headers, so a failing read is answered with an error status
+        let mut cursor = HistoryCursor::new();
+        let mut records = [HistoryRecord::default(); HISTORY_READ_BATCH];
+        let history_control = self.context.history_control();
+        let mut count = match history_control.read(&query, &mut cursor, &mut records).await {
+            Ok(count) => count,
+            Err(e) => {
+                log::error!("Failed to read the history: {:?}", e);
+                return send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to read the history",
+                )
+                .await;
+            }
+        };
+
+        let mut body = match format {
+            HistoryFormat::Csv => {
+                let mut body = ChunkedBody::start(http_socket, "text/csv", "history.csv").await?;
+                body.write(HistoryRecord::CSV_HEADER.as_bytes()).await?;
+                body
+            }
+            HistoryFormat::Json => {
+                let mut body = ChunkedBody::start(http_socket, "application/json", "history.json").await?;
+                body.write(b"[").await?;
+                body
+            }
+        };
+
+        // The rest is read in batches, so the history is not held back while the body is sent
+        let mut first = true;
+        loop {
+            for record in &records[..count] {
+                match format {
+                    HistoryFormat::Csv => {
+                        let mut row = heapless::String::<128>::new();
+                        record.write_csv_row(&mut row).map_err(|_| Error::ServerError)?;
+                        body.write(row.as_bytes()).await?;
+                    }
+                    HistoryFormat::Json => {
+                        if !first {
+                            body.write(b",").await?;
+                        }
+                        let mut item = [0u8; 256];
+                        let len = serde_json_core::to_slice(record, &mut item).map_err(|e| {
+                            log::error!("Serialization error: {}", e);
+                            Error::ServerError
+                        })?;
+                        body.write(&item[..len]).await?;
+                    }
+                }
+                first = false;
+            }
+
+            if count < records.len() {
+                break;
+            }
+            count = history_control
+                .read(&query, &mut cursor, &mut records)
+                .await
+                .map_err(|e| {
+                    // The headers are sent already. The body ends without the final chunk on a closing
+                    // connection, which the client sees as an incomplete download.
+                    log::error!("Failed to read the history: {:?}", e);
+                    Error::ServerError
+                })?;
+        }
+
+        if format == HistoryFormat::Json {
+            body.write(b"]").await?;
+        }
+        body.finish().await
+    }
+
+    async fn api_charger<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving charger request");
+        let status = self.context.charger_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_channel_map<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving channel map request");
+        let channel_map = self.context.configuration_storage().get_settings().await.channel_map;
+        let channel_count = self.context.vcp_control().channel_count();
+        send_serialized_type(allocator, http_socket, &channel_map.fitted(channel_count)).await
+    }
+
+    async fn api_set_channel_map<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set channel map request");
+        let Some(channel_map) = from_request::<ChannelMap>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !channel_map.is_valid(self.context.vcp_control().channel_count()) {
+            log::error!("Invalid channel map: {:?}", channel_map);
+            return send_error(
+                http_socket,
+                StatusCode::BadRequest,
+                "Invalid channel map, exactly one channel must be the battery",
+            )
+            .await;
+        }
+
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.channel_map = channel_map;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Channel map updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save channel map",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_temperature<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving temperature request");
+        let status = self.context.temperature_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_temperature_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving temperature settings request");
+        let temperature_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .temperature_settings;
+        send_serialized_type(allocator, http_socket, &temperature_settings).await
+    }
+
+    async fn api_set_temperature_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set temperature settings request");
+        let Some(temperature_settings) = from_request::<TemperatureSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !temperature_settings.is_valid() {
+            log::error!("Invalid temperature settings: {:?}", temperature_settings);
+            return send_error(
+                http_socket,
+                StatusCode::BadRequest,
+                "Invalid temperature settings, the coefficient must be within -6..0 mV/cell/°C",
+            )
+            .await;
+        }
+
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.temperature_settings = temperature_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Temperature settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save temperature settings",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_health<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving state of health request");
+        let status = self.context.health_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_reset_health<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving reset state of health request");
+        self.context.health_control().reset();
+        send_ok(http_socket, "State-of-health trend cleared").await
+    }
+
+    async fn api_cycles<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving cycles request");
+        let status = self.context.cycle_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_reset_cycles<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving reset cycles request");
+        self.context.cycle_control().reset();
+        send_ok(http_socket, "Cycle statistics cleared").await
+    }
+
+    async fn api_health_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving state of health settings request");
+        let health_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .health_settings;
+        send_serialized_type(allocator, http_socket, &health_settings).await
+    }
+
+    async fn api_set_health_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set state of health settings request");
+        let Some(health_settings) = from_request::<HealthSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !health_settings.is_valid() {
+            log::error!("Invalid state of health settings: {:?}", health_settings);
+            return send_error(
+                http_socket,
+                StatusCode::BadRequest,
+                "Invalid state of health settings, the resistance rise must be within 10..500% and the capacity within 10..100%",
+            )
+            .await;
+        }
+
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.health_settings = health_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "State of health settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save state of health settings",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_equalization<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving equalization request");
+        let status = self.context.equalization_control().status().await;
+        send_serialized_type(allocator, http_socket, &status).await
+    }
+
+    async fn api_start_equalization<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving start equalization request");
+        if !self.context.equalization_control().status().await.supported {
+            return send_error(
+                http_socket,
+                StatusCode::BadRequest,
+                "The battery profile has no equalization voltage or no charger output is set",
+            )
+            .await;
+        }
+        self.context.equalization_control().start();
+        send_ok(http_socket, "Equalization started").await
+    }
+
+    async fn api_stop_equalization<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving stop equalization request");
+        self.context.equalization_control().stop();
+        send_ok(http_socket, "Equalization stopped").await
+    }
+
+    async fn api_equalization_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving equalization settings request");
+        let equalization_settings = self
+            .context
+            .configuration_storage()
+            .get_settings()
+            .await
+            .equalization_settings;
+        send_serialized_type(allocator, http_socket, &equalization_settings).await
+    }
+
+    async fn api_set_equalization_settings<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        log::debug!("Serving set equalization settings request");
+        let Some(equalization_settings) = from_request::<EqualizationSettings>(request) else {
+            return send_error(http_socket, StatusCode::BadRequest, "Invalid request body").await;
+        };
+        if !equalization_settings.is_valid() {
+            log::error!("Invalid equalization settings: {:?}", equalization_settings);
+            return send_error(
+                http_socket,
+                StatusCode::BadRequest,
+                "Invalid equalization settings, the temperature limit must be within 20..55°C and the current limit positive",
+            )
+            .await;
+        }
+
+        self.context
+            .configuration_storage()
+            .modify_settings(|settings| {
+                settings.equalization_settings = equalization_settings;
+            })
+            .await;
+        match self.context.configuration_storage().save().await {
+            Ok(_) => send_ok(http_socket, "Equalization settings updated. Reboot to apply.").await,
+            Err(e) => {
+                log::error!("Failed to save configuration: {:?}", e);
+                send_error(
+                    http_socket,
+                    StatusCode::InternalServerError,
+                    "Failed to save equalization settings",
+                )
+                .await
+            }
+        }
+    }
+
+    async fn api_not_found<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        _allocator: &mut PrefixArena<'_>,
+        _request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        send_error(http_socket, StatusCode::NotFound, "Not Found").await
+    }
+
+    async fn handle_rest_api<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+        api: &str,
+    ) -> Result<(), Error> {
+        match (request.method, api) {
+            (HttpMethod::GET, "version") => self.api_version(allocator, request, http_socket).await,
+            (HttpMethod::GET, "reboot") => self.api_reboot(allocator, request, http_socket).await,
+            (HttpMethod::GET, "wifi_config") => self.api_wifi_config(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_wifi_config") => self.api_set_wifi_config(allocator, request, http_socket).await,
+            (HttpMethod::GET, "date_time") => self.api_date_time(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_date_time") => self.api_set_date_time(allocator, request, http_socket).await,
+            (HttpMethod::GET, "battery") => self.api_battery(allocator, request, http_socket).await,
+            (HttpMethod::GET, "battery_profile") => self.api_battery_profile(allocator, request, http_socket).await,
+            (HttpMethod::GET, "battery_profile_presets") => {
+                self.api_battery_profile_presets(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "set_battery_profile") => {
+                self.api_set_battery_profile(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "lvd") => self.api_lvd(allocator, request, http_socket).await,
+            (HttpMethod::GET, "lvd_settings") => self.api_lvd_settings(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_lvd_settings") => self.api_set_lvd_settings(allocator, request, http_socket).await,
+            (HttpMethod::GET, "outputs") => self.api_outputs(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_output") | (HttpMethod::PUT, "output") => {
+                self.api_set_output(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "output_defaults") => self.api_output_defaults(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_output_defaults") => {
+                self.api_set_output_defaults(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "poe_watchdog") => self.api_poe_watchdog(allocator, request, http_socket).await,
+            (HttpMethod::GET, "poe_watchdog_settings") => {
+                self.api_poe_watchdog_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "set_poe_watchdog_settings") => {
+                self.api_set_poe_watchdog_settings(allocator, request, http_socket)
+                    .await
+            }
+            (HttpMethod::GET, "efuse") => self.api_efuse(allocator, request, http_socket).await,
+            (HttpMethod::GET, "efuse_settings") => self.api_efuse_settings(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_efuse_settings") => {
+                self.api_set_efuse_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "reset_fuse") => self.api_reset_fuse(allocator, request, http_socket).await,
+            (HttpMethod::GET, "vcp_settings") => self.api_vcp_settings(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_vcp_settings") => self.api_set_vcp_settings(allocator, request, http_socket).await,
+            (HttpMethod::GET, "vcp_health") => self.api_vcp_health(allocator, request, http_socket).await,
+            (HttpMethod::GET, "vcp_calibration") => self.api_vcp_calibration(allocator, request, http_socket).await,
+            (HttpMethod::GET, "vcp_uncalibrated") => self.api_vcp_uncalibrated(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_vcp_calibration") => {
+                self.api_set_vcp_calibration(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "calibrate_vcp") => self.api_calibrate_vcp(allocator, request, http_socket).await,
+            (HttpMethod::GET, "readings") => self.api_readings(allocator, request, http_socket).await,
+            (HttpMethod::GET, "alarms") => self.api_alarms(allocator, request, http_socket).await,
+            (HttpMethod::GET, "energy") => self.api_energy(allocator, request, http_socket).await,
+            (HttpMethod::POST, "reset_energy") => self.api_reset_energy(allocator, request, http_socket).await,
+            (HttpMethod::GET, "history") => self.api_history(allocator, request, http_socket).await,
+            (HttpMethod::GET, "charger") => self.api_charger(allocator, request, http_socket).await,
+            (HttpMethod::GET, "channel_map") => self.api_channel_map(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_channel_map") => self.api_set_channel_map(allocator, request, http_socket).await,
+            (HttpMethod::GET, "temperature") => self.api_temperature(allocator, request, http_socket).await,
+            (HttpMethod::GET, "temperature_settings") => {
+                self.api_temperature_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "set_temperature_settings") => {
+                self.api_set_temperature_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "health") => self.api_health(allocator, request, http_socket).await,
+            (HttpMethod::POST, "reset_health") => self.api_reset_health(allocator, request, http_socket).await,
+            (HttpMethod::GET, "cycles") => self.api_cycles(allocator, request, http_socket).await,
+            (HttpMethod::POST, "reset_cycles") => self.api_reset_cycles(allocator, request, http_socket).await,
+            (HttpMethod::GET, "health_settings") => self.api_health_settings(allocator, request, http_socket).await,
+            (HttpMethod::POST, "set_health_settings") => {
+                self.api_set_health_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "equalization") => self.api_equalization(allocator, request, http_socket).await,
+            (HttpMethod::POST, "start_equalization") => {
+                self.api_start_equalization(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "stop_equalization") => {
+                self.api_stop_equalization(allocator, request, http_socket).await
+            }
+            (HttpMethod::GET, "equalization_settings") => {
+                self.api_equalization_settings(allocator, request, http_socket).await
+            }
+            (HttpMethod::POST, "set_equalization_settings") => {
+                self.api_set_equalization_settings(allocator, request, http_socket)
+                    .await
+            }
+            _ => self.api_not_found(allocator, request, http_socket).await,
+        }
+    }
+
+    async fn handle_request_impl<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        // The query string is left to the handlers
+        let path = request.path.split_once('?').map_or(request.path, |(path, _)| path);
+        if path == "/" {
+            // Show main page
+            log::debug!("Serving main configuration page");
+
+            return HttpResponseBuilder::new(http_socket)
+                .with_status(StatusCode::Ok)
+                .await?
+                .with_compressed_page(MAIN_CONFIGURATION_HTML_GZ)
+                .await;
+        }
+
+        let Some(api) = path.strip_prefix("/api/") else {
+            return send_error(http_socket, StatusCode::NotFound, "Not Found").await;
+        };
+
+        if request.method == HttpMethod::OPTIONS {
+            log::debug!("Serving {} preflight request", api);
+            trace_headers(request);
+            //TODO: Implement more strict header checking
+            return HttpResponseBuilder::new(http_socket).preflight_response().await;
+        }
+
+        self.handle_rest_api(allocator, request, http_socket, api).await
+    }
+
+    /// Serves the live telemetry at `/ws/telemetry`. The client requests every frame, the protocol is
+    /// described in the telemetry module.
+    async fn handle_websocket_connection_impl<'h>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        web_socket: &mut WebSocket<'h, '_>,
+    ) -> Result<(), ()> {
+        if request.path != "/ws/telemetry" {
+            log::warn!("Unknown WebSocket endpoint: {}", request.path);
+            return web_socket.close().await.map_err(|_| ());
+        }
+
+        let Some(_session) = TelemetrySession::open() else {
+            log::warn!(
+                "Live telemetry refused, {} session(s) already open",
+                MAX_TELEMETRY_SESSIONS
+            );
+            return web_socket.close().await.map_err(|_| ());
+        };
+
+        log::info!("Serving live telemetry");
+        let channel_count = self.context.vcp_control().channel_count();
+        let mut subscription = TelemetrySubscription::new(channel_count);
+        let mut request_buffer = [0u8; TELEMETRY_REQUEST_SIZE];
+        let mut frame_buffer = [0u8; TELEMETRY_FRAME_SIZE];
+        let mut next_frame_at = Instant::now();
+
+        loop {
+            // The client requests every frame, so a read is only dropped when the session ends and never
+            // leaves the stream in the middle of a message
+            let len = match select(
+                web_socket.read(&mut request_buffer),
+                Timer::after(TELEMETRY_IDLE_TIMEOUT_S.s()),
+            )
+            .await
+            {
+                Either::First(Ok(0)) | Either::First(Err(_)) => {
+                    log::info!("Live telemetry closed");
+                    return Ok(());
+                }
+                Either::First(Ok(len)) => len,
+                Either::Second(()) => {
+                    log::info!("Live telemetry idle, closing");
+                    return web_socket.close().await.map_err(|_| ());
+                }
+            };
+            match serde_json_core::from_slice::<TelemetryRequest>(&request_buffer[..len]) {
+                Ok((telemetry_request, _)) => subscription.apply(&telemetry_request, channel_count),
+                Err(e) => log::warn!("Invalid telemetry request: {}", e),
+            }
+
+            // The frames keep the subscribed period however fast the client asks for them
+            Timer::at(next_frame_at).await;
+            next_frame_at = Instant::now() + subscription.period();
+
+            let frame = TelemetryFrame::new(
+                subscription.readings(self.context.vcp_control()),
+                self.context.battery_control().status().await,
+                self.context.outputs_control().statuses().await,
+            );
+            let len = serde_json_core::to_slice(&frame, &mut frame_buffer).map_err(|e| {
+                log::error!("Serialization error: {}", e);
+            })?;
+            web_socket.write_all(&frame_buffer[..len]).await.map_err(|_| ())?;
+        }
+    }
+}
+
+fn trace_headers(request: &HttpRequest<'_>) {
+    log::debug!("Request header");
+    for header in request.headers.iter() {
+        log::debug!(" - : {}: {}", header.name, header.value);
+    }
+}
+
+impl<'a> HttpHandler for HttpWebAPIHandler<'a> {
+    async fn handle_request<HttpSocket: HttpWriteSocket>(
+        &mut self,
+        allocator: &mut PrefixArena<'_>,
+        request: &HttpRequest<'_>,
+        http_socket: &mut HttpSocket,
+    ) -> Result<(), Error> {
+        self.context
+            .shared_resources()
+            .led_controller
+            .set_animation(LED_1, LedAnimation::Decay(color::ORANGE, 300))
+            .await;
+
+        self.handle_request_impl(allocator, request, http_socket).await
+    }
+
+    async fn handle_websocket_connection<'h>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        web_socket: &mut WebSocket<'h, '_>,
+    ) -> Result<(), ()> {
+        self.context
+            .shared_resources()
+            .led_controller
+            .set_animation(LED_1, LedAnimation::Decay(color::ORANGE, 300))
+            .await;
+
+        let res = self.handle_websocket_connection_impl(request, web_socket).await;
+
+        res
+    }
+}
+
+async fn send_serialized_type<T, WriteSocket: HttpWriteSocket>(
+    allocator: &mut PrefixArena<'_>,
     http_socket: &mut WriteSocket,
     value: &T,
 ) -> Result<(), Error>
@@ -380,12 +1541,18 @@ where
     T: serde::Serialize,
 {
     let mut temp_buf = allocator.view();
-    let value_buf = temp_buf.init_with(|uninitialized| {
-        serde_json_core::to_slice(value, uninitialized).map_err(|e| {
+    let value_buf = match temp_buf.init_with(|uninitialized| serde_json_core::to_slice(value, uninitialized)) {
+        Ok(value_buf) => value_buf,
+        Err(e) => {
             log::error!("Serialization error: {}", e);
-            Error::ServerError
-        })
-    })?;
+            return send_error(
+                http_socket,
+                StatusCode::InternalServerError,
+                "Failed to serialize the response",
+            )
+            .await;
+        }
+    };
 
     HttpResponseBuilder::new(http_socket)
         .with_status(StatusCode::Ok)
@@ -396,14 +1563,128 @@ where
         .await
 }
 
-fn from_request<'de, T>(request: &HttpRequest<'de>) -> Result<T, nanofish::Error>
+/// The body of the error responses, e.g. `{"error":"Invalid channel"}`
+#[derive(serde::Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+/// The body of the successful responses which carry no data, e.g. `{"ok":true,"message":"Fuse reset"}`
+#[derive(serde::Serialize)]
+struct OkBody<'a> {
+    ok: bool,
+    message: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct VersionBody<'a> {
+    version: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct DateTimeBody<'a> {
+    date_time: &'a str,
+}
+
+async fn send_error<WriteSocket: HttpWriteSocket>(
+    http_socket: &mut WriteSocket,
+    status: StatusCode,
+    message: &str,
+) -> Result<(), Error> {
+    send_message(http_socket, status, &ErrorBody { error: message }).await
+}
+
+async fn send_ok<WriteSocket: HttpWriteSocket>(http_socket: &mut WriteSocket, message: &str) -> Result<(), Error> {
+    send_message(http_socket, StatusCode::Ok, &OkBody { ok: true, message }).await
+}
+
+async fn send_message<T, WriteSocket: HttpWriteSocket>(
+    http_socket: &mut WriteSocket,
+    status: StatusCode,
+    body: &T,
+) -> Result<(), Error>
+where
+    T: serde::Serialize,
+{
+    let mut body_buf = [0u8; MESSAGE_BODY_SIZE];
+    let len = serde_json_core::to_slice(body, &mut body_buf).map_err(|e| {
+        log::error!("Serialization error: {}", e);
+        Error::ServerError
+    })?;
+
+    HttpResponseBuilder::new(http_socket)
+        .with_status(status)
+        .await?
+        .with_header("Content-Type", "application/json")
+        .await?
+        .with_body_from_slice(&body_buf[..len])
+        .await
+}
+
+/// Parses the JSON body of the request, `None` if it is not valid
+fn from_request<'de, T>(request: &HttpRequest<'de>) -> Option<T>
 where
     T: serde::Deserialize<'de>,
 {
-    let (value, _) = serde_json_core::from_slice(request.body).map_err(|e| {
-        log::error!("Deserialization error: {}", e);
-        nanofish::Error::ServerError
-    })?;
+    match serde_json_core::from_slice(request.body) {
+        Ok((value, _)) => Some(value),
+        Err(e) => {
+            log::error!("Deserialization error: {}", e);
+            None
+        }
+    }
+}
+
+fn query_string<'r>(request: &HttpRequest<'r>) -> &'r str {
+    request.path.split_once('?').map_or("", |(_, query)| query)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+/// Parses `channel=&from=&to=&resolution=&format=` of a history request. All are optional: all the
+/// channels, the whole history, minutes and JSON by default.
+fn parse_history_request(query: &str, channel_count: usize) -> Option<(HistoryQuery, HistoryFormat)> {
+    let mut history_query = HistoryQuery {
+        resolution: HistoryResolution::Minute,
+        channel: None,
+        from_s: 0,
+        to_s: u32::MAX,
+    };
+    let mut format = HistoryFormat::Json;
+
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        match name {
+            "channel" if value.is_empty() => history_query.channel = None,
+            "channel" => {
+                let channel: ChannelNum = value.parse().ok()?;
+                if channel as usize >= channel_count {
+                    return None;
+                }
+                history_query.channel = Some(channel);
+            }
+            "from" => history_query.from_s = value.parse().ok()?,
+            "to" => history_query.to_s = value.parse().ok()?,
+            "resolution" => {
+                history_query.resolution = HistoryResolution::ALL
+                    .into_iter()
+                    .find(|resolution| resolution.name() == value)?
+            }
+            "format" => {
+                format = match value {
+                    "csv" => HistoryFormat::Csv,
+                    "json" => HistoryFormat::Json,
+                    _ => return None,
+                }
+            }
+            // Unknown parameters, like cache busters, are ignored
+            _ => {}
+        }
+    }
 
-    Ok(value)
+    Some((history_query, format))
 }
@@ -0,0 +1,127 @@
+//! Live telemetry over the `/ws/telemetry` WebSocket.
+//!
+//! The client pulls the frames: every text message it sends is a [`TelemetryRequest`] and is
+//! answered with one [`TelemetryFrame`]. The first request usually subscribes, e.g.
+//! `{"channels":[0,2],"period_ms":500}`, and `{}` then asks for the next frame with the same
+//! subscription. A frame is not sent before the subscribed period has passed since the previous one,
+//! however fast the client asks. An invalid request keeps the subscription and still gets a frame.
+//!
+//! The server closes the session when no request comes within [`TELEMETRY_IDLE_TIMEOUT_S`]. Up to
+//! [`MAX_TELEMETRY_SESSIONS`] sessions are open at a time, the server closes any further WebSocket
+//! right away so the remaining sockets stay free for the REST API.
+
+use embassy_time::{Duration, Instant};
+use portable_atomic::{AtomicUsize, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::battery::BatteryStatus;
+use crate::outputs::{OUTPUT_COUNT, OutputStatus};
+use crate::vcp_sensors::{ChannelNum, VCP_CHANNELS, VcpControl, VcpReadingValues};
+
+/// The shortest and the longest period of the telemetry frames
+const MIN_PERIOD_MS: u32 = 100;
+const MAX_PERIOD_MS: u32 = 60_000;
+/// The period until the client subscribes
+const DEFAULT_PERIOD_MS: u32 = 1000;
+/// The live telemetry sessions at a time, the other sockets are left to the REST API
+pub const MAX_TELEMETRY_SESSIONS: usize = 1;
+/// How long a client may take to request the next frame, in seconds
+pub const TELEMETRY_IDLE_TIMEOUT_S: u64 = 10;
+
+static TELEMETRY_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A slot of the live telemetry, released when dropped
+pub struct TelemetrySession(());
+
+impl TelemetrySession {
+    /// Takes a free slot, `None` if all the sessions are open
+    pub fn open() -> Option<Self> {
+        TELEMETRY_SESSIONS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |sessions| {
+                (sessions < MAX_TELEMETRY_SESSIONS).then_some(sessions + 1)
+            })
+            .ok()
+            .map(|_| Self(()))
+    }
+}
+
+impl Drop for TelemetrySession {
+    fn drop(&mut self) {
+        TELEMETRY_SESSIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A request of the client for the next frame, e.g. `{"channels":[0,2],"period_ms":500}` to subscribe or
+/// `{}` to keep the subscription. The missing fields are left as they are.
+#[derive(Deserialize)]
+pub struct TelemetryRequest {
+    pub channels: Option<heapless::Vec<ChannelNum, VCP_CHANNELS>>,
+    pub period_ms: Option<u32>,
+}
+
+/// The channels and the rate a client receives the telemetry at. All the fitted channels once a second
+/// until the client subscribes.
+pub struct TelemetrySubscription {
+    channels: [bool; VCP_CHANNELS],
+    period_ms: u32,
+}
+
+impl TelemetrySubscription {
+    pub fn new(channel_count: usize) -> Self {
+        Self {
+            channels: core::array::from_fn(|channel| channel < channel_count),
+            period_ms: DEFAULT_PERIOD_MS,
+        }
+    }
+
+    /// Applies the request, the channels which are not fitted are skipped and the period is clamped
+    pub fn apply(&mut self, request: &TelemetryRequest, channel_count: usize) {
+        if let Some(channels) = &request.channels {
+            self.channels = [false; VCP_CHANNELS];
+            for &channel in channels.iter().filter(|&&channel| (channel as usize) < channel_count) {
+                self.channels[channel as usize] = true;
+            }
+        }
+        if let Some(period_ms) = request.period_ms {
+            self.period_ms = period_ms.clamp(MIN_PERIOD_MS, MAX_PERIOD_MS);
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.period_ms as u64)
+    }
+
+    /// The latest readings of the subscribed channels. The channels without a reading yet are skipped.
+    pub fn readings(&self, vcp_control: &VcpControl<'_>) -> heapless::Vec<VcpReadingValues, VCP_CHANNELS> {
+        (0..VCP_CHANNELS)
+            .filter(|&channel| self.channels[channel])
+            .filter_map(|channel| vcp_control.latest_reading(channel as ChannelNum))
+            .map(VcpReadingValues::from)
+            .collect()
+    }
+}
+
+/// A frame of the live telemetry
+#[derive(Serialize)]
+pub struct TelemetryFrame {
+    pub uptime_ms: u64,
+    pub channels: heapless::Vec<VcpReadingValues, VCP_CHANNELS>,
+    /// Includes the state of charge
+    pub battery: BatteryStatus,
+    pub outputs: [OutputStatus; OUTPUT_COUNT],
+}
+
+impl TelemetryFrame {
+    pub fn new(
+        channels: heapless::Vec<VcpReadingValues, VCP_CHANNELS>,
+        battery: BatteryStatus,
+        outputs: [OutputStatus; OUTPUT_COUNT],
+    ) -> Self {
+        Self {
+            uptime_ms: Instant::now().as_millis(),
+            channels,
+            battery,
+            outputs,
+        }
+    }
+}